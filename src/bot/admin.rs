@@ -0,0 +1,80 @@
+//! Carries out requests from the admin HTTP API, which has no Discord client of its own
+
+use crate::state::{AdminAction, AdminFailure, AppState};
+use serenity::all::{Cache, Http};
+
+use super::commands::forcelink::{LinkOutcome, LinkedBy, link_user};
+use super::commands::unverify::unverify_user;
+
+/// Perform an admin action, returning a summary for the API response
+pub async fn perform(
+    http: &Http,
+    cache: &Cache,
+    state: &AppState,
+    action: AdminAction,
+) -> Result<String, AdminFailure> {
+    match action {
+        AdminAction::Unverify { guild_id, user_id } => {
+            match unverify_user(http, cache, state, guild_id, user_id).await {
+                Ok(Some(removed_roles)) => Ok(format!(
+                    "Unverified user {}, removed {} roles",
+                    user_id,
+                    removed_roles.len()
+                )),
+                Ok(None) => Err(AdminFailure::NotFound(format!(
+                    "User {} is not verified",
+                    user_id
+                ))),
+                Err(e) => Err(AdminFailure::Internal(e.to_string())),
+            }
+        }
+        AdminAction::Link {
+            guild_id,
+            user_id,
+            keycloak,
+        } => match link_user(
+            http,
+            cache,
+            state,
+            guild_id,
+            user_id,
+            &keycloak,
+            LinkedBy::AdminApi,
+        )
+        .await
+        {
+            Ok(LinkOutcome::Linked {
+                keycloak_username,
+                awaiting_join: false,
+            }) => Ok(format!(
+                "Linked user {} to {} and assigned their roles",
+                user_id, keycloak_username
+            )),
+            Ok(LinkOutcome::Linked {
+                keycloak_username,
+                awaiting_join: true,
+            }) => Ok(format!(
+                "Linked user {} to {}, their roles will be assigned when they join",
+                user_id, keycloak_username
+            )),
+            Ok(LinkOutcome::NotFound) => Err(AdminFailure::NotFound(format!(
+                "No Keycloak user found for {}",
+                keycloak
+            ))),
+            Ok(LinkOutcome::AlreadyLinked {
+                keycloak_username,
+                discord_user_id,
+            }) => Err(AdminFailure::Conflict(format!(
+                "{} is already linked to user {}",
+                keycloak_username, discord_user_id
+            ))),
+            Ok(LinkOutcome::EmailDomainRejected { keycloak_username }) => {
+                Err(AdminFailure::Conflict(format!(
+                    "{} doesn't have a verified email from one of the guild's email domains",
+                    keycloak_username
+                )))
+            }
+            Err(e) => Err(AdminFailure::Internal(e.to_string())),
+        },
+    }
+}
@@ -0,0 +1,162 @@
+use crate::bot::Error;
+use crate::keys::redis_key;
+use crate::state::AppState;
+use redis::AsyncCommands;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommand, CreateCommandOption,
+    CreateInteractionResponse, CreateInteractionResponseMessage, Mentionable, ResolvedValue,
+};
+use std::sync::Arc;
+
+use super::utils::is_admin;
+
+/// Register the alwaysrole command
+pub fn register() -> CreateCommand<'static> {
+    CreateCommand::new("alwaysrole")
+        .description("Give a role to every member who verifies, in any role mode")
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::Role, "role", "The role to assign")
+                .required(true),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "remove",
+                "Stop assigning the role instead",
+            )
+            .required(false),
+        )
+}
+
+/// Handle the alwaysrole command
+pub async fn handle(
+    ctx: &Context,
+    command: &CommandInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let user = &command.user;
+
+    // Get guild_id from context
+    let guild_id = match command.guild_id {
+        Some(id) => id,
+        None => {
+            let response = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content("This command can only be used in a server.")
+                    .ephemeral(true),
+            );
+            command.create_response(&ctx.http, response).await?;
+            return Ok(());
+        }
+    };
+
+    // Check if user has administrator permissions
+    if !is_admin(ctx, &command.member, guild_id, user.id).await? {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("You need administrator permissions to configure verification roles.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    // Get the role and remove flag from command options
+    let mut role = None;
+    let mut remove = false;
+    for option in command.data.options() {
+        match (option.name, option.value) {
+            ("role", ResolvedValue::Role(r)) => role = Some(r),
+            ("remove", ResolvedValue::Boolean(b)) => remove = b,
+            _ => {}
+        }
+    }
+
+    let Some(role) = role else {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("Role parameter is required.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    };
+
+    let mut conn = state.redis.clone();
+    let redis_key = redis_key!("guild:{}:roles:always", guild_id);
+
+    if remove {
+        let _: () = conn.srem(&redis_key, role.id.to_string()).await?;
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content(format!(
+                    "{} will no longer be given on verification. Members who have it keep it.",
+                    role.mention()
+                ))
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    // Check various restrictions on the selected role
+    let everyone_role_id = serenity::all::RoleId::from(guild_id.get());
+    let restriction = if role.id == everyone_role_id {
+        Some("You cannot assign @everyone on verification.".to_string())
+    } else if role.managed() {
+        Some(
+            "You cannot use a managed role (bot/integration role) as a verification role."
+                .to_string(),
+        )
+    } else {
+        let bot_user_id = ctx.cache.current_user().id;
+        let bot_member = guild_id.member(&ctx.http, bot_user_id).await?;
+        let guild_roles = guild_id.roles(&ctx.http).await?;
+
+        // Find bot's highest role position
+        let bot_position = bot_member
+            .roles
+            .iter()
+            .filter_map(|role_id| guild_roles.get(role_id))
+            .map(|role| role.position)
+            .max()
+            .unwrap_or(0);
+        let target_role_position = guild_roles.get(&role.id).map(|r| r.position).unwrap_or(0);
+
+        (bot_position <= target_role_position).then(|| {
+            format!(
+                "I cannot assign {}. My highest role is at position {}, but this role is at position {}.\n\
+                Please move my role higher than {} in the server settings.",
+                role.mention(),
+                bot_position,
+                target_role_position,
+                role.mention()
+            )
+        })
+    };
+
+    if let Some(message) = restriction {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content(message)
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    let _: () = conn.sadd(&redis_key, role.id.to_string()).await?;
+
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(format!(
+                "{} will be given to every member who verifies, alongside the verified role. \
+                Run `/reverify` to give it to members who already verified.",
+                role.mention()
+            ))
+            .ephemeral(true),
+    );
+    command.create_response(&ctx.http, response).await?;
+
+    Ok(())
+}
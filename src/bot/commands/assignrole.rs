@@ -0,0 +1,192 @@
+use crate::bot::Error;
+use crate::bot::discord::DiscordApi;
+use crate::redact::redact;
+use crate::state::AppState;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommand, CreateCommandOption,
+    CreateEmbed, CreateMessage, EditInteractionResponse, Mentionable, Permissions, ResolvedValue,
+};
+use std::sync::Arc;
+
+use super::utils::{Deferred, admin_grant, load_guild_config, log_destination};
+
+/// Register the assignrole command
+pub fn register() -> CreateCommand<'static> {
+    CreateCommand::new("assignrole")
+        .description("Give a member one of the roles verification manages (admin only)")
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::User, "user", "The member to give it to")
+                .required(true),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Role,
+                "role",
+                "A level, class, group, attribute or always-assigned role",
+            )
+            .required(true),
+        )
+        .default_member_permissions(Permissions::ADMINISTRATOR)
+}
+
+/// Handle the assignrole command. Only roles in the guild's verification config can be
+/// given, so the bot can't be used to hand out arbitrary roles.
+pub async fn handle(
+    ctx: &Context,
+    command: &CommandInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let user = &command.user;
+
+    let reply = Deferred::command(&ctx.http, command).await?;
+
+    // Get guild_id from context
+    let guild_id = match command.guild_id {
+        Some(id) => id,
+        None => {
+            reply
+                .edit(
+                    EditInteractionResponse::new()
+                        .content("This command can only be used in a server."),
+                )
+                .await?;
+            return Ok(());
+        }
+    };
+
+    // Check if user has administrator permissions
+    let Some(grant) = admin_grant(ctx, &command.member, guild_id, user.id).await? else {
+        reply
+            .edit(
+                EditInteractionResponse::new()
+                    .content("You need administrator permissions to assign verification roles."),
+            )
+            .await?;
+        return Ok(());
+    };
+
+    // Get the target user and role from command options
+    let mut target_user = None;
+    let mut role = None;
+    for option in command.data.options() {
+        match (option.name, option.value) {
+            ("user", ResolvedValue::User(u, _)) => target_user = Some(u.clone()),
+            ("role", ResolvedValue::Role(r)) => role = Some(r.clone()),
+            _ => {}
+        }
+    }
+
+    let (Some(target_user), Some(role)) = (target_user, role) else {
+        reply
+            .edit(EditInteractionResponse::new().content("User and role parameters are required."))
+            .await?;
+        return Ok(());
+    };
+
+    let mut conn = state.redis.clone();
+    let guild_config = load_guild_config(&ctx.http, &mut conn, guild_id).await?;
+
+    if !guild_config.managed_roles().contains(&role.id) {
+        reply
+            .edit(EditInteractionResponse::new().content(format!(
+                "{} isn't a role verification manages in this server. Only level, class, group, \
+                attribute and `/alwaysrole` roles can be assigned.",
+                role.mention()
+            )))
+            .await?;
+        return Ok(());
+    }
+
+    let member_roles = match DiscordApi::member_roles(&*ctx.http, guild_id, target_user.id).await {
+        Ok(roles) => roles,
+        Err(_) => {
+            reply
+                .edit(EditInteractionResponse::new().content(format!(
+                    "{} is not a member of this server.",
+                    target_user.mention()
+                )))
+                .await?;
+            return Ok(());
+        }
+    };
+    if member_roles.contains(&role.id) {
+        reply
+            .edit(EditInteractionResponse::new().content(format!(
+                "{} already has {}.",
+                target_user.mention(),
+                role.mention()
+            )))
+            .await?;
+        return Ok(());
+    }
+
+    if let Err(e) = DiscordApi::add_role(&*ctx.http, guild_id, target_user.id, role.id).await {
+        tracing::warn!(
+            "Failed to assign role {} to user {}: {}",
+            role.id,
+            redact(target_user.id),
+            e
+        );
+        reply
+            .edit(EditInteractionResponse::new().content(format!(
+                "Failed to assign {}: {}",
+                role.mention(),
+                e
+            )))
+            .await?;
+        return Ok(());
+    }
+
+    tracing::info!(
+        "Admin {} ({}) assigned role {} to user {} in guild {}",
+        redact(user.id),
+        grant,
+        role.id,
+        redact(target_user.id),
+        guild_id
+    );
+
+    if let Some(channel_id) = guild_config.get_log_channel()
+        && let Some(channel_id) =
+            log_destination(&ctx.http, &ctx.cache, &mut conn, guild_id, channel_id).await
+    {
+        let embed = CreateEmbed::new()
+            .title("Role Manually Assigned")
+            .description("This role was given by an admin rather than from the user's attributes.")
+            .color(0xFAB387) // Peach
+            .field("User", target_user.mention().to_string(), false)
+            .field("Role", role.mention().to_string(), false)
+            .field(
+                "Assigned By",
+                format!("{} ({})", user.mention(), grant),
+                false,
+            )
+            .timestamp(chrono::Utc::now());
+
+        if let Err(e) = ctx
+            .http
+            .send_message(
+                channel_id.into(),
+                Vec::new(),
+                &CreateMessage::new().embed(embed),
+            )
+            .await
+        {
+            tracing::warn!(
+                "Failed to send role assignment log to channel {}: {}",
+                channel_id,
+                e
+            );
+        }
+    }
+
+    reply
+        .edit(EditInteractionResponse::new().content(format!(
+            "Gave {} to {}. Re-verifying them may remove it again if their attributes don't match.",
+            role.mention(),
+            target_user.mention()
+        )))
+        .await?;
+
+    Ok(())
+}
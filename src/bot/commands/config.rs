@@ -1,24 +1,31 @@
 use crate::bot::Error;
+use crate::keys::{RoleSlot, discord_id_of, guild_role, redis_key};
 use crate::state::AppState;
 use redis::AsyncCommands;
 use serenity::all::{
-    CommandInteraction, Context, CreateCommand, CreateComponent, CreateContainer,
-    CreateContainerComponent, CreateInteractionResponse, CreateInteractionResponseMessage,
-    CreateSeparator, CreateTextDisplay, EditInteractionResponse, Mentionable, MessageFlags,
+    ChunkGuildFilter, CommandInteraction, CommandOptionType, Context, CreateCommand,
+    CreateCommandOption, CreateComponent, CreateContainer, CreateContainerComponent,
+    CreateInteractionResponse, CreateInteractionResponseMessage, CreateSeparator,
+    CreateTextDisplay, EditInteractionResponse, GuildId, Mentionable, MessageFlags, ResolvedOption,
+    ResolvedValue, UserId,
 };
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
-use super::utils::{count_guild_members_with_role_cached, is_admin};
+use super::utils::{Deferred, is_admin};
+use super::verify::VerifyEvent;
 
 /// Generate ASCII progress bar
 fn generate_progress_bar(current: usize, total: usize, width: usize) -> String {
     if total == 0 {
-        return format!("[{}] 0%", " ".repeat(width));
+        return format!("[{}] 0%", "░".repeat(width));
     }
 
-    let percentage = ((current as f64 / total as f64 * 100.0).min(100.0)).round() as usize;
+    // More verified members than members (e.g. a stale count) shows as complete, not over 100%
+    let current = current.min(total);
+    let percentage = (current as f64 / total as f64 * 100.0).round() as usize;
     let filled = (current as f64 / total as f64 * width as f64).round() as usize;
-    let empty = width.saturating_sub(filled);
+    let empty = width - filled;
 
     format!(
         "[{}{}] {}%",
@@ -32,6 +39,101 @@ fn generate_progress_bar(current: usize, total: usize, width: usize) -> String {
 pub fn register() -> CreateCommand<'static> {
     CreateCommand::new("config")
         .description("Show server verification configuration and statistics")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "refresh",
+                "Rebuild the verified member list from the global account links",
+            )
+            .required(false),
+        )
+}
+
+/// Rebuild the guild's verified member set by cross-referencing the global
+/// `discord:{id}:keycloak` mappings with the cached guild membership.
+/// Migrates guilds verified before the set existed, and corrects drift.
+/// Returns the size of the set afterwards.
+async fn backfill_verified_members(
+    ctx: &Context,
+    conn: &mut redis::aio::ConnectionManager,
+    guild_id: GuildId,
+) -> Result<usize, Error> {
+    // Scope to drop guild reference before await
+    let (member_ids, cache_complete): (HashSet<UserId>, bool) = {
+        let Some(guild) = guild_id.to_guild_cached(&ctx.cache) else {
+            return Ok(0);
+        };
+        (
+            guild.members.iter().map(|m| m.user.id).collect(),
+            guild.members.len() >= guild.member_count as usize,
+        )
+    };
+
+    // Keys are "discord:{discord_id}:keycloak"
+    let mut verified = Vec::new();
+    {
+        let mut iter = conn
+            .scan_match::<_, String>(redis_key!("discord:*:keycloak"))
+            .await?;
+        while let Some(key) = iter.next_item().await {
+            if let Some(user_id) =
+                discord_id_of(&key).filter(|id| member_ids.contains(&UserId::new(*id)))
+            {
+                verified.push(user_id);
+            }
+        }
+    }
+
+    // Only a complete member cache can tell who isn't verified anymore. Otherwise the
+    // members found are added, and the ones not cached yet keep their entries.
+    let key = redis_key!("guild:{}:verified_members", guild_id);
+    if cache_complete {
+        let mut pipe = redis::pipe();
+        pipe.atomic().del(&key).ignore();
+        if !verified.is_empty() {
+            pipe.sadd(&key, &verified).ignore();
+        }
+        pipe.query_async::<()>(conn).await?;
+        return Ok(verified.len());
+    }
+
+    if !verified.is_empty() {
+        let _: () = conn.sadd(&key, &verified).await?;
+    }
+    Ok(conn.scard(&key).await?)
+}
+
+/// Count the guild's members, returning the count and whether it includes bots.
+/// Humans are only counted when every member is cached. Otherwise this asks the
+/// gateway for the member list so later runs are exact, and falls back to the cached
+/// or HTTP-reported member count in the meantime.
+async fn member_total(ctx: &Context, guild_id: GuildId) -> Result<(usize, bool), Error> {
+    // Scope to drop guild reference before await
+    let cached = guild_id.to_guild_cached(&ctx.cache).map(|g| {
+        let humans = g.members.iter().filter(|m| !m.user.bot()).count();
+        (
+            g.members.len() >= g.member_count as usize,
+            humans,
+            g.member_count as usize,
+        )
+    });
+
+    match cached {
+        Some((true, humans, _)) => return Ok((humans, false)),
+        // Chunks arrive as separate gateway events and fill the cache in the background
+        _ => ctx
+            .shard
+            .chunk_guild(guild_id, None, false, ChunkGuildFilter::None, None),
+    }
+
+    match cached {
+        Some((_, _, member_count)) if member_count > 0 => Ok((member_count, true)),
+        _ => {
+            let guild = ctx.http.get_guild_with_counts(guild_id).await?;
+            let approximate = guild.approximate_member_count.map(u64::from).unwrap_or(0);
+            Ok((approximate as usize, true))
+        }
+    }
 }
 
 /// Handle the config command
@@ -68,7 +170,7 @@ pub async fn handle(
     }
 
     // Large guilds need member pagination; defer so Discord doesn't time out the interaction
-    command.defer_ephemeral(&ctx.http).await?;
+    let reply = Deferred::command(&ctx.http, command).await?;
 
     // Load guild role configuration
     let mut conn = state.redis.clone();
@@ -90,7 +192,7 @@ pub async fn handle(
     };
 
     // Format unverified role info
-    let unverified_redis_key = format!("guild:{}:role:unverified", guild_id);
+    let unverified_redis_key = guild_role(guild_id, &RoleSlot::Unverified);
     let unverified_role_info: String =
         if let Ok(Some(role_id_str)) = conn.get::<_, Option<String>>(&unverified_redis_key).await {
             if let Ok(role_id_u64) = role_id_str.parse::<u64>() {
@@ -131,20 +233,46 @@ pub async fn handle(
         crate::bot::guild_config::RoleMode::Custom => {
             "* **Custom Mode** (assigning roles based on selected levels and classes)"
         }
+        crate::bot::guild_config::RoleMode::Groups => {
+            "* **Groups Mode** (assigning roles based on Keycloak group membership, see `/setgrouprole`)"
+        }
         crate::bot::guild_config::RoleMode::None => {
             "* **None** (only the verified role is being assigned)"
         }
     };
 
-    let (verified_count, total_members) = guild_config.verified_role.map_or((0, 0), |role_id| {
-        count_guild_members_with_role_cached(guild_id, &ctx.cache, role_id)
-    });
+    let refresh = matches!(
+        command.data.options().first(),
+        Some(ResolvedOption {
+            value: ResolvedValue::Boolean(true),
+            ..
+        })
+    );
+
+    let (total_members, total_includes_bots) = member_total(ctx, guild_id).await?;
+    let total_label = if total_includes_bots {
+        " (total includes bots, member list still loading)"
+    } else {
+        " (bots excluded)"
+    };
+
+    // Count the guild's verified member set, migrating from the global mappings
+    // the first time (or on request)
+    let verified_members_key = redis_key!("guild:{}:verified_members", guild_id);
+    let exists: bool = conn.exists(&verified_members_key).await?;
+    let (verified_count, recounted) = if exists && !refresh {
+        let count: usize = conn.scard(&verified_members_key).await?;
+        (count, false)
+    } else {
+        (
+            backfill_verified_members(ctx, &mut conn, guild_id).await?,
+            true,
+        )
+    };
 
     let cache_note = guild_id
         .to_guild_cached(&ctx.cache)
-        .filter(|g| {
-            guild_config.verified_role.is_some() && g.members.len() < g.member_count as usize / 2
-        })
+        .filter(|g| recounted && g.members.len() < g.member_count as usize / 2)
         .map(|_| "\n*(member cache still loading, count may be low briefly)*");
 
     let verified_stats = match guild_config.verified_role {
@@ -152,9 +280,10 @@ pub async fn handle(
             let progress_bar = generate_progress_bar(verified_count, total_members, 20);
             let remaining = total_members.saturating_sub(verified_count);
             format!(
-                "Verified Users (this server): {verified_count}/{total_members} (total includes bots)\n{progress_bar}\n{remaining} users still need to verify{cache_note}",
+                "Verified Users (this server): {verified_count}/{total_members}{total_label}\n{progress_bar}\n{remaining} users still need to verify{cache_note}",
                 verified_count = verified_count,
                 total_members = total_members,
+                total_label = total_label,
                 progress_bar = progress_bar,
                 remaining = remaining,
                 cache_note = cache_note.unwrap_or(""),
@@ -162,15 +291,23 @@ pub async fn handle(
         }
         None => format!(
             "Verified Users: configure a verified role with `/setverifiedrole` to see server statistics ({total_members} members)",
-            total_members = total_members.max(
-                guild_id
-                    .to_guild_cached(&ctx.cache)
-                    .map(|g| g.member_count as usize)
-                    .unwrap_or(0),
-            ),
+            total_members = total_members,
         ),
     };
 
+    // Funnel counters, so re-verifications can be told apart from new users
+    let counts: HashMap<String, u64> = conn
+        .hgetall(redis_key!("guild:{}:verify_counts", guild_id))
+        .await?;
+    let count_of = |event: VerifyEvent| counts.get(event.as_str()).copied().unwrap_or(0);
+    let verified_stats = format!(
+        "{}\nVerification activity: {} links issued, {} completed, {} already verified elsewhere",
+        verified_stats,
+        count_of(VerifyEvent::NewToken),
+        count_of(VerifyEvent::Completed),
+        count_of(VerifyEvent::AlreadyVerified),
+    );
+
     // Create components v2 message
     let container = CreateContainer::new(vec![
         CreateContainerComponent::TextDisplay(CreateTextDisplay::new("# Configuration")),
@@ -186,9 +323,8 @@ pub async fn handle(
         )),
     ]);
 
-    command
-        .edit_response(
-            &ctx.http,
+    reply
+        .edit(
             EditInteractionResponse::new()
                 .components(vec![CreateComponent::Container(container)])
                 .flags(MessageFlags::IS_COMPONENTS_V2),
@@ -197,3 +333,51 @@ pub async fn handle(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_bar_when_everyone_is_verified() {
+        assert_eq!(generate_progress_bar(10, 10, 5), "[█████] 100%");
+    }
+
+    #[test]
+    fn over_counted_members_stay_within_the_bar() {
+        assert_eq!(generate_progress_bar(15, 10, 5), "[█████] 100%");
+    }
+
+    #[test]
+    fn empty_guild_shows_an_empty_bar() {
+        assert_eq!(generate_progress_bar(0, 0, 4), "[░░░░] 0%");
+        assert_eq!(generate_progress_bar(3, 0, 4), "[░░░░] 0%");
+    }
+
+    #[test]
+    fn rounds_partial_progress() {
+        // 1/3 of 10 blocks is 3.33, rounded down; 2/3 is 6.67, rounded up
+        assert_eq!(generate_progress_bar(1, 3, 10), "[███░░░░░░░] 33%");
+        assert_eq!(generate_progress_bar(2, 3, 10), "[███████░░░] 67%");
+        assert_eq!(generate_progress_bar(1, 8, 4), "[█░░░] 13%");
+    }
+
+    #[test]
+    fn blocks_always_fill_the_width() {
+        for total in 0..=12 {
+            for current in 0..=total + 3 {
+                let bar = generate_progress_bar(current, total, 10);
+                let blocks = bar.chars().filter(|c| matches!(c, '█' | '░')).count();
+                assert_eq!(blocks, 10, "{current}/{total}: {bar}");
+
+                let percentage: usize = bar
+                    .rsplit(' ')
+                    .next()
+                    .and_then(|p| p.strip_suffix('%'))
+                    .and_then(|p| p.parse().ok())
+                    .unwrap();
+                assert!(percentage <= 100, "{current}/{total}: {bar}");
+            }
+        }
+    }
+}
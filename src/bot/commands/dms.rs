@@ -0,0 +1,60 @@
+use crate::bot::Error;
+use crate::bot::i18n::{self, Locale};
+use crate::keys::redis_key;
+use crate::state::AppState;
+use redis::AsyncCommands;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommand, CreateCommandOption,
+    CreateInteractionResponse, CreateInteractionResponseMessage, ResolvedValue,
+};
+use std::sync::Arc;
+
+/// Register the dms command
+pub fn register() -> CreateCommand<'static> {
+    CreateCommand::new("dms")
+        .description("Turn direct messages from the bot on or off")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "enabled",
+                "Whether the bot may DM you",
+            )
+            .required(true),
+        )
+}
+
+/// Handle the dms command. Works in any server and in DMs, the setting covers every server.
+pub async fn handle(
+    ctx: &Context,
+    command: &CommandInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let locale = Locale::from_discord(&command.locale);
+
+    let enabled = command
+        .data
+        .options()
+        .into_iter()
+        .find_map(|option| match (option.name, option.value) {
+            ("enabled", ResolvedValue::Boolean(b)) => Some(b),
+            _ => None,
+        })
+        .unwrap_or(true);
+
+    let mut conn = state.redis.clone();
+    let key = redis_key!("user:{}:dm_opt_out", command.user.id);
+    if enabled {
+        let _: () = conn.del(&key).await?;
+    } else {
+        let _: () = conn.set(&key, "1").await?;
+    }
+
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(i18n::dms_updated(locale, enabled))
+            .ephemeral(true),
+    );
+    command.create_response(&ctx.http, response).await?;
+
+    Ok(())
+}
@@ -0,0 +1,113 @@
+use crate::bot::Error;
+use crate::bot::guild_config::ExportedConfig;
+use crate::keys::{RoleSlot, guild_role};
+use crate::state::AppState;
+use redis::AsyncCommands;
+use serenity::all::{
+    CommandInteraction, Context, CreateCommand, CreateInteractionResponse,
+    CreateInteractionResponseMessage, Permissions, RoleId,
+};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use super::utils::{is_admin, load_guild_config, trim_redis_value};
+
+/// Register the exportconfig command
+pub fn register() -> CreateCommand<'static> {
+    CreateCommand::new("exportconfig")
+        .description("Export this server's verification configuration as JSON (admin only)")
+        .default_member_permissions(Permissions::ADMINISTRATOR)
+}
+
+/// Handle the exportconfig command
+pub async fn handle(
+    ctx: &Context,
+    command: &CommandInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let user = &command.user;
+
+    // Get guild_id from context
+    let guild_id = match command.guild_id {
+        Some(id) => id,
+        None => {
+            let response = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content("This command can only be used in a server.")
+                    .ephemeral(true),
+            );
+            command.create_response(&ctx.http, response).await?;
+            return Ok(());
+        }
+    };
+
+    // Check if user has administrator permissions
+    if !is_admin(ctx, &command.member, guild_id, user.id).await? {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("You need administrator permissions to export server configuration.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    let mut conn = state.redis.clone();
+    let guild_config = load_guild_config(&ctx.http, &mut conn, guild_id).await?;
+    let guild_roles = guild_id.roles(&ctx.http).await?;
+
+    let role_name = |role_id: RoleId| guild_roles.get(&role_id).map(|r| r.name.to_string());
+
+    let unverified_role = trim_redis_value(
+        conn.get(guild_role(guild_id, &RoleSlot::Unverified))
+            .await?,
+    )
+    .and_then(|s| s.parse::<u64>().ok())
+    .and_then(|id| role_name(RoleId::new(id)));
+
+    let log_channel = guild_config.log_channel.and_then(|channel_id| {
+        guild_id
+            .to_guild_cached(&ctx.cache)
+            .and_then(|g| g.channels.get(&channel_id).map(|c| c.base.name.to_string()))
+    });
+
+    // Map each configured role's current name to its Redis key suffix
+    let mut roles = BTreeMap::new();
+    for (level, role_id) in &guild_config.level_roles {
+        if let Some(name) = role_name(*role_id) {
+            roles.insert(name, format!("level:{}", level));
+        }
+    }
+    for (class, role_id) in &guild_config.class_roles {
+        if let Some(name) = role_name(*role_id) {
+            roles.insert(name, format!("class:{}", class));
+        }
+    }
+    for (group, role_id) in &guild_config.group_roles {
+        if let Some(name) = role_name(*role_id) {
+            roles.insert(name, format!("group:{}", group));
+        }
+    }
+
+    let exported = ExportedConfig {
+        verified_role: guild_config.verified_role.and_then(role_name),
+        unverified_role,
+        log_channel,
+        mode: guild_config.mode.as_str().to_string(),
+        roles,
+    };
+
+    let json = serde_json::to_string_pretty(&exported)?;
+
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(format!(
+                "Copy this configuration and run `/importconfig` in the other server:\n```json\n{}\n```",
+                json
+            ))
+            .ephemeral(true),
+    );
+    command.create_response(&ctx.http, response).await?;
+
+    Ok(())
+}
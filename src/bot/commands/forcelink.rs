@@ -0,0 +1,281 @@
+use crate::bot::Error;
+use crate::keys::redis_key;
+use crate::redact::redact;
+use crate::state::{AppState, VerificationComplete};
+use redis::AsyncCommands;
+use serenity::all::{
+    Cache, CommandInteraction, CommandOptionType, Context, CreateCommand, CreateCommandOption,
+    CreateEmbed, CreateMessage, EditInteractionResponse, GuildId, Http, Mentionable, Permissions,
+    ResolvedValue, UserId,
+};
+use std::sync::Arc;
+
+use super::utils::{
+    AdminGrant, Deferred, admin_grant, load_guild_config, log_destination, trim_redis_value,
+};
+use super::verify::{Completion, complete_verification};
+
+/// Register the forcelink command
+pub fn register() -> CreateCommand<'static> {
+    CreateCommand::new("forcelink")
+        .description("Manually link a user to a Keycloak account, skipping login (admin only)")
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::User, "user", "The Discord user to link")
+                .required(true),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "keycloak",
+                "The Keycloak username or user ID",
+            )
+            .required(true),
+        )
+        .default_member_permissions(Permissions::ADMINISTRATOR)
+}
+
+/// Handle the forcelink command
+pub async fn handle(
+    ctx: &Context,
+    command: &CommandInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let user = &command.user;
+
+    // Keycloak lookups and role assignment can take a while
+    let reply = Deferred::command(&ctx.http, command).await?;
+
+    // Get guild_id from context
+    let guild_id = match command.guild_id {
+        Some(id) => id,
+        None => {
+            reply
+                .edit(
+                    EditInteractionResponse::new()
+                        .content("This command can only be used in a server."),
+                )
+                .await?;
+            return Ok(());
+        }
+    };
+
+    // Check if user has administrator permissions
+    let Some(grant) = admin_grant(ctx, &command.member, guild_id, user.id).await? else {
+        reply
+            .edit(
+                EditInteractionResponse::new()
+                    .content("You need administrator permissions to manually link users."),
+            )
+            .await?;
+        return Ok(());
+    };
+
+    // Get the target user and Keycloak account from command options
+    let mut target_user = None;
+    let mut keycloak_query = None;
+    for option in command.data.options() {
+        match (option.name, option.value) {
+            ("user", ResolvedValue::User(u, _)) => target_user = Some(u.clone()),
+            ("keycloak", ResolvedValue::String(k)) => keycloak_query = Some(k.trim().to_string()),
+            _ => {}
+        }
+    }
+
+    let (Some(target_user), Some(keycloak_query)) = (target_user, keycloak_query) else {
+        reply
+            .edit(
+                EditInteractionResponse::new()
+                    .content("User and keycloak parameters are required."),
+            )
+            .await?;
+        return Ok(());
+    };
+
+    let message = match link_user(
+        &ctx.http,
+        &ctx.cache,
+        state,
+        guild_id,
+        target_user.id,
+        &keycloak_query,
+        LinkedBy::Admin(user.id, grant),
+    )
+    .await?
+    {
+        LinkOutcome::Linked {
+            keycloak_username,
+            awaiting_join: false,
+        } => format!(
+            "Linked {} to `{}` and assigned their roles.",
+            target_user.id.mention(),
+            keycloak_username
+        ),
+        LinkOutcome::Linked {
+            keycloak_username,
+            awaiting_join: true,
+        } => format!(
+            "Linked {} to `{}`. They aren't in this server, their roles will be assigned when they join.",
+            target_user.id.mention(),
+            keycloak_username
+        ),
+        LinkOutcome::NotFound => format!("No Keycloak user found for `{}`.", keycloak_query),
+        LinkOutcome::AlreadyLinked {
+            keycloak_username,
+            discord_user_id,
+        } => format!(
+            "`{}` is already linked to <@{}>. Run `/unverify` on them first.",
+            keycloak_username, discord_user_id
+        ),
+        LinkOutcome::EmailDomainRejected { keycloak_username } => format!(
+            "`{}` doesn't have a verified email from one of this server's email domains, \
+            so it wasn't linked. Use `/setemaildomain` to accept its domain.",
+            keycloak_username
+        ),
+    };
+
+    reply
+        .edit(EditInteractionResponse::new().content(message))
+        .await?;
+
+    Ok(())
+}
+
+/// Result of linking a Discord user to a Keycloak account
+pub enum LinkOutcome {
+    Linked {
+        keycloak_username: String,
+        /// The user isn't in the guild, roles are assigned when they join
+        awaiting_join: bool,
+    },
+    /// No Keycloak user matched the username or ID
+    NotFound,
+    /// The Keycloak account is linked to another Discord user
+    AlreadyLinked {
+        keycloak_username: String,
+        discord_user_id: String,
+    },
+    /// The account's email isn't from a domain the guild requires, nothing was linked
+    EmailDomainRejected { keycloak_username: String },
+}
+
+/// Who requested a manual link, and how an admin was allowed to, shown in the logs
+#[derive(Clone, Copy)]
+pub enum LinkedBy {
+    Admin(UserId, AdminGrant),
+    AdminApi,
+}
+
+/// Link a Discord user to a Keycloak account by username or ID without a login, then
+/// assign their roles
+pub async fn link_user(
+    http: &Http,
+    cache: &Cache,
+    state: &AppState,
+    guild_id: GuildId,
+    target_id: UserId,
+    keycloak_query: &str,
+    linked_by: LinkedBy,
+) -> Result<LinkOutcome, Error> {
+    // Accept either a username or a user ID
+    let keycloak_user = match state.keycloak.find_user_by_username(keycloak_query).await {
+        Ok(Some(u)) => Some(u),
+        _ => state.keycloak.get_user(keycloak_query).await.ok(),
+    };
+
+    let Some((keycloak_user_id, keycloak_username)) =
+        keycloak_user.and_then(|u| u.id.map(|id| (id, u.username.unwrap_or_default())))
+    else {
+        return Ok(LinkOutcome::NotFound);
+    };
+
+    // Refuse to steal a Keycloak account already linked to someone else
+    let mut conn = state.redis.clone();
+    let existing_discord_id = trim_redis_value(
+        conn.get(redis_key!("keycloak:{}:discord", keycloak_user_id))
+            .await?,
+    );
+    if let Some(existing) = existing_discord_id
+        && existing != target_id.to_string()
+    {
+        return Ok(LinkOutcome::AlreadyLinked {
+            keycloak_username,
+            discord_user_id: existing,
+        });
+    }
+
+    tracing::warn!(
+        "{} force-linked user {} to Keycloak user {} in guild {}",
+        match linked_by {
+            LinkedBy::Admin(admin_id, grant) => format!("Admin {} ({})", redact(admin_id), grant),
+            LinkedBy::AdminApi => "Admin API".to_string(),
+        },
+        redact(target_id),
+        redact(&keycloak_user_id),
+        guild_id
+    );
+
+    // Writes the mappings and assigns roles as if the user had logged in
+    let completion = VerificationComplete {
+        discord_user_id: target_id,
+        guild_id,
+        keycloak_user_id,
+        claims: None,
+        locale: String::new(),
+        started_at: None,
+        state_token: None,
+        span: tracing::Span::current(),
+    };
+    let completion = complete_verification(http, cache, state, completion, false).await?;
+    if completion == Completion::EmailDomainRejected {
+        return Ok(LinkOutcome::EmailDomainRejected { keycloak_username });
+    }
+
+    // Log prominently since this skips the normal identity verification
+    let guild_config = load_guild_config(http, &mut conn, guild_id).await?;
+    if let Some(channel_id) = guild_config.get_log_channel()
+        && let Some(channel_id) =
+            log_destination(http, cache, &mut conn, guild_id, channel_id).await
+    {
+        let embed = CreateEmbed::new()
+            .title("User Manually Linked")
+            .description("This user was linked by an admin without logging in.")
+            .color(0xFAB387) // Peach
+            .field("User", target_id.mention().to_string(), false)
+            .field(
+                state.config.identity_label.clone(),
+                keycloak_username.clone(),
+                false,
+            )
+            .field(
+                "Linked By",
+                match linked_by {
+                    LinkedBy::Admin(admin_id, grant) => {
+                        format!("{} ({})", admin_id.mention(), grant)
+                    }
+                    LinkedBy::AdminApi => "Admin API".to_string(),
+                },
+                false,
+            )
+            .timestamp(chrono::Utc::now());
+
+        if let Err(e) = http
+            .send_message(
+                channel_id.into(),
+                Vec::new(),
+                &CreateMessage::new().embed(embed),
+            )
+            .await
+        {
+            tracing::warn!(
+                "Failed to send manual link log to channel {}: {}",
+                channel_id,
+                e
+            );
+        }
+    }
+
+    Ok(LinkOutcome::Linked {
+        keycloak_username,
+        awaiting_join: completion == Completion::AwaitingJoin,
+    })
+}
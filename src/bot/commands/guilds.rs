@@ -0,0 +1,143 @@
+use crate::bot::Error;
+use crate::bot::guild_config::RoleMode;
+use crate::keys::{RoleSlot, guild_role, redis_key};
+use crate::state::AppState;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommand, CreateCommandOption,
+    CreateInteractionResponse, CreateInteractionResponseMessage, GuildId, Permissions,
+    ResolvedOption, ResolvedValue,
+};
+use std::sync::Arc;
+
+use super::utils::is_owner;
+
+/// Guilds listed per page, keeps the reply well under Discord's message limit
+const GUILDS_PER_PAGE: usize = 15;
+
+/// Register the guilds command
+pub fn register() -> CreateCommand<'static> {
+    CreateCommand::new("guilds")
+        .description("List every server the bot is in with its config summary (bot owner only)")
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::Integer, "page", "Page to show")
+                .min_int_value(1)
+                .required(false),
+        )
+        .default_member_permissions(Permissions::ADMINISTRATOR)
+}
+
+/// Handle the guilds command
+pub async fn handle(
+    ctx: &Context,
+    command: &CommandInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    // Guild admins only see their own server, this is for whoever runs the bot
+    if !is_owner(&state.config, command.user.id) {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("Only the bot owner can list the servers the bot is in.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    let page = match command.data.options().first() {
+        Some(ResolvedOption {
+            value: ResolvedValue::Integer(page),
+            ..
+        }) => (*page).max(1) as usize,
+        _ => 1,
+    };
+
+    // Names from the cache, sorted so pages stay stable between calls
+    let mut guilds: Vec<(GuildId, String)> = ctx
+        .cache
+        .guilds()
+        .into_iter()
+        .map(|guild_id| {
+            let name = guild_id
+                .to_guild_cached(&ctx.cache)
+                .map(|g| g.name.to_string())
+                .unwrap_or_else(|| "Unknown".to_string());
+            (guild_id, name)
+        })
+        .collect();
+    guilds.sort_by_key(|(_, name)| name.to_lowercase());
+
+    let total_pages = guilds.len().div_ceil(GUILDS_PER_PAGE).max(1);
+    let page = page.min(total_pages);
+    let guilds = guilds
+        .into_iter()
+        .skip((page - 1) * GUILDS_PER_PAGE)
+        .take(GUILDS_PER_PAGE)
+        .collect::<Vec<_>>();
+
+    // One round trip for the whole page
+    let mut pipe = redis::pipe();
+    for (guild_id, _) in &guilds {
+        pipe.get(redis_key!("guild:{}:role_mode", guild_id))
+            .get(guild_role(guild_id, &RoleSlot::Verified))
+            .scard(redis_key!("guild:{}:verified_members", guild_id));
+    }
+    let mut conn = state.redis.clone();
+    let summaries: Vec<(Option<String>, Option<String>, usize)> = if guilds.is_empty() {
+        Vec::new()
+    } else {
+        let values: Vec<redis::Value> = pipe.query_async(&mut conn).await?;
+        values
+            .chunks(3)
+            .map(|chunk| {
+                Ok((
+                    redis::from_redis_value(&chunk[0])?,
+                    redis::from_redis_value(&chunk[1])?,
+                    redis::from_redis_value(&chunk[2])?,
+                ))
+            })
+            .collect::<Result<_, redis::RedisError>>()?
+    };
+
+    let lines = guilds
+        .iter()
+        .zip(summaries)
+        .map(
+            |((guild_id, name), (mode, verified_role, verified_count))| {
+                let mode = mode.and_then(|m| m.parse().ok()).unwrap_or(RoleMode::None);
+                format!(
+                    "* **{}** (`{}`): mode {}, {}, {} verified",
+                    name,
+                    guild_id,
+                    mode,
+                    if verified_role.is_some() {
+                        "verified role set"
+                    } else {
+                        "no verified role"
+                    },
+                    verified_count
+                )
+            },
+        )
+        .collect::<Vec<_>>();
+
+    let content = format!(
+        "# Servers ({} total)\n{}\n\nPage {}/{}",
+        ctx.cache.guild_count(),
+        if lines.is_empty() {
+            "The bot isn't in any servers.".to_string()
+        } else {
+            lines.join("\n")
+        },
+        page,
+        total_pages
+    );
+
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(content)
+            .ephemeral(true),
+    );
+    command.create_response(&ctx.http, response).await?;
+
+    Ok(())
+}
@@ -0,0 +1,218 @@
+use crate::bot::Error;
+use crate::bot::i18n::{self, Locale};
+use crate::keys::discord_keycloak;
+use crate::redact::redact;
+use crate::state::AppState;
+use keycloak::types::FederatedIdentityRepresentation;
+use redis::AsyncCommands;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommand, CreateCommandOption,
+    CreateEmbed, CreateInteractionResponse, CreateInteractionResponseMessage, Mentionable,
+    Permissions, ResolvedValue, UserId,
+};
+use std::sync::Arc;
+
+use super::utils::{is_admin, trim_redis_value};
+
+/// Discord's limit on fields in an embed
+const MAX_EMBED_FIELDS: usize = 25;
+
+/// Register the identities command
+pub fn register() -> CreateCommand<'static> {
+    CreateCommand::new("identities")
+        .description("Show the identity providers Keycloak has linked to a verified user")
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::User, "user", "The user to look up")
+                .required(true),
+        )
+        .default_member_permissions(Permissions::ADMINISTRATOR)
+}
+
+/// Handle the identities command
+pub async fn handle(
+    ctx: &Context,
+    command: &CommandInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let user = &command.user;
+
+    // Get guild_id from context
+    let guild_id = match command.guild_id {
+        Some(id) => id,
+        None => {
+            let response = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content("This command can only be used in a server.")
+                    .ephemeral(true),
+            );
+            command.create_response(&ctx.http, response).await?;
+            return Ok(());
+        }
+    };
+
+    // Check if user has administrator permissions
+    if !is_admin(ctx, &command.member, guild_id, user.id).await? {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("You need administrator permissions to view linked identities.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    // Get the target user from command options
+    let mut target_user = None;
+    for option in command.data.options() {
+        if let ("user", ResolvedValue::User(u, _)) = (option.name, option.value) {
+            target_user = Some(u);
+        }
+    }
+
+    let Some(target_user) = target_user else {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("User parameter is required.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    };
+
+    // Look up Keycloak user ID from Redis
+    let mut conn = state.redis.clone();
+    let Some(keycloak_user_id) =
+        trim_redis_value(conn.get(discord_keycloak(target_user.id)).await?)
+    else {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content(i18n::not_verified(
+                    Locale::from_discord(&command.locale),
+                    target_user.mention(),
+                ))
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    };
+
+    let identities = match state
+        .keycloak
+        .get_federated_identities(&keycloak_user_id)
+        .await
+    {
+        Ok(identities) => identities,
+        Err(e) => {
+            tracing::error!(
+                "Failed to fetch federated identities of Keycloak user {}: {}",
+                redact(&keycloak_user_id),
+                e
+            );
+            let response = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content("Failed to fetch linked identities from Keycloak.")
+                    .ephemeral(true),
+            );
+            command.create_response(&ctx.http, response).await?;
+            return Ok(());
+        }
+    };
+
+    let mut embed = CreateEmbed::new()
+        .title(format!("Linked Identities for {}", target_user.name))
+        .field("Keycloak User", format!("`{}`", keycloak_user_id), false);
+
+    let description = match discord_link_status(&identities, target_user.id) {
+        DiscordLink::Matches => {
+            embed = embed.color(0xA6E3A1); // Green
+            "Keycloak's Discord link matches this user."
+        }
+        DiscordLink::Mismatch => {
+            embed = embed.color(0xF38BA8); // Red
+            "Keycloak's Discord link is a different Discord account than this user. They'll \
+            get a wrong account error if they verify again."
+        }
+        DiscordLink::Missing => {
+            embed = embed.color(0xF9E2AF); // Yellow
+            "Keycloak has no Discord link for this user, it was unlinked after they verified."
+        }
+    };
+    embed = embed.description(description);
+
+    for identity in identities.iter().take(MAX_EMBED_FIELDS - 1) {
+        let provider = identity.identity_provider.as_deref().unwrap_or("unknown");
+        let external_id = identity.user_id.as_deref().unwrap_or("unknown");
+        let value = match identity.user_name.as_deref() {
+            Some(name) if !name.is_empty() => format!("`{}` ({})", external_id, name),
+            _ => format!("`{}`", external_id),
+        };
+        embed = embed.field(provider, value, false);
+    }
+
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .embed(embed)
+            .ephemeral(true),
+    );
+    command.create_response(&ctx.http, response).await?;
+
+    Ok(())
+}
+
+/// How Keycloak's Discord link compares to the Discord user it's mapped from
+#[derive(Debug, PartialEq, Eq)]
+enum DiscordLink {
+    Matches,
+    Mismatch,
+    Missing,
+}
+
+/// Compare the linked `discord` identity against the user we looked up
+fn discord_link_status(
+    identities: &[FederatedIdentityRepresentation],
+    user_id: UserId,
+) -> DiscordLink {
+    match identities
+        .iter()
+        .find(|i| i.identity_provider.as_deref() == Some("discord"))
+    {
+        Some(identity) if identity.user_id.as_deref() == Some(&user_id.to_string()) => {
+            DiscordLink::Matches
+        }
+        Some(_) => DiscordLink::Mismatch,
+        None => DiscordLink::Missing,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(provider: &str, user_id: &str) -> FederatedIdentityRepresentation {
+        FederatedIdentityRepresentation {
+            identity_provider: Some(provider.into()),
+            user_id: Some(user_id.into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn discord_link_is_compared_to_the_user() {
+        let user_id = UserId::new(42);
+        assert_eq!(
+            discord_link_status(
+                &[identity("google", "1"), identity("discord", "42")],
+                user_id
+            ),
+            DiscordLink::Matches
+        );
+        assert_eq!(
+            discord_link_status(&[identity("discord", "43")], user_id),
+            DiscordLink::Mismatch
+        );
+        assert_eq!(
+            discord_link_status(&[identity("google", "42")], user_id),
+            DiscordLink::Missing
+        );
+    }
+}
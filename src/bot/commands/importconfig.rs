@@ -0,0 +1,267 @@
+use crate::bot::Error;
+use crate::bot::guild_config::{ExportedConfig, RoleMode};
+use crate::keys::{RoleSlot, guild_role, redis_key};
+use crate::state::{AppState, SetupRolesSession};
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommand, CreateCommandOption,
+    EditInteractionResponse, EditRole, GuildId, Http, Permissions, ResolvedOption, ResolvedValue,
+    RoleId,
+};
+use std::sync::Arc;
+
+use super::utils::{Deferred, is_admin};
+
+/// Register the importconfig command
+pub fn register() -> CreateCommand<'static> {
+    CreateCommand::new("importconfig")
+        .description("Import verification configuration exported from another server (admin only)")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "json",
+                "The JSON produced by /exportconfig",
+            )
+            .required(true),
+        )
+        .default_member_permissions(Permissions::ADMINISTRATOR)
+}
+
+/// Map a Redis key suffix to the value used by the custom roles multiselect
+fn custom_selection(role_key: &str) -> Option<&'static str> {
+    Some(match role_key {
+        "level:Undergrad" => "level:undergrad",
+        "level:Graduate" => "level:graduate",
+        "class:First-Year" => "class:first-year",
+        "class:Sophomore" => "class:sophomore",
+        "class:Junior" => "class:junior",
+        "class:Senior" => "class:senior",
+        "class:Fifth-Year Senior" => "class:fifth-year",
+        "class:Masters" => "class:masters",
+        "class:Doctoral" => "class:doctoral",
+        _ => return None,
+    })
+}
+
+/// Find a role by name in the guild, creating it if it doesn't exist
+async fn find_or_create_role(http: &Http, guild_id: GuildId, name: &str) -> Result<RoleId, Error> {
+    let guild = guild_id.to_partial_guild(http).await?;
+    if let Some(role) = guild.roles.iter().find(|r| r.name.as_str() == name) {
+        return Ok(role.id);
+    }
+
+    let role = guild_id
+        .create_role(http, EditRole::new().name(name))
+        .await?;
+    Ok(role.id)
+}
+
+/// Handle the importconfig command
+pub async fn handle(
+    ctx: &Context,
+    command: &CommandInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let user = &command.user;
+
+    // Get guild_id from context
+    let guild_id = match command.guild_id {
+        Some(id) => id,
+        None => {
+            let reply = Deferred::command(&ctx.http, command).await?;
+            reply
+                .edit(
+                    EditInteractionResponse::new()
+                        .content("This command can only be used in a server."),
+                )
+                .await?;
+            return Ok(());
+        }
+    };
+
+    // Role creation can take a while, defer so Discord doesn't time out the interaction
+    let reply = Deferred::command(&ctx.http, command).await?;
+
+    // Check if user has administrator permissions
+    if !is_admin(ctx, &command.member, guild_id, user.id).await? {
+        reply
+            .edit(
+                EditInteractionResponse::new()
+                    .content("You need administrator permissions to import server configuration."),
+            )
+            .await?;
+        return Ok(());
+    }
+
+    let json = match command.data.options().first() {
+        Some(ResolvedOption {
+            value: ResolvedValue::String(s),
+            ..
+        }) => s.to_string(),
+        _ => {
+            reply
+                .edit(EditInteractionResponse::new().content("JSON parameter is required."))
+                .await?;
+            return Ok(());
+        }
+    };
+
+    // Accept the JSON pasted with or without the code fence from /exportconfig
+    let json = json
+        .trim()
+        .trim_start_matches("```json")
+        .trim_matches('`')
+        .trim();
+
+    let imported: ExportedConfig = match serde_json::from_str(json) {
+        Ok(config) => config,
+        Err(e) => {
+            reply
+                .edit(
+                    EditInteractionResponse::new()
+                        .content(format!("Invalid configuration JSON: {}", e)),
+                )
+                .await?;
+            return Ok(());
+        }
+    };
+
+    // Validate the mode and role keys before touching anything
+    if !matches!(
+        imported.mode.as_str(),
+        "none" | "levels" | "classes" | "custom" | "groups"
+    ) {
+        reply
+            .edit(
+                EditInteractionResponse::new()
+                    .content(format!("Unknown role mode: {}", imported.mode)),
+            )
+            .await?;
+        return Ok(());
+    }
+
+    let mut custom_roles = Vec::new();
+    let mut group_roles = Vec::new();
+    for (role_name, role_key) in &imported.roles {
+        if let Some(group) = role_key.strip_prefix("group:") {
+            group_roles.push((group.to_string(), role_name.clone()));
+            continue;
+        }
+
+        match custom_selection(role_key) {
+            Some(selection) => custom_roles.push(selection.to_string()),
+            None => {
+                reply
+                    .edit(
+                        EditInteractionResponse::new()
+                            .content(format!("Unknown role key: {}", role_key)),
+                    )
+                    .await?;
+                return Ok(());
+            }
+        }
+    }
+
+    let mut conn = state.redis.clone();
+    let mut summary = Vec::new();
+
+    if let Some(name) = &imported.verified_role {
+        let role_id = find_or_create_role(&ctx.http, guild_id, name).await?;
+        redis::cmd("SET")
+            .arg(guild_role(guild_id, &RoleSlot::Verified))
+            .arg(role_id.to_string())
+            .query_async::<()>(&mut conn)
+            .await?;
+        summary.push(format!("* **Verified Role:** <@&{}>", role_id));
+    }
+
+    if let Some(name) = &imported.unverified_role {
+        let role_id = find_or_create_role(&ctx.http, guild_id, name).await?;
+        redis::cmd("SET")
+            .arg(guild_role(guild_id, &RoleSlot::Unverified))
+            .arg(role_id.to_string())
+            .query_async::<()>(&mut conn)
+            .await?;
+        summary.push(format!("* **Unverified Role:** <@&{}>", role_id));
+    }
+
+    if let Some(name) = &imported.log_channel {
+        let channel_id = guild_id.to_guild_cached(&ctx.cache).and_then(|g| {
+            g.channels
+                .iter()
+                .find(|c| c.base.name.as_str() == name)
+                .map(|c| c.id)
+        });
+
+        match channel_id {
+            Some(channel_id) => {
+                redis::pipe()
+                    .atomic()
+                    .set(
+                        redis_key!("guild:{}:log_channel", guild_id),
+                        channel_id.to_string(),
+                    )
+                    .ignore()
+                    .del(redis_key!("guild:{}:log_thread", guild_id))
+                    .ignore()
+                    .query_async::<()>(&mut conn)
+                    .await?;
+                summary.push(format!("* **Log Channel:** <#{}>", channel_id));
+            }
+            None => summary.push(format!(
+                "* **Log Channel:** no channel named `{}` found, use `/setlogchannel`",
+                name
+            )),
+        }
+    }
+
+    // Group roles are mapped individually by name
+    for (group, role_name) in &group_roles {
+        let role_id = find_or_create_role(&ctx.http, guild_id, role_name).await?;
+        redis::cmd("SET")
+            .arg(guild_role(guild_id, &RoleSlot::Group(group.to_string())))
+            .arg(role_id.to_string())
+            .query_async::<()>(&mut conn)
+            .await?;
+        summary.push(format!("* `group:{}`: <@&{}>", group, role_id));
+    }
+
+    // Reuse the /setuproles machinery to create level/class roles
+    let mode: RoleMode = imported.mode.parse().unwrap_or(RoleMode::None);
+    if matches!(mode, RoleMode::None | RoleMode::Groups) {
+        redis::cmd("SET")
+            .arg(redis_key!("guild:{}:role_mode", guild_id))
+            .arg(mode.as_str())
+            .query_async::<()>(&mut conn)
+            .await?;
+        summary.push(format!("* **Mode:** {}", mode));
+    } else {
+        let mut session = SetupRolesSession::new(mode.clone());
+        session.set_custom_roles(custom_roles);
+
+        if let Err(e) = session.validate() {
+            reply
+                .edit(EditInteractionResponse::new().content(e))
+                .await?;
+            return Ok(());
+        }
+
+        let roles = session
+            .save_and_create_roles(&ctx.http, guild_id, &mut conn, async |_, _, _: &str| {})
+            .await?;
+        summary.push(format!("* **Mode:** {}", mode));
+        summary.extend(
+            roles
+                .iter()
+                .map(|(role_key, role_id)| format!("* `{}`: <@&{}>", role_key, role_id)),
+        );
+    }
+
+    reply
+        .edit(
+            EditInteractionResponse::new()
+                .content(format!("Configuration imported:\n{}", summary.join("\n"))),
+        )
+        .await?;
+
+    Ok(())
+}
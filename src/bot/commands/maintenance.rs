@@ -0,0 +1,128 @@
+use crate::bot::Error;
+use crate::state::{AppState, guild_maintenance_key, maintenance_key};
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommand, CreateCommandOption,
+    CreateInteractionResponse, CreateInteractionResponseMessage, Permissions, ResolvedValue,
+};
+use std::sync::Arc;
+
+use super::utils::is_owner;
+
+use super::setverifymessage::MAX_VERIFY_PROMPT_LENGTH;
+
+/// Register the maintenance command
+pub fn register() -> CreateCommand<'static> {
+    CreateCommand::new("maintenance")
+        .description("Pause or resume new verifications (bot owner only)")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "paused",
+                "Whether new verifications are paused",
+            )
+            .required(true),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "scope",
+                "Where to pause verification (defaults to every server)",
+            )
+            .add_string_choice("Every server", "global")
+            .add_string_choice("This server", "server")
+            .required(false),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "message",
+                "Shown to members who try to verify (omit for the default)",
+            )
+            .max_length(MAX_VERIFY_PROMPT_LENGTH as u16)
+            .required(false),
+        )
+        .default_member_permissions(Permissions::ADMINISTRATOR)
+}
+
+/// Handle the maintenance command
+pub async fn handle(
+    ctx: &Context,
+    command: &CommandInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    // Pausing verification is for whoever runs the bot, e.g. during Keycloak maintenance
+    if !is_owner(&state.config, command.user.id) {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("Only the bot owner can pause verification.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    let mut paused = false;
+    let mut scope = "global";
+    let mut message = String::new();
+    for option in command.data.options() {
+        match (option.name, option.value) {
+            ("paused", ResolvedValue::Boolean(b)) => paused = b,
+            ("scope", ResolvedValue::String(s)) => scope = s,
+            ("message", ResolvedValue::String(s)) => message = s.trim().replace("\\n", "\n"),
+            _ => {}
+        }
+    }
+
+    let (redis_key, place) = match (scope, command.guild_id) {
+        ("server", Some(guild_id)) => (guild_maintenance_key(guild_id), "this server"),
+        ("server", None) => {
+            let response = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content("Pausing a single server can only be done from that server.")
+                    .ephemeral(true),
+            );
+            command.create_response(&ctx.http, response).await?;
+            return Ok(());
+        }
+        _ => (maintenance_key(), "every server"),
+    };
+
+    let mut conn = state.redis.clone();
+    let reply = if paused {
+        // An empty value still pauses, with the default message
+        redis::cmd("SET")
+            .arg(&redis_key)
+            .arg(&message)
+            .query_async::<()>(&mut conn)
+            .await?;
+        tracing::warn!(
+            "Verification paused in {} by the bot owner (from guild {:?})",
+            place,
+            command.guild_id
+        );
+        format!(
+            "New verifications are paused in {}. Verifications already past login still complete.",
+            place
+        )
+    } else {
+        redis::cmd("DEL")
+            .arg(&redis_key)
+            .query_async::<()>(&mut conn)
+            .await?;
+        tracing::info!(
+            "Verification resumed in {} (from guild {:?})",
+            place,
+            command.guild_id
+        );
+        format!("New verifications are resumed in {}.", place)
+    };
+
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(reply)
+            .ephemeral(true),
+    );
+    command.create_response(&ctx.http, response).await?;
+
+    Ok(())
+}
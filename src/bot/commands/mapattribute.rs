@@ -0,0 +1,241 @@
+use crate::bot::Error;
+use crate::keys::redis_key;
+use crate::state::AppState;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommand, CreateCommandOption,
+    CreateInteractionResponse, CreateInteractionResponseMessage, Mentionable, ResolvedValue,
+};
+use std::sync::Arc;
+
+use super::utils::{is_admin, load_guild_config};
+
+/// Register the mapattribute command
+pub fn register() -> CreateCommand<'static> {
+    CreateCommand::new("mapattribute")
+        .description("View or map Keycloak attribute values to roles (omit all options to list)")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "attribute",
+                "The Keycloak user attribute, e.g. class",
+            )
+            .required(false),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "value",
+                "The attribute value to match, e.g. Sophomore",
+            )
+            .required(false),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Role,
+                "role",
+                "The role to assign for this value (omit to remove the mapping)",
+            )
+            .required(false),
+        )
+}
+
+/// Handle the mapattribute command
+pub async fn handle(
+    ctx: &Context,
+    command: &CommandInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let user = &command.user;
+
+    // Get guild_id from context
+    let guild_id = match command.guild_id {
+        Some(id) => id,
+        None => {
+            let response = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content("This command can only be used in a server.")
+                    .ephemeral(true),
+            );
+            command.create_response(&ctx.http, response).await?;
+            return Ok(());
+        }
+    };
+
+    // Check if user has administrator permissions
+    if !is_admin(ctx, &command.member, guild_id, user.id).await? {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("You need administrator permissions to configure attribute roles.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    // Get the attribute, value and optional role from command options
+    let mut attribute = None;
+    let mut value = None;
+    let mut role = None;
+    for option in command.data.options() {
+        match (option.name, option.value) {
+            ("attribute", ResolvedValue::String(a)) => attribute = Some(a.trim().to_string()),
+            ("value", ResolvedValue::String(v)) => value = Some(v.trim().to_string()),
+            ("role", ResolvedValue::Role(r)) => role = Some(r),
+            _ => {}
+        }
+    }
+
+    let mut conn = state.redis.clone();
+
+    let (attribute, value) = match (
+        attribute.filter(|a| !a.is_empty()),
+        value.filter(|v| !v.is_empty()),
+    ) {
+        (Some(attribute), Some(value)) => (attribute, value),
+        // No options, list the current mappings
+        (None, None) if role.is_none() => {
+            let guild_config = load_guild_config(&ctx.http, &mut conn, guild_id).await?;
+            let mut mappings: Vec<String> = guild_config
+                .attribute_roles
+                .iter()
+                .map(|((attribute, value), role_id)| {
+                    format!("* `{}` = `{}`: <@&{}>", attribute, value, role_id)
+                })
+                .collect();
+            mappings.sort();
+
+            let content = if mappings.is_empty() {
+                "No attribute roles are mapped. Use `/mapattribute` with an attribute, value and role to add one.".to_string()
+            } else {
+                format!("Attribute roles:\n{}", mappings.join("\n"))
+            };
+
+            let response = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content(content)
+                    .ephemeral(true),
+            );
+            command.create_response(&ctx.http, response).await?;
+            return Ok(());
+        }
+        _ => {
+            let response = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content("Both the attribute and value parameters are required.")
+                    .ephemeral(true),
+            );
+            command.create_response(&ctx.http, response).await?;
+            return Ok(());
+        }
+    };
+
+    // The attribute is delimited by ':' in the Redis key
+    if attribute.contains(':') {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("Attribute names cannot contain `:`.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    let redis_key = redis_key!("guild:{}:attrmap:{}:{}", guild_id, attribute, value);
+
+    // No role given, remove the mapping
+    let Some(role) = role else {
+        redis::cmd("DEL")
+            .arg(&redis_key)
+            .query_async::<()>(&mut conn)
+            .await?;
+
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content(format!(
+                    "Removed the role mapping for `{}` = `{}`.",
+                    attribute, value
+                ))
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    };
+
+    // Check various restrictions on the selected role
+    let bot_user_id = ctx.cache.current_user().id;
+    let bot_member = guild_id.member(&ctx.http, bot_user_id).await?;
+    let guild_roles = guild_id.roles(&ctx.http).await?;
+
+    // Check if it's the @everyone role
+    let everyone_role_id = serenity::all::RoleId::from(guild_id.get());
+    if role.id == everyone_role_id {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("You cannot map an attribute to @everyone.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    // Check if it's a managed role
+    if role.managed() {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content(
+                    "You cannot use a managed role (bot/integration role) as an attribute role.",
+                )
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    // Find bot's highest role position
+    let bot_top_role = bot_member
+        .roles
+        .iter()
+        .filter_map(|role_id| guild_roles.get(role_id))
+        .max_by_key(|role| role.position);
+
+    let bot_position = bot_top_role.map(|r| r.position).unwrap_or(0);
+    let target_role_position = guild_roles.get(&role.id).map(|r| r.position).unwrap_or(0);
+
+    if bot_position <= target_role_position {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content(format!(
+                    "I cannot assign {}. My highest role is at position {}, but this role is at position {}.\n\
+                    Please move my role higher than {} in the server settings.",
+                    role.mention(),
+                    bot_position,
+                    target_role_position,
+                    role.mention()
+                ))
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    // Store the role ID in Redis
+    redis::cmd("SET")
+        .arg(&redis_key)
+        .arg(role.id.to_string())
+        .query_async::<()>(&mut conn)
+        .await?;
+
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(format!(
+                "Users whose Keycloak attribute `{}` is `{}` will now receive {} when they verify.",
+                attribute,
+                value,
+                role.mention()
+            ))
+            .ephemeral(true),
+    );
+    command.create_response(&ctx.http, response).await?;
+
+    Ok(())
+}
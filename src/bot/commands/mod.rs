@@ -1,21 +1,52 @@
+pub mod alwaysrole;
+pub mod assignrole;
 pub mod config;
+pub mod dms;
+pub mod exportconfig;
+pub mod forcelink;
+pub mod guilds;
+pub mod identities;
+pub mod importconfig;
+pub mod maintenance;
+pub mod mapattribute;
+pub mod mystatus;
+pub mod postverifybutton;
+pub mod protectrole;
+pub mod purgeunverified;
+pub mod reconcile;
+pub mod renamerole;
+pub mod resetconfig;
+pub mod resync;
 pub mod reverify;
+pub mod setemaildomain;
+pub mod setexempt;
+pub mod setgrouprole;
+pub mod setleavegrace;
 pub mod setlogchannel;
+pub mod setlogstyle;
+pub mod setnickname;
+pub mod setreminderinterval;
+pub mod setrolesync;
 pub mod setunverifiedrole;
 pub mod setuproles;
 pub mod setverifiedrole;
+pub mod setverifygate;
+pub mod setverifymessage;
+pub mod testlog;
+pub mod unlinkdiscord;
 pub mod unverify;
 pub mod userinfo;
 mod utils;
 pub mod verify;
 
 use crate::bot::Error;
-use serenity::all::{Command, Context};
+use crate::config::Config;
+use serenity::all::{Command, Http};
 
 /// Register all slash commands globally
-pub async fn register_commands(ctx: &Context) -> Result<(), Error> {
+pub async fn register_commands(http: &Http, config: &Config) -> Result<(), Error> {
     let commands = [
-        verify::register(),
+        verify::register(&config.identity_label),
         unverify::register(),
         userinfo::register(),
         setverifiedrole::register(),
@@ -24,9 +55,46 @@ pub async fn register_commands(ctx: &Context) -> Result<(), Error> {
         setuproles::register(),
         config::register(),
         reverify::register(),
+        purgeunverified::register(),
+        setreminderinterval::register(),
+        exportconfig::register(),
+        importconfig::register(),
+        setgrouprole::register(),
+        setverifymessage::register(),
+        testlog::register(),
+        forcelink::register(),
+        reconcile::register(),
+        protectrole::register(),
+        resetconfig::register(),
+        mapattribute::register(),
+        postverifybutton::register(),
+        setnickname::register(),
+        resync::register(),
+        guilds::register(),
+        alwaysrole::register(),
+        dms::register(),
+        mystatus::register(),
+        setleavegrace::register(),
+        assignrole::register(),
+        setlogstyle::register(),
+        maintenance::register(),
+        renamerole::register(),
+        setexempt::register(),
+        setverifygate::register(),
+        setrolesync::register(),
+        identities::register(),
+        unlinkdiscord::register(),
+        setemaildomain::register(),
     ];
 
-    Command::set_global_commands(&ctx.http, &commands).await?;
+    Command::set_global_commands(http, &commands).await?;
+
+    Ok(())
+}
+
+/// Remove all global slash commands
+pub async fn clear_commands(http: &Http) -> Result<(), Error> {
+    Command::set_global_commands(http, &[]).await?;
 
     Ok(())
 }
@@ -0,0 +1,95 @@
+use crate::bot::Error;
+use crate::bot::i18n::{self, Locale};
+use crate::keys::{discord_keycloak, redis_key};
+use crate::state::AppState;
+use redis::AsyncCommands;
+use serenity::all::{
+    CommandInteraction, Context, CreateCommand, CreateInteractionResponse,
+    CreateInteractionResponseMessage, Mentionable,
+};
+use std::sync::Arc;
+
+use super::utils::{format_verified_at, load_guild_config};
+
+/// Register the mystatus command
+pub fn register() -> CreateCommand<'static> {
+    CreateCommand::new("mystatus")
+        .description("Show this server's verification mode and your own verification status")
+}
+
+/// Handle the mystatus command. Only ever shows the caller's own roles, never counts
+/// or other members, so it's open to everyone.
+pub async fn handle(
+    ctx: &Context,
+    command: &CommandInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let locale = Locale::from_discord(&command.locale);
+
+    let (Some(guild_id), Some(member)) = (command.guild_id, command.member.as_deref()) else {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content(i18n::server_only(locale))
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    };
+
+    let mut conn = state.redis.clone();
+    let guild_config = load_guild_config(&ctx.http, &mut conn, guild_id).await?;
+
+    let linked: bool = redis::cmd("EXISTS")
+        .arg(discord_keycloak(command.user.id))
+        .query_async(&mut conn)
+        .await?;
+    let verified_at = if linked {
+        format_verified_at(
+            conn.get(redis_key!("discord:{}:verified_at", command.user.id))
+                .await?,
+        )
+    } else {
+        None
+    };
+    let verified = guild_config
+        .verified_role
+        .is_some_and(|role_id| member.roles.contains(&role_id));
+
+    // The verification roles the member holds, the verified role first
+    let managed_roles = guild_config.managed_roles();
+    let roles: Vec<String> = guild_config
+        .verified_role
+        .filter(|role_id| member.roles.contains(role_id))
+        .into_iter()
+        .chain(
+            member
+                .roles
+                .iter()
+                .filter(|role_id| managed_roles.contains(role_id))
+                .copied(),
+        )
+        .map(|role_id| role_id.mention().to_string())
+        .collect();
+
+    let guild_name = guild_id
+        .to_guild_cached(&ctx.cache)
+        .map(|g| g.name.to_string())
+        .unwrap_or_else(|| "This server".to_string());
+
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(i18n::my_status(
+                locale,
+                &guild_name,
+                guild_config.mode.as_str(),
+                verified,
+                linked,
+                verified_at.as_deref(),
+                &roles,
+            ))
+            .ephemeral(true),
+    );
+    command.create_response(&ctx.http, response).await?;
+
+    Ok(())
+}
@@ -0,0 +1,110 @@
+use crate::bot::Error;
+use crate::state::AppState;
+use serenity::all::{
+    ButtonStyle, CommandInteraction, CommandOptionType, Context, CreateActionRow, CreateButton,
+    CreateCommand, CreateCommandOption, CreateComponent, CreateInteractionResponse,
+    CreateInteractionResponseMessage, CreateMessage, Permissions, ResolvedValue,
+};
+use std::sync::Arc;
+
+use super::utils::is_admin;
+
+/// Custom id of the posted button, routed to `verify::handle_component`
+pub const START_VERIFY_ID: &str = "start_verify";
+
+/// Register the postverifybutton command
+pub fn register() -> CreateCommand<'static> {
+    CreateCommand::new("postverifybutton")
+        .description("Post a message with a button that starts verification in this channel")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "message",
+                "Text shown above the button",
+            )
+            .required(false),
+        )
+        .default_member_permissions(Permissions::ADMINISTRATOR)
+}
+
+/// Handle the postverifybutton command
+pub async fn handle(
+    ctx: &Context,
+    command: &CommandInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let user = &command.user;
+
+    // Get guild_id from context
+    let guild_id = match command.guild_id {
+        Some(id) => id,
+        None => {
+            let response = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content("This command can only be used in a server.")
+                    .ephemeral(true),
+            );
+            command.create_response(&ctx.http, response).await?;
+            return Ok(());
+        }
+    };
+
+    // Check if user has administrator permissions
+    if !is_admin(ctx, &command.member, guild_id, user.id).await? {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("You need administrator permissions to post the verify button.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    let mut text = None;
+    for option in command.data.options() {
+        if let ("message", ResolvedValue::String(m)) = (option.name, option.value) {
+            text = Some(m.trim().to_string()).filter(|m| !m.is_empty());
+        }
+    }
+    let text = text.unwrap_or_else(|| {
+        format!(
+            "Click the button below to verify your {} and get access to the server.",
+            state.config.identity_label
+        )
+    });
+
+    let button = CreateButton::new(START_VERIFY_ID)
+        .label("Verify")
+        .style(ButtonStyle::Primary);
+    let message = CreateMessage::new()
+        .content(text)
+        .components(vec![CreateComponent::ActionRow(CreateActionRow::Buttons(
+            vec![button].into(),
+        ))]);
+
+    let content = match ctx
+        .http
+        .send_message(command.channel_id, Vec::new(), &message)
+        .await
+    {
+        Ok(_) => "Posted the verify button. Pin it so new members can find it.".to_string(),
+        Err(e) => {
+            tracing::warn!(
+                "Failed to post verify button in channel {}: {}",
+                command.channel_id,
+                e
+            );
+            "I couldn't post in this channel. Make sure I can view it and send messages there."
+                .to_string()
+        }
+    };
+
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(content)
+            .ephemeral(true),
+    );
+    command.create_response(&ctx.http, response).await?;
+
+    Ok(())
+}
@@ -0,0 +1,369 @@
+use crate::bot::Error;
+use crate::bot::i18n::{self, Locale};
+use crate::redact::redact;
+use crate::state::AppState;
+use serenity::all::{
+    ButtonStyle, CommandInteraction, CommandOptionType, ComponentInteraction, Context,
+    CreateActionRow, CreateButton, CreateCommand, CreateCommandOption, CreateComponent,
+    CreateContainer, CreateContainerComponent, CreateEmbed, CreateInteractionResponse,
+    CreateInteractionResponseMessage, CreateMessage, CreateTextDisplay, EditInteractionResponse,
+    Mentionable, MessageFlags, Permissions, ResolvedValue,
+};
+use std::sync::Arc;
+
+use super::utils::{
+    admin_grant, load_guild_config, log_destination, send_dm, unverified_members_cached,
+};
+
+/// Number of members processed between progress updates
+const PURGE_BATCH_SIZE: usize = 25;
+
+/// Register the purgeunverified command
+pub fn register() -> CreateCommand<'static> {
+    CreateCommand::new("purgeunverified")
+        .description("Remind or kick members who have not verified (admin only)")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "action",
+                "What to do with unverified members",
+            )
+            .add_string_choice("Send a reminder DM", "remind")
+            .add_string_choice("Kick from the server", "kick")
+            .required(true),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "days",
+                "Only include members who joined at least this many days ago",
+            )
+            .min_int_value(0)
+            .max_int_value(3650)
+            .required(false),
+        )
+        .default_member_permissions(Permissions::ADMINISTRATOR)
+}
+
+/// Describe the selected action for confirmation and progress messages
+fn describe_action(action: &str) -> &'static str {
+    match action {
+        "kick" => "kick",
+        _ => "send a verification reminder to",
+    }
+}
+
+/// Handle the purgeunverified command by showing a confirmation
+pub async fn handle(
+    ctx: &Context,
+    command: &CommandInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let user = &command.user;
+
+    // Get guild_id from context
+    let guild_id = match command.guild_id {
+        Some(id) => id,
+        None => {
+            let response = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content("This command can only be used in a server.")
+                    .ephemeral(true),
+            );
+            command.create_response(&ctx.http, response).await?;
+            return Ok(());
+        }
+    };
+
+    // Check if user has administrator permissions
+    if admin_grant(ctx, &command.member, guild_id, user.id)
+        .await?
+        .is_none()
+    {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("You need administrator permissions to purge unverified members.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    let mut action = "remind";
+    let mut days = None;
+    for option in command.data.options() {
+        match (option.name, option.value) {
+            ("action", ResolvedValue::String(value)) => action = value,
+            ("days", ResolvedValue::Integer(value)) => days = Some(value),
+            _ => {}
+        }
+    }
+
+    // A verified role is required to know who is unverified
+    let mut conn = state.redis.clone();
+    let guild_config = load_guild_config(&ctx.http, &mut conn, guild_id).await?;
+    let verified_role = guild_config.get_verified_role()?;
+
+    let members = unverified_members_cached(
+        guild_id,
+        &ctx.cache,
+        verified_role,
+        &guild_config.exempt_roles,
+        days,
+    );
+    if members.is_empty() {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("No unverified members match that filter.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    let age_text = match days {
+        Some(days) => format!(" and joined at least {} days ago", days),
+        None => String::new(),
+    };
+
+    let confirm_button = CreateButton::new(format!(
+        "purge_confirm:{}:{}",
+        action,
+        days.map(|d| d.to_string()).unwrap_or_default()
+    ))
+    .label("Confirm")
+    .style(if action == "kick" {
+        ButtonStyle::Danger
+    } else {
+        ButtonStyle::Primary
+    });
+    let cancel_button = CreateButton::new("purge_cancel")
+        .label("Cancel")
+        .style(ButtonStyle::Secondary);
+
+    let container = CreateContainer::new(vec![
+        CreateContainerComponent::TextDisplay(CreateTextDisplay::new("# Confirm Purge")),
+        CreateContainerComponent::TextDisplay(CreateTextDisplay::new(format!(
+            "This will **{}** {} members who have not verified{}.",
+            describe_action(action),
+            members.len(),
+            age_text
+        ))),
+        CreateContainerComponent::ActionRow(CreateActionRow::Buttons(
+            vec![confirm_button, cancel_button].into(),
+        )),
+    ]);
+
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .components(vec![CreateComponent::Container(container)])
+            .flags(MessageFlags::EPHEMERAL | MessageFlags::IS_COMPONENTS_V2),
+    );
+    command.create_response(&ctx.http, response).await?;
+
+    Ok(())
+}
+
+/// Handle the confirm and cancel buttons, processing members in batches
+pub async fn handle_component(
+    ctx: &Context,
+    interaction: &ComponentInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let guild_id = match interaction.guild_id {
+        Some(id) => id,
+        None => return Ok(()),
+    };
+
+    let custom_id = interaction.data.custom_id.as_str();
+
+    if custom_id == "purge_cancel" {
+        let container = CreateContainer::new(vec![CreateContainerComponent::TextDisplay(
+            CreateTextDisplay::new("# Cancelled\n\nNo changes were made."),
+        )]);
+
+        let response = CreateInteractionResponse::UpdateMessage(
+            CreateInteractionResponseMessage::new()
+                .components(vec![CreateComponent::Container(container)])
+                .flags(MessageFlags::EPHEMERAL | MessageFlags::IS_COMPONENTS_V2),
+        );
+        interaction.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    let Some(params) = custom_id.strip_prefix("purge_confirm:") else {
+        return Ok(());
+    };
+    let (action, days) = params.split_once(':').unwrap_or((params, ""));
+    let days = days.parse::<i64>().ok();
+
+    // Permissions may have changed since the confirmation was shown
+    let Some(grant) = admin_grant(ctx, &interaction.member, guild_id, interaction.user.id).await?
+    else {
+        let container = CreateContainer::new(vec![CreateContainerComponent::TextDisplay(
+            CreateTextDisplay::new(
+                "# Error\n\nYou need administrator permissions to purge unverified members.",
+            ),
+        )]);
+
+        let response = CreateInteractionResponse::UpdateMessage(
+            CreateInteractionResponseMessage::new()
+                .components(vec![CreateComponent::Container(container)])
+                .flags(MessageFlags::EPHEMERAL | MessageFlags::IS_COMPONENTS_V2),
+        );
+        interaction.create_response(&ctx.http, response).await?;
+        return Ok(());
+    };
+
+    tracing::warn!(
+        "Admin {} ({}) confirmed a purge of unverified members ({}) in guild {}",
+        redact(interaction.user.id),
+        grant,
+        action,
+        guild_id
+    );
+
+    // Acknowledge now, progress is reported by editing the message
+    interaction
+        .create_response(&ctx.http, CreateInteractionResponse::Acknowledge)
+        .await?;
+
+    // Recompute the member list since members may have verified in the meantime
+    let mut conn = state.redis.clone();
+    let guild_config = load_guild_config(&ctx.http, &mut conn, guild_id).await?;
+    let verified_role = guild_config.get_verified_role()?;
+    let members = unverified_members_cached(
+        guild_id,
+        &ctx.cache,
+        verified_role,
+        &guild_config.exempt_roles,
+        days,
+    );
+    let total = members.len();
+
+    // Resolve the guild name up front so no cache reference is held across awaits
+    let guild_name = guild_id
+        .to_guild_cached(&ctx.cache)
+        .map(|g| g.name.to_string())
+        .unwrap_or_else(|| "this server".to_string());
+    let locale = guild_id
+        .to_guild_cached(&ctx.cache)
+        .map(|g| Locale::from_discord(&g.preferred_locale))
+        .unwrap_or_default();
+
+    let mut succeeded = 0;
+    let mut failed = 0;
+
+    for (batch_index, batch) in members.chunks(PURGE_BATCH_SIZE).enumerate() {
+        for user_id in batch {
+            let result = match action {
+                "kick" => guild_id
+                    .kick(&ctx.http, *user_id, Some("Did not verify"))
+                    .await
+                    .map_err(Error::from),
+                _ => send_dm(
+                    &ctx.http,
+                    &mut conn,
+                    *user_id,
+                    CreateMessage::new().content(i18n::reminder_dm(
+                        locale,
+                        &state.config.identity_label,
+                        &guild_name,
+                    )),
+                )
+                .await
+                .and_then(|sent| {
+                    if sent {
+                        Ok(())
+                    } else {
+                        Err("user opted out of DMs".into())
+                    }
+                }),
+            };
+
+            match result {
+                Ok(()) => succeeded += 1,
+                Err(e) => {
+                    tracing::warn!("Failed to {} user {}: {}", action, redact(user_id), e);
+                    failed += 1;
+                }
+            }
+
+            // Sleep between users to stay well under Discord's rate limit
+            tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+        }
+
+        let processed = (batch_index * PURGE_BATCH_SIZE + batch.len()).min(total);
+        let container = CreateContainer::new(vec![CreateContainerComponent::TextDisplay(
+            CreateTextDisplay::new(format!(
+                "# Purge In Progress\n\nProcessed {}/{} members.\nSucceeded: {} | Failed: {}",
+                processed, total, succeeded, failed
+            )),
+        )]);
+
+        if let Err(e) = interaction
+            .edit_response(
+                &ctx.http,
+                EditInteractionResponse::new()
+                    .components(vec![CreateComponent::Container(container)])
+                    .flags(MessageFlags::IS_COMPONENTS_V2),
+            )
+            .await
+        {
+            tracing::warn!("Failed to update purge progress: {}", e);
+        }
+    }
+
+    // Log a summary to the log channel first, it doesn't depend on the interaction token
+    if let Some(channel_id) = guild_config.get_log_channel()
+        && let Some(channel_id) =
+            log_destination(&ctx.http, &ctx.cache, &mut conn, guild_id, channel_id).await
+    {
+        let embed = CreateEmbed::new()
+            .title("Unverified Members Purged")
+            .color(0xF9E2AF) // Yellow
+            .field("Action", describe_action(action), false)
+            .field("Succeeded", succeeded.to_string(), true)
+            .field("Failed", failed.to_string(), true)
+            .field(
+                "Purged By",
+                format!("{} ({})", interaction.user.id.mention(), grant),
+                false,
+            )
+            .timestamp(chrono::Utc::now());
+
+        if let Err(e) = ctx
+            .http
+            .send_message(
+                channel_id.into(),
+                Vec::new(),
+                &CreateMessage::new().embed(embed),
+            )
+            .await
+        {
+            tracing::warn!("Failed to send purge log to channel {}: {}", channel_id, e);
+        }
+    }
+
+    let container = CreateContainer::new(vec![CreateContainerComponent::TextDisplay(
+        CreateTextDisplay::new(format!(
+            "# Purge Complete\n\nProcessed all **{}** members.\nSucceeded: {} | Failed: {}",
+            total, succeeded, failed
+        )),
+    )]);
+
+    // A long purge can outlast the interaction token, so this edit is best effort
+    if let Err(e) = interaction
+        .edit_response(
+            &ctx.http,
+            EditInteractionResponse::new()
+                .components(vec![CreateComponent::Container(container)])
+                .flags(MessageFlags::IS_COMPONENTS_V2),
+        )
+        .await
+    {
+        tracing::warn!("Failed to show purge completion: {}", e);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,321 @@
+use crate::bot::Error;
+use crate::keys::{discord_id_of, redis_key};
+use crate::redact::redact;
+use crate::state::AppState;
+use redis::AsyncCommands;
+use serenity::all::{
+    ButtonStyle, CommandInteraction, ComponentInteraction, Context, CreateActionRow, CreateButton,
+    CreateCommand, CreateComponent, CreateContainer, CreateContainerComponent, CreateEmbed,
+    CreateInteractionResponse, CreateInteractionResponseMessage, CreateMessage, CreateTextDisplay,
+    EditInteractionResponse, GuildId, MessageFlags, Permissions, UserId,
+};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use super::unverify::unverify_user;
+use super::utils::{Deferred, is_admin, load_guild_config, log_destination, trim_redis_value};
+
+/// Most stale members to list in the report
+const MAX_LISTED: usize = 50;
+
+/// Register the reconcile command
+pub fn register() -> CreateCommand<'static> {
+    CreateCommand::new("reconcile")
+        .description("Find verified members whose Keycloak account was deleted (admin only)")
+        .default_member_permissions(Permissions::ADMINISTRATOR)
+}
+
+/// Result of checking this guild's verified members against Keycloak
+struct StaleMappings {
+    checked: usize,
+    /// Members whose Keycloak user returned 404
+    stale: Vec<UserId>,
+    /// Members that couldn't be checked, e.g. because Keycloak is down
+    skipped: usize,
+}
+
+/// Check each verified member of the guild for a Keycloak user that no longer exists
+async fn find_stale_mappings(
+    ctx: &Context,
+    state: &AppState,
+    guild_id: GuildId,
+) -> Result<StaleMappings, Error> {
+    // Only consider members of this guild (scope to drop guild reference before await)
+    let member_ids: HashSet<UserId> = {
+        let guild = guild_id
+            .to_guild_cached(&ctx.cache)
+            .ok_or("Guild not in cache")?;
+        guild.members.iter().map(|m| m.user.id).collect()
+    };
+
+    // Keys are "discord:{discord_id}:keycloak"
+    let mut conn = state.redis.clone();
+    let mut keys = Vec::new();
+    {
+        let mut iter = conn
+            .scan_match::<_, String>(redis_key!("discord:*:keycloak"))
+            .await?;
+        while let Some(key) = iter.next_item().await {
+            keys.push(key);
+        }
+    }
+
+    let mut result = StaleMappings {
+        checked: 0,
+        stale: Vec::new(),
+        skipped: 0,
+    };
+
+    for key in keys {
+        let Some(user_id) = discord_id_of(&key)
+            .map(UserId::new)
+            .filter(|id| member_ids.contains(id))
+        else {
+            continue;
+        };
+
+        let Some(keycloak_user_id) = trim_redis_value(conn.get(&key).await?) else {
+            continue;
+        };
+
+        result.checked += 1;
+        match state.keycloak.user_exists(&keycloak_user_id).await {
+            Ok(true) => {}
+            Ok(false) => result.stale.push(user_id),
+            Err(e) => {
+                tracing::warn!(
+                    "Skipping user {} during reconcile, Keycloak lookup failed: {}",
+                    redact(user_id),
+                    e
+                );
+                result.skipped += 1;
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+/// Note shown when some members couldn't be checked
+fn skipped_text(skipped: usize) -> String {
+    if skipped == 0 {
+        String::new()
+    } else {
+        format!(
+            "\n\n{} members could not be checked because Keycloak did not respond and were skipped.",
+            skipped
+        )
+    }
+}
+
+/// Handle the reconcile command by reporting stale mappings and asking for confirmation
+pub async fn handle(
+    ctx: &Context,
+    command: &CommandInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let user = &command.user;
+
+    // Checking every member against Keycloak can take a while
+    let reply = Deferred::command(&ctx.http, command).await?;
+
+    // Get guild_id from context
+    let guild_id = match command.guild_id {
+        Some(id) => id,
+        None => {
+            reply
+                .edit(
+                    EditInteractionResponse::new()
+                        .content("This command can only be used in a server."),
+                )
+                .await?;
+            return Ok(());
+        }
+    };
+
+    // Check if user has administrator permissions
+    if !is_admin(ctx, &command.member, guild_id, user.id).await? {
+        reply
+            .edit(
+                EditInteractionResponse::new()
+                    .content("You need administrator permissions to reconcile verifications."),
+            )
+            .await?;
+        return Ok(());
+    }
+
+    let result = find_stale_mappings(ctx, state, guild_id).await?;
+
+    if result.stale.is_empty() {
+        reply
+            .edit(EditInteractionResponse::new().content(format!(
+                "Checked {} verified members, all Keycloak accounts still exist.{}",
+                result.checked,
+                skipped_text(result.skipped)
+            )))
+            .await?;
+        return Ok(());
+    }
+
+    // Cap the list so the message stays within Discord's length limit
+    let mut stale_list = result
+        .stale
+        .iter()
+        .take(MAX_LISTED)
+        .map(|user_id| format!("* <@{}>", user_id))
+        .collect::<Vec<_>>()
+        .join("\n");
+    if result.stale.len() > MAX_LISTED {
+        stale_list.push_str(&format!(
+            "\n...and {} more",
+            result.stale.len() - MAX_LISTED
+        ));
+    }
+
+    let confirm_button = CreateButton::new("reconcile_confirm")
+        .label("Unverify")
+        .style(ButtonStyle::Danger);
+    let cancel_button = CreateButton::new("reconcile_cancel")
+        .label("Cancel")
+        .style(ButtonStyle::Secondary);
+
+    let container = CreateContainer::new(vec![
+        CreateContainerComponent::TextDisplay(CreateTextDisplay::new("# Stale Verifications")),
+        CreateContainerComponent::TextDisplay(CreateTextDisplay::new(format!(
+            "Checked {} verified members. These {} members are linked to Keycloak accounts \
+            that no longer exist:\n{}{}",
+            result.checked,
+            result.stale.len(),
+            stale_list,
+            skipped_text(result.skipped)
+        ))),
+        CreateContainerComponent::ActionRow(CreateActionRow::Buttons(
+            vec![confirm_button, cancel_button].into(),
+        )),
+    ]);
+
+    reply
+        .edit(
+            EditInteractionResponse::new()
+                .components(vec![CreateComponent::Container(container)])
+                .flags(MessageFlags::IS_COMPONENTS_V2),
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Handle the confirm and cancel buttons of the reconcile report
+pub async fn handle_component(
+    ctx: &Context,
+    interaction: &ComponentInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let guild_id = match interaction.guild_id {
+        Some(id) => id,
+        None => return Ok(()),
+    };
+
+    let message = match interaction.data.custom_id.as_str() {
+        "reconcile_cancel" => "# Cancelled\n\nNo changes were made.".to_string(),
+        "reconcile_confirm" => {
+            // Permissions may have changed since the report was shown
+            if !is_admin(ctx, &interaction.member, guild_id, interaction.user.id).await? {
+                "# Error\n\nYou need administrator permissions to reconcile verifications."
+                    .to_string()
+            } else {
+                // Acknowledge now, the checks are rerun before anything is removed
+                let reply = Deferred::component(&ctx.http, interaction).await?;
+
+                let message = unverify_stale(ctx, state, guild_id).await?;
+                let container = CreateContainer::new(vec![CreateContainerComponent::TextDisplay(
+                    CreateTextDisplay::new(message),
+                )]);
+
+                reply
+                    .edit(
+                        EditInteractionResponse::new()
+                            .components(vec![CreateComponent::Container(container)])
+                            .flags(MessageFlags::IS_COMPONENTS_V2),
+                    )
+                    .await?;
+                return Ok(());
+            }
+        }
+        _ => return Ok(()),
+    };
+
+    let container = CreateContainer::new(vec![CreateContainerComponent::TextDisplay(
+        CreateTextDisplay::new(message),
+    )]);
+
+    let response = CreateInteractionResponse::UpdateMessage(
+        CreateInteractionResponseMessage::new()
+            .components(vec![CreateComponent::Container(container)])
+            .flags(MessageFlags::EPHEMERAL | MessageFlags::IS_COMPONENTS_V2),
+    );
+    interaction.create_response(&ctx.http, response).await?;
+
+    Ok(())
+}
+
+/// Recheck the guild's mappings and unverify members whose Keycloak account is gone
+async fn unverify_stale(
+    ctx: &Context,
+    state: &AppState,
+    guild_id: GuildId,
+) -> Result<String, Error> {
+    // Rerun the checks so a Keycloak outage since the report can't unverify anyone
+    let result = find_stale_mappings(ctx, state, guild_id).await?;
+
+    let mut succeeded = 0;
+    let mut failed = 0;
+    for user_id in &result.stale {
+        match unverify_user(&ctx.http, &ctx.cache, state, guild_id, *user_id).await {
+            Ok(_) => succeeded += 1,
+            Err(e) => {
+                tracing::warn!("Failed to unverify stale user {}: {}", redact(user_id), e);
+                failed += 1;
+            }
+        }
+    }
+
+    // Log a summary to the log channel if configured
+    let mut conn = state.redis.clone();
+    let guild_config = load_guild_config(&ctx.http, &mut conn, guild_id).await?;
+    if let Some(channel_id) = guild_config.get_log_channel()
+        && let Some(channel_id) =
+            log_destination(&ctx.http, &ctx.cache, &mut conn, guild_id, channel_id).await
+    {
+        let embed = CreateEmbed::new()
+            .title("Stale Verifications Reconciled")
+            .color(0xF9E2AF) // Yellow
+            .field("Unverified", succeeded.to_string(), true)
+            .field("Failed", failed.to_string(), true)
+            .field("Skipped", result.skipped.to_string(), true)
+            .timestamp(chrono::Utc::now());
+
+        if let Err(e) = ctx
+            .http
+            .send_message(
+                channel_id.into(),
+                Vec::new(),
+                &CreateMessage::new().embed(embed),
+            )
+            .await
+        {
+            tracing::warn!(
+                "Failed to send reconcile log to channel {}: {}",
+                channel_id,
+                e
+            );
+        }
+    }
+
+    Ok(format!(
+        "# Reconcile Complete\n\nUnverified {} members whose Keycloak account no longer exists.\nFailed: {}{}",
+        succeeded,
+        failed,
+        skipped_text(result.skipped)
+    ))
+}
@@ -0,0 +1,148 @@
+use crate::bot::Error;
+use crate::state::AppState;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommand, CreateCommandOption,
+    CreateInteractionResponse, CreateInteractionResponseMessage, EditRole, Mentionable,
+    ResolvedValue,
+};
+use std::sync::Arc;
+
+use super::utils::{is_admin, load_guild_config};
+
+/// Discord's role name limit
+const MAX_ROLE_NAME_LENGTH: usize = 100;
+
+/// Register the renamerole command
+pub fn register() -> CreateCommand<'static> {
+    CreateCommand::new("renamerole")
+        .description("Rename a role created by /setuproles, keeping it mapped")
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::Role, "role", "The role to rename")
+                .required(true),
+        )
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::String, "name", "The new name")
+                .max_length(MAX_ROLE_NAME_LENGTH as u16)
+                .required(true),
+        )
+}
+
+/// Handle the renamerole command. Roles are tracked by id, so this only changes the
+/// name shown in Discord, the level or class it's mapped to stays the same.
+pub async fn handle(
+    ctx: &Context,
+    command: &CommandInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let user = &command.user;
+
+    // Get guild_id from context
+    let guild_id = match command.guild_id {
+        Some(id) => id,
+        None => {
+            let response = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content("This command can only be used in a server.")
+                    .ephemeral(true),
+            );
+            command.create_response(&ctx.http, response).await?;
+            return Ok(());
+        }
+    };
+
+    // Check if user has administrator permissions
+    if !is_admin(ctx, &command.member, guild_id, user.id).await? {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("You need administrator permissions to rename verification roles.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    // Get the role and new name from command options
+    let mut role = None;
+    let mut name = None;
+    for option in command.data.options() {
+        match (option.name, option.value) {
+            ("role", ResolvedValue::Role(r)) => role = Some(r.clone()),
+            ("name", ResolvedValue::String(s)) => {
+                name = Some(s.trim().to_string()).filter(|s| !s.is_empty())
+            }
+            _ => {}
+        }
+    }
+
+    let (Some(role), Some(name)) = (role, name) else {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("Role and name parameters are required.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    };
+
+    let mut conn = state.redis.clone();
+    let guild_config = load_guild_config(&ctx.http, &mut conn, guild_id).await?;
+
+    // Only the level and class roles /setuproles creates, other roles are the admins' own
+    let mapped_to = guild_config
+        .level_roles
+        .iter()
+        .chain(guild_config.class_roles.iter())
+        .find(|(_, role_id)| **role_id == role.id)
+        .map(|(name, _)| name.clone());
+
+    let Some(mapped_to) = mapped_to else {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content(format!(
+                    "{} wasn't created by `/setuproles`. Rename it in the server settings instead.",
+                    role.mention()
+                ))
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    };
+
+    let message = match guild_id
+        .edit_role(&ctx.http, role.id, EditRole::new().name(name.clone()))
+        .await
+    {
+        Ok(_) => {
+            tracing::info!(
+                "Renamed role {} ({}) to '{}' in guild {}",
+                role.id,
+                mapped_to,
+                name,
+                guild_id
+            );
+            format!(
+                "Renamed {} to **{}**. It's still assigned for {}.",
+                role.mention(),
+                name,
+                mapped_to
+            )
+        }
+        Err(e) => {
+            tracing::warn!("Failed to rename role {}: {}", role.id, e);
+            format!(
+                "Failed to rename {}: {}. The bot's role must be above it.",
+                role.mention(),
+                e
+            )
+        }
+    };
+
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(message)
+            .ephemeral(true),
+    );
+    command.create_response(&ctx.http, response).await?;
+
+    Ok(())
+}
@@ -0,0 +1,243 @@
+use crate::bot::Error;
+use crate::keys::redis_key;
+use crate::redact::redact;
+use crate::state::AppState;
+use redis::AsyncCommands;
+use serenity::all::{
+    ButtonStyle, CommandInteraction, CommandOptionType, ComponentInteraction, Context,
+    CreateActionRow, CreateButton, CreateCommand, CreateCommandOption, CreateComponent,
+    CreateContainer, CreateContainerComponent, CreateInteractionResponse,
+    CreateInteractionResponseMessage, CreateTextDisplay, EditInteractionResponse, MessageFlags,
+    Permissions, ResolvedOption, ResolvedValue,
+};
+use std::sync::Arc;
+
+use super::utils::{Deferred, admin_grant, is_admin, load_guild_config};
+
+/// Register the resetconfig command
+pub fn register() -> CreateCommand<'static> {
+    CreateCommand::new("resetconfig")
+        .description("Delete all of this server's verification configuration (admin only)")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "delete_roles",
+                "Also delete the level and class roles created by /setuproles",
+            )
+            .required(false),
+        )
+        .default_member_permissions(Permissions::ADMINISTRATOR)
+}
+
+/// Handle the resetconfig command by asking for confirmation first
+pub async fn handle(
+    ctx: &Context,
+    command: &CommandInteraction,
+    _state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let user = &command.user;
+
+    // Get guild_id from context
+    let guild_id = match command.guild_id {
+        Some(id) => id,
+        None => {
+            let response = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content("This command can only be used in a server.")
+                    .ephemeral(true),
+            );
+            command.create_response(&ctx.http, response).await?;
+            return Ok(());
+        }
+    };
+
+    // Check if user has administrator permissions
+    if !is_admin(ctx, &command.member, guild_id, user.id).await? {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("You need administrator permissions to reset server configuration.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    let delete_roles = matches!(
+        command.data.options().first(),
+        Some(ResolvedOption {
+            value: ResolvedValue::Boolean(true),
+            ..
+        })
+    );
+
+    let roles_text = if delete_roles {
+        "The level and class roles created by `/setuproles` will also be deleted."
+    } else {
+        "Roles in Discord will not be deleted."
+    };
+
+    let confirm_button = CreateButton::new(format!("resetconfig_confirm:{}", delete_roles))
+        .label("Reset")
+        .style(ButtonStyle::Danger);
+    let cancel_button = CreateButton::new("resetconfig_cancel")
+        .label("Cancel")
+        .style(ButtonStyle::Secondary);
+
+    let container = CreateContainer::new(vec![
+        CreateContainerComponent::TextDisplay(CreateTextDisplay::new("# Confirm Reset")),
+        CreateContainerComponent::TextDisplay(CreateTextDisplay::new(format!(
+            "This will permanently delete **all** verification configuration for this server, \
+            including roles, log channel, role mode and reminders. \
+            Verified users stay linked to their accounts.\n\n{}",
+            roles_text
+        ))),
+        CreateContainerComponent::ActionRow(CreateActionRow::Buttons(
+            vec![confirm_button, cancel_button].into(),
+        )),
+    ]);
+
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .components(vec![CreateComponent::Container(container)])
+            .flags(MessageFlags::EPHEMERAL | MessageFlags::IS_COMPONENTS_V2),
+    );
+    command.create_response(&ctx.http, response).await?;
+
+    Ok(())
+}
+
+/// Handle the confirm and cancel buttons of the reset confirmation
+pub async fn handle_component(
+    ctx: &Context,
+    interaction: &ComponentInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let guild_id = match interaction.guild_id {
+        Some(id) => id,
+        None => return Ok(()),
+    };
+
+    let custom_id = interaction.data.custom_id.as_str();
+
+    let message = if custom_id == "resetconfig_cancel" {
+        "# Cancelled\n\nNo changes were made.".to_string()
+    } else if let Some(delete_roles) = custom_id.strip_prefix("resetconfig_confirm:") {
+        // Permissions may have changed since the confirmation was shown
+        let grant = admin_grant(ctx, &interaction.member, guild_id, interaction.user.id).await?;
+        if let Some(grant) = grant {
+            tracing::warn!(
+                "Admin {} ({}) confirmed a configuration reset for guild {}",
+                redact(interaction.user.id),
+                grant,
+                guild_id
+            );
+
+            // Acknowledge now, deleting roles can take a while
+            let reply = Deferred::component(&ctx.http, interaction).await?;
+
+            let message = reset_guild(ctx, state, guild_id, delete_roles == "true").await?;
+            let container = CreateContainer::new(vec![CreateContainerComponent::TextDisplay(
+                CreateTextDisplay::new(message),
+            )]);
+
+            reply
+                .edit(
+                    EditInteractionResponse::new()
+                        .components(vec![CreateComponent::Container(container)])
+                        .flags(MessageFlags::IS_COMPONENTS_V2),
+                )
+                .await?;
+            return Ok(());
+        } else {
+            "# Error\n\nYou need administrator permissions to reset server configuration."
+                .to_string()
+        }
+    } else {
+        return Ok(());
+    };
+
+    let container = CreateContainer::new(vec![CreateContainerComponent::TextDisplay(
+        CreateTextDisplay::new(message),
+    )]);
+
+    let response = CreateInteractionResponse::UpdateMessage(
+        CreateInteractionResponseMessage::new()
+            .components(vec![CreateComponent::Container(container)])
+            .flags(MessageFlags::EPHEMERAL | MessageFlags::IS_COMPONENTS_V2),
+    );
+    interaction.create_response(&ctx.http, response).await?;
+
+    Ok(())
+}
+
+/// Delete every `guild:{guild_id}:*` key and optionally the roles /setuproles created,
+/// returning a report of what was removed
+async fn reset_guild(
+    ctx: &Context,
+    state: &AppState,
+    guild_id: serenity::all::GuildId,
+    delete_roles: bool,
+) -> Result<String, Error> {
+    let mut conn = state.redis.clone();
+
+    // Load the config first, so the created roles are known before their keys are gone
+    let guild_config = load_guild_config(&ctx.http, &mut conn, guild_id).await?;
+
+    let mut keys = Vec::new();
+    {
+        let mut iter = conn
+            .scan_match::<_, String>(redis_key!("guild:{}:*", guild_id))
+            .await?;
+        while let Some(key) = iter.next_item().await {
+            keys.push(key);
+        }
+    }
+
+    for chunk in keys.chunks(100) {
+        let _: () = conn.del(chunk).await?;
+    }
+
+    tracing::info!(
+        "Reset configuration for guild {}, deleted {} keys",
+        guild_id,
+        keys.len()
+    );
+
+    let mut report = format!(
+        "# Configuration Reset\n\nDeleted {} configuration keys.",
+        keys.len()
+    );
+
+    if delete_roles {
+        let mut deleted = Vec::new();
+        let mut failed = Vec::new();
+
+        for (name, role_id) in guild_config
+            .level_roles
+            .iter()
+            .chain(guild_config.class_roles.iter())
+        {
+            match guild_id.delete_role(&ctx.http, *role_id, None).await {
+                Ok(()) => deleted.push(format!("* {}", name)),
+                Err(e) => {
+                    tracing::warn!("Failed to delete role {}: {}", role_id, e);
+                    failed.push(format!("* {} ({})", name, e));
+                }
+            }
+        }
+
+        if deleted.is_empty() {
+            report.push_str("\n\nNo roles were deleted.");
+        } else {
+            report.push_str(&format!("\n\nDeleted roles:\n{}", deleted.join("\n")));
+        }
+        if !failed.is_empty() {
+            report.push_str(&format!(
+                "\n\nFailed to delete roles:\n{}",
+                failed.join("\n")
+            ));
+        }
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,143 @@
+use crate::bot::Error;
+use crate::keys::discord_keycloak;
+use crate::state::AppState;
+use redis::AsyncCommands;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommand, CreateCommandOption,
+    EditInteractionResponse, Mentionable, Permissions, ResolvedOption, ResolvedValue,
+};
+use std::sync::Arc;
+
+use super::utils::{Deferred, is_admin, load_guild_config, trim_redis_value};
+use super::verify::{
+    RoleChanges, assign_verification_roles, fetch_email, fetch_role_inputs, format_roles,
+};
+
+/// Register the resync command
+pub fn register() -> CreateCommand<'static> {
+    CreateCommand::new("resync")
+        .description(
+            "Re-apply a verified user's roles from their current Keycloak data (admin only)",
+        )
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::User, "user", "The user to resync")
+                .required(true),
+        )
+        .default_member_permissions(Permissions::ADMINISTRATOR)
+}
+
+/// Handle the resync command
+pub async fn handle(
+    ctx: &Context,
+    command: &CommandInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let user = &command.user;
+
+    // Keycloak lookups and role changes can take a while
+    let reply = Deferred::command(&ctx.http, command).await?;
+
+    // Get guild_id from context
+    let guild_id = match command.guild_id {
+        Some(id) => id,
+        None => {
+            reply
+                .edit(
+                    EditInteractionResponse::new()
+                        .content("This command can only be used in a server."),
+                )
+                .await?;
+            return Ok(());
+        }
+    };
+
+    // Check if user has administrator permissions
+    if !is_admin(ctx, &command.member, guild_id, user.id).await? {
+        reply
+            .edit(
+                EditInteractionResponse::new()
+                    .content("You need administrator permissions to resync users."),
+            )
+            .await?;
+        return Ok(());
+    }
+
+    let options = command.data.options();
+    let Some(ResolvedOption {
+        value: ResolvedValue::User(target_user, _),
+        ..
+    }) = options.first()
+    else {
+        reply
+            .edit(EditInteractionResponse::new().content("User parameter is required."))
+            .await?;
+        return Ok(());
+    };
+
+    // Only verified users have a Keycloak account to read roles from
+    let mut conn = state.redis.clone();
+    let keycloak_user_id = trim_redis_value(conn.get(discord_keycloak(target_user.id)).await?);
+    let Some(keycloak_user_id) = keycloak_user_id else {
+        reply
+            .edit(EditInteractionResponse::new().content(format!(
+                "{} is not verified, they must verify with `/verify` first.",
+                target_user.mention()
+            )))
+            .await?;
+        return Ok(());
+    };
+
+    let guild_config = load_guild_config(&ctx.http, &mut conn, guild_id).await?;
+    let email = fetch_email(state, &guild_config, &keycloak_user_id).await?;
+    if !guild_config.allows_email(email.as_deref()) {
+        reply
+            .edit(EditInteractionResponse::new().content(format!(
+                "{} doesn't have a verified email from one of this server's email domains, \
+                so they can't be given roles.",
+                target_user.mention()
+            )))
+            .await?;
+        return Ok(());
+    }
+
+    let mut issues = Vec::new();
+    let (attributes, groups) =
+        fetch_role_inputs(state, &guild_config, &keycloak_user_id, None, &mut issues).await?;
+
+    let RoleChanges {
+        added,
+        removed,
+        issues: role_issues,
+        ..
+    } = assign_verification_roles(
+        ctx.http.as_ref(),
+        &guild_config,
+        target_user.id,
+        attributes.as_ref(),
+        &groups,
+        email.as_deref(),
+        &state.config,
+    )
+    .await?;
+    issues.extend(role_issues);
+
+    let mut message = if added.is_empty() && removed.is_empty() {
+        format!("{}'s roles are already up to date.", target_user.mention())
+    } else {
+        format!(
+            "Resynced roles for {}.\n\n**Added:** {}\n**Removed:** {}",
+            target_user.mention(),
+            format_roles(added),
+            format_roles(removed)
+        )
+    };
+    if !issues.is_empty() {
+        message.push_str(&format!("\n\n**Issues:**\n{}", issues.join("\n")));
+    }
+
+    reply
+        .edit(EditInteractionResponse::new().content(message))
+        .await?;
+
+    Ok(())
+}
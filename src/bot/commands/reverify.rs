@@ -1,13 +1,15 @@
 use crate::bot::Error;
+use crate::keys::{discord_id_of, redis_key};
 use crate::state::{AppState, ReverifyJob, VerificationComplete};
 use redis::AsyncCommands;
 use serenity::all::{
-    CommandInteraction, Context, CreateCommand, CreateMessage, EditInteractionResponse, Permissions,
+    Cache, ChannelId, CommandInteraction, Context, CreateCommand, CreateMessage,
+    EditInteractionResponse, Http, Permissions,
 };
 use std::sync::Arc;
 use std::sync::atomic::Ordering;
 
-use super::utils::{is_admin, load_guild_config, trim_redis_value};
+use super::utils::{Deferred, is_admin, load_guild_config, log_destination, trim_redis_value};
 
 /// Batch size for reverification to avoid Discord rate limits
 const REVERIFY_BATCH_SIZE: usize = 50;
@@ -31,10 +33,9 @@ pub async fn handle(
     let guild_id = match command.guild_id {
         Some(id) => id,
         None => {
-            command.defer_ephemeral(&ctx.http).await?;
-            command
-                .edit_response(
-                    &ctx.http,
+            let reply = Deferred::command(&ctx.http, command).await?;
+            reply
+                .edit(
                     EditInteractionResponse::new()
                         .content("This command can only be used in a server."),
                 )
@@ -45,10 +46,9 @@ pub async fn handle(
 
     // Check if user has administrator permissions
     if !is_admin(ctx, &command.member, guild_id, user.id).await? {
-        command.defer_ephemeral(&ctx.http).await?;
-        command
-            .edit_response(
-                &ctx.http,
+        let reply = Deferred::command(&ctx.http, command).await?;
+        reply
+            .edit(
                 EditInteractionResponse::new()
                     .content("You need administrator permissions to run reverification."),
             )
@@ -62,10 +62,9 @@ pub async fn handle(
         .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
         .is_err()
     {
-        command.defer_ephemeral(&ctx.http).await?;
-        command
-            .edit_response(
-                &ctx.http,
+        let reply = Deferred::command(&ctx.http, command).await?;
+        reply
+            .edit(
                 EditInteractionResponse::new().content(
                     "A reverification is already in progress. Please wait for it to finish.",
                 ),
@@ -75,7 +74,7 @@ pub async fn handle(
     }
 
     // Defer immediately now that we're past the quick checks
-    command.defer_ephemeral(&ctx.http).await?;
+    let reply = Deferred::command(&ctx.http, command).await?;
 
     // Load guild config to get log channel
     let mut conn = state.redis.clone();
@@ -83,7 +82,7 @@ pub async fn handle(
     let log_channel = guild_config.get_log_channel();
 
     // Scan Redis for all verified users: keys are "discord:{user_id}:keycloak"
-    let pattern = "discord:*:keycloak".to_string();
+    let pattern = redis_key!("discord:*:keycloak");
     let keys: Vec<String> = redis::cmd("KEYS")
         .arg(&pattern)
         .query_async(&mut conn)
@@ -92,11 +91,8 @@ pub async fn handle(
     if keys.is_empty() {
         // Clear the flag since we're not actually starting a job
         state.reverify_in_progress.store(false, Ordering::SeqCst);
-        command
-            .edit_response(
-                &ctx.http,
-                EditInteractionResponse::new().content("No verified users found in this server."),
-            )
+        reply
+            .edit(EditInteractionResponse::new().content("No verified users found in this server."))
             .await?;
         return Ok(());
     }
@@ -104,13 +100,7 @@ pub async fn handle(
     // Build VerificationComplete entries for all verified users
     let mut users = Vec::new();
     for key in &keys {
-        // Key format: "discord:{user_id}:keycloak"
-        let parts: Vec<&str> = key.split(':').collect();
-        if parts.len() != 3 {
-            continue;
-        }
-
-        let Ok(user_id_u64) = parts[1].parse::<u64>() else {
+        let Some(user_id_u64) = discord_id_of(key) else {
             continue;
         };
 
@@ -122,6 +112,11 @@ pub async fn handle(
             discord_user_id: serenity::all::UserId::new(user_id_u64),
             guild_id,
             keycloak_user_id,
+            claims: None,
+            locale: String::new(),
+            started_at: None,
+            state_token: None,
+            span: tracing::Span::current(),
         });
     }
 
@@ -129,9 +124,8 @@ pub async fn handle(
     if total_users == 0 {
         // Clear the flag since we're not actually starting a job
         state.reverify_in_progress.store(false, Ordering::SeqCst);
-        command
-            .edit_response(
-                &ctx.http,
+        reply
+            .edit(
                 EditInteractionResponse::new().content("No verified members found in this guild."),
             )
             .await?;
@@ -162,6 +156,8 @@ pub async fn handle(
 
     // Send start message to log channel if configured
     if let Some(channel_id) = log_channel
+        && let Some(channel_id) =
+            log_destination(&ctx.http, &ctx.cache, &mut conn, guild_id, channel_id).await
         && let Err(e) = ctx
             .http
             .send_message(
@@ -181,10 +177,8 @@ pub async fn handle(
     }
 
     // Respond to the interaction
-    command
-        .edit_response(
-            &ctx.http,
-            EditInteractionResponse::new().content(format!(
+    reply
+        .edit(EditInteractionResponse::new().content(format!(
                 "Starting reverification for **{}** users across **{}** batches of up to {}.\n{}",
                 total_users,
                 total_batches,
@@ -195,9 +189,20 @@ pub async fn handle(
                         "Configure a log channel with `/setlogchannel` to receive progress updates."
                             .to_string(),
                 }
-            )),
-        )
+            )))
         .await?;
 
     Ok(())
 }
+
+/// Where a reverify batch posts its progress: the job's log channel, if still writable
+pub async fn progress_channel(
+    http: &Http,
+    cache: &Cache,
+    state: &AppState,
+    job: &ReverifyJob,
+) -> Option<ChannelId> {
+    let channel_id = job.log_channel?;
+    let mut conn = state.redis.clone();
+    log_destination(http, cache, &mut conn, job.guild_id, channel_id).await
+}
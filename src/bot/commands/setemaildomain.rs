@@ -0,0 +1,264 @@
+use crate::bot::Error;
+use crate::keys::redis_key;
+use crate::state::AppState;
+use redis::AsyncCommands;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommand, CreateCommandOption,
+    CreateInteractionResponse, CreateInteractionResponseMessage, Mentionable, ResolvedValue,
+};
+use std::sync::Arc;
+
+use super::utils::{is_admin, load_guild_config, role_assign_problem};
+
+/// Register the setemaildomain command
+pub fn register() -> CreateCommand<'static> {
+    CreateCommand::new("setemaildomain")
+        .description(
+            "Only let members verify with an email from certain domains (omit all to list)",
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "domain",
+                "The email domain to accept, e.g. andrew.cmu.edu",
+            )
+            .required(false),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Role,
+                "role",
+                "A role to give members with an email from this domain",
+            )
+            .required(false),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "remove",
+                "Stop accepting this domain",
+            )
+            .required(false),
+        )
+}
+
+/// Handle the setemaildomain command
+pub async fn handle(
+    ctx: &Context,
+    command: &CommandInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let user = &command.user;
+
+    // Get guild_id from context
+    let guild_id = match command.guild_id {
+        Some(id) => id,
+        None => {
+            let response = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content("This command can only be used in a server.")
+                    .ephemeral(true),
+            );
+            command.create_response(&ctx.http, response).await?;
+            return Ok(());
+        }
+    };
+
+    // Check if user has administrator permissions
+    if !is_admin(ctx, &command.member, guild_id, user.id).await? {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("You need administrator permissions to configure email domains.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    // Get the domain, role and whether to remove it from command options
+    let mut domain = None;
+    let mut role = None;
+    let mut remove = false;
+    for option in command.data.options() {
+        match (option.name, option.value) {
+            ("domain", ResolvedValue::String(d)) => domain = Some(d),
+            ("role", ResolvedValue::Role(r)) => role = Some(r),
+            ("remove", ResolvedValue::Boolean(b)) => remove = b,
+            _ => {}
+        }
+    }
+
+    let mut conn = state.redis.clone();
+    let redis_key = redis_key!("guild:{}:email_domains", guild_id);
+
+    let Some(domain) = domain else {
+        // No domain, list the current ones
+        let content = if role.is_none() && !remove {
+            let guild_config = load_guild_config(&ctx.http, &mut conn, guild_id).await?;
+            let mut domains: Vec<String> = guild_config
+                .email_domains
+                .iter()
+                .map(|(domain, role_id)| match role_id {
+                    Some(role_id) => format!("* `{}`: <@&{}>", domain, role_id),
+                    None => format!("* `{}`", domain),
+                })
+                .collect();
+            domains.sort();
+
+            if domains.is_empty() {
+                "Members can verify with any email. Use `/setemaildomain` with a domain to only accept that domain.".to_string()
+            } else {
+                format!(
+                    "Members can only verify with an email from:\n{}",
+                    domains.join("\n")
+                )
+            }
+        } else {
+            "Domain parameter is required.".to_string()
+        };
+
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content(content)
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    };
+
+    let Some(domain) = normalize_domain(domain) else {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("The domain must be an email domain, e.g. `andrew.cmu.edu`.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    };
+
+    if remove {
+        let _: () = conn.hdel(&redis_key, &domain).await?;
+        let remaining: usize = conn.hlen(&redis_key).await?;
+
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content(if remaining == 0 {
+                    format!(
+                        "`{}` was removed. No domains are required anymore, members can verify with any email.",
+                        domain
+                    )
+                } else {
+                    format!("`{}` is no longer accepted.", domain)
+                })
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    if let Some(role) = role {
+        // Check if it's the @everyone role
+        if role.id.get() == guild_id.get() {
+            let response = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content("You cannot map an email domain to @everyone.")
+                    .ephemeral(true),
+            );
+            command.create_response(&ctx.http, response).await?;
+            return Ok(());
+        }
+
+        // Check if it's a managed role
+        if role.managed() {
+            let response = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content("You cannot use a managed role (bot/integration role) as an email domain role.")
+                    .ephemeral(true),
+            );
+            command.create_response(&ctx.http, response).await?;
+            return Ok(());
+        }
+
+        if let Some(problem) = role_assign_problem(&ctx.http, &ctx.cache, guild_id, role.id).await?
+        {
+            let response = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content(format!(
+                        "I cannot assign {}. {}",
+                        role.mention(),
+                        problem.describe(role.id)
+                    ))
+                    .ephemeral(true),
+            );
+            command.create_response(&ctx.http, response).await?;
+            return Ok(());
+        }
+    }
+
+    // An empty value accepts the domain without a role
+    let role_value = role.map(|r| r.id.to_string()).unwrap_or_default();
+    let _: () = conn.hset(&redis_key, &domain, role_value).await?;
+
+    tracing::info!(
+        "Email domain {} accepted in guild {} with role {:?}",
+        domain,
+        guild_id,
+        role.map(|r| r.id)
+    );
+
+    let message = match role {
+        Some(role) => format!(
+            "Members with an email from `{}` can verify and will get {}. Members with an email from other domains can only verify if their domain is also added.",
+            domain,
+            role.mention()
+        ),
+        None => format!(
+            "Members with an email from `{}` can verify. Members with an email from other domains can only verify if their domain is also added.",
+            domain
+        ),
+    };
+
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(message)
+            .ephemeral(true),
+    );
+    command.create_response(&ctx.http, response).await?;
+
+    Ok(())
+}
+
+/// Lowercase a domain, dropping a leading `@`. `None` if it isn't a plausible domain.
+fn normalize_domain(domain: &str) -> Option<String> {
+    let domain = domain.trim().trim_start_matches('@').to_lowercase();
+    let valid = domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && domain
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-');
+    valid.then_some(domain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_domains() {
+        assert_eq!(
+            normalize_domain(" @Andrew.CMU.edu ").as_deref(),
+            Some("andrew.cmu.edu")
+        );
+        assert_eq!(normalize_domain("cmu.edu").as_deref(), Some("cmu.edu"));
+    }
+
+    #[test]
+    fn rejects_things_that_arent_domains() {
+        assert_eq!(normalize_domain("localhost"), None);
+        assert_eq!(normalize_domain("scotty@cmu.edu"), None);
+        assert_eq!(normalize_domain("cmu.edu."), None);
+        assert_eq!(normalize_domain("cmu edu.org"), None);
+        assert_eq!(normalize_domain(""), None);
+    }
+}
@@ -0,0 +1,110 @@
+use crate::bot::Error;
+use crate::keys::redis_key;
+use crate::state::AppState;
+use redis::AsyncCommands;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommand, CreateCommandOption,
+    CreateInteractionResponse, CreateInteractionResponseMessage, Mentionable, ResolvedValue,
+};
+use std::sync::Arc;
+
+use super::utils::is_admin;
+
+/// Register the setexempt command
+pub fn register() -> CreateCommand<'static> {
+    CreateCommand::new("setexempt")
+        .description("Exempt members with a role, e.g. staff, from unverified purges and reminders")
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::Role, "role", "The role to exempt")
+                .required(true),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "remove",
+                "Stop exempting the role instead",
+            )
+            .required(false),
+        )
+}
+
+/// Handle the setexempt command
+pub async fn handle(
+    ctx: &Context,
+    command: &CommandInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let user = &command.user;
+
+    // Get guild_id from context
+    let guild_id = match command.guild_id {
+        Some(id) => id,
+        None => {
+            let response = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content("This command can only be used in a server.")
+                    .ephemeral(true),
+            );
+            command.create_response(&ctx.http, response).await?;
+            return Ok(());
+        }
+    };
+
+    // Check if user has administrator permissions
+    if !is_admin(ctx, &command.member, guild_id, user.id).await? {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("You need administrator permissions to configure exempt roles.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    // Get the role and remove flag from command options
+    let mut role = None;
+    let mut remove = false;
+    for option in command.data.options() {
+        match (option.name, option.value) {
+            ("role", ResolvedValue::Role(r)) => role = Some(r),
+            ("remove", ResolvedValue::Boolean(b)) => remove = b,
+            _ => {}
+        }
+    }
+
+    let Some(role) = role else {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("Role parameter is required.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    };
+
+    let mut conn = state.redis.clone();
+    let redis_key = redis_key!("guild:{}:exempt_roles", guild_id);
+
+    let message = if remove {
+        let _: () = conn.srem(&redis_key, role.id.to_string()).await?;
+        format!(
+            "Unverified members with {} will be included in purges and reminders again.",
+            role.mention()
+        )
+    } else {
+        let _: () = conn.sadd(&redis_key, role.id.to_string()).await?;
+        format!(
+            "Members with {} will be skipped by `/purgeunverified` and verification reminders.",
+            role.mention()
+        )
+    };
+
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(message)
+            .ephemeral(true),
+    );
+    command.create_response(&ctx.http, response).await?;
+
+    Ok(())
+}
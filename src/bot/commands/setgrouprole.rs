@@ -0,0 +1,181 @@
+use crate::bot::Error;
+use crate::keys::{RoleSlot, guild_role};
+use crate::state::AppState;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommand, CreateCommandOption,
+    CreateInteractionResponse, CreateInteractionResponseMessage, Mentionable, ResolvedValue,
+};
+use std::sync::Arc;
+
+use super::utils::is_admin;
+
+/// Register the setgrouprole command
+pub fn register() -> CreateCommand<'static> {
+    CreateCommand::new("setgrouprole")
+        .description("Map a Keycloak group to a role for Groups mode")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "group",
+                "The Keycloak group name",
+            )
+            .required(true),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Role,
+                "role",
+                "The role to assign to members of the group (omit to remove the mapping)",
+            )
+            .required(false),
+        )
+}
+
+/// Handle the setgrouprole command
+pub async fn handle(
+    ctx: &Context,
+    command: &CommandInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let user = &command.user;
+
+    // Get guild_id from context
+    let guild_id = match command.guild_id {
+        Some(id) => id,
+        None => {
+            let response = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content("This command can only be used in a server.")
+                    .ephemeral(true),
+            );
+            command.create_response(&ctx.http, response).await?;
+            return Ok(());
+        }
+    };
+
+    // Check if user has administrator permissions
+    if !is_admin(ctx, &command.member, guild_id, user.id).await? {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("You need administrator permissions to configure group roles.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    // Get the group and optional role from command options
+    let mut group = None;
+    let mut role = None;
+    for option in command.data.options() {
+        match (option.name, option.value) {
+            ("group", ResolvedValue::String(g)) => group = Some(g.trim().to_string()),
+            ("role", ResolvedValue::Role(r)) => role = Some(r),
+            _ => {}
+        }
+    }
+
+    let Some(group) = group.filter(|g| !g.is_empty()) else {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("Group parameter is required.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    };
+
+    let mut conn = state.redis.clone();
+    let redis_key = guild_role(guild_id, &RoleSlot::Group(group.to_string()));
+
+    // No role given, remove the mapping
+    let Some(role) = role else {
+        redis::cmd("DEL")
+            .arg(&redis_key)
+            .query_async::<()>(&mut conn)
+            .await?;
+
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content(format!("Removed the role mapping for group `{}`.", group))
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    };
+
+    // Check various restrictions on the selected role
+    let bot_user_id = ctx.cache.current_user().id;
+    let bot_member = guild_id.member(&ctx.http, bot_user_id).await?;
+    let guild_roles = guild_id.roles(&ctx.http).await?;
+
+    // Check if it's the @everyone role
+    let everyone_role_id = serenity::all::RoleId::from(guild_id.get());
+    if role.id == everyone_role_id {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("You cannot map a group to @everyone.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    // Check if it's a managed role
+    if role.managed() {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("You cannot use a managed role (bot/integration role) as a group role.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    // Find bot's highest role position
+    let bot_top_role = bot_member
+        .roles
+        .iter()
+        .filter_map(|role_id| guild_roles.get(role_id))
+        .max_by_key(|role| role.position);
+
+    let bot_position = bot_top_role.map(|r| r.position).unwrap_or(0);
+    let target_role_position = guild_roles.get(&role.id).map(|r| r.position).unwrap_or(0);
+
+    if bot_position <= target_role_position {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content(format!(
+                    "I cannot assign {}. My highest role is at position {}, but this role is at position {}.\n\
+                    Please move my role higher than {} in the server settings.",
+                    role.mention(),
+                    bot_position,
+                    target_role_position,
+                    role.mention()
+                ))
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    // Store the role ID in Redis
+    redis::cmd("SET")
+        .arg(&redis_key)
+        .arg(role.id.to_string())
+        .query_async::<()>(&mut conn)
+        .await?;
+
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(format!(
+                "Members of the Keycloak group `{}` will now receive {} when they verify in Groups mode.",
+                group,
+                role.mention()
+            ))
+            .ephemeral(true),
+    );
+    command.create_response(&ctx.http, response).await?;
+
+    Ok(())
+}
@@ -0,0 +1,228 @@
+use crate::bot::Error;
+use crate::keys::{guild_id_of, redis_key};
+use crate::redact::redact;
+use crate::state::AppState;
+use redis::AsyncCommands;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommand, CreateCommandOption,
+    CreateInteractionResponse, CreateInteractionResponseMessage, GuildId, ResolvedOption,
+    ResolvedValue, UserId,
+};
+use std::sync::Arc;
+
+use super::utils::{is_admin, trim_redis_value};
+
+/// Register the setleavegrace command
+pub fn register() -> CreateCommand<'static> {
+    CreateCommand::new("setleavegrace")
+        .description("Forget members' verification in this server some time after they leave")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "hours",
+                "Hours a member can be gone before their verification here is removed (0 disables)",
+            )
+            .min_int_value(0)
+            .max_int_value(720)
+            .required(true),
+        )
+}
+
+/// Handle the setleavegrace command
+pub async fn handle(
+    ctx: &Context,
+    command: &CommandInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let user = &command.user;
+
+    // Get guild_id from context
+    let guild_id = match command.guild_id {
+        Some(id) => id,
+        None => {
+            let response = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content("This command can only be used in a server.")
+                    .ephemeral(true),
+            );
+            command.create_response(&ctx.http, response).await?;
+            return Ok(());
+        }
+    };
+
+    // Check if user has administrator permissions
+    if !is_admin(ctx, &command.member, guild_id, user.id).await? {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("You need administrator permissions to configure the leave grace period.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    // Get the grace period from command options
+    let hours = match command.data.options().first() {
+        Some(ResolvedOption {
+            value: ResolvedValue::Integer(h),
+            ..
+        }) => *h,
+        _ => {
+            let response = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content("Hours parameter is required.")
+                    .ephemeral(true),
+            );
+            command.create_response(&ctx.http, response).await?;
+            return Ok(());
+        }
+    };
+
+    let mut conn = state.redis.clone();
+    let redis_key = redis_key!("guild:{}:leave_grace", guild_id);
+
+    let message = if hours == 0 {
+        // Members already scheduled keep their verification too
+        redis::pipe()
+            .del(&redis_key)
+            .ignore()
+            .del(redis_key!("guild:{}:leave_cleanup", guild_id))
+            .ignore()
+            .query_async::<()>(&mut conn)
+            .await?;
+        "Members will keep their verification in this server after leaving.".to_string()
+    } else {
+        redis::cmd("SET")
+            .arg(&redis_key)
+            .arg(hours.to_string())
+            .query_async::<()>(&mut conn)
+            .await?;
+        format!(
+            "Verified members who leave and don't rejoin within {} hours will have to verify again. \
+            Members who rejoin in time get their roles back.",
+            hours
+        )
+    };
+
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(message)
+            .ephemeral(true),
+    );
+    command.create_response(&ctx.http, response).await?;
+
+    Ok(())
+}
+
+/// Schedule a leaving member's verification in the guild to be forgotten once the grace
+/// period passes. Does nothing unless the guild opted in and the member was verified.
+pub async fn schedule_leave_cleanup(
+    state: &AppState,
+    guild_id: GuildId,
+    user_id: UserId,
+) -> Result<(), Error> {
+    let mut conn = state.redis.clone();
+
+    let Some(grace_secs) = trim_redis_value(
+        conn.get(redis_key!("guild:{}:leave_grace", guild_id))
+            .await?,
+    )
+    .and_then(|s| s.parse::<i64>().ok())
+    .filter(|hours| *hours > 0)
+    .map(|hours| hours * 3600) else {
+        return Ok(());
+    };
+
+    let verified: bool = conn
+        .sismember(
+            redis_key!("guild:{}:verified_members", guild_id),
+            user_id.get(),
+        )
+        .await?;
+    if !verified {
+        return Ok(());
+    }
+
+    let due = chrono::Utc::now().timestamp() + grace_secs;
+    let _: () = conn
+        .zadd(
+            redis_key!("guild:{}:leave_cleanup", guild_id),
+            user_id.get(),
+            due,
+        )
+        .await?;
+
+    tracing::info!(
+        "User {} left guild {}, forgetting their verification in {} hours unless they rejoin",
+        redact(user_id),
+        guild_id,
+        grace_secs / 3600
+    );
+
+    Ok(())
+}
+
+/// Cancel a scheduled cleanup for a member who rejoined. Returns whether one was
+/// pending, i.e. the member came back within the grace period.
+pub async fn cancel_leave_cleanup(
+    conn: &mut redis::aio::ConnectionManager,
+    guild_id: GuildId,
+    user_id: UserId,
+) -> Result<bool, Error> {
+    let removed: usize = conn
+        .zrem(
+            redis_key!("guild:{}:leave_cleanup", guild_id),
+            user_id.get(),
+        )
+        .await?;
+    Ok(removed > 0)
+}
+
+/// Forget the guild verification of members whose grace period ran out. Called
+/// periodically by the bot task. The global Discord to Keycloak link is kept, so
+/// rejoining and running `/verify` takes the already-verified fast path.
+pub async fn run_leave_cleanups(state: &AppState) -> Result<(), Error> {
+    let mut conn = state.redis.clone();
+    let now = chrono::Utc::now().timestamp();
+
+    // Keys are "guild:{guild_id}:leave_cleanup"
+    let mut keys = Vec::new();
+    {
+        let mut iter = conn
+            .scan_match::<_, String>(redis_key!("guild:*:leave_cleanup"))
+            .await?;
+        while let Some(key) = iter.next_item().await {
+            keys.push(key);
+        }
+    }
+
+    for key in keys {
+        let Some(guild_id) = guild_id_of(&key).map(GuildId::new) else {
+            continue;
+        };
+
+        let due: Vec<u64> = conn.zrangebyscore(&key, "-inf", now).await?;
+        for user_id in due.into_iter().map(UserId::new) {
+            redis::pipe()
+                .srem(
+                    redis_key!("guild:{}:verified_members", guild_id),
+                    user_id.get(),
+                )
+                .ignore()
+                .del(redis_key!("guild:{}:awaiting_join:{}", guild_id, user_id))
+                .ignore()
+                .zrem(&key, user_id.get())
+                .ignore()
+                .query_async::<()>(&mut conn)
+                .await?;
+
+            tracing::info!(
+                "Forgot verification of user {} in guild {} after they left",
+                redact(user_id),
+                guild_id
+            );
+        }
+    }
+
+    Ok(())
+}
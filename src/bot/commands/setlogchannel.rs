@@ -1,4 +1,5 @@
 use crate::bot::Error;
+use crate::keys::redis_key;
 use crate::state::AppState;
 use serenity::all::{
     CommandInteraction, CommandOptionType, Context, CreateCommand, CreateCommandOption,
@@ -7,7 +8,7 @@ use serenity::all::{
 };
 use std::sync::Arc;
 
-use super::utils::is_admin;
+use super::utils::{check_log_channel, is_admin};
 
 /// Register the setlogchannel command
 pub fn register() -> CreateCommand<'static> {
@@ -17,7 +18,7 @@ pub fn register() -> CreateCommand<'static> {
             CreateCommandOption::new(
                 CommandOptionType::Channel,
                 "channel",
-                "The channel where verification logs will be sent",
+                "The channel or thread where verification logs will be sent",
             )
             .required(true),
         )
@@ -76,81 +77,57 @@ pub async fn handle(
 
     let channel_id = channel.id();
 
-    // Validate the channel exists and we can send messages to it
-    let full_channel = match ctx.http.get_channel(channel_id).await {
-        Ok(ch) => ch,
-        Err(_) => {
-            let response = CreateInteractionResponse::Message(
-                CreateInteractionResponseMessage::new()
-                    .content("Unable to access that channel. Please make sure the bot has permission to view it.")
-                    .ephemeral(true),
-            );
-            command.create_response(&ctx.http, response).await?;
-            return Ok(());
-        }
-    };
-
-    // Check if it's a guild text channel
-    if !matches!(
-        full_channel,
-        serenity::all::Channel::Guild(ref gc) if matches!(
-                        gc.base.kind,
-            serenity::all::ChannelType::Text | serenity::all::ChannelType::News
-        )
-    ) {
+    // Validate the channel is a text channel we can send messages to
+    if let Some(problem) = check_log_channel(&ctx.http, &ctx.cache, guild_id, channel_id).await? {
         let response = CreateInteractionResponse::Message(
             CreateInteractionResponseMessage::new()
-                .content("The log channel must be a text or news channel.")
+                .content(problem.describe(channel_id))
                 .ephemeral(true),
         );
         command.create_response(&ctx.http, response).await?;
         return Ok(());
     }
 
-    // Check if the bot has permission to send messages in the channel
-    if let serenity::all::Channel::Guild(gc) = &full_channel {
-        let bot_user_id = ctx.cache.current_user().id;
-        let bot_member = guild_id.member(&ctx.http, bot_user_id).await?;
-
-        // Get bot permissions in this channel (scope to drop guild reference before await)
-        let has_permission = {
-            let guild = guild_id
-                .to_guild_cached(&ctx.cache)
-                .ok_or("Guild not in cache")?;
-
-            let permissions = guild.user_permissions_in(gc, &bot_member);
-            permissions.send_messages()
-        };
-
-        if !has_permission {
-            let response = CreateInteractionResponse::Message(
-                CreateInteractionResponseMessage::new()
-                    .content(format!(
-                        "I don't have permission to send messages in {}. Please update my permissions for that channel.",
-                        channel_id.mention()
-                    ))
-                    .ephemeral(true),
-            );
-            command.create_response(&ctx.http, response).await?;
-            return Ok(());
-        }
-    }
+    // Threads also store their parent, where logs go if the thread can't be reopened
+    let parent_id = channel
+        .thread_metadata
+        .is_some()
+        .then_some(channel.parent_id)
+        .flatten();
 
     // Store the channel ID in Redis
     let mut conn = state.redis.clone();
-    let redis_key = format!("guild:{}:log_channel", guild_id);
-
-    redis::cmd("SET")
-        .arg(&redis_key)
-        .arg(channel_id.to_string())
-        .query_async::<()>(&mut conn)
-        .await?;
+    let mut pipe = redis::pipe();
+    pipe.atomic()
+        .set(
+            redis_key!("guild:{}:log_channel", guild_id),
+            channel_id.to_string(),
+        )
+        .ignore();
+    match parent_id {
+        Some(parent_id) => pipe.set(
+            redis_key!("guild:{}:log_thread", guild_id),
+            parent_id.to_string(),
+        ),
+        None => pipe.del(redis_key!("guild:{}:log_thread", guild_id)),
+    }
+    .ignore();
+    pipe.query_async::<()>(&mut conn).await?;
+
+    let fallback = match parent_id {
+        Some(parent_id) => format!(
+            " If the thread auto-archives I'll reopen it, or post in {} when I can't.",
+            parent_id.mention()
+        ),
+        None => String::new(),
+    };
 
     let response = CreateInteractionResponse::Message(
         CreateInteractionResponseMessage::new()
             .content(format!(
-                "Log channel has been set to {}. Verification and unverification events will be logged there.",
-                channel_id.mention()
+                "Log channel has been set to {}. Verification and unverification events will be logged there.{}",
+                channel_id.mention(),
+                fallback
             ))
             .ephemeral(true),
     );
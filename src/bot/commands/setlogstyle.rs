@@ -0,0 +1,419 @@
+use crate::bot::Error;
+use crate::keys::redis_key;
+use crate::state::AppState;
+use redis::AsyncCommands;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommand, CreateCommandOption,
+    CreateEmbed, CreateInteractionResponse, CreateInteractionResponseMessage, GuildId, Mentionable,
+    ResolvedValue, RoleId, UserId,
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::utils::is_admin;
+
+/// Maximum length of a custom log embed title, Discord's embed title limit
+const MAX_TITLE_LENGTH: usize = 256;
+
+/// Maximum length of a custom log embed description
+const MAX_DESCRIPTION_LENGTH: usize = 1000;
+
+/// The log channel events whose embeds can be styled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogEvent {
+    Verified,
+    Unverified,
+}
+
+impl LogEvent {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LogEvent::Verified => "verified",
+            LogEvent::Unverified => "unverified",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "verified" => Some(LogEvent::Verified),
+            "unverified" => Some(LogEvent::Unverified),
+            _ => None,
+        }
+    }
+}
+
+/// A guild's overrides for one log embed. Unset fields keep the built-in defaults.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogStyle {
+    pub color: Option<u32>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+}
+
+impl LogStyle {
+    /// Load the guild's style for an event from `guild:{}:log_style:{event}`
+    pub async fn load(
+        conn: &mut redis::aio::ConnectionManager,
+        guild_id: GuildId,
+        event: LogEvent,
+    ) -> Result<Self, Error> {
+        let fields: HashMap<String, String> = conn
+            .hgetall(redis_key!(
+                "guild:{}:log_style:{}",
+                guild_id,
+                event.as_str()
+            ))
+            .await?;
+
+        Ok(Self {
+            color: fields.get("color").and_then(|c| parse_color(c)),
+            title: fields.get("title").cloned().filter(|t| !t.is_empty()),
+            description: fields.get("description").cloned().filter(|d| !d.is_empty()),
+        })
+    }
+
+    /// Like [`LogStyle::load`], falling back to the default style so a Redis hiccup
+    /// never costs a log message
+    pub async fn load_or_default(
+        conn: &mut redis::aio::ConnectionManager,
+        guild_id: GuildId,
+        event: LogEvent,
+    ) -> Self {
+        Self::load(conn, guild_id, event).await.unwrap_or_else(|e| {
+            tracing::warn!(
+                "Failed to load {} log style for guild {}: {}",
+                event.as_str(),
+                guild_id,
+                e
+            );
+            Self::default()
+        })
+    }
+
+    /// The embed title, rendered from the custom template or the given default
+    pub fn title(&self, default: &str, user_id: UserId, roles: &[RoleId]) -> String {
+        match &self.title {
+            Some(template) => render_log_template(template, user_id, roles),
+            None => default.to_string(),
+        }
+    }
+
+    /// Apply the overrides to a log embed built with the default title and color.
+    /// `roles` fills `{roles}`: the roles added on verify, or removed on unverify.
+    pub fn apply(
+        &self,
+        mut embed: CreateEmbed<'static>,
+        user_id: UserId,
+        roles: &[RoleId],
+    ) -> CreateEmbed<'static> {
+        if let Some(color) = self.color {
+            embed = embed.color(color);
+        }
+        if let Some(template) = &self.title {
+            embed = embed.title(render_log_template(template, user_id, roles));
+        }
+        if let Some(template) = &self.description {
+            embed = embed.description(render_log_template(template, user_id, roles));
+        }
+        embed
+    }
+}
+
+/// Fill a log template's `{user}`, `{roles}` and `{timestamp}` placeholders
+pub fn render_log_template(template: &str, user_id: UserId, roles: &[RoleId]) -> String {
+    let roles = if roles.is_empty() {
+        "None".to_string()
+    } else {
+        roles
+            .iter()
+            .map(|role_id| role_id.mention().to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    template
+        .replace("{user}", &user_id.mention().to_string())
+        .replace("{roles}", &roles)
+        .replace(
+            "{timestamp}",
+            &format!("<t:{}:f>", chrono::Utc::now().timestamp()),
+        )
+}
+
+/// Parse a hex color like `#A6E3A1` or `a6e3a1`
+pub fn parse_color(s: &str) -> Option<u32> {
+    let hex = s.trim().trim_start_matches('#');
+    if hex.len() != 6 {
+        return None;
+    }
+    u32::from_str_radix(hex, 16).ok()
+}
+
+/// Register the setlogstyle command
+pub fn register() -> CreateCommand<'static> {
+    CreateCommand::new("setlogstyle")
+        .description("Customize the color and text of log channel embeds")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "event",
+                "Which log embed to style",
+            )
+            .add_string_choice("User verified", "verified")
+            .add_string_choice("User unverified", "unverified")
+            .required(true),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "color",
+                "Hex color, e.g. #A6E3A1",
+            )
+            .max_length(7)
+            .required(false),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "title",
+                "Title template, {user} {roles} {timestamp} are filled in",
+            )
+            .max_length(MAX_TITLE_LENGTH as u16)
+            .required(false),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "description",
+                "Description template, {user} {roles} {timestamp} are filled in",
+            )
+            .max_length(MAX_DESCRIPTION_LENGTH as u16)
+            .required(false),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "reset",
+                "Go back to the default style for this event",
+            )
+            .required(false),
+        )
+}
+
+/// Handle the setlogstyle command
+pub async fn handle(
+    ctx: &Context,
+    command: &CommandInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let user = &command.user;
+
+    // Get guild_id from context
+    let guild_id = match command.guild_id {
+        Some(id) => id,
+        None => {
+            let response = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content("This command can only be used in a server.")
+                    .ephemeral(true),
+            );
+            command.create_response(&ctx.http, response).await?;
+            return Ok(());
+        }
+    };
+
+    // Check if user has administrator permissions
+    if !is_admin(ctx, &command.member, guild_id, user.id).await? {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("You need administrator permissions to style log messages.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    // Get the event and overrides from command options
+    let mut event = None;
+    let mut color = None;
+    let mut title = None;
+    let mut description = None;
+    let mut reset = false;
+    for option in command.data.options() {
+        match (option.name, option.value) {
+            ("event", ResolvedValue::String(s)) => event = LogEvent::parse(s),
+            ("color", ResolvedValue::String(s)) => color = Some(s.trim().to_string()),
+            ("title", ResolvedValue::String(s)) => title = Some(s.trim().to_string()),
+            ("description", ResolvedValue::String(s)) => {
+                description = Some(s.trim().replace("\\n", "\n"))
+            }
+            ("reset", ResolvedValue::Boolean(b)) => reset = b,
+            _ => {}
+        }
+    }
+
+    let Some(event) = event else {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("Event parameter is required.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    };
+
+    let mut conn = state.redis.clone();
+    let redis_key = redis_key!("guild:{}:log_style:{}", guild_id, event.as_str());
+
+    let message = if reset {
+        redis::cmd("DEL")
+            .arg(&redis_key)
+            .query_async::<()>(&mut conn)
+            .await?;
+        format!(
+            "The {} log embed has been reset to the default style.",
+            event.as_str()
+        )
+    } else if color.is_none() && title.is_none() && description.is_none() {
+        let style = LogStyle::load(&mut conn, guild_id, event).await?;
+        describe_style(event, &style)
+    } else {
+        let parsed_color = match color.as_deref().map(parse_color) {
+            Some(None) => {
+                let response = CreateInteractionResponse::Message(
+                    CreateInteractionResponseMessage::new()
+                        .content("Colors must be six hex digits, like `#A6E3A1`.")
+                        .ephemeral(true),
+                );
+                command.create_response(&ctx.http, response).await?;
+                return Ok(());
+            }
+            Some(Some(c)) => Some(c),
+            None => None,
+        };
+
+        let mut fields: Vec<(&str, String)> = Vec::new();
+        if let Some(c) = parsed_color {
+            fields.push(("color", format!("{:06X}", c)));
+        }
+        if let Some(t) = title.filter(|t| !t.is_empty()) {
+            fields.push(("title", t));
+        }
+        if let Some(d) = description.filter(|d| !d.is_empty()) {
+            fields.push(("description", d));
+        }
+
+        if !fields.is_empty() {
+            let _: () = conn.hset_multiple(&redis_key, &fields).await?;
+        }
+
+        let style = LogStyle::load(&mut conn, guild_id, event).await?;
+        format!(
+            "Updated the {} log embed. Run `/testlog` to preview it.\n\n{}",
+            event.as_str(),
+            describe_style(event, &style)
+        )
+    };
+
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(message)
+            .ephemeral(true),
+    );
+    command.create_response(&ctx.http, response).await?;
+
+    Ok(())
+}
+
+/// Summarize an event's current style for command replies
+fn describe_style(event: LogEvent, style: &LogStyle) -> String {
+    if *style == LogStyle::default() {
+        return format!("The {} log embed uses the default style.", event.as_str());
+    }
+
+    let mut lines = vec![format!("**{} log embed**", event.as_str())];
+    lines.push(format!(
+        "Color: {}",
+        style
+            .color
+            .map(|c| format!("`#{:06X}`", c))
+            .unwrap_or_else(|| "Default".to_string())
+    ));
+    lines.push(format!(
+        "Title: {}",
+        style
+            .title
+            .as_deref()
+            .map(|t| format!("`{}`", t))
+            .unwrap_or_else(|| "Default".to_string())
+    ));
+    lines.push(format!(
+        "Description: {}",
+        style
+            .description
+            .as_deref()
+            .map(|d| format!("`{}`", d))
+            .unwrap_or_else(|| "None".to_string())
+    ));
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hex_colors() {
+        assert_eq!(parse_color("#A6E3A1"), Some(0xA6E3A1));
+        assert_eq!(parse_color("f38ba8"), Some(0xF38BA8));
+        assert_eq!(parse_color(" #fab387 "), Some(0xFAB387));
+    }
+
+    #[test]
+    fn rejects_malformed_colors() {
+        assert_eq!(parse_color(""), None);
+        assert_eq!(parse_color("#FFF"), None);
+        assert_eq!(parse_color("#GGGGGG"), None);
+        assert_eq!(parse_color("#A6E3A1FF"), None);
+    }
+
+    #[test]
+    fn renders_user_and_roles() {
+        let rendered = render_log_template(
+            "{user} got {roles}",
+            UserId::new(1),
+            &[RoleId::new(2), RoleId::new(3)],
+        );
+        assert_eq!(rendered, "<@1> got <@&2>, <@&3>");
+    }
+
+    #[test]
+    fn renders_no_roles_as_none() {
+        assert_eq!(render_log_template("{roles}", UserId::new(1), &[]), "None");
+    }
+
+    #[test]
+    fn renders_timestamp_as_discord_timestamp() {
+        let rendered = render_log_template("at {timestamp}", UserId::new(1), &[]);
+        assert!(rendered.starts_with("at <t:"));
+        assert!(rendered.ends_with(":f>"));
+    }
+
+    #[test]
+    fn custom_title_falls_back_to_default() {
+        let style = LogStyle::default();
+        assert_eq!(
+            style.title("User Verified", UserId::new(1), &[]),
+            "User Verified"
+        );
+
+        let style = LogStyle {
+            title: Some("Welcome {user}".to_string()),
+            ..LogStyle::default()
+        };
+        assert_eq!(
+            style.title("User Verified", UserId::new(1), &[]),
+            "Welcome <@1>"
+        );
+    }
+}
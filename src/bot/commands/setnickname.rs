@@ -0,0 +1,108 @@
+use crate::bot::Error;
+use crate::keys::redis_key;
+use crate::state::AppState;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommand, CreateCommandOption,
+    CreateInteractionResponse, CreateInteractionResponseMessage, ResolvedOption, ResolvedValue,
+};
+use std::sync::Arc;
+
+use super::utils::is_admin;
+
+/// Maximum length of a nickname template, placeholders expand afterwards and the
+/// result is cut to Discord's nickname limit
+pub const MAX_NICK_TEMPLATE_LENGTH: usize = 64;
+
+/// Register the setnickname command
+pub fn register() -> CreateCommand<'static> {
+    CreateCommand::new("setnickname")
+        .description("Set the nickname given to members when they verify")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "template",
+                "e.g. \"[V] {name}\" or \"{first} {last}\", placeholders {first} {last} {name} (omit to disable)",
+            )
+            .max_length(MAX_NICK_TEMPLATE_LENGTH as u16)
+            .required(false),
+        )
+}
+
+/// Handle the setnickname command
+pub async fn handle(
+    ctx: &Context,
+    command: &CommandInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let user = &command.user;
+
+    // Get guild_id from context
+    let guild_id = match command.guild_id {
+        Some(id) => id,
+        None => {
+            let response = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content("This command can only be used in a server.")
+                    .ephemeral(true),
+            );
+            command.create_response(&ctx.http, response).await?;
+            return Ok(());
+        }
+    };
+
+    // Check if user has administrator permissions
+    if !is_admin(ctx, &command.member, guild_id, user.id).await? {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("You need administrator permissions to set the verified nickname.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    // Get the optional template from command options
+    let template = match command.data.options().first() {
+        Some(ResolvedOption {
+            value: ResolvedValue::String(s),
+            ..
+        }) => Some(s.trim().to_string()),
+        _ => None,
+    };
+
+    let mut conn = state.redis.clone();
+    let redis_key = redis_key!("guild:{}:nick_template", guild_id);
+
+    let message = match template.filter(|t| !t.is_empty()) {
+        None => {
+            redis::cmd("DEL")
+                .arg(&redis_key)
+                .query_async::<()>(&mut conn)
+                .await?;
+            "Nicknames are no longer changed on verification.".to_string()
+        }
+        Some(template) => {
+            redis::cmd("SET")
+                .arg(&redis_key)
+                .arg(&template)
+                .query_async::<()>(&mut conn)
+                .await?;
+            let preview = super::verify::render_nickname(&template, "Scotty", "Dog", "scotty")
+                .unwrap_or_default();
+            format!(
+                "Verified members will be renamed using `{}`, e.g. **{}**.\n\n\
+                The server owner's nickname can't be changed by bots and is skipped.",
+                template, preview
+            )
+        }
+    };
+
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(message)
+            .ephemeral(true),
+    );
+    command.create_response(&ctx.http, response).await?;
+
+    Ok(())
+}
@@ -0,0 +1,217 @@
+use crate::bot::Error;
+use crate::bot::guild_config::GuildConfig;
+use crate::bot::i18n::{self, Locale};
+use crate::keys::{guild_id_of, redis_key};
+use crate::redact::redact;
+use crate::state::AppState;
+use redis::AsyncCommands;
+use serenity::all::{
+    Cache, CommandInteraction, CommandOptionType, Context, CreateCommand, CreateCommandOption,
+    CreateInteractionResponse, CreateInteractionResponseMessage, CreateMessage, GuildId, Http,
+    ResolvedOption, ResolvedValue,
+};
+use std::sync::Arc;
+
+use super::utils::{is_admin, send_dm, trim_redis_value, unverified_members_cached};
+
+/// Register the setreminderinterval command
+pub fn register() -> CreateCommand<'static> {
+    CreateCommand::new("setreminderinterval")
+        .description("Periodically DM unverified members a reminder to verify")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "hours",
+                "Hours between reminders for each member (0 disables reminders)",
+            )
+            .min_int_value(0)
+            .max_int_value(720)
+            .required(true),
+        )
+}
+
+/// Handle the setreminderinterval command
+pub async fn handle(
+    ctx: &Context,
+    command: &CommandInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let user = &command.user;
+
+    // Get guild_id from context
+    let guild_id = match command.guild_id {
+        Some(id) => id,
+        None => {
+            let response = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content("This command can only be used in a server.")
+                    .ephemeral(true),
+            );
+            command.create_response(&ctx.http, response).await?;
+            return Ok(());
+        }
+    };
+
+    // Check if user has administrator permissions
+    if !is_admin(ctx, &command.member, guild_id, user.id).await? {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("You need administrator permissions to configure verification reminders.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    // Get the interval from command options
+    let hours = match command.data.options().first() {
+        Some(ResolvedOption {
+            value: ResolvedValue::Integer(h),
+            ..
+        }) => *h,
+        _ => {
+            let response = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content("Hours parameter is required.")
+                    .ephemeral(true),
+            );
+            command.create_response(&ctx.http, response).await?;
+            return Ok(());
+        }
+    };
+
+    let mut conn = state.redis.clone();
+    let redis_key = redis_key!("guild:{}:reminder_interval", guild_id);
+
+    let message = if hours == 0 {
+        redis::cmd("DEL")
+            .arg(&redis_key)
+            .query_async::<()>(&mut conn)
+            .await?;
+        "Verification reminders have been disabled.".to_string()
+    } else {
+        redis::cmd("SET")
+            .arg(&redis_key)
+            .arg(hours.to_string())
+            .query_async::<()>(&mut conn)
+            .await?;
+        format!(
+            "Unverified members will now be reminded to verify at most once every {} hours.",
+            hours
+        )
+    };
+
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(message)
+            .ephemeral(true),
+    );
+    command.create_response(&ctx.http, response).await?;
+
+    Ok(())
+}
+
+/// DM a `/verify` reminder to unverified members of every guild that opted in.
+/// Called periodically by the bot task. Each member is reminded at most once per
+/// the guild's configured interval, tracked by a last-reminded timestamp in Redis.
+pub async fn send_reminders(http: &Http, cache: &Cache, state: &AppState) -> Result<(), Error> {
+    let mut conn = state.redis.clone();
+
+    // Keys are "guild:{guild_id}:reminder_interval"
+    let mut keys = Vec::new();
+    {
+        let mut iter = conn
+            .scan_match::<_, String>(redis_key!("guild:*:reminder_interval"))
+            .await?;
+        while let Some(key) = iter.next_item().await {
+            keys.push(key);
+        }
+    }
+
+    for key in keys {
+        let Some(guild_id) = guild_id_of(&key).map(GuildId::new) else {
+            continue;
+        };
+
+        let Some(interval_secs) = trim_redis_value(conn.get(&key).await?)
+            .and_then(|s| s.parse::<i64>().ok())
+            .filter(|hours| *hours > 0)
+            .map(|hours| hours * 3600)
+        else {
+            continue;
+        };
+
+        let guild_config = match GuildConfig::load(&mut conn, http, guild_id).await {
+            Ok(config) => config,
+            Err(e) => {
+                tracing::warn!("Failed to load config for guild {}: {}", guild_id, e);
+                continue;
+            }
+        };
+
+        let Some(verified_role) = guild_config.verified_role else {
+            continue;
+        };
+
+        let guild_name = guild_id
+            .to_guild_cached(cache)
+            .map(|g| g.name.to_string())
+            .unwrap_or_else(|| "this server".to_string());
+        let locale = guild_id
+            .to_guild_cached(cache)
+            .map(|g| Locale::from_discord(&g.preferred_locale))
+            .unwrap_or_default();
+
+        let now = chrono::Utc::now().timestamp();
+
+        for user_id in unverified_members_cached(
+            guild_id,
+            cache,
+            verified_role,
+            &guild_config.exempt_roles,
+            None,
+        ) {
+            let reminded_key = redis_key!("guild:{}:reminded:{}", guild_id, user_id);
+            let last_reminded = trim_redis_value(conn.get(&reminded_key).await?)
+                .and_then(|s| s.parse::<i64>().ok());
+
+            if last_reminded.is_some_and(|t| now - t < interval_secs) {
+                continue;
+            }
+
+            if let Err(e) = send_dm(
+                http,
+                &mut conn,
+                user_id,
+                CreateMessage::new().content(i18n::reminder_dm(
+                    locale,
+                    &state.config.identity_label,
+                    &guild_name,
+                )),
+            )
+            .await
+            {
+                // Usually closed DMs, don't retry until the next interval
+                tracing::debug!(
+                    "Failed to send verification reminder to user {}: {}",
+                    redact(user_id),
+                    e
+                );
+            }
+
+            // Record the attempt either way so closed DMs aren't retried immediately
+            redis::cmd("SET")
+                .arg(&reminded_key)
+                .arg(now.to_string())
+                .arg("EX")
+                .arg(interval_secs)
+                .query_async::<()>(&mut conn)
+                .await?;
+
+            // Sleep between users to stay well under Discord's rate limit
+            tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,425 @@
+use crate::bot::Error;
+use crate::bot::discord::DiscordApi;
+use crate::bot::guild_config::GuildConfig;
+use crate::keys::{discord_keycloak, guild_id_of, redis_key};
+use crate::redact::redact;
+use crate::state::AppState;
+use redis::AsyncCommands;
+use serenity::all::{
+    Cache, CommandInteraction, CommandOptionType, Context, CreateCommand, CreateCommandOption,
+    CreateEmbed, CreateInteractionResponse, CreateInteractionResponseMessage, CreateMessage,
+    GuildId, Http, ResolvedValue, RoleId, UserId,
+};
+use std::sync::Arc;
+
+use super::utils::{is_admin, log_destination, trim_redis_value};
+use super::verify::{
+    cached_attributes, fetch_role_inputs, swap_in_verified_role, wanted_managed_roles,
+};
+
+/// Guilds with more verified members than this need `force:true` to turn role sync on,
+/// since each sync walks every verified member
+const LARGE_GUILD_VERIFIED_MEMBERS: usize = 5000;
+
+/// What a guild's role sync found and fixed
+#[derive(Debug, Default)]
+struct RoleSyncSummary {
+    checked: usize,
+    /// Members who were missing at least one role
+    repaired: usize,
+    roles_added: usize,
+    roles_removed: usize,
+    failed: usize,
+}
+
+/// Register the setrolesync command
+pub fn register() -> CreateCommand<'static> {
+    CreateCommand::new("setrolesync")
+        .description("Periodically give verified members back roles they're missing")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "hours",
+                "Hours between syncs (0 disables)",
+            )
+            .min_int_value(0)
+            .max_int_value(720)
+            .required(true),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "force",
+                "Turn it on even in a large server",
+            )
+            .required(false),
+        )
+}
+
+/// Handle the setrolesync command
+pub async fn handle(
+    ctx: &Context,
+    command: &CommandInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let user = &command.user;
+
+    // Get guild_id from context
+    let guild_id = match command.guild_id {
+        Some(id) => id,
+        None => {
+            let response = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content("This command can only be used in a server.")
+                    .ephemeral(true),
+            );
+            command.create_response(&ctx.http, response).await?;
+            return Ok(());
+        }
+    };
+
+    // Check if user has administrator permissions
+    if !is_admin(ctx, &command.member, guild_id, user.id).await? {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("You need administrator permissions to configure role sync.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    // Get the interval from command options
+    let mut hours = None;
+    let mut force = false;
+    for option in command.data.options() {
+        match (option.name, option.value) {
+            ("hours", ResolvedValue::Integer(h)) => hours = Some(h),
+            ("force", ResolvedValue::Boolean(b)) => force = b,
+            _ => {}
+        }
+    }
+
+    let Some(hours) = hours else {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("Hours parameter is required.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    };
+
+    let mut conn = state.redis.clone();
+    let redis_key = redis_key!("guild:{}:role_sync_interval", guild_id);
+
+    let message = if hours == 0 {
+        redis::cmd("DEL")
+            .arg(&redis_key)
+            .query_async::<()>(&mut conn)
+            .await?;
+        "Role sync has been disabled.".to_string()
+    } else {
+        let verified_members: usize = conn
+            .scard(redis_key!("guild:{}:verified_members", guild_id))
+            .await?;
+        if verified_members > LARGE_GUILD_VERIFIED_MEMBERS && !force {
+            let response = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content(format!(
+                        "This server has {} verified members. Each sync checks all of them and \
+                        can take a long time, so role sync is off for servers this large. \
+                        Run it again with `force:true` to turn it on anyway.",
+                        verified_members
+                    ))
+                    .ephemeral(true),
+            );
+            command.create_response(&ctx.http, response).await?;
+            return Ok(());
+        }
+
+        redis::cmd("SET")
+            .arg(&redis_key)
+            .arg(hours.to_string())
+            .query_async::<()>(&mut conn)
+            .await?;
+        format!(
+            "Every {} hours, verified members missing the verified role or their level and \
+            class roles will get them back. Restored roles are posted in the log channel.",
+            hours
+        )
+    };
+
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(message)
+            .ephemeral(true),
+    );
+    command.create_response(&ctx.http, response).await?;
+
+    Ok(())
+}
+
+/// Sync roles in every guild that opted in and is due. Called periodically by the bot
+/// task, each guild runs at most once per its configured interval.
+pub async fn run_role_syncs(http: &Http, cache: &Cache, state: &AppState) -> Result<(), Error> {
+    let mut conn = state.redis.clone();
+
+    // Keys are "guild:{guild_id}:role_sync_interval"
+    let mut keys = Vec::new();
+    {
+        let mut iter = conn
+            .scan_match::<_, String>(redis_key!("guild:*:role_sync_interval"))
+            .await?;
+        while let Some(key) = iter.next_item().await {
+            keys.push(key);
+        }
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    for key in keys {
+        let Some(guild_id) = guild_id_of(&key).map(GuildId::new) else {
+            continue;
+        };
+
+        let Some(interval_secs) = trim_redis_value(conn.get(&key).await?)
+            .and_then(|s| s.parse::<i64>().ok())
+            .filter(|hours| *hours > 0)
+            .map(|hours| hours * 3600)
+        else {
+            continue;
+        };
+
+        // Claim the run before starting, so a sync that fails partway isn't retried
+        // until the next interval
+        let last_key = redis_key!("guild:{}:role_sync_last", guild_id);
+        let last_run =
+            trim_redis_value(conn.get(&last_key).await?).and_then(|s| s.parse::<i64>().ok());
+        if last_run.is_some_and(|t| now - t < interval_secs) {
+            continue;
+        }
+        let _: () = conn.set(&last_key, now).await?;
+
+        let guild_config = match GuildConfig::load(&mut conn, http, guild_id).await {
+            Ok(config) => config,
+            Err(e) => {
+                tracing::warn!("Failed to load config for guild {}: {}", guild_id, e);
+                continue;
+            }
+        };
+
+        let summary = match sync_guild_roles(http, cache, state, &guild_config).await {
+            Ok(summary) => summary,
+            Err(e) => {
+                tracing::warn!("Failed to sync roles in guild {}: {}", guild_id, e);
+                continue;
+            }
+        };
+
+        tracing::info!(
+            "Role sync for guild {}: checked {}, repaired {} ({} added, {} removed), failed {}",
+            guild_id,
+            summary.checked,
+            summary.repaired,
+            summary.roles_added,
+            summary.roles_removed,
+            summary.failed
+        );
+
+        if summary.repaired > 0 || summary.failed > 0 {
+            log_summary(http, cache, &mut conn, &guild_config, &summary).await;
+        }
+    }
+
+    Ok(())
+}
+
+/// Give each of a guild's verified members the verified role and the level and class
+/// roles their attributes map to, if they're missing any. The only role removed is the
+/// unverified role, since `/resync` is how admins apply attribute changes.
+async fn sync_guild_roles(
+    http: &Http,
+    cache: &Cache,
+    state: &AppState,
+    guild_config: &GuildConfig,
+) -> Result<RoleSyncSummary, Error> {
+    let guild_id = guild_config.guild_id;
+    let mut summary = RoleSyncSummary::default();
+
+    let Some(verified_role) = guild_config.verified_role else {
+        return Ok(summary);
+    };
+
+    let mut conn = state.redis.clone();
+    let members: Vec<u64> = conn
+        .smembers(redis_key!("guild:{}:verified_members", guild_id))
+        .await?;
+
+    let reads_attributes =
+        guild_config.should_assign_level_roles() || guild_config.should_assign_class_roles();
+
+    for user_id in members.into_iter().map(UserId::new) {
+        // Members who left, or aren't cached yet, are skipped
+        let Some(member_roles) = guild_id
+            .to_guild_cached(cache)
+            .and_then(|guild| guild.members.get(&user_id).map(|m| m.roles.to_vec()))
+        else {
+            continue;
+        };
+        summary.checked += 1;
+
+        let mut wanted = Vec::new();
+        if reads_attributes
+            && let Some(keycloak_user_id) =
+                trim_redis_value(conn.get(discord_keycloak(user_id)).await?)
+        {
+            // Attributes cached at the last verification save a Keycloak request
+            let claims = cached_attributes(&mut conn, &keycloak_user_id).await;
+            let mut issues = Vec::new();
+            match fetch_role_inputs(
+                state,
+                guild_config,
+                &keycloak_user_id,
+                claims.as_ref(),
+                &mut issues,
+            )
+            .await
+            {
+                Ok((attributes, _)) => wanted.extend(
+                    wanted_managed_roles(
+                        guild_config,
+                        attributes.as_ref(),
+                        &[],
+                        None,
+                        &state.config,
+                    )
+                    .into_iter()
+                    .filter(|(_, kind, _)| matches!(*kind, "level" | "class"))
+                    .map(|(role_id, _, _)| role_id),
+                ),
+                Err(e) => {
+                    tracing::debug!(
+                        "Failed to fetch attributes of user {}: {}",
+                        redact(user_id),
+                        e
+                    );
+                }
+            }
+        }
+
+        let missing: Vec<RoleId> = wanted
+            .into_iter()
+            .filter(|role_id| !member_roles.contains(role_id))
+            .collect();
+        let missing_verified_role = !member_roles.contains(&verified_role);
+        let holds_unverified_role = guild_config
+            .unverified_role
+            .is_some_and(|role_id| member_roles.contains(&role_id));
+        if missing.is_empty() && !missing_verified_role && !holds_unverified_role {
+            continue;
+        }
+
+        summary.repaired += 1;
+        // The same swap as verification, so a member never keeps the unverified role
+        // next to the verified one
+        if missing_verified_role || holds_unverified_role {
+            match swap_in_verified_role(http, guild_config, user_id, &member_roles, verified_role)
+                .await
+            {
+                Ok(removed) => {
+                    tracing::info!(
+                        "Restored verified role {} to user {} in guild {}",
+                        verified_role,
+                        redact(user_id),
+                        guild_id
+                    );
+                    if missing_verified_role {
+                        summary.roles_added += 1;
+                    }
+                    if removed.is_some() {
+                        summary.roles_removed += 1;
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        "Failed to restore verified role {} to user {}: {}",
+                        verified_role,
+                        redact(user_id),
+                        e
+                    );
+                    summary.failed += 1;
+                }
+            }
+        }
+        for role_id in missing {
+            match DiscordApi::add_role(http, guild_id, user_id, role_id).await {
+                Ok(()) => {
+                    tracing::info!(
+                        "Restored role {} to user {} in guild {}",
+                        role_id,
+                        redact(user_id),
+                        guild_id
+                    );
+                    summary.roles_added += 1;
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        "Failed to restore role {} to user {}: {}",
+                        role_id,
+                        redact(user_id),
+                        e
+                    );
+                    summary.failed += 1;
+                }
+            }
+        }
+
+        // Sleep between users to stay well under Discord's rate limit
+        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+    }
+
+    Ok(summary)
+}
+
+/// Post a role sync's summary to the guild's log channel, if it has one
+async fn log_summary(
+    http: &Http,
+    cache: &Cache,
+    conn: &mut redis::aio::ConnectionManager,
+    guild_config: &GuildConfig,
+    summary: &RoleSyncSummary,
+) {
+    let guild_id = guild_config.guild_id;
+    let Some(channel_id) = guild_config.get_log_channel() else {
+        return;
+    };
+    let Some(channel_id) = log_destination(http, cache, conn, guild_id, channel_id).await else {
+        return;
+    };
+
+    let embed = CreateEmbed::new()
+        .title("Roles Restored")
+        .description("Verified members were missing roles and were given them back.")
+        .color(0x89B4FA) // Blue
+        .field("Members Checked", summary.checked.to_string(), true)
+        .field("Members Repaired", summary.repaired.to_string(), true)
+        .field("Roles Added", summary.roles_added.to_string(), true)
+        .field("Roles Removed", summary.roles_removed.to_string(), true)
+        .field("Failed", summary.failed.to_string(), true)
+        .timestamp(chrono::Utc::now());
+
+    if let Err(e) = http
+        .send_message(
+            channel_id.into(),
+            Vec::new(),
+            &CreateMessage::new().embed(embed),
+        )
+        .await
+    {
+        tracing::warn!(
+            "Failed to send role sync log to channel {}: {}",
+            channel_id,
+            e
+        );
+    }
+}
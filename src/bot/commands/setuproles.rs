@@ -1,16 +1,22 @@
 use crate::bot::Error;
-use crate::state::AppState;
+use crate::bot::guild_config::RoleMode;
+use crate::keys::redis_key;
+use crate::state::{AppState, SetupRolesSession};
 use redis::AsyncCommands;
 use serenity::all::{
     ButtonStyle, CommandInteraction, ComponentInteraction, ComponentInteractionDataKind, Context,
     CreateActionRow, CreateButton, CreateCommand, CreateComponent, CreateContainer,
     CreateContainerComponent, CreateInteractionResponse, CreateInteractionResponseMessage,
     CreateSelectMenu, CreateSelectMenuKind, CreateSelectMenuOption, CreateTextDisplay,
-    MessageFlags,
+    EditInteractionResponse, MessageFlags,
 };
 use std::sync::Arc;
+use uuid::Uuid;
 
-use super::utils::is_admin;
+use super::utils::{Deferred, is_admin};
+
+const SESSION_EXPIRED: &str = "Session expired. Please run `/setuproles` again.";
+const MENU_OUTDATED: &str = "This menu is outdated. Please run `/setuproles` again.";
 
 /// Register the setuproles command
 pub fn register() -> CreateCommand<'static> {
@@ -53,7 +59,7 @@ pub async fn handle(
 
     // Get current configuration from Redis
     let mut conn = state.redis.clone();
-    let role_mode_key = format!("guild:{}:role_mode", guild_id);
+    let role_mode_key = redis_key!("guild:{}:role_mode", guild_id);
     let current_mode: Option<String> = conn.get(&role_mode_key).await?;
     let current_mode = current_mode.unwrap_or_else(|| "none".to_string());
 
@@ -75,6 +81,9 @@ pub async fn handle(
                 // different custom mode from custom mode
                 CreateSelectMenuOption::new("Custom", "custom")
                     .description("Choose which levels and classes to assign"),
+                CreateSelectMenuOption::new("Groups", "groups")
+                    .description("Assign roles mapped to Keycloak groups with /setgrouprole")
+                    .default_selection(current_mode == "groups"),
             ]
             .into(),
         },
@@ -111,7 +120,7 @@ pub async fn handle_component(
 
     if custom_id == "role_mode_select" {
         handle_mode_selection(ctx, interaction, state).await
-    } else if custom_id == "custom_roles_multiselect" {
+    } else if custom_id.starts_with("custom_roles_multiselect:") {
         handle_custom_roles_selection(ctx, interaction, state).await
     } else if custom_id.starts_with("save_roles_button:") {
         handle_save_roles(ctx, interaction, state).await
@@ -133,16 +142,17 @@ async fn handle_mode_selection(
 
     // Get the selected mode
     let selected_mode = match &interaction.data.kind {
-        ComponentInteractionDataKind::StringSelect { values } => {
-            values.first().map(|s| s.as_str()).unwrap_or("none")
-        }
-        _ => "none",
+        ComponentInteractionDataKind::StringSelect { values } => values
+            .first()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(RoleMode::None),
+        _ => RoleMode::None,
     };
 
     // For "none" mode, just save it and show confirmation
-    if selected_mode == "none" {
+    if selected_mode == RoleMode::None {
         let mut conn = state.redis.clone();
-        let role_mode_key = format!("guild:{}:role_mode", guild_id);
+        let role_mode_key = redis_key!("guild:{}:role_mode", guild_id);
         redis::cmd("SET")
             .arg(&role_mode_key)
             .arg("none")
@@ -167,23 +177,51 @@ async fn handle_mode_selection(
         return Ok(());
     }
 
-    // Create a new session for this mode
+    // Group roles are mapped individually, so just save the mode
+    if selected_mode == RoleMode::Groups {
+        let mut conn = state.redis.clone();
+        let role_mode_key = redis_key!("guild:{}:role_mode", guild_id);
+        redis::cmd("SET")
+            .arg(&role_mode_key)
+            .arg("groups")
+            .query_async::<()>(&mut conn)
+            .await?;
+
+        let container = CreateContainer::new(vec![
+            CreateContainerComponent::TextDisplay(CreateTextDisplay::new("# Mode Updated")),
+            CreateContainerComponent::TextDisplay(CreateTextDisplay::new(
+                "Role assignment mode has been set to **Groups**.\n\n\
+                Use `/setgrouprole` to map Keycloak groups to roles. \
+                Users only receive roles for groups that have been mapped.",
+            )),
+        ]);
+
+        let response = CreateInteractionResponse::UpdateMessage(
+            CreateInteractionResponseMessage::new()
+                .components(vec![CreateComponent::Container(container)])
+                .flags(MessageFlags::EPHEMERAL | MessageFlags::IS_COMPONENTS_V2),
+        );
+
+        interaction.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    // Create a new session for this mode, replacing any earlier one for this admin
+    let session = SetupRolesSession::new(selected_mode.clone());
+    let nonce = session.nonce;
     {
         let mut sessions = state.setuproles_sessions.write().await;
-        sessions.insert(
-            (guild_id, interaction.user.id),
-            crate::state::SetupRolesSession::new(selected_mode.to_string()),
-        );
+        sessions.insert((guild_id, interaction.user.id), session);
     }
 
     // Determine what roles will be created
     let (mode_name, mode_description, roles_to_create) = match selected_mode {
-        "levels" => (
+        RoleMode::Levels => (
             "Levels Mode",
             "The following roles will be created:\n\n",
             vec!["Undergrad", "Graduate"],
         ),
-        "classes" => (
+        RoleMode::Classes => (
             "Classes Mode",
             "The following roles will be created:\n\n",
             vec![
@@ -196,11 +234,11 @@ async fn handle_mode_selection(
                 "Doctoral",
             ],
         ),
-        "custom" => {
+        RoleMode::Custom => {
             // For custom mode, show a multiselect instead
-            return handle_custom_mode_selection(ctx, interaction).await;
+            return handle_custom_mode_selection(ctx, interaction, nonce).await;
         }
-        _ => return Ok(()),
+        RoleMode::None | RoleMode::Groups => return Ok(()),
     };
 
     // Create roles list text
@@ -210,8 +248,8 @@ async fn handle_mode_selection(
         .collect::<Vec<_>>()
         .join("\n");
 
-    // Create save button with mode data embedded
-    let save_button = CreateButton::new(format!("save_roles_button:{}", selected_mode))
+    // Create save button with mode and session nonce embedded
+    let save_button = CreateButton::new(format!("save_roles_button:{}:{}", selected_mode, nonce))
         .label("Save")
         .style(ButtonStyle::Primary);
 
@@ -236,6 +274,7 @@ async fn handle_mode_selection(
 async fn handle_custom_mode_selection(
     ctx: &Context,
     interaction: &ComponentInteraction,
+    nonce: Uuid,
 ) -> Result<(), Error> {
     // Create multiselect for custom role selection
     let all_possible_roles = vec![
@@ -256,7 +295,7 @@ async fn handle_custom_mode_selection(
         .collect();
 
     let custom_role_select = CreateSelectMenu::new(
-        "custom_roles_multiselect",
+        format!("custom_roles_multiselect:{}", nonce),
         CreateSelectMenuKind::String {
             options: role_options.into(),
         },
@@ -265,7 +304,7 @@ async fn handle_custom_mode_selection(
     .max_values(9)
     .placeholder("Select which roles to create");
 
-    let save_button = CreateButton::new("save_roles_button:custom")
+    let save_button = CreateButton::new(format!("save_roles_button:custom:{}", nonce))
         .label("Save")
         .style(ButtonStyle::Primary);
 
@@ -305,16 +344,31 @@ async fn handle_custom_roles_selection(
         _ => vec![],
     };
 
-    // Update the session with the custom roles selection
-    let mut sessions = state.setuproles_sessions.write().await;
-    if let Some(session) = sessions.get_mut(&(guild_id, interaction.user.id)) {
-        session.set_custom_roles(selected_roles);
-    }
+    // Update the session with the custom roles selection, ignoring outdated menus
+    let nonce = interaction
+        .data
+        .custom_id
+        .strip_prefix("custom_roles_multiselect:")
+        .and_then(|n| n.parse::<Uuid>().ok());
+    let error = {
+        let mut sessions = state.setuproles_sessions.write().await;
+        match sessions.get_mut(&(guild_id, interaction.user.id)) {
+            Some(session) if session.is_expired() => Some(SESSION_EXPIRED),
+            Some(session) if Some(session.nonce) != nonce => Some(MENU_OUTDATED),
+            Some(session) => {
+                session.set_custom_roles(selected_roles);
+                None
+            }
+            None => Some(SESSION_EXPIRED),
+        }
+    };
 
-    // Acknowledge without updating message
-    interaction
-        .create_response(&ctx.http, CreateInteractionResponse::Acknowledge)
-        .await?;
+    // Acknowledge without updating message, unless the session is gone
+    let response = match error {
+        Some(message) => error_response(message),
+        None => CreateInteractionResponse::Acknowledge,
+    };
+    interaction.create_response(&ctx.http, response).await?;
 
     Ok(())
 }
@@ -336,62 +390,78 @@ async fn handle_save_roles(
         sessions.get(&(guild_id, interaction.user.id)).cloned()
     };
 
-    let session = match session {
+    let session = match session.filter(|s| !s.is_expired()) {
         Some(s) => s,
         None => {
-            // No session found, shouldn't get here
-            let container = CreateContainer::new(vec![CreateContainerComponent::TextDisplay(
-                CreateTextDisplay::new(
-                    "# Error\n\nSession expired. Please run `/setuproles` again.",
-                ),
-            )]);
-
-            let response = CreateInteractionResponse::UpdateMessage(
-                CreateInteractionResponseMessage::new()
-                    .components(vec![CreateComponent::Container(container)])
-                    .flags(MessageFlags::EPHEMERAL | MessageFlags::IS_COMPONENTS_V2),
-            );
-
-            interaction.create_response(&ctx.http, response).await?;
+            // No session found, it was either saved already or swept after its TTL
+            interaction
+                .create_response(&ctx.http, error_response(SESSION_EXPIRED))
+                .await?;
             return Ok(());
         }
     };
 
+    // Reject clicks from a menu that was superseded by a newer /setuproles
+    let nonce = interaction
+        .data
+        .custom_id
+        .rsplit(':')
+        .next()
+        .and_then(|n| n.parse::<Uuid>().ok());
+    if nonce != Some(session.nonce) {
+        interaction
+            .create_response(&ctx.http, error_response(MENU_OUTDATED))
+            .await?;
+        return Ok(());
+    }
+
     // Validate the session has all required data
     if let Err(error_msg) = session.validate() {
-        let container = CreateContainer::new(vec![CreateContainerComponent::TextDisplay(
-            CreateTextDisplay::new(format!("# Error\n\n{}", error_msg)),
-        )]);
-
-        let response = CreateInteractionResponse::UpdateMessage(
-            CreateInteractionResponseMessage::new()
-                .components(vec![CreateComponent::Container(container)])
-                .flags(MessageFlags::EPHEMERAL | MessageFlags::IS_COMPONENTS_V2),
-        );
-
-        interaction.create_response(&ctx.http, response).await?;
+        interaction
+            .create_response(&ctx.http, error_response(error_msg))
+            .await?;
         return Ok(());
     }
 
-    // Create the roles
+    // Creating several roles can take a while, defer before starting
+    let reply = Deferred::component(&ctx.http, interaction).await?;
+
+    // Create the roles, showing progress as each one is created
     let mut conn = state.redis.clone();
+    let show_progress = async |index: usize, total: usize, role_name: &str| {
+        let container = CreateContainer::new(vec![
+            CreateContainerComponent::TextDisplay(CreateTextDisplay::new("# Saving Roles")),
+            CreateContainerComponent::TextDisplay(CreateTextDisplay::new(format!(
+                "Creating role {}/{}: **{}**...",
+                index, total, role_name
+            ))),
+        ]);
+
+        // Progress is cosmetic, keep creating roles if an edit fails
+        if let Err(e) = reply
+            .edit(
+                EditInteractionResponse::new()
+                    .components(vec![CreateComponent::Container(container)])
+                    .flags(MessageFlags::IS_COMPONENTS_V2),
+            )
+            .await
+        {
+            tracing::warn!("Failed to show setuproles progress: {}", e);
+        }
+    };
     let created_roles = match session
-        .save_and_create_roles(&ctx.http, guild_id, &mut conn)
+        .save_and_create_roles(&ctx.http, guild_id, &mut conn, show_progress)
         .await
     {
         Ok(roles) => roles,
         Err(e) => {
-            let container = CreateContainer::new(vec![CreateContainerComponent::TextDisplay(
-                CreateTextDisplay::new(format!("# Error\n\n{}", e)),
-            )]);
-
-            let response = CreateInteractionResponse::UpdateMessage(
-                CreateInteractionResponseMessage::new()
-                    .components(vec![CreateComponent::Container(container)])
-                    .flags(MessageFlags::EPHEMERAL | MessageFlags::IS_COMPONENTS_V2),
-            );
-
-            interaction.create_response(&ctx.http, response).await?;
+            reply
+                .edit(
+                    EditInteractionResponse::new()
+                        .components(vec![CreateComponent::Container(error_container(e))])
+                        .flags(MessageFlags::IS_COMPONENTS_V2),
+                )
+                .await?;
             return Ok(());
         }
     };
@@ -419,12 +489,28 @@ async fn handle_save_roles(
         ))),
     ]);
 
-    let response = CreateInteractionResponse::UpdateMessage(
+    reply
+        .edit(
+            EditInteractionResponse::new()
+                .components(vec![CreateComponent::Container(container)])
+                .flags(MessageFlags::IS_COMPONENTS_V2),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Build the error container shown in place of the setuproles menu
+fn error_container(message: impl std::fmt::Display) -> CreateContainer<'static> {
+    CreateContainer::new(vec![CreateContainerComponent::TextDisplay(
+        CreateTextDisplay::new(format!("# Error\n\n{}", message)),
+    )])
+}
+
+/// Replace the setuproles menu with an error
+fn error_response(message: impl std::fmt::Display) -> CreateInteractionResponse<'static> {
+    CreateInteractionResponse::UpdateMessage(
         CreateInteractionResponseMessage::new()
-            .components(vec![CreateComponent::Container(container)])
+            .components(vec![CreateComponent::Container(error_container(message))])
             .flags(MessageFlags::EPHEMERAL | MessageFlags::IS_COMPONENTS_V2),
-    );
-
-    interaction.create_response(&ctx.http, response).await?;
-    Ok(())
+    )
 }
@@ -1,4 +1,5 @@
 use crate::bot::Error;
+use crate::keys::{RoleSlot, guild_role};
 use crate::state::AppState;
 use serenity::all::{
     CommandInteraction, CommandOptionType, Context, CreateCommand, CreateCommandOption,
@@ -133,7 +134,7 @@ pub async fn handle(
 
     // Store the role ID in Redis
     let mut conn = state.redis.clone();
-    let redis_key = format!("guild:{}:role:verified", guild_id);
+    let redis_key = guild_role(guild_id, &RoleSlot::Verified);
 
     redis::cmd("SET")
         .arg(&redis_key)
@@ -0,0 +1,242 @@
+use crate::bot::Error;
+use crate::keys::redis_key;
+use crate::state::AppState;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommand, CreateCommandOption,
+    CreateInteractionResponse, CreateInteractionResponseMessage, GuildId, ResolvedValue,
+};
+use std::sync::Arc;
+
+use super::utils::{is_admin, trim_redis_value};
+
+/// A guild's minimum account and membership age before `/verify` works. Unset limits
+/// don't gate anything.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VerifyGate {
+    pub min_account_age_days: Option<i64>,
+    pub min_membership_minutes: Option<i64>,
+}
+
+/// Why a user can't verify yet, with the unix time they can
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GateBlock {
+    AccountTooNew { ready_at: i64 },
+    JoinedTooRecently { ready_at: i64 },
+}
+
+impl VerifyGate {
+    /// Load the guild's gate from `guild:{}:min_account_age_days` and
+    /// `guild:{}:min_membership_minutes`
+    pub async fn load(
+        conn: &mut redis::aio::ConnectionManager,
+        guild_id: GuildId,
+    ) -> Result<Self, Error> {
+        let (account_age, membership): (Option<String>, Option<String>) = redis::pipe()
+            .get(redis_key!("guild:{}:min_account_age_days", guild_id))
+            .get(redis_key!("guild:{}:min_membership_minutes", guild_id))
+            .query_async(conn)
+            .await?;
+
+        let parse = |value| {
+            trim_redis_value(value)
+                .and_then(|s| s.parse::<i64>().ok())
+                .filter(|limit| *limit > 0)
+        };
+        Ok(Self {
+            min_account_age_days: parse(account_age),
+            min_membership_minutes: parse(membership),
+        })
+    }
+
+    /// Check a user against the gate. `created_at` is the account's creation time from
+    /// its snowflake, `joined_at` when they joined the guild, if Discord sent it.
+    pub fn check(&self, now: i64, created_at: i64, joined_at: Option<i64>) -> Option<GateBlock> {
+        if let Some(days) = self.min_account_age_days {
+            let ready_at = created_at + days * 86_400;
+            if now < ready_at {
+                return Some(GateBlock::AccountTooNew { ready_at });
+            }
+        }
+
+        if let (Some(minutes), Some(joined_at)) = (self.min_membership_minutes, joined_at) {
+            let ready_at = joined_at + minutes * 60;
+            if now < ready_at {
+                return Some(GateBlock::JoinedTooRecently { ready_at });
+            }
+        }
+
+        None
+    }
+}
+
+/// Register the setverifygate command
+pub fn register() -> CreateCommand<'static> {
+    CreateCommand::new("setverifygate")
+        .description("Require a minimum account or membership age before members can verify")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "account_age_days",
+                "Days since the Discord account was created (0 disables)",
+            )
+            .min_int_value(0)
+            .max_int_value(365)
+            .required(false),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "membership_minutes",
+                "Minutes since the member joined this server (0 disables)",
+            )
+            .min_int_value(0)
+            .max_int_value(10080)
+            .required(false),
+        )
+}
+
+/// Handle the setverifygate command
+pub async fn handle(
+    ctx: &Context,
+    command: &CommandInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let user = &command.user;
+
+    // Get guild_id from context
+    let guild_id = match command.guild_id {
+        Some(id) => id,
+        None => {
+            let response = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content("This command can only be used in a server.")
+                    .ephemeral(true),
+            );
+            command.create_response(&ctx.http, response).await?;
+            return Ok(());
+        }
+    };
+
+    // Check if user has administrator permissions
+    if !is_admin(ctx, &command.member, guild_id, user.id).await? {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("You need administrator permissions to configure the verification gate.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    // Get the limits from command options, omitted ones are left as they are
+    let mut account_age_days = None;
+    let mut membership_minutes = None;
+    for option in command.data.options() {
+        match (option.name, option.value) {
+            ("account_age_days", ResolvedValue::Integer(days)) => account_age_days = Some(days),
+            ("membership_minutes", ResolvedValue::Integer(minutes)) => {
+                membership_minutes = Some(minutes)
+            }
+            _ => {}
+        }
+    }
+
+    let mut conn = state.redis.clone();
+    let mut pipe = redis::pipe();
+    for (key, limit) in [
+        (
+            redis_key!("guild:{}:min_account_age_days", guild_id),
+            account_age_days,
+        ),
+        (
+            redis_key!("guild:{}:min_membership_minutes", guild_id),
+            membership_minutes,
+        ),
+    ] {
+        match limit {
+            Some(0) => pipe.del(key).ignore(),
+            Some(limit) => pipe.set(key, limit).ignore(),
+            None => continue,
+        };
+    }
+    pipe.query_async::<()>(&mut conn).await?;
+
+    let gate = VerifyGate::load(&mut conn, guild_id).await?;
+    let message = match (gate.min_account_age_days, gate.min_membership_minutes) {
+        (None, None) => "Anyone in the server can verify, there is no minimum age.".to_string(),
+        (account_age, membership) => {
+            let mut lines = vec!["Members can only verify once:".to_string()];
+            if let Some(days) = account_age {
+                lines.push(format!("* their Discord account is {} days old", days));
+            }
+            if let Some(minutes) = membership {
+                lines.push(format!(
+                    "* they've been in this server for {} minutes",
+                    minutes
+                ));
+            }
+            lines.join("\n")
+        }
+    };
+
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(message)
+            .ephemeral(true),
+    );
+    command.create_response(&ctx.http, response).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    #[test]
+    fn no_gate_by_default() {
+        assert_eq!(VerifyGate::default().check(NOW, NOW, Some(NOW)), None);
+    }
+
+    #[test]
+    fn blocks_new_accounts_until_old_enough() {
+        let gate = VerifyGate {
+            min_account_age_days: Some(7),
+            ..VerifyGate::default()
+        };
+        let created_at = NOW - 86_400;
+        assert_eq!(
+            gate.check(NOW, created_at, None),
+            Some(GateBlock::AccountTooNew {
+                ready_at: created_at + 7 * 86_400
+            })
+        );
+        assert_eq!(gate.check(NOW, NOW - 7 * 86_400, None), None);
+    }
+
+    #[test]
+    fn blocks_recent_joins_until_old_enough() {
+        let gate = VerifyGate {
+            min_membership_minutes: Some(10),
+            ..VerifyGate::default()
+        };
+        assert_eq!(
+            gate.check(NOW, 0, Some(NOW - 60)),
+            Some(GateBlock::JoinedTooRecently {
+                ready_at: NOW + 540
+            })
+        );
+        assert_eq!(gate.check(NOW, 0, Some(NOW - 600)), None);
+    }
+
+    #[test]
+    fn unknown_join_time_is_not_blocked() {
+        let gate = VerifyGate {
+            min_membership_minutes: Some(10),
+            ..VerifyGate::default()
+        };
+        assert_eq!(gate.check(NOW, 0, None), None);
+    }
+}
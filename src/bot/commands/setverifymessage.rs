@@ -0,0 +1,120 @@
+use crate::bot::Error;
+use crate::bot::i18n::Locale;
+use crate::keys::redis_key;
+use crate::state::{AppState, PENDING_VERIFICATION_TTL_SECS};
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommand, CreateCommandOption,
+    CreateInteractionResponse, CreateInteractionResponseMessage, ResolvedOption, ResolvedValue,
+};
+use std::sync::Arc;
+
+use super::utils::is_admin;
+
+/// Maximum length of a custom verify prompt. Leaves room for the verification link
+/// within Discord's 2000 character message limit.
+pub const MAX_VERIFY_PROMPT_LENGTH: usize = 1500;
+
+/// Register the setverifymessage command
+pub fn register() -> CreateCommand<'static> {
+    CreateCommand::new("setverifymessage")
+        .description("Customize the message shown by /verify")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "message",
+                "Message shown with the verification link, {link} marks where it goes (omit to reset)",
+            )
+            .max_length(MAX_VERIFY_PROMPT_LENGTH as u16)
+            .required(false),
+        )
+}
+
+/// Handle the setverifymessage command
+pub async fn handle(
+    ctx: &Context,
+    command: &CommandInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let user = &command.user;
+
+    // Get guild_id from context
+    let guild_id = match command.guild_id {
+        Some(id) => id,
+        None => {
+            let response = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content("This command can only be used in a server.")
+                    .ephemeral(true),
+            );
+            command.create_response(&ctx.http, response).await?;
+            return Ok(());
+        }
+    };
+
+    // Check if user has administrator permissions
+    if !is_admin(ctx, &command.member, guild_id, user.id).await? {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("You need administrator permissions to set the verify message.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    // Get the optional message from command options
+    let prompt = match command.data.options().first() {
+        Some(ResolvedOption {
+            value: ResolvedValue::String(s),
+            ..
+        }) => Some(s.trim().replace("\\n", "\n")),
+        _ => None,
+    };
+
+    let mut conn = state.redis.clone();
+    let redis_key = redis_key!("guild:{}:verify_prompt", guild_id);
+
+    let message = match prompt.filter(|p| !p.is_empty()) {
+        None => {
+            redis::cmd("DEL")
+                .arg(&redis_key)
+                .query_async::<()>(&mut conn)
+                .await?;
+            "The verify message has been reset to the default.".to_string()
+        }
+        Some(prompt) if prompt.chars().count() > MAX_VERIFY_PROMPT_LENGTH => {
+            format!(
+                "The verify message must be at most {} characters.",
+                MAX_VERIFY_PROMPT_LENGTH
+            )
+        }
+        Some(prompt) if prompt.matches("{link}").count() > 1 => {
+            "The verify message can only contain `{link}` once.".to_string()
+        }
+        Some(prompt) => {
+            redis::cmd("SET")
+                .arg(&redis_key)
+                .arg(&prompt)
+                .query_async::<()>(&mut conn)
+                .await?;
+            format!(
+                "The verify message has been updated. Preview:\n\n{}",
+                super::verify::render_verify_prompt(
+                    Some(&prompt),
+                    &format!("{}/verify?state=...", state.config.app_url),
+                    chrono::Utc::now().timestamp() + PENDING_VERIFICATION_TTL_SECS,
+                    Locale::from_discord(&command.locale)
+                )
+            )
+        }
+    };
+
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(message)
+            .ephemeral(true),
+    );
+    command.create_response(&ctx.http, response).await?;
+
+    Ok(())
+}
@@ -0,0 +1,104 @@
+use crate::bot::Error;
+use crate::state::AppState;
+use serenity::all::{
+    CommandInteraction, Context, CreateCommand, CreateInteractionResponse,
+    CreateInteractionResponseMessage, CreateMessage, Mentionable, Permissions, RoleId,
+};
+use std::sync::Arc;
+
+use super::setlogstyle::{LogEvent, LogStyle};
+use super::utils::{check_log_channel, is_admin, load_guild_config};
+use super::verify::verified_log_embed;
+
+/// Register the testlog command
+pub fn register() -> CreateCommand<'static> {
+    CreateCommand::new("testlog")
+        .description("Send a sample log message to the configured log channel (admin only)")
+        .default_member_permissions(Permissions::ADMINISTRATOR)
+}
+
+/// Handle the testlog command
+pub async fn handle(
+    ctx: &Context,
+    command: &CommandInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let user = &command.user;
+
+    // Get guild_id from context
+    let guild_id = match command.guild_id {
+        Some(id) => id,
+        None => {
+            let response = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content("This command can only be used in a server.")
+                    .ephemeral(true),
+            );
+            command.create_response(&ctx.http, response).await?;
+            return Ok(());
+        }
+    };
+
+    // Check if user has administrator permissions
+    if !is_admin(ctx, &command.member, guild_id, user.id).await? {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content("You need administrator permissions to test the log channel.")
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    let mut conn = state.redis.clone();
+    let guild_config = load_guild_config(&ctx.http, &mut conn, guild_id).await?;
+
+    let message = match guild_config.get_log_channel() {
+        None => "No log channel is configured. Use `/setlogchannel` to set one.".to_string(),
+        Some(channel_id) => {
+            match check_log_channel(&ctx.http, &ctx.cache, guild_id, channel_id.into()).await? {
+                Some(problem) => problem.describe(channel_id.into()),
+                None => {
+                    // Same embed as a real verification, using the admin as the sample user
+                    let roles: Vec<RoleId> = guild_config.verified_role.into_iter().collect();
+                    let style =
+                        LogStyle::load_or_default(&mut conn, guild_id, LogEvent::Verified).await;
+                    let title = style.title("User Verified", user.id, &roles);
+                    let embed = style
+                        .apply(
+                            verified_log_embed(user.id, roles.clone(), Vec::new(), None),
+                            user.id,
+                            &roles,
+                        )
+                        .title(format!("{} (Test)", title));
+
+                    match ctx
+                        .http
+                        .send_message(
+                            channel_id.into(),
+                            Vec::new(),
+                            &CreateMessage::new().embed(embed),
+                        )
+                        .await
+                    {
+                        Ok(_) => format!("Sent a test log message to {}.", channel_id.mention()),
+                        Err(e) => format!(
+                            "Failed to send a test log message to {}: {}",
+                            channel_id.mention(),
+                            e
+                        ),
+                    }
+                }
+            }
+        }
+    };
+
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .content(message)
+            .ephemeral(true),
+    );
+    command.create_response(&ctx.http, response).await?;
+
+    Ok(())
+}
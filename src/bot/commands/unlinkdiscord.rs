@@ -0,0 +1,457 @@
+use crate::bot::Error;
+use crate::bot::i18n::{self, Locale};
+use crate::keys::{discord_keycloak, redis_key};
+use crate::redact::redact;
+use crate::state::AppState;
+use redis::AsyncCommands;
+use serenity::all::{
+    ButtonStyle, Cache, CommandInteraction, CommandOptionType, ComponentInteraction, Context,
+    CreateActionRow, CreateButton, CreateCommand, CreateCommandOption, CreateComponent,
+    CreateContainer, CreateContainerComponent, CreateEmbed, CreateInteractionResponse,
+    CreateInteractionResponseMessage, CreateMessage, CreateTextDisplay, EditInteractionResponse,
+    GuildId, Http, Mentionable, MessageFlags, Permissions, ResolvedValue, UserId,
+};
+use std::sync::Arc;
+
+use super::unverify::unverify_everywhere;
+use super::utils::{
+    AdminGrant, Deferred, admin_grant, is_admin, is_owner, load_guild_config, log_destination,
+    trim_redis_value,
+};
+
+/// Register the unlinkdiscord command
+pub fn register() -> CreateCommand<'static> {
+    CreateCommand::new("unlinkdiscord")
+        .description("Unlink the Discord account from a user's Keycloak account (admin only)")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::User,
+                "user",
+                "The verified Discord user to unlink",
+            )
+            .required(false),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "keycloak",
+                "The Keycloak username or user ID (bot owners can unlink accounts not verified here)",
+            )
+            .required(false),
+        )
+        .default_member_permissions(Permissions::ADMINISTRATOR)
+}
+
+/// Handle the unlinkdiscord command by asking for confirmation first
+pub async fn handle(
+    ctx: &Context,
+    command: &CommandInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let user = &command.user;
+
+    // Keycloak lookups can take a while
+    let reply = Deferred::command(&ctx.http, command).await?;
+
+    // Get guild_id from context
+    let guild_id = match command.guild_id {
+        Some(id) => id,
+        None => {
+            reply
+                .edit(
+                    EditInteractionResponse::new()
+                        .content("This command can only be used in a server."),
+                )
+                .await?;
+            return Ok(());
+        }
+    };
+
+    // Check if user has administrator permissions
+    if !is_admin(ctx, &command.member, guild_id, user.id).await? {
+        reply
+            .edit(
+                EditInteractionResponse::new()
+                    .content("You need administrator permissions to unlink Discord accounts."),
+            )
+            .await?;
+        return Ok(());
+    }
+
+    // Get the target user or Keycloak account from command options
+    let mut target_user = None;
+    let mut keycloak_query = None;
+    for option in command.data.options() {
+        match (option.name, option.value) {
+            ("user", ResolvedValue::User(u, _)) => target_user = Some(u.clone()),
+            ("keycloak", ResolvedValue::String(k)) => {
+                keycloak_query = Some(k.trim().to_string()).filter(|k| !k.is_empty())
+            }
+            _ => {}
+        }
+    }
+
+    let mut conn = state.redis.clone();
+    let keycloak_user = match (target_user, keycloak_query) {
+        (Some(target_user), _) => {
+            match trim_redis_value(conn.get(discord_keycloak(target_user.id)).await?) {
+                Some(keycloak_user_id) => state.keycloak.get_user(&keycloak_user_id).await.ok(),
+                None => {
+                    reply
+                        .edit(EditInteractionResponse::new().content(i18n::not_verified(
+                            Locale::from_discord(&command.locale),
+                            target_user.mention(),
+                        )))
+                        .await?;
+                    return Ok(());
+                }
+            }
+        }
+        // Accept either a username or a user ID
+        (None, Some(query)) => match state.keycloak.find_user_by_username(&query).await {
+            Ok(Some(u)) => Some(u),
+            _ => state.keycloak.get_user(&query).await.ok(),
+        },
+        (None, None) => {
+            reply
+                .edit(
+                    EditInteractionResponse::new()
+                        .content("Pass either a user or a keycloak account to unlink."),
+                )
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let Some((keycloak_user_id, keycloak_username)) =
+        keycloak_user.and_then(|u| u.id.map(|id| (id, u.username.unwrap_or_default())))
+    else {
+        reply
+            .edit(EditInteractionResponse::new().content("No Keycloak user found."))
+            .await?;
+        return Ok(());
+    };
+
+    if !can_unlink(state, &mut conn, guild_id, user.id, &keycloak_user_id).await? {
+        reply
+            .edit(EditInteractionResponse::new().content(format!(
+                "`{}` isn't verified in this server. Unlinking changes the account everywhere, \
+                so only bot owners can unlink accounts verified elsewhere.",
+                keycloak_username
+            )))
+            .await?;
+        return Ok(());
+    }
+
+    let linked_discord = match linked_discord_account(state, &keycloak_user_id).await {
+        Ok(linked) => linked,
+        Err(e) => {
+            tracing::error!(
+                "Failed to fetch federated identities of Keycloak user {}: {}",
+                redact(&keycloak_user_id),
+                e
+            );
+            reply
+                .edit(
+                    EditInteractionResponse::new()
+                        .content("Failed to fetch linked identities from Keycloak."),
+                )
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let Some(linked_discord) = linked_discord else {
+        reply
+            .edit(EditInteractionResponse::new().content(format!(
+                "`{}` has no Discord account linked in Keycloak. Use `/unverify` to remove \
+                a verification left behind in this server.",
+                keycloak_username
+            )))
+            .await?;
+        return Ok(());
+    };
+
+    // The Discord user the bot has mapped to the account, which may not be the linked one
+    let verified_discord = trim_redis_value(
+        conn.get(redis_key!("keycloak:{}:discord", keycloak_user_id))
+            .await?,
+    );
+    let verification_text = match verified_discord {
+        Some(discord_user_id) => format!(
+            "<@{}> will also be unverified in this server and lose their verification roles.",
+            discord_user_id
+        ),
+        None => {
+            "No Discord user is verified with this account, so no roles will change.".to_string()
+        }
+    };
+
+    let confirm_button = CreateButton::new(format!("unlinkdiscord_confirm:{}", keycloak_user_id))
+        .label("Unlink")
+        .style(ButtonStyle::Danger);
+    let cancel_button = CreateButton::new("unlinkdiscord_cancel")
+        .label("Cancel")
+        .style(ButtonStyle::Secondary);
+
+    let container = CreateContainer::new(vec![
+        CreateContainerComponent::TextDisplay(CreateTextDisplay::new("# Confirm Unlink")),
+        CreateContainerComponent::TextDisplay(CreateTextDisplay::new(format!(
+            "This will unlink Discord account <@{}> from `{}` in Keycloak. They'll link \
+            a Discord account again the next time they verify.\n\n{}",
+            linked_discord, keycloak_username, verification_text
+        ))),
+        CreateContainerComponent::ActionRow(CreateActionRow::Buttons(
+            vec![confirm_button, cancel_button].into(),
+        )),
+    ]);
+
+    reply
+        .edit(
+            EditInteractionResponse::new()
+                .components(vec![CreateComponent::Container(container)])
+                .flags(MessageFlags::IS_COMPONENTS_V2),
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Handle the confirm and cancel buttons of the unlink confirmation
+pub async fn handle_component(
+    ctx: &Context,
+    interaction: &ComponentInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let guild_id = match interaction.guild_id {
+        Some(id) => id,
+        None => return Ok(()),
+    };
+
+    let custom_id = interaction.data.custom_id.as_str();
+
+    let message = if custom_id == "unlinkdiscord_cancel" {
+        "# Cancelled\n\nNo changes were made.".to_string()
+    } else if let Some(keycloak_user_id) = custom_id.strip_prefix("unlinkdiscord_confirm:") {
+        // Permissions may have changed since the confirmation was shown
+        let grant = admin_grant(ctx, &interaction.member, guild_id, interaction.user.id).await?;
+        if let Some(grant) = grant {
+            // Acknowledge now, Keycloak and role removal can take a while
+            let reply = Deferred::component(&ctx.http, interaction).await?;
+
+            let message = unlink_discord(
+                &ctx.http,
+                &ctx.cache,
+                state,
+                guild_id,
+                keycloak_user_id,
+                (interaction.user.id, grant),
+            )
+            .await?;
+            let container = CreateContainer::new(vec![CreateContainerComponent::TextDisplay(
+                CreateTextDisplay::new(message),
+            )]);
+
+            reply
+                .edit(
+                    EditInteractionResponse::new()
+                        .components(vec![CreateComponent::Container(container)])
+                        .flags(MessageFlags::IS_COMPONENTS_V2),
+                )
+                .await?;
+            return Ok(());
+        } else {
+            "# Error\n\nYou need administrator permissions to unlink Discord accounts.".to_string()
+        }
+    } else {
+        return Ok(());
+    };
+
+    let container = CreateContainer::new(vec![CreateContainerComponent::TextDisplay(
+        CreateTextDisplay::new(message),
+    )]);
+
+    let response = CreateInteractionResponse::UpdateMessage(
+        CreateInteractionResponseMessage::new()
+            .components(vec![CreateComponent::Container(container)])
+            .flags(MessageFlags::EPHEMERAL | MessageFlags::IS_COMPONENTS_V2),
+    );
+    interaction.create_response(&ctx.http, response).await?;
+
+    Ok(())
+}
+
+/// The external id of the Discord account Keycloak has linked to a user, if any
+async fn linked_discord_account(
+    state: &AppState,
+    keycloak_user_id: &str,
+) -> anyhow::Result<Option<String>> {
+    let identities = state
+        .keycloak
+        .get_federated_identities(keycloak_user_id)
+        .await?;
+    Ok(identities
+        .iter()
+        .find(|i| i.identity_provider.as_deref() == Some("discord"))
+        .map(|i| i.user_id.as_deref().unwrap_or_default().to_string()))
+}
+
+/// Whether an admin may unlink a Keycloak account from this guild. The unlink applies to
+/// the account everywhere, so server admins can only unlink an account whose verified
+/// Discord user is verified in their server. Bot owners can unlink any account.
+async fn can_unlink(
+    state: &AppState,
+    conn: &mut redis::aio::ConnectionManager,
+    guild_id: GuildId,
+    admin_id: UserId,
+    keycloak_user_id: &str,
+) -> Result<bool, Error> {
+    if is_owner(&state.config, admin_id) {
+        return Ok(true);
+    }
+
+    let Some(verified_discord) = trim_redis_value(
+        conn.get(redis_key!("keycloak:{}:discord", keycloak_user_id))
+            .await?,
+    ) else {
+        return Ok(false);
+    };
+
+    Ok(conn
+        .sismember(
+            redis_key!("guild:{}:verified_members", guild_id),
+            verified_discord,
+        )
+        .await?)
+}
+
+/// Remove the Discord identity from a Keycloak user, then unverify whoever the bot has
+/// mapped to it in every server they're verified in. Returns the message to show the admin.
+async fn unlink_discord(
+    http: &Http,
+    cache: &Cache,
+    state: &AppState,
+    guild_id: GuildId,
+    keycloak_user_id: &str,
+    (admin_id, grant): (UserId, AdminGrant),
+) -> Result<String, Error> {
+    // The account may have been unverified here since the confirmation was shown
+    let mut conn = state.redis.clone();
+    if !can_unlink(state, &mut conn, guild_id, admin_id, keycloak_user_id).await? {
+        return Ok(
+            "# Error\n\nThis account isn't verified in this server. Only bot owners can unlink \
+            accounts verified elsewhere."
+                .to_string(),
+        );
+    }
+
+    let linked_discord = match linked_discord_account(state, keycloak_user_id).await {
+        Ok(Some(linked)) => linked,
+        Ok(None) => {
+            return Ok("# Error\n\nThe Discord account was already unlinked.".to_string());
+        }
+        Err(e) => {
+            tracing::error!(
+                "Failed to fetch federated identities of Keycloak user {}: {}",
+                redact(keycloak_user_id),
+                e
+            );
+            return Ok("# Error\n\nFailed to fetch linked identities from Keycloak.".to_string());
+        }
+    };
+
+    if let Err(e) = state
+        .keycloak
+        .delete_federated_identity(keycloak_user_id, "discord")
+        .await
+    {
+        tracing::error!(
+            "Failed to unlink Discord from Keycloak user {}: {}",
+            redact(keycloak_user_id),
+            e
+        );
+        return Ok("# Error\n\nFailed to unlink the Discord account in Keycloak.".to_string());
+    }
+
+    tracing::warn!(
+        "Admin {} ({}) unlinked Discord account {} from Keycloak user {} in guild {}",
+        redact(admin_id),
+        grant,
+        redact(&linked_discord),
+        redact(keycloak_user_id),
+        guild_id
+    );
+
+    // Clear the mappings and roles of whoever verified with the account. The Keycloak
+    // unlink has already happened, so a failure here is reported rather than returned.
+    let verified_discord = trim_redis_value(
+        conn.get(redis_key!("keycloak:{}:discord", keycloak_user_id))
+            .await?,
+    )
+    .and_then(|id| id.parse::<u64>().ok())
+    .map(UserId::new);
+    let unverified_text = match verified_discord {
+        Some(target_id) => match unverify_everywhere(http, cache, state, target_id).await {
+            Ok(guilds) => match guilds.map_or(0, |guilds| guilds.len()) {
+                1 => format!(" {} was unverified in 1 server.", target_id.mention()),
+                count => format!(
+                    " {} was unverified in {} servers.",
+                    target_id.mention(),
+                    count
+                ),
+            },
+            Err(e) => {
+                tracing::warn!(
+                    "Failed to unverify user {} after unlinking: {}",
+                    redact(target_id),
+                    e
+                );
+                format!(
+                    " Unverifying {} failed, run `/unverify` on them.",
+                    target_id.mention()
+                )
+            }
+        },
+        None => String::new(),
+    };
+
+    // Log to the log channel since this changes a user's Keycloak account
+    let guild_config = load_guild_config(http, &mut conn, guild_id).await?;
+    if let Some(channel_id) = guild_config.get_log_channel()
+        && let Some(channel_id) =
+            log_destination(http, cache, &mut conn, guild_id, channel_id).await
+    {
+        let mut embed = CreateEmbed::new()
+            .title("Discord Unlinked")
+            .description("An admin unlinked a Discord account from a Keycloak account.")
+            .color(0xFAB387) // Peach
+            .field("Discord Account", format!("<@{}>", linked_discord), false)
+            .field("Keycloak User", format!("`{}`", keycloak_user_id), false)
+            .field(
+                "Unlinked By",
+                format!("{} ({})", admin_id.mention(), grant),
+                false,
+            )
+            .timestamp(chrono::Utc::now());
+
+        if let Some(target_id) = verified_discord {
+            embed = embed.field("Unverified", target_id.mention().to_string(), false);
+        }
+
+        if let Err(e) = http
+            .send_message(
+                channel_id.into(),
+                Vec::new(),
+                &CreateMessage::new().embed(embed),
+            )
+            .await
+        {
+            tracing::warn!("Failed to send unlink log to channel {}: {}", channel_id, e);
+        }
+    }
+
+    Ok(format!(
+        "# Unlinked\n\nUnlinked <@{}> from the Keycloak account.{}",
+        linked_discord, unverified_text
+    ))
+}
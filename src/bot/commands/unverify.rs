@@ -1,14 +1,24 @@
 use crate::bot::Error;
+use crate::bot::discord::DiscordApi;
+use crate::bot::guild_config::GuildConfig;
+use crate::bot::i18n::{self, Locale};
+use crate::keys::{discord_keycloak, redis_key};
+use crate::redact::redact;
 use crate::state::AppState;
 use redis::AsyncCommands;
 use serenity::all::{
-    CommandInteraction, CommandOptionType, Context, CreateCommand, CreateCommandOption,
-    CreateEmbed, CreateInteractionResponse, CreateInteractionResponseMessage, CreateMessage,
-    Mentionable, ResolvedOption, ResolvedValue,
+    ButtonStyle, Cache, CommandInteraction, CommandOptionType, ComponentInteraction, Context,
+    CreateActionRow, CreateButton, CreateCommand, CreateCommandOption, CreateComponent,
+    CreateContainer, CreateContainerComponent, CreateEmbed, CreateEmbedFooter,
+    CreateInteractionResponse, CreateInteractionResponseMessage, CreateMessage, CreateTextDisplay,
+    GuildId, Http, Mentionable, MessageFlags, ResolvedOption, ResolvedValue, RoleId, UserId,
 };
 use std::sync::Arc;
 
-use super::utils::{is_admin, load_guild_config, trim_redis_value};
+use super::setlogstyle::{LogEvent, LogStyle};
+use super::utils::{
+    admin_grant, is_guild_member, load_guild_config, log_destination, trim_redis_value,
+};
 
 /// Register the unverify command
 pub fn register() -> CreateCommand<'static> {
@@ -24,7 +34,7 @@ pub fn register() -> CreateCommand<'static> {
         )
 }
 
-/// Handle the unverify command
+/// Handle the unverify command by asking for confirmation first
 pub async fn handle(
     ctx: &Context,
     command: &CommandInteraction,
@@ -58,7 +68,11 @@ pub async fn handle(
     };
 
     // If targeting another user, require administrator permissions
-    if target_user.id != user.id && !is_admin(ctx, &command.member, guild_id, user.id).await? {
+    if target_user.id != user.id
+        && admin_grant(ctx, &command.member, guild_id, user.id)
+            .await?
+            .is_none()
+    {
         let response = CreateInteractionResponse::Message(
             CreateInteractionResponseMessage::new()
                 .content("You need administrator permissions to unverify other users.")
@@ -68,111 +82,488 @@ pub async fn handle(
         return Ok(());
     }
 
-    // Look up Keycloak user ID from Redis
-    let mut conn = state.redis.clone();
-    let redis_key = format!("discord:{}:keycloak", target_user.id);
     // Check if user is actually verified
-    let keycloak_user_id = match trim_redis_value(conn.get(&redis_key).await?) {
+    let mut conn = state.redis.clone();
+    let redis_key = discord_keycloak(target_user.id);
+    if trim_redis_value(conn.get(&redis_key).await?).is_none() {
+        let response = CreateInteractionResponse::Message(
+            CreateInteractionResponseMessage::new()
+                .content(i18n::not_verified(
+                    Locale::from_discord(&command.locale),
+                    target_user.mention(),
+                ))
+                .ephemeral(true),
+        );
+        command.create_response(&ctx.http, response).await?;
+        return Ok(());
+    }
+
+    // Work out which roles would be removed so the confirmation can list them
+    let roles_to_remove = roles_to_remove(ctx, state, guild_id, target_user.id).await?;
+    let roles_text = if roles_to_remove.is_empty() {
+        "No roles will be removed.".to_string()
+    } else {
+        format!(
+            "The following roles will be removed:\n{}",
+            roles_to_remove
+                .iter()
+                .map(|role_id| format!("* <@&{}>", role_id))
+                .collect::<Vec<_>>()
+                .join("\n")
+        )
+    };
+
+    let confirm_button = CreateButton::new(format!("unverify_confirm:{}", target_user.id))
+        .label("Unverify")
+        .style(ButtonStyle::Danger);
+    let cancel_button = CreateButton::new("unverify_cancel")
+        .label("Cancel")
+        .style(ButtonStyle::Secondary);
+
+    let container = CreateContainer::new(vec![
+        CreateContainerComponent::TextDisplay(CreateTextDisplay::new("# Confirm Unverification")),
+        CreateContainerComponent::TextDisplay(CreateTextDisplay::new(format!(
+            "This will remove verification for {}.\n\n{}",
+            target_user.mention(),
+            roles_text
+        ))),
+        CreateContainerComponent::ActionRow(CreateActionRow::Buttons(
+            vec![confirm_button, cancel_button].into(),
+        )),
+    ]);
+
+    let response = CreateInteractionResponse::Message(
+        CreateInteractionResponseMessage::new()
+            .components(vec![CreateComponent::Container(container)])
+            .flags(MessageFlags::EPHEMERAL | MessageFlags::IS_COMPONENTS_V2),
+    );
+    command.create_response(&ctx.http, response).await?;
+
+    Ok(())
+}
+
+/// Handle the confirm and cancel buttons of the unverify confirmation
+pub async fn handle_component(
+    ctx: &Context,
+    interaction: &ComponentInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let guild_id = match interaction.guild_id {
         Some(id) => id,
-        None => {
-            let response = CreateInteractionResponse::Message(
-                CreateInteractionResponseMessage::new()
-                    .content(format!("{} is not verified.", target_user.mention()))
-                    .ephemeral(true),
-            );
-            command.create_response(&ctx.http, response).await?;
+        None => return Ok(()),
+    };
+
+    let custom_id = interaction.data.custom_id.as_str();
+
+    let message = if custom_id == "unverify_cancel" {
+        "# Cancelled\n\nNo changes were made.".to_string()
+    } else if let Some(target_id) = custom_id.strip_prefix("unverify_confirm:") {
+        let Ok(target_id) = target_id.parse::<u64>() else {
             return Ok(());
+        };
+        let target_id = UserId::new(target_id);
+
+        // Permissions may have changed since the confirmation was shown
+        let grant = if target_id == interaction.user.id {
+            None
+        } else {
+            admin_grant(ctx, &interaction.member, guild_id, interaction.user.id).await?
+        };
+
+        if target_id != interaction.user.id && grant.is_none() {
+            "# Error\n\nYou need administrator permissions to unverify other users.".to_string()
+        } else {
+            if let Some(grant) = grant {
+                tracing::warn!(
+                    "Admin {} ({}) confirmed unverifying user {} in guild {}",
+                    redact(interaction.user.id),
+                    grant,
+                    redact(target_id),
+                    guild_id
+                );
+            }
+
+            match unverify_user(&ctx.http, &ctx.cache, state, guild_id, target_id).await? {
+                Some(_) => format!(
+                    "# Unverified\n\nRemoved verification for {}.",
+                    target_id.mention()
+                ),
+                None => format!(
+                    "# Error\n\n{}",
+                    i18n::not_verified(
+                        Locale::from_discord(&interaction.locale),
+                        target_id.mention()
+                    )
+                ),
+            }
         }
+    } else {
+        return Ok(());
     };
 
-    // Remove Redis mappings
-    redis::cmd("DEL")
-        .arg(format!("keycloak:{}:discord", keycloak_user_id))
-        .query_async::<()>(&mut conn)
-        .await?;
+    let container = CreateContainer::new(vec![CreateContainerComponent::TextDisplay(
+        CreateTextDisplay::new(message),
+    )]);
 
-    redis::cmd("DEL")
-        .arg(&redis_key)
-        .query_async::<()>(&mut conn)
-        .await?;
+    let response = CreateInteractionResponse::UpdateMessage(
+        CreateInteractionResponseMessage::new()
+            .components(vec![CreateComponent::Container(container)])
+            .flags(MessageFlags::EPHEMERAL | MessageFlags::IS_COMPONENTS_V2),
+    );
+    interaction.create_response(&ctx.http, response).await?;
+
+    Ok(())
+}
+
+/// Collect the verified and managed level/class/group roles currently held by a member
+async fn roles_to_remove(
+    ctx: &Context,
+    state: &AppState,
+    guild_id: GuildId,
+    user_id: UserId,
+) -> Result<Vec<RoleId>, Error> {
+    let member = guild_id.member(&ctx.http, user_id).await?;
+    let mut conn = state.redis.clone();
+
+    let Ok(guild_config) = load_guild_config(&ctx.http, &mut conn, guild_id).await else {
+        return Ok(Vec::new());
+    };
+
+    Ok(removable_roles(&guild_config, member.roles.iter()))
+}
+
+/// Select the roles unverify removes from a member's current roles, skipping protected roles.
+/// Matching is purely by the role ids stored in the guild config, never by role name.
+fn removable_roles<'a>(
+    guild_config: &GuildConfig,
+    member_roles: impl IntoIterator<Item = &'a RoleId>,
+) -> Vec<RoleId> {
+    member_roles
+        .into_iter()
+        .filter(|role_id| !guild_config.protected_roles.contains(role_id))
+        .filter(|role_id| {
+            guild_config.verified_role.as_ref() == Some(*role_id)
+                || guild_config.level_roles.values().any(|r| r == *role_id)
+                || guild_config.class_roles.values().any(|r| r == *role_id)
+                || guild_config.group_roles.values().any(|r| r == *role_id)
+                || guild_config.attribute_roles.values().any(|r| r == *role_id)
+                || guild_config.always_roles.contains(role_id)
+                || guild_config
+                    .email_domains
+                    .values()
+                    .any(|r| r.as_ref() == Some(*role_id))
+        })
+        .copied()
+        .collect()
+}
+
+/// Remove the verified and mapped roles from a member and restore the unverified role.
+/// Returns the removed roles and the restored role, if any.
+async fn remove_verification_roles(
+    discord: &impl DiscordApi,
+    guild_config: &GuildConfig,
+    target_id: UserId,
+    member_roles: &[RoleId],
+) -> Result<(Vec<RoleId>, Option<RoleId>), Error> {
+    let guild_id = guild_config.guild_id;
+    let mut removed_roles = Vec::new();
 
+    // Remove the verified role and any level, class and group roles if present
+    for role_id in removable_roles(guild_config, member_roles) {
+        match discord.remove_role(guild_id, target_id, role_id).await {
+            Ok(()) => removed_roles.push(role_id),
+            // Failing to remove the verified role itself is a hard error
+            Err(e) if guild_config.verified_role == Some(role_id) => return Err(e),
+            Err(e) => tracing::warn!("Failed to remove role {}: {}", role_id, e),
+        }
+    }
+
+    // Put the member back behind the verification gate. The role is already
+    // dropped from the config if it was deleted, so a failure here is only logged.
+    let mut restored_role = None;
+    if let Some(role_id) = guild_config.unverified_role
+        && !member_roles.contains(&role_id)
+    {
+        match discord.add_role(guild_id, target_id, role_id).await {
+            Ok(()) => restored_role = Some(role_id),
+            Err(e) => tracing::warn!("Failed to add unverified role {}: {}", role_id, e),
+        }
+    }
+
+    Ok((removed_roles, restored_role))
+}
+
+/// Remove the Redis mappings and managed roles for a user, logging the result.
+/// Returns `None` if the user was not verified. A user who left the guild has no
+/// roles to remove, so only the mappings are cleared.
+pub async fn unverify_user(
+    http: &Http,
+    cache: &Cache,
+    state: &AppState,
+    guild_id: GuildId,
+    target_id: UserId,
+) -> Result<Option<Vec<RoleId>>, Error> {
+    // Look up Keycloak user ID from Redis
+    let mut conn = state.redis.clone();
+    let Some(keycloak_user_id) = trim_redis_value(conn.get(discord_keycloak(target_id)).await?)
+    else {
+        return Ok(None);
+    };
+
+    // Roles go first, so a failure leaves the mappings in place for a retry
+    let removed_roles = unverify_in_guild(http, cache, state, guild_id, target_id).await?;
+    clear_link(&mut conn, target_id, &keycloak_user_id).await?;
+
+    Ok(Some(removed_roles))
+}
+
+/// Unverify a user in every guild they're verified in, then remove their mappings.
+/// Returns the guilds they were unverified in, `None` if they were not verified.
+pub async fn unverify_everywhere(
+    http: &Http,
+    cache: &Cache,
+    state: &AppState,
+    target_id: UserId,
+) -> Result<Option<Vec<GuildId>>, Error> {
+    let mut conn = state.redis.clone();
+    let Some(keycloak_user_id) = trim_redis_value(conn.get(discord_keycloak(target_id)).await?)
+    else {
+        return Ok(None);
+    };
+
+    let mut guilds = Vec::new();
+    for guild_id in cache.guilds() {
+        let verified: bool = conn
+            .sismember(
+                redis_key!("guild:{}:verified_members", guild_id),
+                target_id.get(),
+            )
+            .await?;
+        if verified {
+            unverify_in_guild(http, cache, state, guild_id, target_id).await?;
+            guilds.push(guild_id);
+        }
+    }
+    clear_link(&mut conn, target_id, &keycloak_user_id).await?;
+
+    Ok(Some(guilds))
+}
+
+/// Delete the global Discord <-> Keycloak mappings and what's cached for the account
+async fn clear_link(
+    conn: &mut redis::aio::ConnectionManager,
+    target_id: UserId,
+    keycloak_user_id: &str,
+) -> Result<(), Error> {
     redis::cmd("DEL")
-        .arg(format!("discord:{}:verified_at", target_user.id))
-        .query_async::<()>(&mut conn)
+        .arg(redis_key!("keycloak:{}:discord", keycloak_user_id))
+        .arg(redis_key!("keycloak:{}:attributes", keycloak_user_id))
+        .arg(redis_key!("keycloak:{}:profile", keycloak_user_id))
+        .arg(discord_keycloak(target_id))
+        .arg(redis_key!("discord:{}:verified_at", target_id))
+        .query_async::<()>(conn)
         .await?;
 
+    Ok(())
+}
+
+/// Remove a user's managed roles in a guild and drop them from its verified members,
+/// logging the result. Returns the removed roles, none if they left the guild.
+async fn unverify_in_guild(
+    http: &Http,
+    cache: &Cache,
+    state: &AppState,
+    guild_id: GuildId,
+    target_id: UserId,
+) -> Result<Vec<RoleId>, Error> {
+    let mut conn = state.redis.clone();
+
+    // Check membership before anything changes, a user who left keeps no roles here
+    let member_roles = if is_guild_member(http, guild_id, target_id).await? {
+        Some(http.member_roles(guild_id, target_id).await?)
+    } else {
+        None
+    };
+
+    let guild_config = load_guild_config(http, &mut conn, guild_id).await.ok();
+
     // Remove verified role and track removed roles for logging
-    let member = guild_id.member(&ctx.http, target_user.id).await?;
     let mut removed_roles = Vec::new();
+    let mut restored_role = None;
+    if let (Some(guild_config), Some(member_roles)) = (&guild_config, &member_roles) {
+        (removed_roles, restored_role) =
+            remove_verification_roles(http, guild_config, target_id, member_roles).await?;
+    }
+
+    redis::cmd("SREM")
+        .arg(redis_key!("guild:{}:verified_members", guild_id))
+        .arg(target_id.get())
+        .query_async::<()>(&mut conn)
+        .await?;
+
+    // Log to log channel if configured and still writable
+    if let Some(guild_config) = guild_config
+        && let Some(channel_id) = guild_config.get_log_channel()
+        && let Some(channel_id) =
+            log_destination(http, cache, &mut conn, guild_id, channel_id).await
+    {
+        // Format roles list
+        let roles_mentions: Vec<String> = removed_roles
+            .iter()
+            .map(|role_id| format!("<@&{}>", role_id))
+            .collect();
+        // Protected roles the member keeps
+        let kept_mentions: Vec<String> = member_roles
+            .iter()
+            .flatten()
+            .filter(|role_id| guild_config.protected_roles.contains(role_id))
+            .map(|role_id| format!("<@&{}>", role_id))
+            .collect();
+
+        let roles_text = match (roles_mentions.is_empty(), kept_mentions.is_empty()) {
+            (true, true) => "None".to_string(),
+            (true, false) => "None (protected)".to_string(),
+            (false, _) => roles_mentions.join(", "),
+        };
+
+        let mut embed = CreateEmbed::new()
+            .title("User Unverified")
+            .color(0xF38BA8) // Red
+            .field("User", target_id.mention().to_string(), false)
+            .field("Roles Removed", roles_text, false)
+            .timestamp(chrono::Utc::now());
+
+        if let Some(role_id) = restored_role {
+            embed = embed.field("Roles Added", format!("<@&{}>", role_id), false);
+        }
+
+        if !kept_mentions.is_empty() {
+            embed = embed.field("Protected Roles Kept", kept_mentions.join(", "), false);
+        }
+
+        if member_roles.is_none() {
+            embed = embed.footer(CreateEmbedFooter::new(
+                "The user isn't in the server, only their link was removed.",
+            ));
+        }
 
-    if let Ok(guild_config) = load_guild_config(&ctx.http, &mut conn, guild_id).await {
-        if let Ok(verified_role) = guild_config.get_verified_role()
-            && member.roles.contains(&verified_role)
+        let style = LogStyle::load_or_default(&mut conn, guild_id, LogEvent::Unverified).await;
+        let embed = style.apply(embed, target_id, &removed_roles);
+
+        if let Err(e) = http
+            .send_message(
+                channel_id.into(),
+                Vec::new(),
+                &CreateMessage::new().embed(embed),
+            )
+            .await
         {
-            member.remove_role(&ctx.http, verified_role, None).await?;
-            removed_roles.push(verified_role);
+            tracing::warn!(
+                "Failed to send unverification log to channel {}: {}",
+                channel_id,
+                e
+            );
         }
+    }
 
-        // Also remove level and class roles if present
-        for role_id in member.roles.iter() {
-            if guild_config.level_roles.values().any(|r| r == role_id)
-                || guild_config.class_roles.values().any(|r| r == role_id)
-            {
-                if let Err(e) = member.remove_role(&ctx.http, *role_id, None).await {
-                    tracing::warn!("Failed to remove role {}: {}", role_id, e);
-                } else {
-                    removed_roles.push(*role_id);
-                }
-            }
+    Ok(removed_roles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::discord::mock::MockDiscord;
+    use crate::bot::guild_config::RoleMode;
+    use serenity::all::GuildId;
+    use std::collections::HashMap;
+
+    /// Guild whose verified role is called "Members" rather than "Verified"
+    fn members_role_fixture() -> GuildConfig {
+        GuildConfig {
+            verified_role: Some(RoleId::new(100)),
+            mode: RoleMode::Levels,
+            level_roles: HashMap::from([("Undergrad".to_string(), RoleId::new(200))]),
+            ..GuildConfig::empty(GuildId::new(1))
         }
+    }
 
-        // Log to log channel if configured
-        if let Some(channel_id) = guild_config.get_log_channel() {
-            // Format roles list
-            let roles_mentions: Vec<String> = removed_roles
-                .iter()
-                .map(|role_id| format!("<@&{}>", role_id))
-                .collect();
-            let roles_text = if roles_mentions.is_empty() {
-                "None".to_string()
-            } else {
-                roles_mentions.join(", ")
-            };
-
-            let embed = CreateEmbed::new()
-                .title("User Unverified")
-                .color(0xF38BA8) // Red
-                .field("User", target_user.mention().to_string(), false)
-                .field("Roles Removed", roles_text, false)
-                .timestamp(chrono::Utc::now());
-
-            if let Err(e) = ctx
-                .http
-                .send_message(
-                    channel_id.into(),
-                    Vec::new(),
-                    &CreateMessage::new().embed(embed),
-                )
+    #[test]
+    fn removes_verified_role_by_configured_id() {
+        let config = members_role_fixture();
+        let member_roles = [RoleId::new(100), RoleId::new(200), RoleId::new(300)];
+
+        assert_eq!(
+            removable_roles(&config, &member_roles),
+            vec![RoleId::new(100), RoleId::new(200)]
+        );
+    }
+
+    #[test]
+    fn ignores_unconfigured_roles() {
+        let mut config = members_role_fixture();
+        config.verified_role = None;
+        let member_roles = [RoleId::new(100), RoleId::new(300)];
+
+        assert!(removable_roles(&config, &member_roles).is_empty());
+    }
+
+    #[test]
+    fn removes_attribute_roles() {
+        let mut config = members_role_fixture();
+        config.attribute_roles.insert(
+            ("department".to_string(), "SCS".to_string()),
+            RoleId::new(400),
+        );
+        let member_roles = [RoleId::new(300), RoleId::new(400)];
+
+        assert_eq!(
+            removable_roles(&config, &member_roles),
+            vec![RoleId::new(400)]
+        );
+    }
+
+    #[test]
+    fn skips_protected_roles() {
+        let mut config = members_role_fixture();
+        config.protected_roles.insert(RoleId::new(200));
+        let member_roles = [RoleId::new(100), RoleId::new(200)];
+
+        assert_eq!(
+            removable_roles(&config, &member_roles),
+            vec![RoleId::new(100)]
+        );
+    }
+
+    #[tokio::test]
+    async fn restores_unverified_role() {
+        let mut config = members_role_fixture();
+        config.unverified_role = Some(RoleId::new(500));
+        let user = UserId::new(7);
+        let discord = MockDiscord::with_member(user, &[RoleId::new(100), RoleId::new(300)]);
+
+        let (removed, restored) =
+            remove_verification_roles(&discord, &config, user, &discord.roles_of(user))
                 .await
-            {
-                tracing::warn!(
-                    "Failed to send unverification log to channel {}: {}",
-                    channel_id,
-                    e
-                );
-            }
-        }
+                .unwrap();
+
+        assert_eq!(removed, vec![RoleId::new(100)]);
+        assert_eq!(restored, Some(RoleId::new(500)));
+        assert_eq!(
+            discord.roles_of(user),
+            vec![RoleId::new(300), RoleId::new(500)]
+        );
     }
 
-    let response = CreateInteractionResponse::Message(
-        CreateInteractionResponseMessage::new()
-            .content(format!(
-                "Removed verification for {}.",
-                target_user.mention()
-            ))
-            .ephemeral(true),
-    );
-    command.create_response(&ctx.http, response).await?;
+    #[tokio::test]
+    async fn fails_when_verified_role_cannot_be_removed() {
+        let config = members_role_fixture();
+        let user = UserId::new(7);
+        let mut discord = MockDiscord::with_member(user, &[RoleId::new(100)]);
+        discord.failing_roles.insert(RoleId::new(100));
 
-    Ok(())
+        let result =
+            remove_verification_roles(&discord, &config, user, &discord.roles_of(user)).await;
+
+        assert!(result.is_err());
+    }
 }
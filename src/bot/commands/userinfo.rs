@@ -1,4 +1,6 @@
 use crate::bot::Error;
+use crate::bot::i18n::{self, Locale};
+use crate::keys::{discord_keycloak, redis_key};
 use crate::state::AppState;
 use redis::AsyncCommands;
 use serenity::all::{
@@ -8,7 +10,7 @@ use serenity::all::{
 };
 use std::sync::Arc;
 
-use super::utils::trim_redis_value;
+use super::utils::{format_verified_at, trim_redis_value};
 
 /// Register the userinfo command
 pub fn register() -> CreateCommand<'static> {
@@ -74,14 +76,17 @@ pub async fn handle(
 
     // Look up Keycloak user ID from Redis
     let mut conn = state.redis.clone();
-    let redis_key = format!("discord:{}:keycloak", target_user.id);
+    let redis_key = discord_keycloak(target_user.id);
 
     let keycloak_user_id = match trim_redis_value(conn.get(&redis_key).await?) {
         Some(id) => id,
         None => {
             let response = CreateInteractionResponse::Message(
                 CreateInteractionResponseMessage::new()
-                    .content(format!("{} is not verified.", target_user.mention()))
+                    .content(i18n::not_verified(
+                        Locale::from_discord(&command.locale),
+                        target_user.mention(),
+                    ))
                     .ephemeral(true),
             );
             command.create_response(&ctx.http, response).await?;
@@ -120,11 +125,22 @@ pub async fn handle(
         "Not provided".to_string()
     };
 
-    let embed = CreateEmbed::new()
+    // Links made before the timestamp was recorded don't have one
+    let verified_at = format_verified_at(
+        conn.get(redis_key!("discord:{}:verified_at", target_user.id))
+            .await?,
+    )
+    .unwrap_or_else(|| "Unknown".to_string());
+
+    let mut embed = CreateEmbed::new()
         .title(format!("User Information for {}", target_user.name))
-        .field("Andrew ID", username, false)
-        .field("Full Name", full_name, false)
-        .field("Email", email, false)
+        .field(state.config.identity_label.clone(), username, false)
+        .field("Full Name", full_name, false);
+    if state.config.userinfo_show_email {
+        embed = embed.field("Email", email, false);
+    }
+    let embed = embed
+        .field("Verified", verified_at, false)
         .colour(Colour::BLUE);
 
     let response = CreateInteractionResponse::Message(
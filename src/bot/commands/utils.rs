@@ -1,25 +1,53 @@
 use crate::bot::Error;
 use crate::bot::guild_config::GuildConfig;
-use serenity::all::{Cache, Context, GuildId, Member, Permissions, RoleId, UserId};
+use crate::config::Config;
+use crate::keys::redis_key;
+use crate::redact::redact;
+use serenity::all::{
+    Cache, Channel, ChannelId, ChannelType, CommandInteraction, ComponentInteraction, Context,
+    CreateMessage, EditInteractionResponse, EditThread, GenericChannelId, GuildId, Http, HttpError,
+    Member, Mentionable, PermissionOverwrite, PermissionOverwriteType, Permissions, RoleId, UserId,
+};
+use std::collections::{HashMap, HashSet};
 
-/// Count members with a role from the gateway cache (fast; safe for large guilds).
-pub fn count_guild_members_with_role_cached(
+/// Members without the given role from the gateway cache, skipping bots and members
+/// holding any exempt role. If `min_age_days` is set, only members who joined at least
+/// that long ago are returned.
+pub fn unverified_members_cached(
     guild_id: GuildId,
     cache: &Cache,
     role_id: RoleId,
-) -> (usize, usize) {
+    exempt_roles: &HashSet<RoleId>,
+    min_age_days: Option<i64>,
+) -> Vec<UserId> {
     let Some(guild) = guild_id.to_guild_cached(cache) else {
-        return (0, 0);
+        return Vec::new();
     };
 
-    let total = guild.member_count as usize;
-    let verified = guild
+    let cutoff = min_age_days.map(|days| {
+        chrono::Utc::now()
+            .timestamp()
+            .saturating_sub(days.saturating_mul(86_400))
+    });
+
+    guild
         .members
         .iter()
-        .filter(|m| m.roles.contains(&role_id))
-        .count();
+        .filter(|m| !m.user.bot() && !m.roles.contains(&role_id))
+        .filter(|m| !m.roles.iter().any(|r| exempt_roles.contains(r)))
+        .filter(|m| match (cutoff, m.joined_at) {
+            (Some(cutoff), Some(joined_at)) => joined_at.unix_timestamp() <= cutoff,
+            (Some(_), None) => false,
+            (None, _) => true,
+        })
+        .map(|m| m.user.id)
+        .collect()
+}
 
-    (verified, total)
+/// Whether the user runs the bot, for operator commands that reach beyond one server.
+/// Server admins aren't owners, and nobody is when no owner ids are configured.
+pub fn is_owner(config: &Config, user_id: UserId) -> bool {
+    config.owner_ids.contains(&user_id.get())
 }
 
 /// Normalize a Redis string value (migration may have left trailing newlines).
@@ -29,6 +57,15 @@ pub fn trim_redis_value(value: Option<String>) -> Option<String> {
         .filter(|s| !s.is_empty())
 }
 
+/// Format a stored `discord:{}:verified_at` unix timestamp for display. None for links
+/// made before the timestamp was recorded, or a value that isn't a timestamp.
+pub fn format_verified_at(value: Option<String>) -> Option<String> {
+    trim_redis_value(value)
+        .and_then(|s| s.parse::<i64>().ok())
+        .and_then(|t| chrono::DateTime::from_timestamp(t, 0))
+        .map(|t| t.format("%B %-d, %Y %H:%M UTC").to_string())
+}
+
 /// Helper function to load the guild's configuration
 pub async fn load_guild_config(
     http: &serenity::all::Http,
@@ -38,42 +75,827 @@ pub async fn load_guild_config(
     GuildConfig::load(redis, http, guild_id).await
 }
 
-/// Check if a user has administrator permissions in a guild
+/// Whether a user is currently a member of a guild. Only Discord's 404 counts as not a
+/// member, other failures are returned as errors.
+pub async fn is_guild_member(
+    http: &Http,
+    guild_id: GuildId,
+    user_id: UserId,
+) -> Result<bool, Error> {
+    match guild_id.member(http, user_id).await {
+        Ok(_) => Ok(true),
+        Err(serenity::Error::Http(HttpError::UnsuccessfulRequest(response)))
+            if response.status_code.as_u16() == 404 =>
+        {
+            Ok(false)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Why a member counts as an admin, so audit logs can say how access was granted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdminGrant {
+    /// The guild owner, who has every permission
+    Owner,
+    /// Administrator from a role, the guild's id for @everyone
+    Permission(RoleId),
+}
+
+impl std::fmt::Display for AdminGrant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AdminGrant::Owner => write!(f, "server owner"),
+            AdminGrant::Permission(role_id) => write!(f, "Administrator from role {}", role_id),
+        }
+    }
+}
+
+/// Check if a user has administrator permissions in a guild.
+/// Use [`admin_grant`] instead when the reason should be logged.
 pub async fn is_admin(
     ctx: &Context,
     member_option: &Option<Box<Member>>,
     guild_id: GuildId,
     user_id: UserId,
 ) -> Result<bool, Error> {
+    Ok(admin_grant(ctx, member_option, guild_id, user_id)
+        .await?
+        .is_some())
+}
+
+/// How a user has administrator permissions in a guild, `None` if they don't.
+/// Falls back to fetching the guild over HTTP while the cache is still warming up.
+pub async fn admin_grant(
+    ctx: &Context,
+    member_option: &Option<Box<Member>>,
+    guild_id: GuildId,
+    user_id: UserId,
+) -> Result<Option<AdminGrant>, Error> {
     // Get the member from the option or fetch it
     let member = match member_option {
         Some(m) => (**m).clone(),
         None => guild_id.member(&ctx.http, user_id).await?,
     };
 
-    let guild = guild_id
-        .to_guild_cached(&ctx.cache)
-        .ok_or("Guild not found in cache")?;
+    let (owner_id, role_permissions) = role_permissions(&ctx.http, &ctx.cache, guild_id).await?;
+
+    Ok(admin_permissions_grant(
+        guild_id,
+        owner_id,
+        user_id,
+        &member.roles,
+        &role_permissions,
+    ))
+}
+
+/// The guild owner and each role's permissions, fetched over HTTP if the guild isn't cached
+async fn role_permissions(
+    http: &Http,
+    cache: &Cache,
+    guild_id: GuildId,
+) -> Result<(UserId, HashMap<RoleId, Permissions>), Error> {
+    if let Some(cached) = cached_role_permissions(cache, guild_id) {
+        return Ok(cached);
+    }
 
-    // Check if user is the owner
-    if guild.owner_id == user_id {
-        return Ok(true);
+    let guild = guild_id.to_partial_guild(http).await?;
+    let role_permissions = guild.roles.iter().map(|r| (r.id, r.permissions)).collect();
+    Ok((guild.owner_id, role_permissions))
+}
+
+/// The guild owner and each role's permissions from the cache, `None` if the guild isn't cached
+fn cached_role_permissions(
+    cache: &Cache,
+    guild_id: GuildId,
+) -> Option<(UserId, HashMap<RoleId, Permissions>)> {
+    let guild = guild_id.to_guild_cached(cache)?;
+    let role_permissions = guild.roles.iter().map(|r| (r.id, r.permissions)).collect();
+    Some((guild.owner_id, role_permissions))
+}
+
+/// How a member is the owner or has administrator from @everyone or their roles.
+/// Only guild-wide permissions are considered, channel overwrites don't apply.
+fn admin_permissions_grant(
+    guild_id: GuildId,
+    owner_id: UserId,
+    user_id: UserId,
+    member_roles: &[RoleId],
+    role_permissions: &HashMap<RoleId, Permissions>,
+) -> Option<AdminGrant> {
+    if owner_id == user_id {
+        return Some(AdminGrant::Owner);
     }
 
-    // For guild-wide permissions, compute from base roles
-    let mut permissions = Permissions::empty();
+    // @everyone shares the guild's id
+    std::iter::once(&RoleId::new(guild_id.get()))
+        .chain(member_roles)
+        .find(|role_id| {
+            role_permissions
+                .get(role_id)
+                .is_some_and(|p| p.administrator())
+        })
+        .map(|role_id| AdminGrant::Permission(*role_id))
+}
 
-    // Add @everyone role permissions
-    if let Some(everyone_role) = guild.roles.get(&RoleId::new(guild_id.get())) {
-        permissions |= everyone_role.permissions;
+/// Guild-wide permissions from @everyone and the member's roles
+fn base_permissions(
+    guild_id: GuildId,
+    member_roles: &[RoleId],
+    role_permissions: &HashMap<RoleId, Permissions>,
+) -> Permissions {
+    // @everyone shares the guild's id
+    std::iter::once(&RoleId::new(guild_id.get()))
+        .chain(member_roles)
+        .filter_map(|role_id| role_permissions.get(role_id))
+        .fold(Permissions::empty(), |acc, p| acc | *p)
+}
+
+/// A member's permissions in a channel, applying the channel's overwrites in Discord's
+/// order: @everyone, then the member's roles combined, then the member.
+/// A channel synced with its category holds the category's overwrites itself, so a
+/// category that denies a permission is covered by the channel's own overwrites.
+fn channel_permissions(
+    guild_id: GuildId,
+    owner_id: UserId,
+    user_id: UserId,
+    member_roles: &[RoleId],
+    role_permissions: &HashMap<RoleId, Permissions>,
+    overwrites: &[PermissionOverwrite],
+) -> Permissions {
+    if owner_id == user_id {
+        return Permissions::all();
+    }
+
+    let mut permissions = base_permissions(guild_id, member_roles, role_permissions);
+    if permissions.administrator() {
+        return Permissions::all();
+    }
+
+    // Overwrites apply in tiers, each tier's denies before its allows
+    let everyone = RoleId::new(guild_id.get());
+    let tier = |kind: &PermissionOverwriteType| match kind {
+        PermissionOverwriteType::Role(id) if *id == everyone => Some(0),
+        PermissionOverwriteType::Role(id) if member_roles.contains(id) => Some(1),
+        PermissionOverwriteType::Member(id) if *id == user_id => Some(2),
+        _ => None,
+    };
+
+    for level in 0..3 {
+        let (allow, deny) = overwrites
+            .iter()
+            .filter(|o| tier(&o.kind) == Some(level))
+            .fold((Permissions::empty(), Permissions::empty()), |(a, d), o| {
+                (a | o.allow, d | o.deny)
+            });
+        permissions = (permissions & !deny) | allow;
     }
 
-    // Add member's role permissions
-    for role_id in &member.roles {
-        if let Some(role) = guild.roles.get(role_id) {
-            permissions |= role.permissions;
+    permissions
+}
+
+/// A deferred interaction response. Slow handlers defer first so Discord doesn't fail
+/// the interaction after its 3 second deadline, then deliver the reply with [`Deferred::edit`].
+pub struct Deferred<'a> {
+    http: &'a Http,
+    interaction: DeferredInteraction<'a>,
+}
+
+enum DeferredInteraction<'a> {
+    Command(&'a CommandInteraction),
+    Component(&'a ComponentInteraction),
+}
+
+impl<'a> Deferred<'a> {
+    /// Defer a slash command, showing an ephemeral "thinking" state until the reply
+    pub async fn command(http: &'a Http, command: &'a CommandInteraction) -> Result<Self, Error> {
+        command.defer_ephemeral(http).await?;
+        Ok(Self {
+            http,
+            interaction: DeferredInteraction::Command(command),
+        })
+    }
+
+    /// Defer a component interaction, keeping its message in place until the reply
+    pub async fn component(
+        http: &'a Http,
+        interaction: &'a ComponentInteraction,
+    ) -> Result<Self, Error> {
+        interaction.defer(http).await?;
+        Ok(Self {
+            http,
+            interaction: DeferredInteraction::Component(interaction),
+        })
+    }
+
+    /// Defer a component interaction with a new ephemeral reply, leaving its message as is
+    pub async fn component_ephemeral(
+        http: &'a Http,
+        interaction: &'a ComponentInteraction,
+    ) -> Result<Self, Error> {
+        interaction.defer_ephemeral(http).await?;
+        Ok(Self {
+            http,
+            interaction: DeferredInteraction::Component(interaction),
+        })
+    }
+
+    /// Replace the deferred response with the reply
+    pub async fn edit(&self, response: EditInteractionResponse<'_>) -> Result<(), Error> {
+        match self.interaction {
+            DeferredInteraction::Command(command) => {
+                command.edit_response(self.http, response).await?;
+            }
+            DeferredInteraction::Component(interaction) => {
+                interaction.edit_response(self.http, response).await?;
+            }
         }
+        Ok(())
     }
+}
 
-    Ok(permissions.administrator())
+/// Why the bot can't post to a log channel
+pub enum LogChannelProblem {
+    Inaccessible,
+    NotTextChannel,
+    ForumChannel,
+    LockedThread,
+    MissingSendPermission,
+}
+
+impl LogChannelProblem {
+    /// Explain the problem for the given channel to an admin
+    pub fn describe(&self, channel_id: GenericChannelId) -> String {
+        match self {
+            Self::Inaccessible => format!(
+                "Unable to access {}. Please make sure the bot has permission to view it.",
+                channel_id.mention()
+            ),
+            Self::NotTextChannel => {
+                "The log channel must be a text channel, news channel or thread.".to_string()
+            }
+            Self::ForumChannel => format!(
+                "{} is a forum channel, which can't be posted to directly. Please choose a post in it instead.",
+                channel_id.mention()
+            ),
+            Self::LockedThread => format!(
+                "{} is a locked thread. Please unlock it or choose another channel.",
+                channel_id.mention()
+            ),
+            Self::MissingSendPermission => format!(
+                "I don't have permission to send messages in {}. Please update my permissions for that channel.",
+                channel_id.mention()
+            ),
+        }
+    }
+}
+
+/// Check that the bot can post to a channel, returning the problem if it can't
+pub async fn check_log_channel(
+    http: &Http,
+    cache: &Cache,
+    guild_id: GuildId,
+    channel_id: GenericChannelId,
+) -> Result<Option<LogChannelProblem>, Error> {
+    // Validate the channel exists and we can see it
+    let full_channel = match http.get_channel(channel_id).await {
+        Ok(channel) => channel,
+        Err(e) if is_inaccessible(&e) => return Ok(Some(LogChannelProblem::Inaccessible)),
+        Err(e) => return Err(e.into()),
+    };
+
+    // Reject channels that can't hold messages before fetching anything else
+    match &full_channel {
+        Channel::Guild(gc) if matches!(gc.base.kind, ChannelType::Text | ChannelType::News) => {}
+        Channel::Guild(gc) if gc.base.kind == ChannelType::Forum => {
+            return Ok(Some(LogChannelProblem::ForumChannel));
+        }
+        Channel::GuildThread(thread) if thread.thread_metadata.locked => {
+            return Ok(Some(LogChannelProblem::LockedThread));
+        }
+        Channel::GuildThread(_) => {}
+        _ => return Ok(Some(LogChannelProblem::NotTextChannel)),
+    }
+
+    // Check if the bot has permission to send messages in the channel
+    let bot_user_id = cache.current_user().id;
+    let bot_member = guild_id.member(http, bot_user_id).await?;
+
+    let (overwrites, send_permission) = match &full_channel {
+        // Threads inherit permissions from their parent channel
+        Channel::GuildThread(thread) => match http.get_channel(thread.parent_id.into()).await {
+            Ok(Channel::Guild(parent)) => (
+                parent.base.permission_overwrites.to_vec(),
+                Permissions::SEND_MESSAGES_IN_THREADS,
+            ),
+            Ok(_) => return Ok(Some(LogChannelProblem::NotTextChannel)),
+            Err(e) if is_inaccessible(&e) => return Ok(Some(LogChannelProblem::Inaccessible)),
+            Err(e) => return Err(e.into()),
+        },
+        Channel::Guild(gc) => (
+            gc.base.permission_overwrites.to_vec(),
+            Permissions::SEND_MESSAGES,
+        ),
+        _ => return Ok(Some(LogChannelProblem::NotTextChannel)),
+    };
+
+    let (owner_id, role_permissions) = role_permissions(http, cache, guild_id).await?;
+    let permissions = channel_permissions(
+        guild_id,
+        owner_id,
+        bot_user_id,
+        &bot_member.roles,
+        &role_permissions,
+        &overwrites,
+    );
+    let has_permission = permissions.contains(Permissions::VIEW_CHANNEL | send_permission);
+
+    if !has_permission {
+        return Ok(Some(LogChannelProblem::MissingSendPermission));
+    }
+
+    Ok(None)
+}
+
+/// Whether Discord answered with Missing Access or Unknown Channel. Anything else, like
+/// an outage or a rate limit, says nothing about whether the channel is still usable.
+fn is_inaccessible(error: &serenity::Error) -> bool {
+    matches!(
+        error,
+        serenity::Error::Http(HttpError::UnsuccessfulRequest(response))
+            if matches!(response.status_code.as_u16(), 403 | 404)
+    )
+}
+
+/// Why the bot can't assign a role
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoleAssignProblem {
+    MissingManageRoles,
+    /// The role is at or above the bot's highest role
+    AboveBot,
+}
+
+impl RoleAssignProblem {
+    pub fn describe(&self, role_id: RoleId) -> String {
+        match self {
+            Self::MissingManageRoles => {
+                "I no longer have the Manage Roles permission, please grant it to my role."
+                    .to_string()
+            }
+            Self::AboveBot => format!(
+                "{} is above my highest role, please move my role above it in the server settings.",
+                role_id.mention()
+            ),
+        }
+    }
+}
+
+/// Check the bot can still assign a role. Roles can be moved and permissions changed
+/// after a role was configured, so this runs again when the role is assigned.
+pub async fn role_assign_problem(
+    http: &Http,
+    cache: &Cache,
+    guild_id: GuildId,
+    role_id: RoleId,
+) -> Result<Option<RoleAssignProblem>, Error> {
+    let bot_member = guild_id.member(http, cache.current_user().id).await?;
+
+    let roles: HashMap<RoleId, (u16, Permissions)> = match guild_id.to_guild_cached(cache) {
+        Some(guild) => guild
+            .roles
+            .iter()
+            .map(|r| (r.id, (r.position, r.permissions)))
+            .collect(),
+        None => guild_id
+            .roles(http)
+            .await?
+            .iter()
+            .map(|r| (r.id, (r.position, r.permissions)))
+            .collect(),
+    };
+
+    Ok(assign_problem(guild_id, &bot_member.roles, &roles, role_id))
+}
+
+/// Whether roles with the given positions and permissions let the bot assign a role
+fn assign_problem(
+    guild_id: GuildId,
+    bot_roles: &[RoleId],
+    roles: &HashMap<RoleId, (u16, Permissions)>,
+    role_id: RoleId,
+) -> Option<RoleAssignProblem> {
+    let role_permissions = roles
+        .iter()
+        .map(|(role_id, (_, permissions))| (*role_id, *permissions))
+        .collect();
+    let permissions = base_permissions(guild_id, bot_roles, &role_permissions);
+    if !permissions.intersects(Permissions::MANAGE_ROLES | Permissions::ADMINISTRATOR) {
+        return Some(RoleAssignProblem::MissingManageRoles);
+    }
+
+    let bot_position = bot_roles
+        .iter()
+        .filter_map(|role_id| roles.get(role_id))
+        .map(|(position, _)| *position)
+        .max()
+        .unwrap_or(0);
+    let role_position = roles.get(&role_id).map_or(0, |(position, _)| *position);
+    if bot_position <= role_position {
+        return Some(RoleAssignProblem::AboveBot);
+    }
+
+    None
+}
+
+/// Whether an error is Discord refusing a request for lack of permissions or role
+/// hierarchy, rather than something worth retrying
+pub fn is_missing_permissions(error: &Error) -> bool {
+    matches!(
+        error.downcast_ref::<serenity::Error>(),
+        Some(serenity::Error::Http(HttpError::UnsuccessfulRequest(response)))
+            if response.status_code.as_u16() == 403
+    )
+}
+
+/// DM a user unless they turned bot DMs off with `/dms`. Returns whether the DM was
+/// sent, `Ok(false)` for an opted out user. Closed DMs are an error for the caller to
+/// log, never a reason to fail what the DM was about.
+pub async fn send_dm(
+    http: &Http,
+    redis: &mut redis::aio::ConnectionManager,
+    user_id: UserId,
+    message: CreateMessage<'_>,
+) -> Result<bool, Error> {
+    let opted_out: bool = redis::cmd("EXISTS")
+        .arg(redis_key!("user:{}:dm_opt_out", user_id))
+        .query_async(redis)
+        .await?;
+    if opted_out {
+        tracing::debug!("User {} opted out of DMs, not sending", redact(user_id));
+        return Ok(false);
+    }
+
+    user_id.direct_message(http, message).await?;
+    Ok(true)
+}
+
+/// How long a log channel that passed the check is trusted without checking again
+const LOG_CHANNEL_CHECK_SECS: u64 = 5 * 60;
+
+/// Where to send a log, checking the configured log channel is still writable first.
+/// A log thread that auto-archived is reopened, and if it can't be, logs go to its
+/// parent channel instead. If nothing is writable, the log channel is cleared and the
+/// guild owner is DMed once about it, so logs aren't silently lost.
+pub async fn log_destination(
+    http: &Http,
+    cache: &Cache,
+    redis: &mut redis::aio::ConnectionManager,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+) -> Option<ChannelId> {
+    // The check costs a few requests, so busy guilds only run it every few minutes
+    let checked_key = redis_key!("guild:{}:log_channel_checked", guild_id);
+    match redis::cmd("GET")
+        .arg(&checked_key)
+        .query_async::<Option<String>>(redis)
+        .await
+    {
+        Ok(value) if trim_redis_value(value) == Some(channel_id.to_string()) => {
+            return Some(channel_id);
+        }
+        Ok(_) => {}
+        Err(e) => {
+            tracing::debug!(
+                "Failed to read log channel check for guild {}: {}",
+                guild_id,
+                e
+            );
+        }
+    }
+
+    // Set by /setlogchannel when the log channel is a thread, holding its parent
+    let parent_id = match redis::cmd("GET")
+        .arg(redis_key!("guild:{}:log_thread", guild_id))
+        .query_async::<Option<String>>(redis)
+        .await
+    {
+        Ok(value) => trim_redis_value(value)
+            .and_then(|s| s.parse::<u64>().ok())
+            .map(ChannelId::new),
+        Err(e) => {
+            tracing::debug!("Failed to read log thread for guild {}: {}", guild_id, e);
+            None
+        }
+    };
+
+    if let Some(parent_id) = parent_id
+        && let Err(e) = reopen_log_thread(http, channel_id).await
+    {
+        tracing::warn!(
+            "Failed to reopen log thread {} in guild {}, falling back to channel {}: {}",
+            channel_id,
+            guild_id,
+            parent_id,
+            e
+        );
+        if let Ok(None) = check_log_channel(http, cache, guild_id, parent_id.into()).await {
+            return Some(parent_id);
+        }
+    }
+
+    let problem = match check_log_channel(http, cache, guild_id, channel_id.into()).await {
+        Ok(Some(problem)) => problem,
+        Ok(None) => {
+            if let Err(e) = redis::cmd("SET")
+                .arg(&checked_key)
+                .arg(channel_id.to_string())
+                .arg("EX")
+                .arg(LOG_CHANNEL_CHECK_SECS)
+                .query_async::<()>(redis)
+                .await
+            {
+                tracing::debug!(
+                    "Failed to record log channel check for guild {}: {}",
+                    guild_id,
+                    e
+                );
+            }
+            return Some(channel_id);
+        }
+        Err(e) => {
+            // Couldn't tell, so still try to send
+            tracing::debug!("Failed to check log channel {}: {}", channel_id, e);
+            return Some(channel_id);
+        }
+    };
+
+    tracing::warn!(
+        "Log channel {} in guild {} is no longer writable, clearing it",
+        channel_id,
+        guild_id
+    );
+
+    // Clearing the key means the owner is only warned once
+    if let Err(e) = redis::cmd("DEL")
+        .arg(redis_key!("guild:{}:log_channel", guild_id))
+        .arg(redis_key!("guild:{}:log_thread", guild_id))
+        .arg(&checked_key)
+        .query_async::<()>(redis)
+        .await
+    {
+        tracing::warn!("Failed to clear log channel for guild {}: {}", guild_id, e);
+    }
+
+    let owner_id = match guild_id.to_partial_guild(http).await {
+        Ok(guild) => guild.owner_id,
+        Err(e) => {
+            tracing::warn!("Failed to fetch guild {} owner: {}", guild_id, e);
+            return None;
+        }
+    };
+
+    let message = format!(
+        "Verification logs for **{}** can no longer be sent: {}\n\n\
+        The log channel has been cleared. Run `/setlogchannel` again once this is fixed.",
+        guild_id
+            .to_guild_cached(cache)
+            .map(|g| g.name.to_string())
+            .unwrap_or_else(|| guild_id.to_string()),
+        problem.describe(channel_id.into())
+    );
+
+    if let Err(e) = send_dm(http, redis, owner_id, CreateMessage::new().content(message)).await {
+        tracing::warn!(
+            "Failed to warn guild owner {} about log channel: {}",
+            redact(owner_id),
+            e
+        );
+    }
+
+    None
+}
+
+/// Unarchive a log thread that auto-archived. Sending to an archived thread fails, and
+/// only members with Manage Threads can reopen a locked one, so a locked thread is an
+/// error for the caller to fall back from.
+async fn reopen_log_thread(http: &Http, thread_id: ChannelId) -> Result<(), Error> {
+    let Channel::GuildThread(thread) = http.get_channel(thread_id.into()).await? else {
+        return Ok(());
+    };
+
+    if thread.thread_metadata.locked {
+        return Err(format!("thread {} is locked", thread_id).into());
+    }
+    if !thread.thread_metadata.archived {
+        return Ok(());
+    }
+
+    thread_id
+        .edit_thread(http, EditThread::new().archived(false))
+        .await?;
+    tracing::info!("Reopened archived log thread {}", thread_id);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUILD: GuildId = GuildId::new(1);
+    const OWNER: UserId = UserId::new(10);
+    const MEMBER: UserId = UserId::new(20);
+
+    fn role_permissions() -> HashMap<RoleId, Permissions> {
+        HashMap::from([
+            (RoleId::new(GUILD.get()), Permissions::SEND_MESSAGES),
+            (RoleId::new(100), Permissions::ADMINISTRATOR),
+            (RoleId::new(200), Permissions::MANAGE_ROLES),
+        ])
+    }
+
+    fn positioned_roles() -> HashMap<RoleId, (u16, Permissions)> {
+        HashMap::from([
+            (RoleId::new(GUILD.get()), (0, Permissions::SEND_MESSAGES)),
+            (RoleId::new(100), (5, Permissions::MANAGE_ROLES)),
+            (RoleId::new(200), (3, Permissions::empty())),
+            (RoleId::new(300), (8, Permissions::empty())),
+        ])
+    }
+
+    #[test]
+    fn can_assign_roles_below_the_bot() {
+        let bot_roles = [RoleId::new(100)];
+        assert_eq!(
+            assign_problem(GUILD, &bot_roles, &positioned_roles(), RoleId::new(200)),
+            None
+        );
+    }
+
+    #[test]
+    fn cannot_assign_roles_at_or_above_the_bot() {
+        let bot_roles = [RoleId::new(100)];
+        assert_eq!(
+            assign_problem(GUILD, &bot_roles, &positioned_roles(), RoleId::new(300)),
+            Some(RoleAssignProblem::AboveBot)
+        );
+        assert_eq!(
+            assign_problem(GUILD, &bot_roles, &positioned_roles(), RoleId::new(100)),
+            Some(RoleAssignProblem::AboveBot)
+        );
+    }
+
+    #[test]
+    fn cannot_assign_roles_without_manage_roles() {
+        let bot_roles = [RoleId::new(300)];
+        assert_eq!(
+            assign_problem(GUILD, &bot_roles, &positioned_roles(), RoleId::new(200)),
+            Some(RoleAssignProblem::MissingManageRoles)
+        );
+    }
+
+    #[test]
+    fn other_errors_are_not_missing_permissions() {
+        let error: Error = "Missing permissions for role 1".into();
+        assert!(!is_missing_permissions(&error));
+    }
+
+    #[test]
+    fn empty_cache_falls_back_instead_of_failing() {
+        assert!(cached_role_permissions(&Cache::new(), GUILD).is_none());
+    }
+
+    #[test]
+    fn owner_is_admin_without_roles() {
+        assert_eq!(
+            admin_permissions_grant(GUILD, OWNER, OWNER, &[], &role_permissions()),
+            Some(AdminGrant::Owner)
+        );
+    }
+
+    #[test]
+    fn administrator_role_grants_admin() {
+        let roles = [RoleId::new(200), RoleId::new(100)];
+        assert_eq!(
+            admin_permissions_grant(GUILD, OWNER, MEMBER, &roles, &role_permissions()),
+            Some(AdminGrant::Permission(RoleId::new(100)))
+        );
+    }
+
+    #[test]
+    fn other_permissions_do_not_grant_admin() {
+        let roles = [RoleId::new(200), RoleId::new(300)];
+        assert_eq!(
+            admin_permissions_grant(GUILD, OWNER, MEMBER, &roles, &role_permissions()),
+            None
+        );
+    }
+
+    fn overwrite(
+        kind: PermissionOverwriteType,
+        allow: Permissions,
+        deny: Permissions,
+    ) -> PermissionOverwrite {
+        PermissionOverwrite { allow, deny, kind }
+    }
+
+    fn sendable(overwrites: &[PermissionOverwrite], member_roles: &[RoleId]) -> bool {
+        let mut permissions = role_permissions();
+        permissions.insert(
+            RoleId::new(GUILD.get()),
+            Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES,
+        );
+        channel_permissions(GUILD, OWNER, MEMBER, member_roles, &permissions, overwrites)
+            .contains(Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES)
+    }
+
+    #[test]
+    fn synced_category_deny_blocks_sending() {
+        // The channel doesn't override anything, so it carries the category's overwrites
+        let category = [overwrite(
+            PermissionOverwriteType::Role(RoleId::new(GUILD.get())),
+            Permissions::empty(),
+            Permissions::SEND_MESSAGES,
+        )];
+        assert!(!sendable(&category, &[RoleId::new(200)]));
+    }
+
+    #[test]
+    fn role_allow_overrides_everyone_deny() {
+        let overwrites = [
+            overwrite(
+                PermissionOverwriteType::Role(RoleId::new(GUILD.get())),
+                Permissions::empty(),
+                Permissions::SEND_MESSAGES,
+            ),
+            overwrite(
+                PermissionOverwriteType::Role(RoleId::new(200)),
+                Permissions::SEND_MESSAGES,
+                Permissions::empty(),
+            ),
+        ];
+        assert!(sendable(&overwrites, &[RoleId::new(200)]));
+        assert!(!sendable(&overwrites, &[]));
+    }
+
+    #[test]
+    fn member_deny_overrides_role_allow() {
+        let overwrites = [
+            overwrite(
+                PermissionOverwriteType::Role(RoleId::new(200)),
+                Permissions::SEND_MESSAGES,
+                Permissions::empty(),
+            ),
+            overwrite(
+                PermissionOverwriteType::Member(MEMBER),
+                Permissions::empty(),
+                Permissions::SEND_MESSAGES,
+            ),
+        ];
+        assert!(!sendable(&overwrites, &[RoleId::new(200)]));
+    }
+
+    #[test]
+    fn administrator_ignores_overwrites() {
+        let overwrites = [overwrite(
+            PermissionOverwriteType::Member(MEMBER),
+            Permissions::empty(),
+            Permissions::SEND_MESSAGES,
+        )];
+        assert!(sendable(&overwrites, &[RoleId::new(100)]));
+    }
+
+    #[test]
+    fn everyone_role_applies_to_all_members() {
+        let mut permissions = role_permissions();
+        permissions.insert(RoleId::new(GUILD.get()), Permissions::ADMINISTRATOR);
+        assert_eq!(
+            admin_permissions_grant(GUILD, OWNER, MEMBER, &[], &permissions),
+            Some(AdminGrant::Permission(RoleId::new(GUILD.get())))
+        );
+    }
+
+    #[test]
+    fn formats_verified_at() {
+        assert_eq!(
+            format_verified_at(Some("1700000000\n".to_string())),
+            Some("November 14, 2023 22:13 UTC".to_string())
+        );
+    }
+
+    #[test]
+    fn missing_verified_at_is_unknown() {
+        assert_eq!(format_verified_at(None), None);
+        assert_eq!(format_verified_at(Some("not a time".to_string())), None);
+    }
+
+    #[test]
+    fn owners_come_from_config() {
+        let mut config = Config::for_tests();
+        assert!(!is_owner(&config, OWNER));
+
+        config.owner_ids = vec![OWNER.get(), 30];
+        assert!(is_owner(&config, OWNER));
+        assert!(is_owner(&config, UserId::new(30)));
+        assert!(!is_owner(&config, MEMBER));
+    }
 }
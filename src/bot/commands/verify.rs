@@ -1,20 +1,112 @@
 use crate::bot::Error;
-use crate::state::{AppState, PendingVerification};
+use crate::bot::discord::DiscordApi;
+use crate::bot::guild_config::{GuildConfig, email_domain};
+use crate::bot::i18n::{self, Locale};
+use crate::config::Config;
+use crate::keys::{discord_keycloak, redis_key};
+use crate::redact::redact;
+use crate::state::{
+    AppState, PENDING_VERIFICATION_TTL_SECS, PendingVerification, VerificationComplete,
+    guild_pending_index_key, pending_index_key,
+};
+use crate::web::claims::VerifyClaims;
+use crate::webhook::VerificationEvent;
 use redis::AsyncCommands;
 use serenity::all::{
-    CommandInteraction, Context, CreateCommand, CreateEmbed, CreateInteractionResponse,
-    CreateInteractionResponseMessage, CreateMessage, GuildId, Mentionable, RoleId, UserId,
+    CommandInteraction, ComponentInteraction, Context, CreateActionRow, CreateButton,
+    CreateCommand, CreateComponent, CreateEmbed, CreateEmbedFooter, CreateInteractionResponse,
+    CreateInteractionResponseMessage, CreateMessage, EditInteractionResponse, EditMember, GuildId,
+    Member, Mentionable, RoleId, User, UserId,
 };
 use std::sync::Arc;
+use tracing::Instrument;
 use uuid::Uuid;
 
-use super::utils::{load_guild_config, trim_redis_value};
+use super::setlogstyle::{LogEvent, LogStyle};
+use super::setverifygate::{GateBlock, VerifyGate};
+use super::utils::{
+    self, Deferred, is_guild_member, load_guild_config, log_destination, trim_redis_value,
+};
+
+use std::collections::{HashMap, HashSet};
+
+/// Number of recent verification durations kept per guild for reporting
+pub const MAX_DURATION_SAMPLES: usize = 1000;
+
+/// How long Keycloak attributes fetched for a user are reused when they verify in another server
+pub const ATTRIBUTE_CACHE_TTL_SECS: u64 = 24 * 60 * 60;
+
+/// How long a worker holds the per-user completion lock, longer than a completion
+/// takes even when Discord rate limits role changes
+const COMPLETION_LOCK_TTL_MS: u64 = 60_000;
+
+/// How long a verification completed outside the guild waits for the user to rejoin
+pub const AWAITING_JOIN_TTL_SECS: u64 = 30 * 24 * 60 * 60;
+
+/// How a completed verification ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Completion {
+    /// Roles were assigned
+    Verified,
+    /// The user isn't in the guild, roles are assigned when they join
+    AwaitingJoin,
+    /// Another worker is already completing this user's verification in the guild
+    InProgress,
+    /// The bot can't assign the verified role, the server's admins were alerted
+    RoleUnassignable,
+    /// The user's email isn't from a domain the guild requires, no roles were assigned
+    EmailDomainRejected,
+}
+
+/// A step of the verification funnel, counted per guild to tell re-verifications from new ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyEvent {
+    /// A fresh verification link was issued
+    NewToken,
+    /// The user was already linked and verified without the web flow
+    AlreadyVerified,
+    /// A verification started from a link finished
+    Completed,
+}
+
+impl VerifyEvent {
+    /// Field in the guild's `verify_counts` hash
+    pub fn as_str(self) -> &'static str {
+        match self {
+            VerifyEvent::NewToken => "new_token",
+            VerifyEvent::AlreadyVerified => "already_verified",
+            VerifyEvent::Completed => "completed",
+        }
+    }
+}
+
+/// Log a funnel event and bump its counter. Counting is best effort and never fails verification.
+async fn record_verify_event(
+    conn: &mut redis::aio::ConnectionManager,
+    guild_id: GuildId,
+    event: VerifyEvent,
+) {
+    tracing::info!(verify_event = event.as_str(), guild_id = %guild_id, "Verification event");
 
-use std::collections::HashSet;
+    let result: redis::RedisResult<()> = conn
+        .hincr(
+            redis_key!("guild:{}:verify_counts", guild_id),
+            event.as_str(),
+            1,
+        )
+        .await;
+    if let Err(e) = result {
+        tracing::warn!(
+            "Failed to count {} verification event: {}",
+            event.as_str(),
+            e
+        );
+    }
+}
 
 /// Register the verify command
-pub fn register() -> CreateCommand<'static> {
-    CreateCommand::new("verify").description("Verify your Andrew ID")
+pub fn register(identity_label: &str) -> CreateCommand<'static> {
+    CreateCommand::new("verify").description(format!("Verify your {}", identity_label))
 }
 
 /// Handle the verify command
@@ -23,7 +115,7 @@ pub async fn handle(
     command: &CommandInteraction,
     state: &Arc<AppState>,
 ) -> Result<(), Error> {
-    let user = &command.user;
+    let locale = Locale::from_discord(&command.locale);
 
     // Get guild_id from context
     let guild_id = match command.guild_id {
@@ -31,7 +123,7 @@ pub async fn handle(
         None => {
             let response = CreateInteractionResponse::Message(
                 CreateInteractionResponseMessage::new()
-                    .content("This command can only be used in a server.")
+                    .content(i18n::server_only(locale))
                     .ephemeral(true),
             );
             command.create_response(&ctx.http, response).await?;
@@ -39,31 +131,202 @@ pub async fn handle(
         }
     };
 
-    // Check if user is already verified globally
+    // Completing an already linked user calls Keycloak and Discord, so reply later
+    let reply = Deferred::command(&ctx.http, command).await?;
+    let result = start_verification(
+        ctx,
+        &command.user,
+        command.member.as_deref(),
+        guild_id,
+        &command.locale,
+        state,
+    )
+    .await;
+
+    deliver(&reply, result).await
+}
+
+/// Handle the "Verify" button posted by /postverifybutton, same as running /verify
+pub async fn handle_component(
+    ctx: &Context,
+    interaction: &ComponentInteraction,
+    state: &Arc<AppState>,
+) -> Result<(), Error> {
+    let Some(guild_id) = interaction.guild_id else {
+        return Ok(());
+    };
+
+    // A new ephemeral reply, the button's message stays as it is
+    let reply = Deferred::component_ephemeral(&ctx.http, interaction).await?;
+    let result = start_verification(
+        ctx,
+        &interaction.user,
+        interaction.member.as_deref(),
+        guild_id,
+        &interaction.locale,
+        state,
+    )
+    .await;
+
+    deliver(&reply, result).await
+}
+
+/// Send the reply to a deferred /verify. Errors are shown to the user in the reply,
+/// since the deferred response can't be answered again by the command dispatcher.
+async fn deliver(
+    reply: &Deferred<'_>,
+    result: Result<EditInteractionResponse<'static>, Error>,
+) -> Result<(), Error> {
+    match result {
+        Ok(response) => reply.edit(response).await,
+        Err(e) => {
+            tracing::error!("Failed to start verification: {}", e);
+            reply
+                .edit(EditInteractionResponse::new().content(format!("An error occurred: {}", e)))
+                .await
+        }
+    }
+}
+
+/// Start verification for a user, returning the ephemeral reply: a fresh verification
+/// link, or a confirmation if they already verified in another server
+async fn start_verification(
+    ctx: &Context,
+    user: &User,
+    member: Option<&Member>,
+    guild_id: GuildId,
+    locale_code: &str,
+    state: &Arc<AppState>,
+) -> Result<EditInteractionResponse<'static>, Error> {
+    let locale = Locale::from_discord(locale_code);
+
+    // Paused by an operator, e.g. during Keycloak maintenance. Completions already
+    // in flight still go through.
+    if let Some(maintenance) = state.maintenance(guild_id).await {
+        return Ok(EditInteractionResponse::new().content(
+            maintenance
+                .message
+                .unwrap_or_else(|| i18n::verification_paused(locale).to_string()),
+        ));
+    }
+
     let mut conn = state.redis.clone();
-    let redis_key = format!("discord:{}:keycloak", user.id);
+
+    // The server may only let accounts and members past a minimum age verify
+    let gate = VerifyGate::load(&mut conn, guild_id).await?;
+    let blocked = gate.check(
+        chrono::Utc::now().timestamp(),
+        user.id.created_at().unix_timestamp(),
+        member
+            .and_then(|m| m.joined_at)
+            .map(|joined_at| joined_at.unix_timestamp()),
+    );
+    if let Some(blocked) = blocked {
+        let content = match blocked {
+            GateBlock::AccountTooNew { ready_at } => i18n::account_too_new(locale, ready_at),
+            GateBlock::JoinedTooRecently { ready_at } => {
+                i18n::joined_too_recently(locale, ready_at)
+            }
+        };
+        return Ok(EditInteractionResponse::new().content(content));
+    }
+
+    // Check if user is already verified globally
+    let redis_key = discord_keycloak(user.id);
     let existing_keycloak_id = trim_redis_value(conn.get(&redis_key).await?);
 
     if let Some(keycloak_user_id) = existing_keycloak_id {
-        // User is already verified globally, complete verification in this server
-        complete_verification(
-            &ctx.http,
-            &ctx.cache,
-            state,
-            user.id,
-            guild_id.get(),
+        // User is already verified globally, complete verification in this server with
+        // the full role set, reusing their attributes from an earlier verification
+        let claims = cached_attributes(&mut conn, &keycloak_user_id).await;
+        let completion = VerificationComplete {
+            discord_user_id: user.id,
+            guild_id,
             keycloak_user_id,
-            true,
-        )
-        .await?;
+            claims,
+            locale: locale_code.to_string(),
+            started_at: None,
+            state_token: None,
+            span: tracing::Span::current(),
+        };
+        let content =
+            match complete_verification(&ctx.http, &ctx.cache, state, completion, true).await? {
+                Completion::Verified => {
+                    record_verify_event(&mut conn, guild_id, VerifyEvent::AlreadyVerified).await;
+                    i18n::already_verified(locale).to_string()
+                }
+                Completion::InProgress => i18n::verification_in_progress(locale).to_string(),
+                // Left the guild between running /verify and the membership check
+                Completion::AwaitingJoin => i18n::awaiting_join_dm(
+                    locale,
+                    &state.config.identity_label,
+                    &guild_id
+                        .to_guild_cached(&ctx.cache)
+                        .map(|guild| guild.name.to_string())
+                        .unwrap_or_else(|| "the server".to_string()),
+                ),
+                Completion::RoleUnassignable => {
+                    i18n::verified_role_unassignable(locale, &state.config.identity_label)
+                }
+                Completion::EmailDomainRejected => {
+                    i18n::email_domain_rejected(locale, &state.config.identity_label)
+                }
+            };
+
+        return Ok(EditInteractionResponse::new().content(content));
+    }
+
+    // Re-send a link that's still live for this server instead of minting another token
+    let token_key = redis_key!("user:{}:verify_token", user.id);
+    if let Some(existing_token) = trim_redis_value(conn.get(&token_key).await?) {
+        let existing = state
+            .pending_verifications
+            .read()
+            .await
+            .get(&existing_token)
+            .cloned();
+
+        match existing {
+            Some(existing) if existing.guild_id == guild_id && !existing.is_expired() => {
+                return verify_link_message(
+                    state,
+                    &mut conn,
+                    guild_id,
+                    &existing_token,
+                    existing.expires_at(),
+                    locale,
+                )
+                .await;
+            }
+            // Started in another server or expired, invalidate it so only one link is live
+            _ => {
+                let removed = state
+                    .pending_verifications
+                    .write()
+                    .await
+                    .remove(&existing_token);
 
-        let response = CreateInteractionResponse::Message(
-            CreateInteractionResponseMessage::new()
-                .content("You are already verified. The verified role has been assigned to you in this server.")
-                .ephemeral(true),
+                let mut pipe = redis::pipe();
+                pipe.del(redis_key!("verify:{}", existing_token))
+                    .ignore()
+                    .zrem(pending_index_key(), &existing_token)
+                    .ignore();
+                if let Some(removed) = removed {
+                    pipe.zrem(guild_pending_index_key(removed.guild_id), &existing_token)
+                        .ignore();
+                }
+                pipe.query_async::<()>(&mut conn).await?;
+            }
+        }
+    }
+
+    // Refuse new links while too many are outstanding, so alts can't flood memory
+    if !pending_capacity_available(&state.config, &mut conn, guild_id).await? {
+        tracing::warn!(
+            "Pending verification cap reached, refusing /verify in guild {}",
+            guild_id
         );
-        command.create_response(&ctx.http, response).await?;
-        return Ok(());
+        return Ok(EditInteractionResponse::new().content(i18n::verify_unavailable(locale)));
     }
 
     // Generate unique state token
@@ -74,6 +337,11 @@ pub async fn handle(
         discord_user_id: user.id,
         discord_username: user.name.to_string(),
         guild_id,
+        guild_name: guild_id
+            .to_guild_cached(&ctx.cache)
+            .map(|g| g.name.to_string())
+            .unwrap_or_default(),
+        locale: locale_code.to_string(),
         created_at: chrono::Utc::now().timestamp(),
     };
 
@@ -84,36 +352,234 @@ pub async fn handle(
         .insert(state_token.to_string(), verification.clone());
 
     // Also store in Redis with TTL
-    let mut conn = state.redis.clone();
-    let key = format!("verify:{}", state_token);
+    let key = redis_key!("verify:{}", state_token);
     let data = serde_json::to_string(&verification)?;
 
     redis::cmd("SETEX")
         .arg(&key)
-        .arg(600) // 10 minutes
+        .arg(PENDING_VERIFICATION_TTL_SECS)
         .arg(&data)
         .query_async::<()>(&mut conn)
         .await?;
+    redis::cmd("SETEX")
+        .arg(&token_key)
+        .arg(PENDING_VERIFICATION_TTL_SECS)
+        .arg(state_token.to_string())
+        .query_async::<()>(&mut conn)
+        .await?;
+    redis::pipe()
+        .zadd(
+            pending_index_key(),
+            state_token.to_string(),
+            verification.created_at,
+        )
+        .ignore()
+        .zadd(
+            guild_pending_index_key(guild_id),
+            state_token.to_string(),
+            verification.created_at,
+        )
+        .ignore()
+        .query_async::<()>(&mut conn)
+        .await?;
+    record_verify_event(&mut conn, guild_id, VerifyEvent::NewToken).await;
+
+    verify_link_message(
+        state,
+        &mut conn,
+        guild_id,
+        &state_token.to_string(),
+        verification.expires_at(),
+        locale,
+    )
+    .await
+}
+
+/// Whether another pending verification fits under the global and per-guild caps.
+/// Expired tokens are pruned from the indexes first, since they're only removed
+/// eagerly when a verification finishes.
+async fn pending_capacity_available(
+    config: &Config,
+    conn: &mut redis::aio::ConnectionManager,
+    guild_id: GuildId,
+) -> Result<bool, Error> {
+    let guild_key = guild_pending_index_key(guild_id);
+    let cutoff = chrono::Utc::now().timestamp() - PENDING_VERIFICATION_TTL_SECS;
+
+    let (global, guild): (usize, usize) = redis::pipe()
+        .zrembyscore(pending_index_key(), "-inf", cutoff)
+        .ignore()
+        .zrembyscore(&guild_key, "-inf", cutoff)
+        .ignore()
+        .zcard(pending_index_key())
+        .zcard(&guild_key)
+        .query_async(conn)
+        .await?;
 
+    Ok(global < config.max_pending_verifications
+        && guild < config.max_pending_verifications_per_guild)
+}
+
+/// Ephemeral message with the verification link for a state token, which stops working
+/// at the unix time `expires_at`
+async fn verify_link_message(
+    state: &AppState,
+    conn: &mut redis::aio::ConnectionManager,
+    guild_id: GuildId,
+    state_token: &str,
+    expires_at: i64,
+    locale: Locale,
+) -> Result<EditInteractionResponse<'static>, Error> {
     // Create verification link
     let verify_url = format!("{}/verify?state={}", state.config.app_url, state_token);
 
-    // Send ephemeral message
-    let response = CreateInteractionResponse::Message(
-        CreateInteractionResponseMessage::new()
-            .content(format!(
-                "Click the link below to verify your account. This link expires in 10 minutes.\n\n{}",
-                verify_url
-            ))
-            .ephemeral(true),
+    // Use the guild's custom prompt if one is configured
+    let prompt = trim_redis_value(
+        conn.get(redis_key!("guild:{}:verify_prompt", guild_id))
+            .await?,
     );
-    command.create_response(&ctx.http, response).await?;
+
+    // Ephemeral message, keeping the URL in the text for clients without buttons
+    Ok(EditInteractionResponse::new()
+        .content(render_verify_prompt(
+            prompt.as_deref(),
+            &verify_url,
+            expires_at,
+            locale,
+        ))
+        .components(vec![verify_button(&verify_url, locale)]))
+}
+
+/// Link button opening the verification page
+pub fn verify_button(verify_url: &str, locale: Locale) -> CreateComponent<'static> {
+    CreateComponent::ActionRow(CreateActionRow::Buttons(
+        vec![CreateButton::new_link(verify_url.to_string()).label(i18n::verify_button(locale))]
+            .into(),
+    ))
+}
+
+/// Build the /verify message from a guild's custom prompt, or the default prompt if unset.
+/// The link replaces the first `{link}` placeholder, otherwise it is appended to the
+/// prompt. Only one link fits in Discord's message limit next to a full length prompt.
+/// The default prompt also says when the link expires.
+pub fn render_verify_prompt(
+    prompt: Option<&str>,
+    verify_url: &str,
+    expires_at: i64,
+    locale: Locale,
+) -> String {
+    match prompt {
+        Some(prompt) if prompt.contains("{link}") => prompt.replacen("{link}", verify_url, 1),
+        Some(prompt) => format!("{}\n\n{}", prompt, verify_url),
+        None => i18n::verify_prompt(locale, verify_url, expires_at),
+    }
+}
+
+/// Longest nickname Discord accepts
+const MAX_NICKNAME_LENGTH: usize = 32;
+
+/// Fill a guild's nickname template with the Keycloak first/last name and the user's
+/// Discord name, cut to Discord's limit. `None` if nothing is left to set.
+pub fn render_nickname(template: &str, first: &str, last: &str, name: &str) -> Option<String> {
+    let nickname = template
+        .replace("{first}", first)
+        .replace("{last}", last)
+        .replace("{name}", name);
+    let nickname = nickname.split_whitespace().collect::<Vec<_>>().join(" ");
+    let nickname: String = nickname.chars().take(MAX_NICKNAME_LENGTH).collect();
+    let nickname = nickname.trim_end();
+
+    (!nickname.is_empty()).then(|| nickname.to_string())
+}
+
+/// Rename a verified member with the guild's nickname template, if one is set.
+/// Failures are reported as issues since the roles were already assigned.
+async fn apply_nickname(
+    http: &serenity::all::Http,
+    cache: &serenity::all::Cache,
+    state: &AppState,
+    guild_id: GuildId,
+    user_id: UserId,
+    keycloak_user_id: &str,
+    issues: &mut Vec<String>,
+) -> Result<(), Error> {
+    let mut redis = state.redis.clone();
+    let template: Option<String> = redis
+        .get(redis_key!("guild:{}:nick_template", guild_id))
+        .await?;
+    let Some(template) = template else {
+        return Ok(());
+    };
+
+    // Discord never lets bots rename the server owner
+    if guild_id.to_guild_cached(cache).map(|guild| guild.owner_id) == Some(user_id) {
+        tracing::warn!(
+            "Skipping nickname for user {}, bots can't rename the server owner",
+            redact(user_id)
+        );
+        return Ok(());
+    }
+
+    let (first, last) = if template.contains("{first}") || template.contains("{last}") {
+        match state.keycloak.get_user(keycloak_user_id).await {
+            Ok(user) => (
+                user.first_name.unwrap_or_default(),
+                user.last_name.unwrap_or_default(),
+            ),
+            Err(e) => {
+                tracing::warn!("Failed to fetch Keycloak name for nickname: {}", e);
+                issues.push(format!("Failed to fetch name for nickname: {}", e));
+                return Ok(());
+            }
+        }
+    } else {
+        Default::default()
+    };
+
+    // The account's own name rather than the server nickname, so reverifying
+    // doesn't stack a prefix onto an already templated nickname
+    let name = match user_id.to_user(http).await {
+        Ok(user) => user.display_name().to_string(),
+        Err(e) => {
+            tracing::warn!("Failed to fetch user for nickname: {}", e);
+            issues.push(format!("Failed to fetch user for nickname: {}", e));
+            return Ok(());
+        }
+    };
+
+    let Some(nickname) = render_nickname(&template, &first, &last, &name) else {
+        return Ok(());
+    };
+
+    if let Err(e) = guild_id
+        .edit_member(http, user_id, EditMember::new().nickname(nickname))
+        .await
+    {
+        tracing::warn!("Failed to set nickname for user {}: {}", redact(user_id), e);
+        issues.push(format!("Failed to set nickname: {}", e));
+    }
 
     Ok(())
 }
 
+/// The values of a multi-valued attribute that get roles: all of them if `assign_all`,
+/// otherwise only the first, warning that the rest are ignored
+fn selected_values<'a>(values: &'a [String], assign_all: bool, attribute: &str) -> &'a [String] {
+    if assign_all || values.len() <= 1 {
+        return values;
+    }
+
+    tracing::warn!(
+        "Attribute {} has {} values, only assigning a role for the first ({})",
+        attribute,
+        values.len(),
+        values[0]
+    );
+    &values[..1]
+}
+
 /// Formats a Vec of role ids to be a comma separated string with <@&__________>
-fn format_roles(roles: Vec<RoleId>) -> String {
+pub fn format_roles(roles: Vec<RoleId>) -> String {
     let roles_mentions: Vec<String> = roles
         .iter()
         .map(|role_id| format!("<@&{}>", role_id))
@@ -125,175 +591,850 @@ fn format_roles(roles: Vec<RoleId>) -> String {
     }
 }
 
-/// Complete the verification process by assigning role and storing mappings
-/// Called by the bot task when it receives a verification completion event.
-/// `send_dm` controls whether the user receives a DM on success: pass false
-/// for background jobs like reverify to avoid spamming users.
-pub async fn complete_verification(
-    http: &serenity::all::Http,
-    _cache: &serenity::all::Cache,
+/// Build the log channel embed for a verified user.
+/// `duration_secs` is the time from /verify to completion, if known.
+pub fn verified_log_embed(
+    user_id: UserId,
+    added_roles: Vec<RoleId>,
+    removed_roles: Vec<RoleId>,
+    duration_secs: Option<i64>,
+) -> CreateEmbed<'static> {
+    let mut embed = CreateEmbed::new()
+        .title("User Verified")
+        .color(0xA6E3A1) // Green
+        .field("User", format!("{}", user_id.mention()), false)
+        .field("Roles Added", format_roles(added_roles), false)
+        .field("Roles Removed", format_roles(removed_roles), false)
+        .timestamp(chrono::Utc::now());
+
+    if let Some(secs) = duration_secs {
+        embed = embed.footer(CreateEmbedFooter::new(format!("Verified in {}s", secs)));
+    }
+
+    embed
+}
+
+/// The Keycloak attributes and groups a guild's role configuration reads.
+/// A failure to fetch groups is pushed to `issues` rather than failing the verification.
+pub async fn fetch_role_inputs(
     state: &AppState,
-    discord_user_id: UserId,
-    guild_id: u64,
-    keycloak_user_id: String,
-    send_dm: bool,
-) -> Result<(), Error> {
-    let guild_id = GuildId::new(guild_id);
+    guild_config: &GuildConfig,
+    keycloak_user_id: &str,
+    claims: Option<&VerifyClaims>,
+    issues: &mut Vec<String>,
+) -> Result<(Option<HashMap<String, Vec<String>>>, Vec<String>), Error> {
+    // Attributes the guild's role configuration reads
+    let level_attribute = state.config.level_attribute.as_str();
+    let class_attribute = state.config.class_attribute.as_str();
+    let mut wanted_attributes: Vec<&str> = guild_config
+        .attribute_roles
+        .keys()
+        .map(|(attribute, _)| attribute.as_str())
+        .collect();
+    if guild_config.should_assign_level_roles() {
+        wanted_attributes.push(level_attribute);
+    }
+    if guild_config.should_assign_class_roles() {
+        wanted_attributes.push(class_attribute);
+    }
 
-    let mut verification_issues = Vec::new();
+    // Prefer attributes from the login's ID token claims, falling back to the admin API
+    // when the token doesn't carry all of them
+    let attributes = match claims.filter(|c| c.has_attributes(&wanted_attributes)) {
+        Some(claims) => Some(claims.attributes()),
+        None => {
+            let attributes = state.keycloak.get_user(keycloak_user_id).await?.attributes;
+            if let Some(attributes) = &attributes {
+                cache_attributes(state, keycloak_user_id, attributes).await;
+            }
+            attributes
+        }
+    };
 
-    // Load the guild's role configuration
-    let mut redis = state.redis.clone();
-    let guild_config = load_guild_config(http, &mut redis, guild_id).await?;
+    // Groups are only needed for groups mode
+    let mut groups = Vec::new();
+    if guild_config.should_assign_group_roles() {
+        match state.keycloak.get_user_groups(keycloak_user_id).await {
+            Ok(g) => groups = g,
+            Err(e) => {
+                tracing::warn!("Failed to fetch Keycloak groups: {}", e);
+                issues.push(format!("Failed to fetch Keycloak groups: {}", e));
+            }
+        }
+    }
 
-    // Track roles that were added and removed for logging
-    let mut added_roles = Vec::new();
-    let mut removed_roles = Vec::new();
+    Ok((attributes, groups))
+}
+
+/// The user's verified Keycloak email, only fetched when the guild requires email domains.
+/// An email Keycloak hasn't verified could be anything the user typed, so it counts as none.
+pub async fn fetch_email(
+    state: &AppState,
+    guild_config: &GuildConfig,
+    keycloak_user_id: &str,
+) -> Result<Option<String>, Error> {
+    if guild_config.email_domains.is_empty() {
+        return Ok(None);
+    }
+    let user = state.keycloak.get_user(keycloak_user_id).await?;
+    Ok(user.email.filter(|_| user.email_verified == Some(true)))
+}
+
+/// Keep attributes fetched from Keycloak for the already-verified fast path. Best effort,
+/// a failure only means the next fast path asks Keycloak again.
+async fn cache_attributes(
+    state: &AppState,
+    keycloak_user_id: &str,
+    attributes: &HashMap<String, Vec<String>>,
+) {
+    let result = async {
+        let data = serde_json::to_string(attributes)?;
+        let mut conn = state.redis.clone();
+        let _: () = conn
+            .set_ex(
+                redis_key!("keycloak:{}:attributes", keycloak_user_id),
+                data,
+                ATTRIBUTE_CACHE_TTL_SECS,
+            )
+            .await?;
+        Ok::<_, Error>(())
+    }
+    .await;
+
+    if let Err(e) = result {
+        tracing::warn!("Failed to cache Keycloak attributes: {}", e);
+    }
+}
 
-    // Remove unverified role if configured
-    let unverified_redis_key = format!("guild:{}:role:unverified", guild_id);
-    if let Ok(Some(unverified_role_str)) =
-        redis.get::<_, Option<String>>(&unverified_redis_key).await
-        && let Ok(unverified_role_id) = unverified_role_str.parse::<u64>()
+/// Attributes cached by an earlier verification, as claims so `fetch_role_inputs` uses
+/// them when they cover everything the guild reads and asks Keycloak otherwise
+pub(super) async fn cached_attributes(
+    conn: &mut redis::aio::ConnectionManager,
+    keycloak_user_id: &str,
+) -> Option<VerifyClaims> {
+    let data: Option<String> = match conn
+        .get(redis_key!("keycloak:{}:attributes", keycloak_user_id))
+        .await
     {
-        let unverified_role = serenity::all::RoleId::new(unverified_role_id);
-        // Ensure the role is removed even if the cache is stale
-        if let Err(e) = http
-            .remove_member_role(guild_id, discord_user_id, unverified_role, None)
-            .await
-        {
-            tracing::warn!("Failed to remove unverified role: {}", e);
-            verification_issues.push(format!("Failed to remove unverified role: {}", e));
-        } else {
-            removed_roles.push(unverified_role);
+        Ok(data) => data,
+        Err(e) => {
+            tracing::warn!("Failed to read cached Keycloak attributes: {}", e);
+            None
         }
-    }
+    };
+    let attributes: HashMap<String, Vec<String>> = serde_json::from_str(&data?).ok()?;
 
-    // Find existing level/class roles to remove first
-    let managed_roles: HashSet<serenity::all::RoleId> = guild_config
-        .level_roles
-        .values()
-        .chain(guild_config.class_roles.values())
-        .copied()
-        .collect();
+    Some(VerifyClaims {
+        claims: attributes
+            .into_iter()
+            .map(|(name, values)| (name, serde_json::json!(values)))
+            .collect(),
+    })
+}
 
-    // Re-fetch member to ensure fresh role state
-    let member = http.get_member(guild_id, discord_user_id).await?;
+/// Roles changed while verifying a member, and any problems to report
+#[derive(Debug, Default)]
+pub struct RoleChanges {
+    pub added: Vec<RoleId>,
+    pub removed: Vec<RoleId>,
+    pub issues: Vec<String>,
+    /// Discord refused the verified role for lack of permissions or role hierarchy
+    pub verified_role_forbidden: bool,
+}
 
-    // Find all managed roles currently on the member
-    let roles_to_remove: Vec<serenity::all::RoleId> = member
-        .roles
+/// The managed roles a member's attributes and groups map to in this guild, with the
+/// kind and name of each for reporting
+pub(super) fn wanted_managed_roles(
+    guild_config: &GuildConfig,
+    attributes: Option<&HashMap<String, Vec<String>>>,
+    groups: &[String],
+    email: Option<&str>,
+    config: &Config,
+) -> Vec<(RoleId, &'static str, String)> {
+    // Roles every verified member gets, whatever the mode
+    let mut wanted: Vec<(RoleId, &'static str, String)> = guild_config
+        .always_roles
         .iter()
-        .filter(|role_id| managed_roles.contains(role_id))
-        .copied()
+        .map(|role_id| (*role_id, "always", role_id.to_string()))
         .collect();
 
-    // Remove verification managed roles from member
-    for role_id in roles_to_remove {
-        if let Err(e) = http
-            .remove_member_role(guild_id, discord_user_id, role_id, None)
-            .await
+    // The role mapped to the member's email domain, in every mode
+    if let Some(email) = email
+        && let Some(domain_role) = guild_config.get_email_domain_role(email)
+    {
+        wanted.push((
+            domain_role,
+            "email domain",
+            email_domain(email).unwrap_or_default(),
+        ));
+    }
+
+    if let Some(attrs) = attributes {
+        let assign_all = config.assign_all_attribute_values;
+        let level_attribute = config.level_attribute.as_str();
+        let class_attribute = config.class_attribute.as_str();
+
+        // Level-based roles
+        if guild_config.should_assign_level_roles()
+            && let Some(level_values) = attrs.get(level_attribute)
         {
+            for level in selected_values(level_values, assign_all, level_attribute) {
+                if let Some(level_role) = guild_config.get_level_role(level) {
+                    wanted.push((level_role, "level", level.clone()));
+                }
+            }
+        }
+
+        // Class-based roles
+        if guild_config.should_assign_class_roles()
+            && let Some(class_values) = attrs.get(class_attribute)
+        {
+            for class in selected_values(class_values, assign_all, class_attribute) {
+                if let Some(class_role) = guild_config.get_class_role(class) {
+                    wanted.push((class_role, "class", class.clone()));
+                }
+            }
+        }
+
+        // Roles mapped with /mapattribute, in every mode
+        for attribute_role in guild_config.get_attribute_roles(attrs) {
+            wanted.push((attribute_role, "attribute", attribute_role.to_string()));
+        }
+    }
+
+    // Group-based roles, only for groups explicitly mapped in this guild
+    if guild_config.should_assign_group_roles() {
+        for group in groups {
+            if let Some(group_role) = guild_config.get_group_role(group) {
+                wanted.push((group_role, "group", group.clone()));
+            }
+        }
+    }
+
+    // The same role can be mapped from several attributes
+    let mut seen = HashSet::new();
+    wanted.retain(|(role_id, _, _)| seen.insert(*role_id));
+    wanted
+}
+
+/// Give a member the verified role and the roles their attributes and groups map to.
+/// Managed roles that no longer apply (e.g. last year's class) are removed, roles the
+/// bot doesn't manage are left alone and roles that still apply aren't touched. Only
+/// failing to assign the verified role is reported as an error, other failures are
+/// collected as issues.
+pub async fn assign_verification_roles(
+    discord: &impl DiscordApi,
+    guild_config: &GuildConfig,
+    user_id: UserId,
+    attributes: Option<&HashMap<String, Vec<String>>>,
+    groups: &[String],
+    email: Option<&str>,
+    config: &Config,
+) -> Result<RoleChanges, Error> {
+    let guild_id = guild_config.guild_id;
+    let mut changes = RoleChanges::default();
+
+    // Every role the guild's verification config can assign
+    let managed_roles = guild_config.managed_roles();
+
+    let wanted = wanted_managed_roles(guild_config, attributes, groups, email, config);
+    let wanted_ids: HashSet<RoleId> = wanted.iter().map(|(role_id, _, _)| *role_id).collect();
+
+    // Fetch the member to ensure fresh role state
+    let member_roles = discord.member_roles(guild_id, user_id).await?;
+
+    // Remove managed roles the member no longer qualifies for
+    for role_id in member_roles
+        .iter()
+        .filter(|role_id| managed_roles.contains(role_id) && !wanted_ids.contains(role_id))
+    {
+        if let Err(e) = discord.remove_role(guild_id, user_id, *role_id).await {
             tracing::warn!(
                 "Failed to remove managed role {} from user {}: {}",
                 role_id,
-                discord_user_id,
+                redact(user_id),
                 e
             );
-            verification_issues.push(format!("Failed to remove managed role {}: {}", role_id, e));
+            changes
+                .issues
+                .push(format!("Failed to remove managed role {}: {}", role_id, e));
         } else {
             tracing::info!(
                 "Removed managed role {} from user {}",
                 role_id,
-                discord_user_id
+                redact(user_id)
             );
-            removed_roles.push(role_id);
+            changes.removed.push(*role_id);
         }
     }
 
     // Re-fetch member to ensure fresh role state
-    let member = http.get_member(guild_id, discord_user_id).await?;
+    let member_roles = discord.member_roles(guild_id, user_id).await?;
 
-    // Assign verified role
     let verified_role = guild_config.get_verified_role()?;
-    if let Err(e) = member.add_role(http, verified_role, None).await {
-        verification_issues.push(format!("Failed to assign verified role: {}", e));
-    } else {
-        added_roles.push(verified_role);
-    }
-
-    // Fetch Keycloak user to get attributes
-    let keycloak_user = state.keycloak.get_user(&keycloak_user_id).await?;
-
-    // Assign additional roles based on mode and user attributes
-    if let Some(attrs) = keycloak_user.attributes.as_ref() {
-        // Try to assign level-based role
-        if guild_config.should_assign_level_roles()
-            && let Some(level_values) = attrs.get("level")
-            && let Some(level) = level_values.first()
-            && let Some(level_role) = guild_config.get_level_role(level)
-        {
-            if let Err(e) = member.add_role(http, level_role, None).await {
-                tracing::warn!("Failed to assign level role {}: {}", level, e);
-                verification_issues.push(format!("Failed to assign level role {}: {}", level, e));
-            } else {
-                added_roles.push(level_role);
+    let had_verified_role = member_roles.contains(&verified_role);
+    match swap_in_verified_role(discord, guild_config, user_id, &member_roles, verified_role).await
+    {
+        Ok(removed) => {
+            if !had_verified_role {
+                changes.added.push(verified_role);
             }
+            changes.removed.extend(removed);
+        }
+        Err(e) => {
+            changes.verified_role_forbidden = utils::is_missing_permissions(&e);
+            changes
+                .issues
+                .push(format!("Failed to assign verified role: {}", e));
         }
+    }
 
-        // Try to assign class-based role
-        if guild_config.should_assign_class_roles()
-            && let Some(class_values) = attrs.get("class")
-            && let Some(class) = class_values.first()
-            && let Some(class_role) = guild_config.get_class_role(class)
-        {
-            if let Err(e) = member.add_role(http, class_role, None).await {
-                tracing::warn!("Failed to assign class role {}: {}", class, e);
-                verification_issues.push(format!("Failed to assign class role {}: {}", class, e));
-            } else {
-                added_roles.push(class_role);
-            }
+    // Add the managed roles the member doesn't hold yet
+    for (role_id, kind, name) in wanted {
+        if member_roles.contains(&role_id) {
+            continue;
         }
+        add_role(
+            discord,
+            guild_id,
+            user_id,
+            role_id,
+            kind,
+            &name,
+            &mut changes,
+        )
+        .await;
     }
 
-    // Store mapping in Redis
-    let mut conn = state.redis.clone();
-    let timestamp = chrono::Utc::now().timestamp();
+    Ok(changes)
+}
 
-    redis::cmd("SET")
-        .arg(format!("discord:{}:verified_at", discord_user_id))
-        .arg(timestamp.to_string())
-        .query_async::<()>(&mut conn)
-        .await?;
+/// Swap the unverified role for the verified role in a single request, so the member
+/// never ends up with both or neither. A deleted unverified role is already dropped
+/// from the guild config and from the member's roles. Returns the unverified role
+/// when the member held it.
+pub async fn swap_in_verified_role(
+    discord: &impl DiscordApi,
+    guild_config: &GuildConfig,
+    user_id: UserId,
+    member_roles: &[RoleId],
+    verified_role: RoleId,
+) -> Result<Option<RoleId>, Error> {
+    let had_unverified_role = guild_config
+        .unverified_role
+        .filter(|role_id| member_roles.contains(role_id));
+    let mut roles: Vec<RoleId> = member_roles
+        .iter()
+        .filter(|role_id| **role_id != verified_role && Some(**role_id) != had_unverified_role)
+        .copied()
+        .collect();
+    roles.push(verified_role);
 
-    redis::cmd("SET")
-        .arg(format!("discord:{}:keycloak", discord_user_id))
-        .arg(&keycloak_user_id)
-        .query_async::<()>(&mut conn)
+    discord
+        .set_roles(guild_config.guild_id, user_id, roles)
         .await?;
+    Ok(had_unverified_role)
+}
 
-    redis::cmd("SET")
-        .arg(format!("keycloak:{}:discord", keycloak_user_id))
-        .arg(discord_user_id.to_string())
-        .query_async::<()>(&mut conn)
-        .await?;
+/// Add one mapped role, recording it or the failure in `changes`
+async fn add_role(
+    discord: &impl DiscordApi,
+    guild_id: GuildId,
+    user_id: UserId,
+    role_id: RoleId,
+    kind: &str,
+    name: &str,
+    changes: &mut RoleChanges,
+) {
+    if let Err(e) = discord.add_role(guild_id, user_id, role_id).await {
+        tracing::warn!("Failed to assign {} role {}: {}", kind, name, e);
+        changes
+            .issues
+            .push(format!("Failed to assign {} role {}: {}", kind, name, e));
+    } else {
+        changes.added.push(role_id);
+    }
+}
 
-    // Log to log channel if configured
-    if let Some(channel_id) = guild_config.get_log_channel() {
-        // Format roles list
+/// Store the global Discord <-> Keycloak mapping. Relinking to another Keycloak
+/// account drops the old account's reverse mapping in the same transaction, so it
+/// can't keep pointing at this Discord user.
+async fn store_link(
+    conn: &mut redis::aio::ConnectionManager,
+    discord_user_id: UserId,
+    keycloak_user_id: &str,
+    timestamp: i64,
+) -> Result<(), Error> {
+    let previous = trim_redis_value(conn.get(discord_keycloak(discord_user_id)).await?);
 
-        let embed = CreateEmbed::new()
-            .title("User Verified")
-            .color(0xA6E3A1) // Green
-            .field("User", format!("{}", discord_user_id.mention()), false)
-            .field("Roles Added", format_roles(added_roles), false)
-            .field("Roles Removed", format_roles(removed_roles), false)
-            .timestamp(chrono::Utc::now());
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    if let Some(previous) = previous.filter(|previous| previous != keycloak_user_id) {
+        pipe.del(redis_key!("keycloak:{}:discord", previous))
+            .ignore();
+    }
+    pipe.set(
+        redis_key!("discord:{}:verified_at", discord_user_id),
+        timestamp.to_string(),
+    )
+    .ignore()
+    .set(discord_keycloak(discord_user_id), keycloak_user_id)
+    .ignore()
+    .set(
+        redis_key!("keycloak:{}:discord", keycloak_user_id),
+        discord_user_id.to_string(),
+    )
+    .ignore();
+    pipe.query_async::<()>(conn).await?;
 
-        if let Err(e) = http
-            .send_message(
-                channel_id.into(),
-                Vec::new(),
+    Ok(())
+}
+
+/// Assign roles to a member who finished verifying while outside the guild, or who
+/// left and rejoined within the guild's `/setleavegrace` period. Called when they join,
+/// does nothing unless one of those applies.
+pub async fn complete_on_join(
+    http: &serenity::all::Http,
+    cache: &serenity::all::Cache,
+    state: &AppState,
+    guild_id: GuildId,
+    user_id: UserId,
+) -> Result<(), Error> {
+    let mut conn = state.redis.clone();
+    let awaiting: Option<String> = conn
+        .get_del(redis_key!("guild:{}:awaiting_join:{}", guild_id, user_id))
+        .await?;
+    let returning =
+        super::setleavegrace::cancel_leave_cleanup(&mut conn, guild_id, user_id).await?;
+    if awaiting.is_none() && !returning {
+        return Ok(());
+    }
+
+    // The link may have been removed with /unverify while they were away
+    let Some(keycloak_user_id) = trim_redis_value(conn.get(discord_keycloak(user_id)).await?)
+    else {
+        return Ok(());
+    };
+
+    tracing::info!(
+        "User {} joined guild {}, assigning roles from their earlier verification",
+        redact(user_id),
+        guild_id
+    );
+
+    let completion = VerificationComplete {
+        discord_user_id: user_id,
+        guild_id,
+        keycloak_user_id,
+        claims: None,
+        locale: String::new(),
+        started_at: None,
+        state_token: None,
+        span: tracing::Span::current(),
+    };
+    // Only DM members who haven't been told their roles were assigned yet
+    complete_verification(http, cache, state, completion, awaiting.is_some()).await?;
+
+    Ok(())
+}
+
+/// Tell a user their verification failed. If the DM can't be delivered, because their
+/// DMs are closed or they turned them off, mention them in the guild's log channel so
+/// an admin can follow up instead.
+pub async fn notify_failure(
+    http: &serenity::all::Http,
+    cache: &serenity::all::Cache,
+    state: &AppState,
+    guild_id: GuildId,
+    user_id: UserId,
+    error: &Error,
+) {
+    let message = format!(
+        "Verification failed: {}\n\nPlease contact a server administrator for assistance.",
+        error
+    );
+
+    let mut conn = state.redis.clone();
+    let reason = match utils::send_dm(
+        http,
+        &mut conn,
+        user_id,
+        CreateMessage::new().content(message),
+    )
+    .await
+    {
+        Ok(true) => return,
+        Ok(false) => "they turned off DMs",
+        Err(e) => {
+            tracing::warn!("Failed to send error DM to user {}: {}", redact(user_id), e);
+            "their DMs are closed"
+        }
+    };
+
+    // Read the key directly, loading the guild config may be what failed
+    let log_channel = match conn
+        .get::<_, Option<String>>(redis_key!("guild:{}:log_channel", guild_id))
+        .await
+    {
+        Ok(value) => trim_redis_value(value).and_then(|s| s.parse::<u64>().ok()),
+        Err(e) => {
+            tracing::warn!("Failed to read log channel for guild {}: {}", guild_id, e);
+            None
+        }
+    };
+    let log_channel = match log_channel {
+        Some(channel_id) => {
+            log_destination(
+                http,
+                cache,
+                &mut conn,
+                guild_id,
+                serenity::all::ChannelId::new(channel_id),
+            )
+            .await
+        }
+        None => None,
+    };
+    let Some(channel_id) = log_channel else {
+        tracing::warn!(
+            "Couldn't tell user {} their verification failed, no log channel to fall back to",
+            redact(user_id)
+        );
+        return;
+    };
+
+    let embed = CreateEmbed::new()
+        .title("Verification Failed")
+        .color(0xF38BA8) // Red
+        .field("User", user_id.mention().to_string(), false)
+        .field("Error", error.to_string(), false)
+        .footer(CreateEmbedFooter::new(format!(
+            "The user wasn't told because {}.",
+            reason
+        )))
+        .timestamp(chrono::Utc::now());
+
+    if let Err(e) = http
+        .send_message(
+            channel_id.into(),
+            Vec::new(),
+            &CreateMessage::new()
+                .content(user_id.mention().to_string())
+                .embed(embed),
+        )
+        .await
+    {
+        tracing::warn!(
+            "Failed to send verification failure to channel {}: {}",
+            channel_id,
+            e
+        );
+    }
+}
+
+/// Complete the verification process by assigning role and storing mappings
+/// Called by the bot task when it receives a verification completion event.
+/// `send_dm` controls whether the user receives a DM on success: pass false
+/// for background jobs like reverify to avoid spamming users.
+/// A user who has left the guild is linked anyway, and gets their roles on rejoining.
+/// Only one worker, across every bot instance, completes a user in a guild at a time,
+/// others return [`Completion::InProgress`] without doing anything, for the caller to
+/// retry or skip.
+pub async fn complete_verification(
+    http: &serenity::all::Http,
+    cache: &serenity::all::Cache,
+    state: &AppState,
+    completion: VerificationComplete,
+    send_dm: bool,
+) -> Result<Completion, Error> {
+    let mut conn = state.redis.clone();
+    let lock_key = redis_key!(
+        "guild:{}:completing:{}",
+        completion.guild_id,
+        completion.discord_user_id
+    );
+    let lock_token = Uuid::new_v4().to_string();
+
+    let acquired: Option<String> = redis::cmd("SET")
+        .arg(&lock_key)
+        .arg(&lock_token)
+        .arg("NX")
+        .arg("PX")
+        .arg(COMPLETION_LOCK_TTL_MS)
+        .query_async(&mut conn)
+        .await?;
+    if acquired.is_none() {
+        tracing::info!(
+            "Verification of user {} in guild {} is already being completed, skipping",
+            redact(completion.discord_user_id),
+            completion.guild_id
+        );
+        return Ok(Completion::InProgress);
+    }
+
+    let result = complete_verification_locked(http, cache, state, completion, send_dm).await;
+
+    // Only release our own lock, if it expired another worker may hold it now
+    let released = redis::Script::new(
+        "if redis.call('GET', KEYS[1]) == ARGV[1] then return redis.call('DEL', KEYS[1]) end return 0",
+    )
+    .key(&lock_key)
+    .arg(&lock_token)
+    .invoke_async::<i64>(&mut conn)
+    .await;
+    if let Err(e) = released {
+        // Expires on its own
+        tracing::warn!("Failed to release completion lock: {}", e);
+    }
+
+    result
+}
+
+async fn complete_verification_locked(
+    http: &serenity::all::Http,
+    cache: &serenity::all::Cache,
+    state: &AppState,
+    completion: VerificationComplete,
+    send_dm: bool,
+) -> Result<Completion, Error> {
+    let VerificationComplete {
+        discord_user_id,
+        guild_id,
+        keycloak_user_id,
+        claims,
+        locale,
+        started_at,
+        ..
+    } = completion;
+
+    let mut verification_issues = Vec::new();
+
+    // Load the guild's role configuration
+    let mut redis = state.redis.clone();
+    let guild_config = load_guild_config(http, &mut redis, guild_id).await?;
+
+    // Servers that only accept some email domains turn everyone else away before any
+    // roles are assigned or the link is stored, including users not in the guild yet
+    let email = fetch_email(state, &guild_config, &keycloak_user_id).await?;
+    if !guild_config.allows_email(email.as_deref()) {
+        return email_domain_rejected(
+            http,
+            cache,
+            state,
+            &guild_config,
+            discord_user_id,
+            &keycloak_user_id,
+            email.as_deref(),
+            &locale,
+            send_dm,
+        )
+        .await;
+    }
+
+    // An old link can be used after leaving the guild, keep the link for when they rejoin
+    if !is_guild_member(http, guild_id, discord_user_id).await? {
+        let mut conn = state.redis.clone();
+        store_link(
+            &mut conn,
+            discord_user_id,
+            &keycloak_user_id,
+            chrono::Utc::now().timestamp(),
+        )
+        .await?;
+        let _: () = conn
+            .set_ex(
+                redis_key!("guild:{}:awaiting_join:{}", guild_id, discord_user_id),
+                "1",
+                AWAITING_JOIN_TTL_SECS,
+            )
+            .await?;
+
+        tracing::info!(
+            "User {} is not in guild {}, roles will be assigned when they join",
+            redact(discord_user_id),
+            guild_id
+        );
+
+        if send_dm {
+            let guild_name = guild_id
+                .to_guild_cached(cache)
+                .map(|guild| guild.name.to_string())
+                .unwrap_or_else(|| "the server".to_string());
+            if let Err(e) = utils::send_dm(
+                http,
+                &mut conn,
+                discord_user_id,
+                CreateMessage::new().content(i18n::awaiting_join_dm(
+                    Locale::from_discord(&locale),
+                    &state.config.identity_label,
+                    &guild_name,
+                )),
+            )
+            .await
+            {
+                tracing::warn!(
+                    "Failed to send verification DM to user {}: {}",
+                    redact(discord_user_id),
+                    e
+                );
+            }
+        }
+
+        return Ok(Completion::AwaitingJoin);
+    }
+
+    // Roles can be moved after /setverifiedrole checked the hierarchy, so check again.
+    // This is the server's problem to fix, not the user's.
+    let verified_role = guild_config.get_verified_role()?;
+    if let Some(problem) = utils::role_assign_problem(http, cache, guild_id, verified_role).await? {
+        return verified_role_unassignable(
+            http,
+            cache,
+            state,
+            &guild_config,
+            discord_user_id,
+            &keycloak_user_id,
+            &locale,
+            send_dm,
+            &problem.describe(verified_role),
+        )
+        .await;
+    }
+
+    let (attributes, groups) = fetch_role_inputs(
+        state,
+        &guild_config,
+        &keycloak_user_id,
+        claims.as_ref(),
+        &mut verification_issues,
+    )
+    .await?;
+
+    let changes = assign_verification_roles(
+        http,
+        &guild_config,
+        discord_user_id,
+        attributes.as_ref(),
+        &groups,
+        email.as_deref(),
+        &state.config,
+    )
+    .await?;
+    if changes.verified_role_forbidden {
+        return verified_role_unassignable(
+            http,
+            cache,
+            state,
+            &guild_config,
+            discord_user_id,
+            &keycloak_user_id,
+            &locale,
+            send_dm,
+            "Discord refused to assign it for missing permissions. Please check my role position.",
+        )
+        .await;
+    }
+
+    let RoleChanges {
+        added: added_roles,
+        removed: removed_roles,
+        issues,
+        ..
+    } = changes;
+    verification_issues.extend(issues);
+
+    apply_nickname(
+        http,
+        cache,
+        state,
+        guild_id,
+        discord_user_id,
+        &keycloak_user_id,
+        &mut verification_issues,
+    )
+    .await?;
+
+    // Track verification per guild, the global mappings below aren't guild scoped
+    let _: () = redis
+        .sadd(
+            redis_key!("guild:{}:verified_members", guild_id),
+            discord_user_id.get(),
+        )
+        .await?;
+
+    // Store mapping in Redis
+    let mut conn = state.redis.clone();
+    let timestamp = chrono::Utc::now().timestamp();
+
+    // Record how long the user took from /verify to completion
+    let duration_secs = started_at.map(|started_at| (timestamp - started_at).max(0));
+    if let Some(secs) = duration_secs {
+        tracing::info!(duration_secs = secs, "Verification completed");
+        tracing::Span::current().record("duration_secs", secs);
+
+        let durations_key = redis_key!("guild:{}:verify_durations", guild_id);
+        redis::pipe()
+            .lpush(&durations_key, secs)
+            .ignore()
+            .ltrim(&durations_key, 0, MAX_DURATION_SAMPLES as isize - 1)
+            .ignore()
+            .query_async::<()>(&mut conn)
+            .await?;
+    }
+
+    store_link(&mut conn, discord_user_id, &keycloak_user_id, timestamp).await?;
+    if started_at.is_some() {
+        record_verify_event(&mut conn, guild_id, VerifyEvent::Completed).await;
+    }
+
+    // Notify the webhook in the background, a slow or failing receiver never blocks verification.
+    // Like the DM, it's skipped for background jobs so reverify doesn't replay every user.
+    if send_dm && let Some(webhook) = state.webhook.clone() {
+        let event = VerificationEvent {
+            discord_user_id: discord_user_id.to_string(),
+            keycloak_user_id: keycloak_user_id.clone(),
+            guild_id: guild_id.to_string(),
+            roles_added: added_roles.iter().map(|r| r.to_string()).collect(),
+            verified_at: timestamp,
+        };
+        tokio::spawn(
+            async move {
+                if let Err(e) = webhook.send(&event).await {
+                    tracing::warn!(
+                        webhook_failed = true,
+                        "Failed to send verification webhook: {}",
+                        e
+                    );
+                }
+            }
+            .in_current_span(),
+        );
+    }
+
+    // Only log if the log channel is configured and still writable
+    let log_channel = match guild_config.get_log_channel() {
+        Some(channel_id) => log_destination(http, cache, &mut redis, guild_id, channel_id).await,
+        None => None,
+    };
+
+    // Log to log channel if configured
+    if let Some(channel_id) = log_channel {
+        let style = LogStyle::load_or_default(&mut redis, guild_id, LogEvent::Verified).await;
+        let embed = style.apply(
+            verified_log_embed(
+                discord_user_id,
+                added_roles.clone(),
+                removed_roles,
+                duration_secs,
+            ),
+            discord_user_id,
+            &added_roles,
+        );
+
+        if let Err(e) = http
+            .send_message(
+                channel_id.into(),
+                Vec::new(),
                 &CreateMessage::new().embed(embed),
             )
             .await
@@ -308,23 +1449,27 @@ pub async fn complete_verification(
 
     // Only DM the user if requested (skipped during reverify to avoid spam)
     if send_dm
-        && let Err(e) = discord_user_id
-            .direct_message(
-                http,
-                CreateMessage::new().content("You have successfully verified your Andrew ID."),
-            )
-            .await
+        && let Err(e) = utils::send_dm(
+            http,
+            &mut conn,
+            discord_user_id,
+            CreateMessage::new().content(i18n::verified_dm(
+                Locale::from_discord(&locale),
+                &state.config.identity_label,
+            )),
+        )
+        .await
     {
         tracing::warn!(
             "Failed to send verification DM to user {}: {}",
-            discord_user_id,
+            redact(discord_user_id),
             e
         );
     }
 
     // Send logs of any verification issue to the log channel
     if !verification_issues.is_empty()
-        && let Some(channel_id) = guild_config.get_log_channel()
+        && let Some(channel_id) = log_channel
     {
         let issue_text = verification_issues.join("\n");
 
@@ -350,5 +1495,463 @@ pub async fn complete_verification(
             .await;
     }
 
-    Ok(())
+    Ok(Completion::Verified)
+}
+
+/// Alert the server's admins that the verified role can't be assigned, and tell the
+/// user it isn't their fault. The link is still stored, so `/verify` finishes straight
+/// away once the admins fix the role.
+#[allow(clippy::too_many_arguments)]
+async fn verified_role_unassignable(
+    http: &serenity::all::Http,
+    cache: &serenity::all::Cache,
+    state: &AppState,
+    guild_config: &GuildConfig,
+    discord_user_id: UserId,
+    keycloak_user_id: &str,
+    locale: &str,
+    send_dm: bool,
+    detail: &str,
+) -> Result<Completion, Error> {
+    let guild_id = guild_config.guild_id;
+    tracing::error!(
+        verified_role_unassignable = true,
+        "Can't assign the verified role in guild {}: {}",
+        guild_id,
+        detail
+    );
+
+    let mut conn = state.redis.clone();
+    store_link(
+        &mut conn,
+        discord_user_id,
+        keycloak_user_id,
+        chrono::Utc::now().timestamp(),
+    )
+    .await?;
+
+    let embed = CreateEmbed::new()
+        .title("Verified Role Unassignable")
+        .description(format!(
+            "I can no longer assign the verified role, check my role position. {}",
+            detail
+        ))
+        .color(0xF38BA8) // Red
+        .field("User", discord_user_id.mention().to_string(), false)
+        .footer(CreateEmbedFooter::new(
+            "The user was told to run /verify again once this is fixed.",
+        ))
+        .timestamp(chrono::Utc::now());
+
+    let log_channel = match guild_config.get_log_channel() {
+        Some(channel_id) => log_destination(http, cache, &mut conn, guild_id, channel_id).await,
+        None => None,
+    };
+    let alerted = match log_channel {
+        Some(channel_id) => http
+            .send_message(
+                channel_id.into(),
+                Vec::new(),
+                &CreateMessage::new().embed(embed),
+            )
+            .await
+            .map(|_| ())
+            .map_err(Error::from),
+        // Without a log channel, the owner is the admin who can fix it
+        None => match guild_id.to_partial_guild(http).await {
+            Ok(guild) => utils::send_dm(
+                http,
+                &mut conn,
+                guild.owner_id,
+                CreateMessage::new().embed(embed),
+            )
+            .await
+            .map(|_| ()),
+            Err(e) => Err(e.into()),
+        },
+    };
+    if let Err(e) = alerted {
+        tracing::warn!(
+            "Failed to alert guild {} about the verified role: {}",
+            guild_id,
+            e
+        );
+    }
+
+    if send_dm
+        && let Err(e) = utils::send_dm(
+            http,
+            &mut conn,
+            discord_user_id,
+            CreateMessage::new().content(i18n::verified_role_unassignable(
+                Locale::from_discord(locale),
+                &state.config.identity_label,
+            )),
+        )
+        .await
+    {
+        tracing::warn!(
+            "Failed to send verification DM to user {}: {}",
+            redact(discord_user_id),
+            e
+        );
+    }
+
+    Ok(Completion::RoleUnassignable)
+}
+
+/// Tell a user their email isn't from a domain the guild allows, and log it for the
+/// admins. Nothing is assigned or stored, and a Discord link made by this login is
+/// removed from the Keycloak account, so they can verify again with another account.
+#[allow(clippy::too_many_arguments)]
+async fn email_domain_rejected(
+    http: &serenity::all::Http,
+    cache: &serenity::all::Cache,
+    state: &AppState,
+    guild_config: &GuildConfig,
+    discord_user_id: UserId,
+    keycloak_user_id: &str,
+    email: Option<&str>,
+    locale: &str,
+    send_dm: bool,
+) -> Result<Completion, Error> {
+    let guild_id = guild_config.guild_id;
+    // Only the domain is logged, the rest of the address is the user's
+    let domain = email.and_then(email_domain);
+    tracing::info!(
+        "User {} can't verify in guild {}, email domain {:?} isn't allowed",
+        redact(discord_user_id),
+        guild_id,
+        domain
+    );
+
+    // Unlink Discord as for a wrong account, unless the user was already verified with
+    // this account, where the link is still used by the servers that accepted it
+    let mut conn = state.redis.clone();
+    let linked = trim_redis_value(conn.get(discord_keycloak(discord_user_id)).await?);
+    if linked.as_deref() != Some(keycloak_user_id)
+        && let Err(e) = state
+            .keycloak
+            .delete_federated_identity(keycloak_user_id, "discord")
+            .await
+    {
+        tracing::warn!(
+            "Failed to unlink Discord from rejected Keycloak user {}: {}",
+            redact(keycloak_user_id),
+            e
+        );
+    }
+
+    if let Some(channel_id) = guild_config.get_log_channel()
+        && let Some(channel_id) =
+            log_destination(http, cache, &mut conn, guild_id, channel_id).await
+    {
+        let embed = CreateEmbed::new()
+            .title("Verification Rejected")
+            .description("The user's email isn't from one of this server's email domains.")
+            .color(0xF9E2AF) // Yellow
+            .field("User", discord_user_id.mention().to_string(), false)
+            .field(
+                "Email Domain",
+                domain.unwrap_or_else(|| "No verified email".to_string()),
+                false,
+            )
+            .timestamp(chrono::Utc::now());
+
+        if let Err(e) = http
+            .send_message(
+                channel_id.into(),
+                Vec::new(),
+                &CreateMessage::new().embed(embed),
+            )
+            .await
+        {
+            tracing::warn!(
+                "Failed to send rejected verification log to channel {}: {}",
+                channel_id,
+                e
+            );
+        }
+    }
+
+    if send_dm
+        && let Err(e) = utils::send_dm(
+            http,
+            &mut conn,
+            discord_user_id,
+            CreateMessage::new().content(i18n::email_domain_rejected(
+                Locale::from_discord(locale),
+                &state.config.identity_label,
+            )),
+        )
+        .await
+    {
+        tracing::warn!(
+            "Failed to send verification DM to user {}: {}",
+            redact(discord_user_id),
+            e
+        );
+    }
+
+    Ok(Completion::EmailDomainRejected)
+}
+
+/// The Redis tests run against a throwaway container, so they need Docker:
+/// `cargo test -- --ignored verify`
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::discord::mock::MockDiscord;
+    use crate::bot::guild_config::RoleMode;
+    use redis::aio::ConnectionManager;
+    use testcontainers_modules::{
+        redis::{REDIS_PORT, Redis},
+        testcontainers::{ContainerAsync, runners::AsyncRunner},
+    };
+
+    const VERIFIED: RoleId = RoleId::new(100);
+    const UNVERIFIED: RoleId = RoleId::new(101);
+    const UNDERGRAD: RoleId = RoleId::new(200);
+    const GRADUATE: RoleId = RoleId::new(201);
+    const OTHER: RoleId = RoleId::new(300);
+
+    fn levels_fixture() -> GuildConfig {
+        GuildConfig {
+            verified_role: Some(VERIFIED),
+            unverified_role: Some(UNVERIFIED),
+            mode: RoleMode::Levels,
+            level_roles: HashMap::from([
+                ("Undergrad".to_string(), UNDERGRAD),
+                ("Graduate".to_string(), GRADUATE),
+            ]),
+            ..GuildConfig::empty(GuildId::new(1))
+        }
+    }
+
+    fn level(value: &str) -> HashMap<String, Vec<String>> {
+        HashMap::from([("level".to_string(), vec![value.to_string()])])
+    }
+
+    #[tokio::test]
+    async fn swaps_unverified_role_and_replaces_stale_level() {
+        let user = UserId::new(7);
+        let discord = MockDiscord::with_member(user, &[UNVERIFIED, GRADUATE, OTHER]);
+
+        let changes = assign_verification_roles(
+            &discord,
+            &levels_fixture(),
+            user,
+            Some(&level("Undergrad")),
+            &[],
+            None,
+            &Config::for_tests(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(changes.added, vec![VERIFIED, UNDERGRAD]);
+        assert_eq!(changes.removed, vec![GRADUATE, UNVERIFIED]);
+        assert!(changes.issues.is_empty());
+        assert_eq!(discord.roles_of(user), vec![OTHER, VERIFIED, UNDERGRAD]);
+    }
+
+    #[tokio::test]
+    async fn reports_failed_mapped_roles_as_issues() {
+        let user = UserId::new(7);
+        let mut discord = MockDiscord::with_member(user, &[]);
+        discord.failing_roles.insert(UNDERGRAD);
+
+        let changes = assign_verification_roles(
+            &discord,
+            &levels_fixture(),
+            user,
+            Some(&level("Undergrad")),
+            &[],
+            None,
+            &Config::for_tests(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(changes.added, vec![VERIFIED]);
+        assert_eq!(changes.issues.len(), 1);
+        assert_eq!(discord.roles_of(user), vec![VERIFIED]);
+    }
+
+    #[test]
+    fn verify_prompt_links_once() {
+        assert_eq!(
+            render_verify_prompt(Some("Go to {link} ({link})"), "URL", 0, Locale::English),
+            "Go to URL ({link})"
+        );
+        assert_eq!(
+            render_verify_prompt(Some("Verify here"), "URL", 0, Locale::English),
+            "Verify here\n\nURL"
+        );
+        assert!(
+            render_verify_prompt(None, "URL", 1_700_000_000, Locale::English)
+                .contains("expires <t:1700000000:R>")
+        );
+    }
+
+    #[test]
+    fn nickname_fills_placeholders() {
+        assert_eq!(
+            render_nickname("[V] {name}", "Scotty", "Dog", "scotty").as_deref(),
+            Some("[V] scotty")
+        );
+        assert_eq!(
+            render_nickname("{first} {last}", "Scotty", "Dog", "scotty").as_deref(),
+            Some("Scotty Dog")
+        );
+    }
+
+    #[test]
+    fn nickname_collapses_missing_names() {
+        assert_eq!(
+            render_nickname("{first} {last}", "Scotty", "", "scotty").as_deref(),
+            Some("Scotty")
+        );
+        assert_eq!(render_nickname("{first} {last}", "", "", "scotty"), None);
+    }
+
+    #[test]
+    fn nickname_is_cut_to_discord_limit() {
+        let nickname =
+            render_nickname("{first} {last}", &"a".repeat(20), &"b".repeat(20), "").unwrap();
+        assert_eq!(nickname.chars().count(), MAX_NICKNAME_LENGTH);
+    }
+
+    #[tokio::test]
+    async fn keeps_managed_roles_that_still_apply() {
+        let user = UserId::new(7);
+        let discord = MockDiscord::with_member(user, &[VERIFIED, UNDERGRAD, OTHER]);
+
+        let changes = assign_verification_roles(
+            &discord,
+            &levels_fixture(),
+            user,
+            Some(&level("Undergrad")),
+            &[],
+            None,
+            &Config::for_tests(),
+        )
+        .await
+        .unwrap();
+
+        assert!(changes.added.is_empty());
+        assert!(changes.removed.is_empty());
+        let mut roles = discord.roles_of(user);
+        roles.sort();
+        assert_eq!(roles, vec![VERIFIED, UNDERGRAD, OTHER]);
+    }
+
+    #[tokio::test]
+    async fn assigns_always_roles_without_attributes() {
+        let user = UserId::new(7);
+        let discord = MockDiscord::with_member(user, &[UNVERIFIED]);
+        let mut config = levels_fixture();
+        config.mode = RoleMode::None;
+        config.always_roles = vec![OTHER];
+
+        let changes = assign_verification_roles(
+            &discord,
+            &config,
+            user,
+            None,
+            &[],
+            None,
+            &Config::for_tests(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(changes.added, vec![VERIFIED, OTHER]);
+        assert_eq!(discord.roles_of(user), vec![VERIFIED, OTHER]);
+    }
+
+    #[tokio::test]
+    async fn removes_managed_roles_without_a_matching_attribute() {
+        let user = UserId::new(7);
+        let discord = MockDiscord::with_member(user, &[VERIFIED, GRADUATE, OTHER]);
+
+        // Keycloak no longer has a level for the user
+        let changes = assign_verification_roles(
+            &discord,
+            &levels_fixture(),
+            user,
+            Some(&HashMap::new()),
+            &[],
+            None,
+            &Config::for_tests(),
+        )
+        .await
+        .unwrap();
+
+        assert!(changes.added.is_empty());
+        assert_eq!(changes.removed, vec![GRADUATE]);
+        let mut roles = discord.roles_of(user);
+        roles.sort();
+        assert_eq!(roles, vec![VERIFIED, OTHER]);
+    }
+
+    #[tokio::test]
+    async fn assigns_the_email_domain_role() {
+        let user = UserId::new(7);
+        let discord = MockDiscord::with_member(user, &[]);
+        let mut config = levels_fixture();
+        config.mode = RoleMode::None;
+        config.email_domains = HashMap::from([("andrew.cmu.edu".to_string(), Some(OTHER))]);
+
+        let changes = assign_verification_roles(
+            &discord,
+            &config,
+            user,
+            None,
+            &[],
+            Some("scotty@andrew.cmu.edu"),
+            &Config::for_tests(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(changes.added, vec![VERIFIED, OTHER]);
+        assert_eq!(discord.roles_of(user), vec![VERIFIED, OTHER]);
+    }
+
+    async fn redis() -> (ContainerAsync<Redis>, ConnectionManager) {
+        let container = Redis::default().start().await.unwrap();
+        let url = format!(
+            "redis://{}:{}",
+            container.get_host().await.unwrap(),
+            container.get_host_port_ipv4(REDIS_PORT).await.unwrap()
+        );
+        let conn = ConnectionManager::new(redis::Client::open(url).unwrap())
+            .await
+            .unwrap();
+        (container, conn)
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Docker"]
+    async fn relinking_drops_the_old_reverse_mapping() {
+        let (_container, mut conn) = redis().await;
+        let user = UserId::new(7);
+
+        store_link(&mut conn, user, "old-account", 1).await.unwrap();
+        store_link(&mut conn, user, "new-account", 2).await.unwrap();
+
+        let old: Option<String> = conn
+            .get(redis_key!("keycloak:{}:discord", "old-account"))
+            .await
+            .unwrap();
+        let new: Option<String> = conn
+            .get(redis_key!("keycloak:{}:discord", "new-account"))
+            .await
+            .unwrap();
+        let linked: Option<String> = conn.get(discord_keycloak(user)).await.unwrap();
+        assert_eq!(old, None);
+        assert_eq!(new.as_deref(), Some("7"));
+        assert_eq!(linked.as_deref(), Some("new-account"));
+    }
 }
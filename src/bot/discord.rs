@@ -0,0 +1,196 @@
+//! The Discord calls made while assigning and removing verification roles, behind a
+//! trait so the role logic can be tested without a live bot. Production uses `Http`.
+
+use crate::bot::Error;
+use serenity::all::{EditMember, GuildId, Http, RoleId, UserId};
+use std::future::Future;
+
+pub trait DiscordApi: Sync {
+    /// Ids of the roles that exist in a guild
+    fn guild_roles(
+        &self,
+        guild_id: GuildId,
+    ) -> impl Future<Output = Result<Vec<RoleId>, Error>> + Send;
+
+    /// Current roles of a guild member
+    fn member_roles(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+    ) -> impl Future<Output = Result<Vec<RoleId>, Error>> + Send;
+
+    fn add_role(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+        role_id: RoleId,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    fn remove_role(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+        role_id: RoleId,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Replace a member's roles in a single request
+    fn set_roles(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+        roles: Vec<RoleId>,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+}
+
+impl DiscordApi for Http {
+    async fn guild_roles(&self, guild_id: GuildId) -> Result<Vec<RoleId>, Error> {
+        Ok(guild_id
+            .roles(self)
+            .await?
+            .iter()
+            .map(|role| role.id)
+            .collect())
+    }
+
+    async fn member_roles(&self, guild_id: GuildId, user_id: UserId) -> Result<Vec<RoleId>, Error> {
+        Ok(self.get_member(guild_id, user_id).await?.roles.to_vec())
+    }
+
+    async fn add_role(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+        role_id: RoleId,
+    ) -> Result<(), Error> {
+        Ok(self
+            .add_member_role(guild_id, user_id, role_id, None)
+            .await?)
+    }
+
+    async fn remove_role(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+        role_id: RoleId,
+    ) -> Result<(), Error> {
+        Ok(self
+            .remove_member_role(guild_id, user_id, role_id, None)
+            .await?)
+    }
+
+    async fn set_roles(
+        &self,
+        guild_id: GuildId,
+        user_id: UserId,
+        roles: Vec<RoleId>,
+    ) -> Result<(), Error> {
+        guild_id
+            .edit_member(self, user_id, EditMember::new().roles(roles))
+            .await?;
+        Ok(())
+    }
+}
+
+/// In-memory guild members for tests
+#[cfg(test)]
+pub mod mock {
+    use super::*;
+    use std::collections::{HashMap, HashSet};
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    pub struct MockDiscord {
+        /// Roles that exist in the guild, `None` to fail the lookup
+        pub guild_roles: Option<Vec<RoleId>>,
+        pub members: Mutex<HashMap<UserId, Vec<RoleId>>>,
+        /// Roles that fail to be added or removed, e.g. above the bot's top role
+        pub failing_roles: HashSet<RoleId>,
+    }
+
+    impl MockDiscord {
+        pub fn with_member(user_id: UserId, roles: &[RoleId]) -> Self {
+            let mock = Self::default();
+            mock.members.lock().unwrap().insert(user_id, roles.to_vec());
+            mock
+        }
+
+        pub fn roles_of(&self, user_id: UserId) -> Vec<RoleId> {
+            self.members
+                .lock()
+                .unwrap()
+                .get(&user_id)
+                .cloned()
+                .unwrap_or_default()
+        }
+
+        fn check(&self, role_id: RoleId) -> Result<(), Error> {
+            if self.failing_roles.contains(&role_id) {
+                return Err(format!("Missing permissions for role {}", role_id).into());
+            }
+            Ok(())
+        }
+    }
+
+    impl DiscordApi for MockDiscord {
+        async fn guild_roles(&self, _guild_id: GuildId) -> Result<Vec<RoleId>, Error> {
+            self.guild_roles
+                .clone()
+                .ok_or_else(|| "Guild roles unavailable".into())
+        }
+
+        async fn member_roles(
+            &self,
+            _guild_id: GuildId,
+            user_id: UserId,
+        ) -> Result<Vec<RoleId>, Error> {
+            self.members
+                .lock()
+                .unwrap()
+                .get(&user_id)
+                .cloned()
+                .ok_or_else(|| "Unknown member".into())
+        }
+
+        async fn add_role(
+            &self,
+            _guild_id: GuildId,
+            user_id: UserId,
+            role_id: RoleId,
+        ) -> Result<(), Error> {
+            self.check(role_id)?;
+            let mut members = self.members.lock().unwrap();
+            let roles = members.entry(user_id).or_default();
+            if !roles.contains(&role_id) {
+                roles.push(role_id);
+            }
+            Ok(())
+        }
+
+        async fn remove_role(
+            &self,
+            _guild_id: GuildId,
+            user_id: UserId,
+            role_id: RoleId,
+        ) -> Result<(), Error> {
+            self.check(role_id)?;
+            if let Some(roles) = self.members.lock().unwrap().get_mut(&user_id) {
+                roles.retain(|r| *r != role_id);
+            }
+            Ok(())
+        }
+
+        async fn set_roles(
+            &self,
+            _guild_id: GuildId,
+            user_id: UserId,
+            roles: Vec<RoleId>,
+        ) -> Result<(), Error> {
+            let current = self.roles_of(user_id);
+            for role_id in roles.iter().filter(|r| !current.contains(r)) {
+                self.check(*role_id)?;
+            }
+            self.members.lock().unwrap().insert(user_id, roles);
+            Ok(())
+        }
+    }
+}
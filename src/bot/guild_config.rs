@@ -1,15 +1,20 @@
 use crate::bot::Error;
+use crate::bot::discord::DiscordApi;
+use crate::keys::{RoleSlot, guild_role, parse_guild_role, redis_key};
 use redis::AsyncCommands;
-use serenity::all::{ChannelId, GuildId, Http, RoleId};
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use serenity::all::{ChannelId, GuildId, RoleId};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::str::FromStr;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum RoleMode {
     None,
     Levels,
     Classes,
     Custom,
+    Groups,
 }
 
 impl FromStr for RoleMode {
@@ -20,42 +25,78 @@ impl FromStr for RoleMode {
             "levels" => Self::Levels,
             "classes" => Self::Classes,
             "custom" => Self::Custom,
+            "groups" => Self::Groups,
             _ => Self::None,
         })
     }
 }
 
 impl RoleMode {
-    pub fn as_str(&self) -> &str {
+    pub fn as_str(&self) -> &'static str {
         match self {
             Self::None => "none",
             Self::Levels => "levels",
             Self::Classes => "classes",
             Self::Custom => "custom",
+            Self::Groups => "groups",
         }
     }
 }
 
+impl std::fmt::Display for RoleMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Portable guild configuration for copying setup between servers.
+/// Roles and channels are referenced by name since ids differ across guilds.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExportedConfig {
+    pub verified_role: Option<String>,
+    pub unverified_role: Option<String>,
+    pub log_channel: Option<String>,
+    pub mode: String,
+    /// Role name to Redis key suffix (e.g. "Undergrad" -> "level:Undergrad" or "Cohort" -> "group:cohort")
+    pub roles: BTreeMap<String, String>,
+}
+
 /// Configuration for roles/channels in a guild
 #[derive(Debug, Clone)]
 pub struct GuildConfig {
     pub guild_id: GuildId,
     pub verified_role: Option<RoleId>,
+    /// Roles given to every verified member alongside the verified role, in any mode
+    pub always_roles: Vec<RoleId>,
+    /// Role held until verification, `None` if unset or deleted
+    pub unverified_role: Option<RoleId>,
     pub log_channel: Option<ChannelId>,
     pub mode: RoleMode,
     pub level_roles: HashMap<String, RoleId>,
     pub class_roles: HashMap<String, RoleId>,
+    pub group_roles: HashMap<String, RoleId>,
+    /// Roles mapped to a Keycloak user attribute value, keyed by (attribute, value).
+    /// Assigned in every mode, alongside the built-in level/class roles.
+    pub attribute_roles: HashMap<(String, String), RoleId>,
+    /// Roles that unverify never removes
+    pub protected_roles: HashSet<RoleId>,
+    /// Members holding any of these, e.g. staff, are skipped by purges and reminders
+    pub exempt_roles: HashSet<RoleId>,
+    /// Email domains members must verify with, each with the role it's mapped to, if any.
+    /// Empty accepts any email.
+    pub email_domains: HashMap<String, Option<RoleId>>,
 }
 
 impl GuildConfig {
     /// Load role configuration from Redis for a guild
     pub async fn load(
         redis: &mut redis::aio::ConnectionManager,
-        http: &Http,
+        discord: &impl DiscordApi,
         guild_id: GuildId,
     ) -> Result<Self, Error> {
-        let guild_roles = match guild_id.roles(http).await {
-            Ok(roles) => Some(roles),
+        let guild_roles: Option<HashSet<RoleId>> = match discord.guild_roles(guild_id).await {
+            Ok(roles) => Some(roles.into_iter().collect()),
             Err(e) => {
                 tracing::warn!(
                     "Failed to fetch roles for guild {} (continuing with Redis config): {}",
@@ -67,13 +108,38 @@ impl GuildConfig {
         };
 
         // Get the verified role
-        let verified_role_key = format!("guild:{}:role:verified", guild_id);
+        let verified_role_key = guild_role(guild_id, &RoleSlot::Verified);
         let verified_role: Option<String> = redis.get(&verified_role_key).await?;
         let verified_role =
             verified_role.and_then(|s| s.parse::<u64>().ok().map(|id| RoleId::new(id)));
 
+        // Get the roles assigned on every verification, ignoring deleted roles
+        let always_key = redis_key!("guild:{}:roles:always", guild_id);
+        let always_roles: Vec<String> = redis.smembers(&always_key).await?;
+        let mut always_roles: Vec<RoleId> = always_roles
+            .iter()
+            .filter_map(|s| s.parse::<u64>().ok().map(RoleId::new))
+            .filter(|role_id| {
+                guild_roles
+                    .as_ref()
+                    .is_none_or(|roles| roles.contains(role_id))
+            })
+            .collect();
+        always_roles.sort();
+
+        // Get the unverified role, ignoring it if the role was deleted
+        let unverified_role_key = guild_role(guild_id, &RoleSlot::Unverified);
+        let unverified_role: Option<String> = redis.get(&unverified_role_key).await?;
+        let unverified_role = unverified_role
+            .and_then(|s| s.parse::<u64>().ok().map(RoleId::new))
+            .filter(|role_id| {
+                guild_roles
+                    .as_ref()
+                    .is_none_or(|roles| roles.contains(role_id))
+            });
+
         // Get the role mode
-        let role_mode_key = format!("guild:{}:role_mode", guild_id);
+        let role_mode_key = redis_key!("guild:{}:role_mode", guild_id);
         let role_mode: Option<String> = redis.get(&role_mode_key).await?;
         let mode = role_mode
             .unwrap_or_else(|| "none".to_string())
@@ -81,7 +147,7 @@ impl GuildConfig {
             .unwrap_or(RoleMode::None);
 
         // Get the log channel
-        let log_channel_key = format!("guild:{}:log_channel", guild_id);
+        let log_channel_key = redis_key!("guild:{}:log_channel", guild_id);
         let log_channel: Option<String> = redis.get(&log_channel_key).await?;
         let log_channel =
             log_channel.and_then(|s| s.parse::<u64>().ok().map(|id| ChannelId::new(id)));
@@ -89,13 +155,13 @@ impl GuildConfig {
         // Get level roles
         let mut level_roles = HashMap::new();
         for level in &["Undergrad", "Graduate"] {
-            let key = format!("guild:{}:role:level:{}", guild_id, level);
+            let key = guild_role(guild_id, &RoleSlot::Level(level.to_string()));
             if let Ok(Some(role_id_str)) = redis.get::<_, Option<String>>(&key).await
                 && let Ok(role_id_u64) = role_id_str.parse::<u64>()
             {
                 let role_id = RoleId::new(role_id_u64);
                 if let Some(roles) = guild_roles.as_ref() {
-                    if roles.contains_key(&role_id) {
+                    if roles.contains(&role_id) {
                         level_roles.insert(level.to_string(), role_id);
                     }
                 } else {
@@ -115,13 +181,13 @@ impl GuildConfig {
             "Masters",
             "Doctoral",
         ] {
-            let key = format!("guild:{}:role:class:{}", guild_id, class);
+            let key = guild_role(guild_id, &RoleSlot::Class(class.to_string()));
             if let Ok(Some(role_id_str)) = redis.get::<_, Option<String>>(&key).await
                 && let Ok(role_id_u64) = role_id_str.parse::<u64>()
             {
                 let role_id = RoleId::new(role_id_u64);
                 if let Some(roles) = guild_roles.as_ref() {
-                    if roles.contains_key(&role_id) {
+                    if roles.contains(&role_id) {
                         class_roles.insert(class.to_string(), role_id);
                     }
                 } else {
@@ -130,13 +196,124 @@ impl GuildConfig {
             }
         }
 
+        // Get group roles, keys are "guild:{guild_id}:role:group:{group_name}"
+        let mut group_keys = Vec::new();
+        {
+            let mut iter = redis
+                .scan_match::<_, String>(redis_key!("guild:{}:role:group:*", guild_id))
+                .await?;
+            while let Some(key) = iter.next_item().await {
+                group_keys.push(key);
+            }
+        }
+
+        let mut group_roles = HashMap::new();
+        for key in group_keys {
+            let Some((_, RoleSlot::Group(group))) = parse_guild_role(&key) else {
+                continue;
+            };
+
+            if let Ok(Some(role_id_str)) = redis.get::<_, Option<String>>(&key).await
+                && let Ok(role_id_u64) = role_id_str.parse::<u64>()
+            {
+                let role_id = RoleId::new(role_id_u64);
+                if let Some(roles) = guild_roles.as_ref() {
+                    if roles.contains(&role_id) {
+                        group_roles.insert(group.to_string(), role_id);
+                    }
+                } else {
+                    group_roles.insert(group.to_string(), role_id);
+                }
+            }
+        }
+
+        // Get attribute roles, keys are "guild:{guild_id}:attrmap:{attribute}:{value}"
+        let attrmap_prefix = redis_key!("guild:{}:attrmap:", guild_id);
+        let mut attrmap_keys = Vec::new();
+        {
+            let mut iter = redis
+                .scan_match::<_, String>(format!("{}*", attrmap_prefix))
+                .await?;
+            while let Some(key) = iter.next_item().await {
+                attrmap_keys.push(key);
+            }
+        }
+
+        let mut attribute_roles = HashMap::new();
+        for key in attrmap_keys {
+            // Attribute names can't contain ':', values can
+            let Some((attribute, value)) = key
+                .strip_prefix(&attrmap_prefix)
+                .and_then(|rest| rest.split_once(':'))
+            else {
+                continue;
+            };
+
+            if let Ok(Some(role_id_str)) = redis.get::<_, Option<String>>(&key).await
+                && let Ok(role_id_u64) = role_id_str.parse::<u64>()
+            {
+                let role_id = RoleId::new(role_id_u64);
+                if guild_roles
+                    .as_ref()
+                    .is_none_or(|roles| roles.contains(&role_id))
+                {
+                    attribute_roles.insert((attribute.to_string(), value.to_string()), role_id);
+                }
+            }
+        }
+
+        // Get protected roles
+        let protected_key = redis_key!("guild:{}:protected_roles", guild_id);
+        let protected_roles: Vec<String> = redis.smembers(&protected_key).await?;
+        let protected_roles = protected_roles
+            .iter()
+            .filter_map(|s| s.parse::<u64>().ok().map(RoleId::new))
+            .collect();
+
+        // Get roles exempt from purges and reminders
+        let exempt_roles: Vec<String> = redis
+            .smembers(redis_key!("guild:{}:exempt_roles", guild_id))
+            .await?;
+        let exempt_roles = exempt_roles
+            .iter()
+            .filter_map(|s| s.parse::<u64>().ok().map(RoleId::new))
+            .collect();
+
+        // Get the required email domains. A deleted role drops the mapping, not the domain,
+        // so its members can still verify.
+        let email_domains: HashMap<String, String> = redis
+            .hgetall(redis_key!("guild:{}:email_domains", guild_id))
+            .await?;
+        let email_domains = email_domains
+            .into_iter()
+            .map(|(domain, role_id)| {
+                let role_id = role_id
+                    .parse::<u64>()
+                    .ok()
+                    .map(RoleId::new)
+                    .filter(|role_id| {
+                        guild_roles
+                            .as_ref()
+                            .is_none_or(|roles| roles.contains(role_id))
+                    });
+                (domain, role_id)
+            })
+            .collect();
+
         Ok(Self {
             guild_id,
             verified_role,
+            always_roles,
+            unverified_role,
             log_channel,
             mode,
             level_roles,
             class_roles,
+            group_roles,
+            attribute_roles,
+            protected_roles,
+            exempt_roles,
+            email_domains,
         })
     }
 
@@ -148,7 +325,8 @@ impl GuildConfig {
         })
     }
 
-    /// Get the log channel if configured
+    /// Get the log channel if configured. This may be a thread, which shares the
+    /// channel id space, so sends to it post into the thread directly.
     pub fn get_log_channel(&self) -> Option<ChannelId> {
         self.log_channel
     }
@@ -163,6 +341,54 @@ impl GuildConfig {
         self.class_roles.get(class).copied()
     }
 
+    /// Get the role mapped to a Keycloak group name
+    pub fn get_group_role(&self, group: &str) -> Option<RoleId> {
+        self.group_roles.get(group).copied()
+    }
+
+    /// Get the roles mapped to any of a user's attribute values
+    pub fn get_attribute_roles(&self, attributes: &HashMap<String, Vec<String>>) -> Vec<RoleId> {
+        self.attribute_roles
+            .iter()
+            .filter(|((attribute, value), _)| {
+                attributes
+                    .get(attribute)
+                    .is_some_and(|values| values.contains(value))
+            })
+            .map(|(_, role_id)| *role_id)
+            .collect()
+    }
+
+    /// Whether an email is accepted here. Any email, or none, is accepted unless the guild
+    /// requires email domains, then only emails from one of them are.
+    pub fn allows_email(&self, email: Option<&str>) -> bool {
+        self.email_domains.is_empty()
+            || email
+                .and_then(email_domain)
+                .is_some_and(|domain| self.email_domains.contains_key(&domain))
+    }
+
+    /// Get the role mapped to an email's domain
+    pub fn get_email_domain_role(&self, email: &str) -> Option<RoleId> {
+        self.email_domains
+            .get(&email_domain(email)?)
+            .copied()
+            .flatten()
+    }
+
+    /// Every role besides the verified role that verification can assign in this guild
+    pub fn managed_roles(&self) -> HashSet<RoleId> {
+        self.level_roles
+            .values()
+            .chain(self.class_roles.values())
+            .chain(self.group_roles.values())
+            .chain(self.attribute_roles.values())
+            .chain(self.always_roles.iter())
+            .chain(self.email_domains.values().flatten())
+            .copied()
+            .collect()
+    }
+
     /// Check if level roles should be assigned based on the mode
     pub fn should_assign_level_roles(&self) -> bool {
         matches!(self.mode, RoleMode::Levels | RoleMode::Custom)
@@ -172,4 +398,254 @@ impl GuildConfig {
     pub fn should_assign_class_roles(&self) -> bool {
         matches!(self.mode, RoleMode::Classes | RoleMode::Custom)
     }
+
+    /// Check if group roles should be assigned based on the mode
+    pub fn should_assign_group_roles(&self) -> bool {
+        matches!(self.mode, RoleMode::Groups)
+    }
+
+    /// A guild with nothing configured, for tests to fill in with struct update syntax
+    #[cfg(test)]
+    pub fn empty(guild_id: GuildId) -> Self {
+        Self {
+            guild_id,
+            verified_role: None,
+            always_roles: Vec::new(),
+            unverified_role: None,
+            log_channel: None,
+            mode: RoleMode::None,
+            level_roles: HashMap::new(),
+            class_roles: HashMap::new(),
+            group_roles: HashMap::new(),
+            attribute_roles: HashMap::new(),
+            protected_roles: HashSet::new(),
+            exempt_roles: HashSet::new(),
+            email_domains: HashMap::new(),
+        }
+    }
+}
+
+/// The lowercased domain of an email address, `None` if it doesn't have one
+pub fn email_domain(email: &str) -> Option<String> {
+    let (_, domain) = email.trim().rsplit_once('@')?;
+    Some(domain.to_lowercase()).filter(|domain| !domain.is_empty())
+}
+
+/// Tests that load from Redis run against a throwaway container, so they need Docker:
+/// `cargo test -- --ignored guild_config`
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::discord::mock::MockDiscord;
+    use redis::aio::ConnectionManager;
+    use testcontainers_modules::{
+        redis::{REDIS_PORT, Redis},
+        testcontainers::{ContainerAsync, runners::AsyncRunner},
+    };
+
+    const GUILD: GuildId = GuildId::new(1);
+
+    async fn redis() -> (ContainerAsync<Redis>, ConnectionManager) {
+        let container = Redis::default().start().await.unwrap();
+        let url = format!(
+            "redis://{}:{}",
+            container.get_host().await.unwrap(),
+            container.get_host_port_ipv4(REDIS_PORT).await.unwrap()
+        );
+        let conn = ConnectionManager::new(redis::Client::open(url).unwrap())
+            .await
+            .unwrap();
+        (container, conn)
+    }
+
+    async fn seed(conn: &mut ConnectionManager, pairs: &[(&str, &str)]) {
+        for (key, value) in pairs {
+            let _: () = conn
+                .set(redis_key!("guild:{}:{}", GUILD, key), *value)
+                .await
+                .unwrap();
+        }
+    }
+
+    fn guild_with_roles(roles: &[u64]) -> MockDiscord {
+        MockDiscord {
+            guild_roles: Some(roles.iter().copied().map(RoleId::new).collect()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Docker"]
+    async fn loads_seeded_keys() {
+        let (_container, mut conn) = redis().await;
+        seed(
+            &mut conn,
+            &[
+                ("role:verified", "100"),
+                ("role:unverified", "101"),
+                ("role_mode", "classes"),
+                ("log_channel", "900"),
+                ("role:level:Undergrad", "200"),
+                ("role:class:Fifth-Year Senior", "300"),
+                ("role:group:cmu:staff", "400"),
+                ("attrmap:department:SCS:AI", "500"),
+            ],
+        )
+        .await;
+        let _: () = conn
+            .sadd(redis_key!("guild:{}:protected_roles", GUILD), "600")
+            .await
+            .unwrap();
+        let _: () = conn
+            .sadd(redis_key!("guild:{}:exempt_roles", GUILD), "700")
+            .await
+            .unwrap();
+        let _: () = conn
+            .hset_multiple(
+                redis_key!("guild:{}:email_domains", GUILD),
+                &[("andrew.cmu.edu", "800"), ("cmu.edu", "")],
+            )
+            .await
+            .unwrap();
+
+        let discord = guild_with_roles(&[100, 101, 200, 300, 400, 500, 600, 700, 800]);
+        let config = GuildConfig::load(&mut conn, &discord, GUILD).await.unwrap();
+
+        assert_eq!(config.verified_role, Some(RoleId::new(100)));
+        assert_eq!(config.unverified_role, Some(RoleId::new(101)));
+        assert_eq!(config.mode, RoleMode::Classes);
+        assert_eq!(config.log_channel, Some(ChannelId::new(900)));
+        assert_eq!(
+            config.level_roles,
+            HashMap::from([("Undergrad".to_string(), RoleId::new(200))])
+        );
+        assert_eq!(
+            config.class_roles,
+            HashMap::from([("Fifth-Year Senior".to_string(), RoleId::new(300))])
+        );
+        assert_eq!(
+            config.group_roles,
+            HashMap::from([("cmu:staff".to_string(), RoleId::new(400))])
+        );
+        assert_eq!(
+            config.attribute_roles,
+            HashMap::from([(
+                ("department".to_string(), "SCS:AI".to_string()),
+                RoleId::new(500)
+            )])
+        );
+        assert_eq!(config.protected_roles, HashSet::from([RoleId::new(600)]));
+        assert_eq!(config.exempt_roles, HashSet::from([RoleId::new(700)]));
+        assert_eq!(
+            config.email_domains,
+            HashMap::from([
+                ("andrew.cmu.edu".to_string(), Some(RoleId::new(800))),
+                ("cmu.edu".to_string(), None),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Docker"]
+    async fn drops_roles_deleted_from_the_guild() {
+        let (_container, mut conn) = redis().await;
+        seed(
+            &mut conn,
+            &[
+                ("role:unverified", "101"),
+                ("role:level:Undergrad", "200"),
+                ("role:class:Junior", "300"),
+                ("role:group:staff", "400"),
+                ("attrmap:department:SCS", "500"),
+            ],
+        )
+        .await;
+
+        // Only the class role still exists
+        let discord = guild_with_roles(&[300]);
+        let config = GuildConfig::load(&mut conn, &discord, GUILD).await.unwrap();
+
+        assert_eq!(config.unverified_role, None);
+        assert!(config.level_roles.is_empty());
+        assert_eq!(
+            config.class_roles,
+            HashMap::from([("Junior".to_string(), RoleId::new(300))])
+        );
+        assert!(config.group_roles.is_empty());
+        assert!(config.attribute_roles.is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Docker"]
+    async fn keeps_roles_when_guild_roles_are_unavailable() {
+        let (_container, mut conn) = redis().await;
+        seed(&mut conn, &[("role:level:Graduate", "201")]).await;
+
+        let discord = MockDiscord::default();
+        let config = GuildConfig::load(&mut conn, &discord, GUILD).await.unwrap();
+
+        assert_eq!(
+            config.level_roles,
+            HashMap::from([("Graduate".to_string(), RoleId::new(201))])
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Docker"]
+    async fn unknown_mode_is_none() {
+        let (_container, mut conn) = redis().await;
+        seed(&mut conn, &[("role_mode", "rainbows")]).await;
+
+        let config = GuildConfig::load(&mut conn, &guild_with_roles(&[]), GUILD)
+            .await
+            .unwrap();
+
+        assert_eq!(config.mode, RoleMode::None);
+        assert_eq!(config.verified_role, None);
+    }
+
+    fn email_domains_fixture() -> GuildConfig {
+        GuildConfig {
+            verified_role: Some(RoleId::new(100)),
+            email_domains: HashMap::from([
+                ("andrew.cmu.edu".to_string(), Some(RoleId::new(800))),
+                ("cmu.edu".to_string(), None),
+            ]),
+            ..GuildConfig::empty(GUILD)
+        }
+    }
+
+    #[test]
+    fn requires_a_listed_email_domain() {
+        let config = email_domains_fixture();
+
+        assert!(config.allows_email(Some("scotty@Andrew.CMU.edu")));
+        assert!(config.allows_email(Some("scotty@cmu.edu")));
+        assert!(!config.allows_email(Some("scotty@gmail.com")));
+        // Subdomains aren't matched, each one is listed on its own
+        assert!(!config.allows_email(Some("scotty@cs.cmu.edu")));
+        assert!(!config.allows_email(Some("not an email")));
+        assert!(!config.allows_email(None));
+    }
+
+    #[test]
+    fn any_email_is_allowed_without_domains() {
+        let mut config = email_domains_fixture();
+        config.email_domains.clear();
+
+        assert!(config.allows_email(Some("scotty@gmail.com")));
+        assert!(config.allows_email(None));
+    }
+
+    #[test]
+    fn email_domains_map_to_roles() {
+        let config = email_domains_fixture();
+
+        assert_eq!(
+            config.get_email_domain_role("scotty@andrew.cmu.edu"),
+            Some(RoleId::new(800))
+        );
+        assert_eq!(config.get_email_domain_role("scotty@cmu.edu"), None);
+        assert!(config.managed_roles().contains(&RoleId::new(800)));
+    }
 }
@@ -0,0 +1,272 @@
+//! Catalog of user-facing bot messages, keyed by the locale Discord reports for an
+//! interaction. English is the fallback for any locale without translations.
+//! To add a string, add a function here with an arm per supported locale.
+
+use std::fmt::Display;
+
+/// A locale with translated messages
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Locale {
+    #[default]
+    English,
+    Spanish,
+}
+
+impl Locale {
+    /// Resolve a Discord locale code such as "en-US" or "es-419", falling back to English
+    pub fn from_discord(locale: &str) -> Self {
+        match locale.split('-').next() {
+            Some("es") => Self::Spanish,
+            _ => Self::English,
+        }
+    }
+}
+
+/// Response when a command is used outside of a server
+pub fn server_only(locale: Locale) -> &'static str {
+    match locale {
+        Locale::English => "This command can only be used in a server.",
+        Locale::Spanish => "Este comando solo se puede usar en un servidor.",
+    }
+}
+
+/// Default /verify prompt, shown above the verification link.
+/// `expires_at` is a unix time, shown as a Discord relative timestamp.
+pub fn verify_prompt(locale: Locale, verify_url: &str, expires_at: i64) -> String {
+    match locale {
+        Locale::English => format!(
+            "Click the link below to verify your account. This link expires <t:{}:R>.\n\n{}",
+            expires_at, verify_url
+        ),
+        Locale::Spanish => format!(
+            "Haz clic en el enlace de abajo para verificar tu cuenta. Este enlace caduca <t:{}:R>.\n\n{}",
+            expires_at, verify_url
+        ),
+    }
+}
+
+/// Label of the link button under the /verify prompt
+pub fn verify_button(locale: Locale) -> &'static str {
+    match locale {
+        Locale::English => "Verify",
+        Locale::Spanish => "Verificar",
+    }
+}
+
+/// Response to /verify when too many verifications are outstanding
+pub fn verify_unavailable(locale: Locale) -> &'static str {
+    match locale {
+        Locale::English => "Verification is temporarily unavailable, please try again shortly.",
+        Locale::Spanish => {
+            "La verificación no está disponible temporalmente, inténtalo de nuevo en breve."
+        }
+    }
+}
+
+/// Response to /verify while an operator has paused verification, without a custom message
+pub fn verification_paused(locale: Locale) -> &'static str {
+    match locale {
+        Locale::English => {
+            "Verification is temporarily paused for maintenance, please try again later."
+        }
+        Locale::Spanish => {
+            "La verificación está pausada temporalmente por mantenimiento, inténtalo de nuevo más tarde."
+        }
+    }
+}
+
+/// Response to /verify from an account younger than the server's minimum age.
+/// `ready_at` is a unix time, shown as a Discord relative timestamp.
+pub fn account_too_new(locale: Locale, ready_at: i64) -> String {
+    match locale {
+        Locale::English => format!(
+            "Your Discord account is too new to verify in this server. You can verify <t:{}:R>.",
+            ready_at
+        ),
+        Locale::Spanish => format!(
+            "Tu cuenta de Discord es demasiado nueva para verificarte en este servidor. Podrás verificarte <t:{}:R>.",
+            ready_at
+        ),
+    }
+}
+
+/// Response to /verify from a member who joined more recently than the server allows
+pub fn joined_too_recently(locale: Locale, ready_at: i64) -> String {
+    match locale {
+        Locale::English => format!(
+            "You joined this server too recently to verify. You can verify <t:{}:R>.",
+            ready_at
+        ),
+        Locale::Spanish => format!(
+            "Te uniste a este servidor hace muy poco para verificarte. Podrás verificarte <t:{}:R>.",
+            ready_at
+        ),
+    }
+}
+
+/// Response to /verify for a user who already verified in another server
+pub fn already_verified(locale: Locale) -> &'static str {
+    match locale {
+        Locale::English => {
+            "You are already verified. The verified role has been assigned to you in this server."
+        }
+        Locale::Spanish => {
+            "Ya estás verificado. Se te ha asignado el rol de verificado en este servidor."
+        }
+    }
+}
+
+/// Response to /verify while another worker is still completing the user's verification
+pub fn verification_in_progress(locale: Locale) -> &'static str {
+    match locale {
+        Locale::English => "Your verification is still being processed, please try again shortly.",
+        Locale::Spanish => {
+            "Tu verificación todavía se está procesando, inténtalo de nuevo en breve."
+        }
+    }
+}
+
+/// DM sent once verification completes
+pub fn verified_dm(locale: Locale, identity_label: &str) -> String {
+    match locale {
+        Locale::English => format!("You have successfully verified your {}.", identity_label),
+        Locale::Spanish => format!("Has verificado tu {} correctamente.", identity_label),
+    }
+}
+
+/// DM sent when verification completes but the server can't give out the verified role
+pub fn verified_role_unassignable(locale: Locale, identity_label: &str) -> String {
+    match locale {
+        Locale::English => format!(
+            "You have successfully verified your {}, but the server couldn't give you your roles. Its admins have been notified, run `/verify` again once they've fixed it.",
+            identity_label
+        ),
+        Locale::Spanish => format!(
+            "Has verificado tu {} correctamente, pero el servidor no pudo asignarte tus roles. Se ha avisado a sus administradores, usa `/verify` de nuevo cuando lo hayan solucionado.",
+            identity_label
+        ),
+    }
+}
+
+/// DM sent when the user's email isn't from a domain the server requires
+pub fn email_domain_rejected(locale: Locale, identity_label: &str) -> String {
+    match locale {
+        Locale::English => format!(
+            "You have verified your {}, but this server only accepts members with an email from certain domains, so you weren't given any roles. Ask its admins which email domains are accepted.",
+            identity_label
+        ),
+        Locale::Spanish => format!(
+            "Has verificado tu {}, pero este servidor solo acepta miembros con un correo de ciertos dominios, así que no se te asignaron roles. Pregunta a sus administradores qué dominios de correo se aceptan.",
+            identity_label
+        ),
+    }
+}
+
+/// DM sent when verification completes after the user left the server
+pub fn awaiting_join_dm(locale: Locale, identity_label: &str, guild_name: &str) -> String {
+    match locale {
+        Locale::English => format!(
+            "You have successfully verified your {}. You're no longer in **{}**, your roles will be assigned when you rejoin.",
+            identity_label, guild_name
+        ),
+        Locale::Spanish => format!(
+            "Has verificado tu {} correctamente. Ya no estás en **{}**, se te asignarán los roles cuando vuelvas a unirte.",
+            identity_label, guild_name
+        ),
+    }
+}
+
+/// DM reminding a member to verify in a server
+pub fn reminder_dm(locale: Locale, identity_label: &str, guild_name: &str) -> String {
+    match locale {
+        Locale::English => format!(
+            "Reminder: you have not verified your {} in **{}** yet. Run `/verify` in the server to get access.",
+            identity_label, guild_name
+        ),
+        Locale::Spanish => format!(
+            "Recordatorio: aún no has verificado tu {} en **{}**. Usa `/verify` en el servidor para obtener acceso.",
+            identity_label, guild_name
+        ),
+    }
+}
+
+/// Response to /dms
+pub fn dms_updated(locale: Locale, enabled: bool) -> &'static str {
+    match (locale, enabled) {
+        (Locale::English, true) => "The bot will DM you again about verification.",
+        (Locale::English, false) => {
+            "The bot won't DM you anymore. If verification fails, server admins are notified instead."
+        }
+        (Locale::Spanish, true) => {
+            "El bot volverá a enviarte mensajes directos sobre la verificación."
+        }
+        (Locale::Spanish, false) => {
+            "El bot ya no te enviará mensajes directos. Si la verificación falla, se avisará a los administradores del servidor."
+        }
+    }
+}
+
+/// /mystatus reply describing the member's verification in a server
+pub fn my_status(
+    locale: Locale,
+    guild_name: &str,
+    mode: &str,
+    verified: bool,
+    linked: bool,
+    verified_at: Option<&str>,
+    roles: &[String],
+) -> String {
+    let roles = roles.join(", ");
+    match locale {
+        Locale::English => {
+            let status = match (verified, linked) {
+                (true, _) => "Verified",
+                (false, true) => "Not verified here yet, run `/verify` to get your roles",
+                (false, false) => "Not verified, run `/verify` to start",
+            };
+            let mut message = format!(
+                "**{}**\nRole mode: {}\nStatus: {}\nVerification roles: {}",
+                guild_name,
+                mode,
+                status,
+                if roles.is_empty() { "none" } else { &roles }
+            );
+            if linked {
+                message.push_str(&format!(
+                    "\nAccount linked: {}",
+                    verified_at.unwrap_or("unknown")
+                ));
+            }
+            message
+        }
+        Locale::Spanish => {
+            let status = match (verified, linked) {
+                (true, _) => "Verificado",
+                (false, true) => "Aún no verificado aquí, usa `/verify` para obtener tus roles",
+                (false, false) => "No verificado, usa `/verify` para empezar",
+            };
+            let mut message = format!(
+                "**{}**\nModo de roles: {}\nEstado: {}\nRoles de verificación: {}",
+                guild_name,
+                mode,
+                status,
+                if roles.is_empty() { "ninguno" } else { &roles }
+            );
+            if linked {
+                message.push_str(&format!(
+                    "\nCuenta vinculada: {}",
+                    verified_at.unwrap_or("desconocido")
+                ));
+            }
+            message
+        }
+    }
+}
+
+/// Response when the target of a command is not verified
+pub fn not_verified(locale: Locale, user: impl Display) -> String {
+    match locale {
+        Locale::English => format!("{} is not verified.", user),
+        Locale::Spanish => format!("{} no está verificado.", user),
+    }
+}
@@ -1,21 +1,46 @@
+mod admin;
 mod commands;
+pub mod discord;
 pub mod guild_config;
+pub mod i18n;
 
-use crate::state::{AppState, ReverifyJob, VerificationComplete};
+use crate::config::Config;
+use crate::keys::{RoleSlot, guild_role};
+use crate::redact::redact;
+use crate::state::{
+    AdminAction, AdminCommand, AppState, ReverifyJob, VerificationComplete, VerifyStatus,
+    completion_queue_key,
+};
 use redis::AsyncCommands;
 use serenity::Client;
 use serenity::all::{
     Context, CreateInteractionResponse, CreateInteractionResponseMessage, CreateMessage,
-    EventHandler, GatewayIntents, Interaction,
+    EventHandler, GatewayIntents, Http, Interaction,
 };
 use serenity::async_trait;
 use std::sync::Arc;
 use std::sync::atomic::Ordering;
 use tokio::sync::mpsc;
 use tokio_stream::{self as stream, StreamExt};
+use tracing::Instrument;
 
 pub type Error = Box<dyn std::error::Error + Send + Sync>;
 
+/// How often to check opted-in guilds for members due a verification reminder
+const REMINDER_CHECK_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_secs(3600);
+
+/// How often to check opted-in guilds for a due /setrolesync run
+const ROLE_SYNC_CHECK_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_secs(900);
+
+/// How often to forget members whose /setleavegrace period ran out
+const LEAVE_CLEANUP_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_secs(600);
+
+/// How often a bot without the web server in its process checks for queued completions
+const COMPLETION_POLL_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_secs(1);
+
+/// How often to evict abandoned /setuproles sessions
+const SESSION_SWEEP_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_secs(60);
+
 pub struct Handler {
     pub state: Arc<AppState>,
 }
@@ -26,6 +51,7 @@ impl EventHandler for Handler {
         match event {
             serenity::all::FullEvent::Ready { data_about_bot, .. } => {
                 tracing::info!("{} is connected!", data_about_bot.user.name);
+                self.state.bot_connected.store(true, Ordering::SeqCst);
 
                 // Reset reverify flag in case bot restarted mid-job
                 self.state
@@ -33,17 +59,21 @@ impl EventHandler for Handler {
                     .store(false, Ordering::SeqCst);
 
                 // Register global slash commands
-                if let Err(e) = commands::register_commands(ctx).await {
+                if let Err(e) = commands::register_commands(&ctx.http, &self.state.config).await {
                     tracing::error!("Failed to register commands: {}", e);
                 } else {
                     tracing::info!("Successfully registered slash commands");
                 }
             }
+            serenity::all::FullEvent::ShardStageUpdate { event, .. } => {
+                let connected = event.new == serenity::all::ConnectionStage::Connected;
+                self.state.bot_connected.store(connected, Ordering::SeqCst);
+            }
             serenity::all::FullEvent::GuildMemberAddition { new_member, .. } => {
                 // Auto-assign unverified role if configured
                 let guild_id = new_member.guild_id;
                 let mut conn = self.state.redis.clone();
-                let redis_key = format!("guild:{}:role:unverified", guild_id);
+                let redis_key = guild_role(guild_id, &RoleSlot::Unverified);
 
                 if let Ok(Some(role_id_str)) = conn.get::<_, Option<String>>(&redis_key).await
                     && let Ok(role_id_u64) = role_id_str.parse::<u64>()
@@ -54,7 +84,7 @@ impl EventHandler for Handler {
                         tracing::warn!(
                             "Failed to assign unverified role {} to user {} in guild {}: {}",
                             role_id,
-                            new_member.user.id,
+                            redact(new_member.user.id),
                             guild_id,
                             e
                         );
@@ -62,11 +92,42 @@ impl EventHandler for Handler {
                         tracing::info!(
                             "Assigned unverified role {} to user {} in guild {}",
                             role_id,
-                            new_member.user.id,
+                            redact(new_member.user.id),
                             guild_id
                         );
                     }
                 }
+
+                // Finish a verification completed while they were outside the guild
+                if let Err(e) = commands::verify::complete_on_join(
+                    &ctx.http,
+                    &ctx.cache,
+                    &self.state,
+                    guild_id,
+                    new_member.user.id,
+                )
+                .await
+                {
+                    tracing::error!(
+                        "Failed to assign roles to rejoining user {} in guild {}: {}",
+                        redact(new_member.user.id),
+                        guild_id,
+                        e
+                    );
+                }
+            }
+            serenity::all::FullEvent::GuildMemberRemoval { guild_id, user, .. } => {
+                if let Err(e) =
+                    commands::setleavegrace::schedule_leave_cleanup(&self.state, *guild_id, user.id)
+                        .await
+                {
+                    tracing::error!(
+                        "Failed to schedule cleanup for user {} leaving guild {}: {}",
+                        redact(user.id),
+                        guild_id,
+                        e
+                    );
+                }
             }
             serenity::all::FullEvent::InteractionCreate { interaction, .. } => {
                 match interaction {
@@ -95,6 +156,89 @@ impl EventHandler for Handler {
                             "reverify" => {
                                 commands::reverify::handle(ctx, command, &self.state).await
                             }
+                            "purgeunverified" => {
+                                commands::purgeunverified::handle(ctx, command, &self.state).await
+                            }
+                            "setreminderinterval" => {
+                                commands::setreminderinterval::handle(ctx, command, &self.state)
+                                    .await
+                            }
+                            "exportconfig" => {
+                                commands::exportconfig::handle(ctx, command, &self.state).await
+                            }
+                            "importconfig" => {
+                                commands::importconfig::handle(ctx, command, &self.state).await
+                            }
+                            "setgrouprole" => {
+                                commands::setgrouprole::handle(ctx, command, &self.state).await
+                            }
+                            "mapattribute" => {
+                                commands::mapattribute::handle(ctx, command, &self.state).await
+                            }
+                            "setverifymessage" => {
+                                commands::setverifymessage::handle(ctx, command, &self.state).await
+                            }
+                            "guilds" => commands::guilds::handle(ctx, command, &self.state).await,
+                            "resync" => commands::resync::handle(ctx, command, &self.state).await,
+                            "setnickname" => {
+                                commands::setnickname::handle(ctx, command, &self.state).await
+                            }
+                            "testlog" => commands::testlog::handle(ctx, command, &self.state).await,
+                            "forcelink" => {
+                                commands::forcelink::handle(ctx, command, &self.state).await
+                            }
+                            "reconcile" => {
+                                commands::reconcile::handle(ctx, command, &self.state).await
+                            }
+                            "dms" => commands::dms::handle(ctx, command, &self.state).await,
+                            "assignrole" => {
+                                commands::assignrole::handle(ctx, command, &self.state).await
+                            }
+                            "setemaildomain" => {
+                                commands::setemaildomain::handle(ctx, command, &self.state).await
+                            }
+                            "unlinkdiscord" => {
+                                commands::unlinkdiscord::handle(ctx, command, &self.state).await
+                            }
+                            "identities" => {
+                                commands::identities::handle(ctx, command, &self.state).await
+                            }
+                            "setrolesync" => {
+                                commands::setrolesync::handle(ctx, command, &self.state).await
+                            }
+                            "setverifygate" => {
+                                commands::setverifygate::handle(ctx, command, &self.state).await
+                            }
+                            "setexempt" => {
+                                commands::setexempt::handle(ctx, command, &self.state).await
+                            }
+                            "renamerole" => {
+                                commands::renamerole::handle(ctx, command, &self.state).await
+                            }
+                            "maintenance" => {
+                                commands::maintenance::handle(ctx, command, &self.state).await
+                            }
+                            "setlogstyle" => {
+                                commands::setlogstyle::handle(ctx, command, &self.state).await
+                            }
+                            "setleavegrace" => {
+                                commands::setleavegrace::handle(ctx, command, &self.state).await
+                            }
+                            "mystatus" => {
+                                commands::mystatus::handle(ctx, command, &self.state).await
+                            }
+                            "alwaysrole" => {
+                                commands::alwaysrole::handle(ctx, command, &self.state).await
+                            }
+                            "protectrole" => {
+                                commands::protectrole::handle(ctx, command, &self.state).await
+                            }
+                            "resetconfig" => {
+                                commands::resetconfig::handle(ctx, command, &self.state).await
+                            }
+                            "postverifybutton" => {
+                                commands::postverifybutton::handle(ctx, command, &self.state).await
+                            }
                             _ => {
                                 tracing::warn!("Unknown command: {}", command.data.name);
                                 Ok(())
@@ -119,9 +263,27 @@ impl EventHandler for Handler {
                         }
                     }
                     Interaction::Component(component) => {
-                        let result =
+                        // Route by custom_id prefix, everything else belongs to setuproles
+                        let custom_id = component.data.custom_id.as_str();
+                        let result = if custom_id == commands::postverifybutton::START_VERIFY_ID {
+                            commands::verify::handle_component(ctx, component, &self.state).await
+                        } else if custom_id.starts_with("unverify_") {
+                            commands::unverify::handle_component(ctx, component, &self.state).await
+                        } else if custom_id.starts_with("purge_") {
+                            commands::purgeunverified::handle_component(ctx, component, &self.state)
+                                .await
+                        } else if custom_id.starts_with("reconcile_") {
+                            commands::reconcile::handle_component(ctx, component, &self.state).await
+                        } else if custom_id.starts_with("unlinkdiscord_") {
+                            commands::unlinkdiscord::handle_component(ctx, component, &self.state)
+                                .await
+                        } else if custom_id.starts_with("resetconfig_") {
+                            commands::resetconfig::handle_component(ctx, component, &self.state)
+                                .await
+                        } else {
                             commands::setuproles::handle_component(ctx, component, &self.state)
-                                .await;
+                                .await
+                        };
 
                         if let Err(e) = result {
                             tracing::error!("Error handling component interaction: {}", e);
@@ -135,10 +297,30 @@ impl EventHandler for Handler {
     }
 }
 
+/// Register (or clear) the global slash commands over HTTP and return, without
+/// connecting to the gateway. Used by the `register-commands` and `clear-commands`
+/// subcommands so deployments can manage command definitions explicitly.
+pub async fn manage_commands(config: &Config, register: bool) -> Result<(), Error> {
+    let http = Http::new(config.discord_token.parse()?);
+    let application = http.get_current_application_info().await?;
+    http.set_application_id(application.id);
+
+    if register {
+        commands::register_commands(&http, config).await?;
+        tracing::info!("Registered slash commands");
+    } else {
+        commands::clear_commands(&http).await?;
+        tracing::info!("Cleared slash commands");
+    }
+
+    Ok(())
+}
+
 pub async fn run(
     state: Arc<AppState>,
     mut verification_rx: mpsc::UnboundedReceiver<VerificationComplete>,
     mut reverify_rx: mpsc::UnboundedReceiver<ReverifyJob>,
+    mut admin_rx: mpsc::UnboundedReceiver<AdminCommand>,
 ) -> Result<(), Error> {
     let token = state.config.discord_token.clone().parse()?;
     let intents = GatewayIntents::GUILDS | GatewayIntents::GUILD_MEMBERS;
@@ -161,39 +343,69 @@ pub async fn run(
         while let Some(completion) = verification_rx.recv().await {
             tracing::info!(
                 "Processing verification completion for Discord user {} in guild {}",
-                completion.discord_user_id,
+                redact(completion.discord_user_id),
                 completion.guild_id
             );
+            tracing::trace!(
+                "Verification completion raw ids: discord {}, keycloak {}",
+                completion.discord_user_id,
+                completion.keycloak_user_id
+            );
 
-            if let Err(e) = commands::verify::complete_verification(
+            // Trace the completion as a child of the web request that produced it
+            let span = tracing::info_span!(
+                parent: &completion.span,
+                "complete_verification",
+                guild_id = %completion.guild_id,
+                duration_secs = tracing::field::Empty,
+            );
+
+            let user_id = completion.discord_user_id;
+            let guild_id = completion.guild_id;
+            let state_token = completion.state_token.clone();
+            let retry = completion.clone();
+
+            let result = commands::verify::complete_verification(
                 &http,
                 &cache,
                 &completion_state,
-                completion.discord_user_id,
-                completion.guild_id.get(),
-                completion.keycloak_user_id,
+                completion,
                 true, // send DM, this is a direct user action
             )
-            .await
-            {
-                tracing::error!("Failed to complete verification: {}", e);
-
-                // Send error message to user via DM
-                let user_id = completion.discord_user_id;
-                let error_message = format!(
-                    "Verification failed: {}\n\nPlease contact a server administrator for assistance.",
-                    e
-                );
+            .instrument(span)
+            .await;
 
-                if let Err(dm_err) = user_id
-                    .direct_message(
-                        &http,
-                        serenity::all::CreateMessage::new().content(error_message),
-                    )
-                    .await
-                {
-                    tracing::error!("Failed to send error DM to user {}: {}", user_id, dm_err);
+            // Let the /pending page know how it went
+            let status = match result {
+                Ok(commands::verify::Completion::Verified) => Some(VerifyStatus::Complete),
+                Ok(commands::verify::Completion::AwaitingJoin) => Some(VerifyStatus::AwaitingJoin),
+                Ok(commands::verify::Completion::RoleUnassignable) => Some(VerifyStatus::Failed),
+                Ok(commands::verify::Completion::EmailDomainRejected) => Some(VerifyStatus::Failed),
+                // Another worker holds the lock for this user, so this completion, which
+                // may be a newer login with its own status page, runs again once it's free
+                Ok(commands::verify::Completion::InProgress) => {
+                    requeue_completion(&completion_state, retry).await
                 }
+                Err(_) => Some(VerifyStatus::Failed),
+            };
+            if let (Some(state_token), Some(status)) = (&state_token, status) {
+                completion_state
+                    .set_verify_status(state_token, status)
+                    .await;
+            }
+
+            if let Err(e) = result {
+                tracing::error!("Failed to complete verification: {}", e);
+
+                commands::verify::notify_failure(
+                    &http,
+                    &cache,
+                    &completion_state,
+                    guild_id,
+                    user_id,
+                    &e,
+                )
+                .await;
             }
         }
     });
@@ -219,9 +431,7 @@ pub async fn run(
                         &reverify_http,
                         &reverify_cache,
                         &reverify_state,
-                        user.discord_user_id,
-                        user.guild_id.get(),
-                        user.keycloak_user_id.clone(),
+                        user.clone(),
                         false, // do not DM users during reverify to avoid spam
                     )
                     .await;
@@ -236,7 +446,14 @@ pub async fn run(
             let failed = results.iter().filter(|r| r.is_err()).count();
 
             // Post progress update to log channel if configured
-            if let Some(channel_id) = job.log_channel {
+            let log_channel = commands::reverify::progress_channel(
+                &reverify_http,
+                &reverify_cache,
+                &reverify_state,
+                &job,
+            )
+            .await;
+            if let Some(channel_id) = log_channel {
                 let is_last_batch = job.batch_index == job.total_batches;
                 let message = if is_last_batch {
                     format!(
@@ -276,7 +493,201 @@ pub async fn run(
         }
     });
 
+    // Spawn task to carry out admin API requests
+    let admin_http = client.http.clone();
+    let admin_cache = client.cache.clone();
+    let admin_state = state.clone();
+    tokio::spawn(async move {
+        while let Some(command) = admin_rx.recv().await {
+            match &command.action {
+                AdminAction::Unverify { guild_id, user_id } => tracing::info!(
+                    "Processing admin API unverify for user {} in guild {}",
+                    redact(user_id),
+                    guild_id
+                ),
+                AdminAction::Link {
+                    guild_id, user_id, ..
+                } => tracing::info!(
+                    "Processing admin API link for user {} in guild {}",
+                    redact(user_id),
+                    guild_id
+                ),
+            }
+
+            let result =
+                admin::perform(&admin_http, &admin_cache, &admin_state, command.action).await;
+
+            // The request may have been dropped, e.g. the client disconnected
+            let _ = command.reply.send(result);
+        }
+    });
+
+    // Spawn task to periodically remind unverified members
+    let reminder_http = client.http.clone();
+    let reminder_cache = client.cache.clone();
+    let reminder_state = state.clone();
+    tokio::spawn(async move {
+        // Skip the immediate first tick so the member cache has time to fill
+        let mut interval = tokio::time::interval_at(
+            tokio::time::Instant::now() + REMINDER_CHECK_INTERVAL,
+            REMINDER_CHECK_INTERVAL,
+        );
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = commands::setreminderinterval::send_reminders(
+                &reminder_http,
+                &reminder_cache,
+                &reminder_state,
+            )
+            .await
+            {
+                tracing::error!("Failed to send verification reminders: {}", e);
+            }
+        }
+    });
+
+    // Spawn task to give verified members back roles they lost
+    let role_sync_http = client.http.clone();
+    let role_sync_cache = client.cache.clone();
+    let role_sync_state = state.clone();
+    tokio::spawn(async move {
+        // Skip the immediate first tick so the member cache has time to fill
+        let mut interval = tokio::time::interval_at(
+            tokio::time::Instant::now() + ROLE_SYNC_CHECK_INTERVAL,
+            ROLE_SYNC_CHECK_INTERVAL,
+        );
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = commands::setrolesync::run_role_syncs(
+                &role_sync_http,
+                &role_sync_cache,
+                &role_sync_state,
+            )
+            .await
+            {
+                tracing::error!("Failed to sync verified roles: {}", e);
+            }
+        }
+    });
+
+    // Spawn task to forget members who left and didn't come back in time
+    let cleanup_state = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(LEAVE_CLEANUP_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = commands::setleavegrace::run_leave_cleanups(&cleanup_state).await {
+                tracing::error!("Failed to clean up members who left: {}", e);
+            }
+        }
+    });
+
+    // Completions queued in Redis, by a web server in another process or by this one
+    // while the completion handler wasn't running
+    tokio::spawn(forward_queued_completions(state.clone()));
+
+    // Spawn task to evict abandoned /setuproles sessions
+    let sweeper_state = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SESSION_SWEEP_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let mut sessions = sweeper_state.setuproles_sessions.write().await;
+            let before = sessions.len();
+            sessions.retain(|_, session| !session.is_expired());
+            if sessions.len() < before {
+                tracing::debug!(
+                    "Evicted {} expired setuproles sessions",
+                    before - sessions.len()
+                );
+            }
+        }
+    });
+
     client.start().await?;
 
     Ok(())
 }
+
+/// Queue a completion that hit another worker's lock in Redis, so it's retried on the
+/// next poll of [`forward_queued_completions`]. The lock expires on its own, so this
+/// can't retry forever. Returns the status to report if it couldn't be queued.
+async fn requeue_completion(
+    state: &AppState,
+    completion: VerificationComplete,
+) -> Option<VerifyStatus> {
+    let queued = match serde_json::to_string(&completion) {
+        Ok(data) => {
+            let mut conn = state.redis.clone();
+            conn.lpush::<_, _, ()>(completion_queue_key(), data)
+                .await
+                .map_err(|e| e.to_string())
+        }
+        Err(e) => Err(e.to_string()),
+    };
+
+    match queued {
+        Ok(()) => None,
+        Err(e) => {
+            tracing::error!(
+                completion_dropped = true,
+                "Failed to requeue verification completion of user {} in guild {}: {}",
+                redact(completion.discord_user_id),
+                completion.guild_id,
+                e
+            );
+            Some(VerifyStatus::Failed)
+        }
+    }
+}
+
+/// Feed completions queued in Redis into the completion handler
+async fn forward_queued_completions(state: Arc<AppState>) {
+    let mut conn = state.redis.clone();
+    let mut interval = tokio::time::interval(COMPLETION_POLL_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        // Drain everything queued since the last tick
+        loop {
+            let data: Option<String> = match conn.rpop(completion_queue_key(), None).await {
+                Ok(data) => data,
+                Err(e) => {
+                    tracing::warn!("Failed to read queued verification completions: {}", e);
+                    break;
+                }
+            };
+            let Some(data) = data else {
+                break;
+            };
+
+            match serde_json::from_str::<VerificationComplete>(&data) {
+                Ok(completion) => {
+                    if state.verification_tx.send(completion).is_err() {
+                        // Put it back for another bot process, or this one after a restart
+                        if let Err(e) = conn.rpush::<_, _, ()>(completion_queue_key(), &data).await
+                        {
+                            tracing::error!(
+                                completion_dropped = true,
+                                "Failed to requeue verification completion: {}",
+                                e
+                            );
+                        }
+                        tracing::error!("Completion handler stopped, no longer forwarding");
+                        return;
+                    }
+                }
+                Err(e) => tracing::error!("Dropping malformed queued completion: {}", e),
+            }
+        }
+    }
+}
@@ -1,6 +1,12 @@
 use anyhow::{Context, Result};
 use serde::Deserialize;
 
+use crate::state::PENDING_VERIFICATION_TTL_SECS;
+
+/// Longest `IDENTITY_LABEL` that fits in the `/verify` description, "Verify your {label}",
+/// within Discord's 100 character limit
+pub const MAX_IDENTITY_LABEL_LENGTH: usize = 88;
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct Config {
     pub discord_token: String,
@@ -13,12 +19,111 @@ pub struct Config {
     pub app_url: String,
     pub redis_url: String,
     pub oauth_relay_url: String,
+    pub otlp_endpoint: Option<String>,
+    pub oidc_scopes: Vec<String>,
+    pub identity_label: String,
+    /// Keycloak user attribute holding the level, e.g. "Undergrad"
+    pub level_attribute: String,
+    /// Keycloak user attribute holding the class, e.g. "Sophomore"
+    pub class_attribute: String,
+    /// Assign level/class roles for every value of a multi-valued attribute, not just the first
+    pub assign_all_attribute_values: bool,
+    /// Endpoint notified of each completed verification
+    pub verification_webhook_url: Option<String>,
+    /// Key for the HMAC signature on webhook payloads, required with the URL
+    pub verification_webhook_secret: Option<String>,
+    /// Bearer token for the admin HTTP API, which is disabled when unset
+    pub admin_api_token: Option<String>,
+    /// How long to keep retrying OIDC discovery at startup before exiting
+    pub oidc_discovery_timeout_secs: u64,
+    /// Directory holding the built frontend assets
+    pub site_root: String,
+    /// Most outstanding /verify links across all guilds
+    pub max_pending_verifications: usize,
+    /// Most outstanding /verify links in a single guild
+    pub max_pending_verifications_per_guild: usize,
+    /// Most Keycloak admin API requests in flight at once, excess calls wait their turn
+    pub keycloak_admin_concurrency: usize,
+    /// Prepended to every Redis key, empty for none
+    pub redis_prefix: String,
+    /// Trust X-Forwarded-Proto/X-Forwarded-Host from a reverse proxy
+    pub trust_proxy_headers: bool,
+    /// Sign the user out of Keycloak once their verification is handed to the bot
+    pub end_session_after_verify: bool,
+    /// Show members' emails in `/userinfo` and the CSV export
+    pub userinfo_show_email: bool,
+    /// Minutes without a request before a web session expires
+    pub session_inactivity_minutes: i64,
+    /// Minutes after it started that a web session expires, however active it is
+    pub session_max_lifetime_minutes: i64,
+    /// Discord user ids of whoever runs the bot, allowed to use operator commands
+    pub owner_ids: Vec<u64>,
+    /// Run the Discord bot in this process
+    pub enable_bot: bool,
+    /// Run the web server in this process
+    pub enable_web: bool,
 }
 
 impl Config {
     pub fn from_env() -> Result<Self> {
         dotenvy::dotenv().ok();
 
+        let verification_webhook_url = dotenvy::var("VERIFICATION_WEBHOOK_URL")
+            .ok()
+            .filter(|s| !s.is_empty());
+        let verification_webhook_secret = dotenvy::var("VERIFICATION_WEBHOOK_SECRET")
+            .ok()
+            .filter(|s| !s.is_empty());
+        if verification_webhook_url.is_some() && verification_webhook_secret.is_none() {
+            anyhow::bail!(
+                "VERIFICATION_WEBHOOK_SECRET must be set when VERIFICATION_WEBHOOK_URL is"
+            );
+        }
+
+        // The prefix also goes into KEYS/SCAN patterns, where these would be wildcards
+        let redis_prefix = dotenvy::var("REDIS_PREFIX").unwrap_or_default();
+        if redis_prefix.contains(['*', '?', '[', ']', '\\']) {
+            anyhow::bail!("REDIS_PREFIX can't contain glob characters, got {redis_prefix:?}");
+        }
+
+        let enable_bot = env_bool("ENABLE_BOT", true)?;
+        let enable_web = env_bool("ENABLE_WEB", true)?;
+
+        let owner_ids = dotenvy::var("BOT_OWNER_IDS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(|id| {
+                id.parse::<u64>()
+                    .with_context(|| format!("BOT_OWNER_IDS must be Discord user ids, got {id:?}"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let session_inactivity_minutes = positive_minutes("SESSION_INACTIVITY_MINUTES", 10)?;
+        let session_max_lifetime_minutes = positive_minutes("SESSION_MAX_LIFETIME_MINUTES", 30)?;
+        // A session starts after its /verify link was issued, so a lifetime at least as
+        // long as the link's means the session never runs out first
+        let link_minutes = PENDING_VERIFICATION_TTL_SECS / 60;
+        if session_max_lifetime_minutes < session_inactivity_minutes.max(link_minutes) {
+            anyhow::bail!(
+                "SESSION_MAX_LIFETIME_MINUTES must be at least SESSION_INACTIVITY_MINUTES and \
+                the {link_minutes} minute verification link lifetime"
+            );
+        }
+
+        let identity_label = dotenvy::var("IDENTITY_LABEL")
+            .ok()
+            .filter(|s| !s.is_empty())
+            .unwrap_or_else(|| "Andrew ID".to_string());
+        if identity_label.chars().count() > MAX_IDENTITY_LABEL_LENGTH {
+            anyhow::bail!("IDENTITY_LABEL must be at most {MAX_IDENTITY_LABEL_LENGTH} characters");
+        }
+
+        if !enable_bot && !enable_web {
+            anyhow::bail!("ENABLE_BOT and ENABLE_WEB are both false, there is nothing to run");
+        }
+
         Ok(Self {
             discord_token: dotenvy::var("DISCORD_TOKEN").context("DISCORD_TOKEN must be set")?,
             keycloak_url: dotenvy::var("KEYCLOAK_URL").context("KEYCLOAK_URL must be set")?,
@@ -37,6 +142,161 @@ impl Config {
                 .context("VALKEY_URL or REDIS_URL must be set")?,
             oauth_relay_url: dotenvy::var("OAUTH_RELAY_URL")
                 .context("OAUTH_RELAY_URL must be set")?,
+            otlp_endpoint: dotenvy::var("OTEL_EXPORTER_OTLP_ENDPOINT")
+                .ok()
+                .filter(|s| !s.is_empty()),
+            oidc_scopes: dotenvy::var("OIDC_SCOPES")
+                .unwrap_or_else(|_| "openid,email,profile".to_string())
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect(),
+            identity_label,
+            level_attribute: dotenvy::var("LEVEL_ATTRIBUTE")
+                .ok()
+                .filter(|s| !s.is_empty())
+                .unwrap_or_else(|| "level".to_string()),
+            class_attribute: dotenvy::var("CLASS_ATTRIBUTE")
+                .ok()
+                .filter(|s| !s.is_empty())
+                .unwrap_or_else(|| "class".to_string()),
+            assign_all_attribute_values: env_bool("ASSIGN_ALL_ATTRIBUTE_VALUES", false)?,
+            verification_webhook_url,
+            verification_webhook_secret,
+            admin_api_token: dotenvy::var("ADMIN_API_TOKEN")
+                .ok()
+                .filter(|s| !s.is_empty()),
+            oidc_discovery_timeout_secs: match dotenvy::var("OIDC_DISCOVERY_TIMEOUT_SECS") {
+                Ok(s) => s
+                    .trim()
+                    .parse()
+                    .context("OIDC_DISCOVERY_TIMEOUT_SECS must be a number of seconds")?,
+                Err(_) => 120,
+            },
+            site_root: dotenvy::var("SITE_ROOT")
+                .ok()
+                .filter(|s| !s.is_empty())
+                .unwrap_or_else(|| "target/site".to_string()),
+            max_pending_verifications: match dotenvy::var("MAX_PENDING_VERIFICATIONS") {
+                Ok(s) => s
+                    .trim()
+                    .parse()
+                    .context("MAX_PENDING_VERIFICATIONS must be a number")?,
+                Err(_) => 5000,
+            },
+            max_pending_verifications_per_guild: match dotenvy::var(
+                "MAX_PENDING_VERIFICATIONS_PER_GUILD",
+            ) {
+                Ok(s) => s
+                    .trim()
+                    .parse()
+                    .context("MAX_PENDING_VERIFICATIONS_PER_GUILD must be a number")?,
+                Err(_) => 500,
+            },
+            keycloak_admin_concurrency: match dotenvy::var("KEYCLOAK_ADMIN_CONCURRENCY") {
+                Ok(s) => match s.trim().parse() {
+                    Ok(0) | Err(_) => {
+                        anyhow::bail!("KEYCLOAK_ADMIN_CONCURRENCY must be a positive number")
+                    }
+                    Ok(n) => n,
+                },
+                Err(_) => 10,
+            },
+            redis_prefix,
+            trust_proxy_headers: env_bool("TRUST_PROXY_HEADERS", false)?,
+            end_session_after_verify: env_bool("END_SESSION_AFTER_VERIFY", false)?,
+            userinfo_show_email: env_bool("USERINFO_SHOW_EMAIL", true)?,
+            session_inactivity_minutes,
+            session_max_lifetime_minutes,
+            owner_ids,
+            enable_bot,
+            enable_web,
         })
     }
 }
+
+/// A boolean from the environment, or the default when unset. Anything other than
+/// true/false/1/0, in any case, fails startup rather than being read as either.
+fn env_bool(name: &str, default: bool) -> Result<bool> {
+    match dotenvy::var(name) {
+        Ok(s) => parse_bool(&s).with_context(|| format!("{name} must be true or false, got {s:?}")),
+        Err(_) => Ok(default),
+    }
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "true" | "1" => Some(true),
+        "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// A positive number of minutes from the environment, or the default when unset
+fn positive_minutes(name: &str, default: i64) -> Result<i64> {
+    match dotenvy::var(name) {
+        Ok(s) => match s.trim().parse() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => anyhow::bail!("{name} must be a positive number of minutes"),
+        },
+        Err(_) => Ok(default),
+    }
+}
+
+#[cfg(test)]
+impl Config {
+    /// Defaults matching `from_env` without reading the environment
+    pub fn for_tests() -> Self {
+        Self {
+            discord_token: String::new(),
+            keycloak_url: "http://localhost:8080".to_string(),
+            keycloak_realm: "test".to_string(),
+            keycloak_oidc_client_id: String::new(),
+            keycloak_oidc_client_secret: String::new(),
+            keycloak_admin_client_id: String::new(),
+            keycloak_admin_client_secret: String::new(),
+            app_url: "http://localhost:3000".to_string(),
+            redis_url: "redis://localhost".to_string(),
+            oauth_relay_url: "http://localhost:3000/auth/callback".to_string(),
+            otlp_endpoint: None,
+            oidc_scopes: vec!["openid".to_string()],
+            identity_label: "Andrew ID".to_string(),
+            level_attribute: "level".to_string(),
+            class_attribute: "class".to_string(),
+            assign_all_attribute_values: false,
+            verification_webhook_url: None,
+            verification_webhook_secret: None,
+            admin_api_token: None,
+            oidc_discovery_timeout_secs: 120,
+            site_root: "target/site".to_string(),
+            max_pending_verifications: 5000,
+            max_pending_verifications_per_guild: 500,
+            keycloak_admin_concurrency: 10,
+            redis_prefix: String::new(),
+            trust_proxy_headers: false,
+            end_session_after_verify: false,
+            userinfo_show_email: true,
+            session_inactivity_minutes: 10,
+            session_max_lifetime_minutes: 30,
+            owner_ids: Vec::new(),
+            enable_bot: true,
+            enable_web: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_booleans_in_any_case() {
+        assert_eq!(parse_bool("TRUE"), Some(true));
+        assert_eq!(parse_bool(" 1 "), Some(true));
+        assert_eq!(parse_bool("False"), Some(false));
+        assert_eq!(parse_bool("0"), Some(false));
+        assert_eq!(parse_bool("no"), None);
+        assert_eq!(parse_bool("off"), None);
+        assert_eq!(parse_bool(""), None);
+    }
+}
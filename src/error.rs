@@ -4,13 +4,55 @@ use axum::response::{IntoResponse, Redirect, Response};
 pub enum AppError {
     VerificationExpired,
     WrongDiscordAccount,
-    AlreadyLinkedToDifferentAccount,
-    DiscordNotLinked,
+    /// The Keycloak account is linked to another Discord, carries the link to relink with
+    AlreadyLinkedToDifferentAccount {
+        state_token: String,
+    },
+    /// Keycloak came back without a Discord link, carries the link to retry with
+    DiscordNotLinked {
+        state_token: String,
+    },
+    /// The user cancelled the Discord link at Keycloak, carries the link to retry with
+    DiscordLinkDeclined {
+        state_token: String,
+    },
+    /// An operator paused new verifications, carries their message if they gave one
+    VerificationPaused {
+        message: Option<String>,
+    },
     KeycloakError(anyhow::Error),
-    RedisError(anyhow::Error),
+    /// Kept as the original error so callers can tell connection failures from bad commands
+    RedisError(redis::RedisError),
     InternalError(anyhow::Error),
 }
 
+impl AppError {
+    /// Whether retrying with the same verification link can't succeed,
+    /// so its pending state should be discarded
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, AppError::WrongDiscordAccount)
+    }
+
+    /// The kind of a Redis error, e.g. `IoError` for a refused connection or
+    /// `TypeError` for WRONGTYPE
+    pub fn redis_kind(&self) -> Option<redis::ErrorKind> {
+        match self {
+            AppError::RedisError(e) => Some(e.kind()),
+            _ => None,
+        }
+    }
+
+    /// Whether this is a Redis failure to reach the server, which is worth retrying,
+    /// rather than a command the server rejected
+    pub fn is_redis_connection_error(&self) -> bool {
+        matches!(
+            self,
+            AppError::RedisError(e)
+                if e.is_io_error() || e.is_connection_dropped() || e.is_timeout()
+        )
+    }
+}
+
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
         match self {
@@ -18,10 +60,29 @@ impl IntoResponse for AppError {
             AppError::WrongDiscordAccount => {
                 Redirect::to("/error?msg=wrong_account").into_response()
             }
-            AppError::AlreadyLinkedToDifferentAccount => {
-                Redirect::to("/error?msg=already_linked").into_response()
-            }
-            AppError::DiscordNotLinked => Redirect::to("/error?msg=not_linked").into_response(),
+            AppError::AlreadyLinkedToDifferentAccount { state_token } => Redirect::to(&format!(
+                "/error?msg=already_linked&state={}",
+                urlencoding::encode(&state_token)
+            ))
+            .into_response(),
+            AppError::DiscordNotLinked { state_token } => Redirect::to(&format!(
+                "/error?msg=not_linked&state={}",
+                urlencoding::encode(&state_token)
+            ))
+            .into_response(),
+            AppError::DiscordLinkDeclined { state_token } => Redirect::to(&format!(
+                "/error?msg=link_declined&state={}",
+                urlencoding::encode(&state_token)
+            ))
+            .into_response(),
+            AppError::VerificationPaused { message } => match message {
+                Some(message) => Redirect::to(&format!(
+                    "/error?msg=paused&detail={}",
+                    urlencoding::encode(&message)
+                ))
+                .into_response(),
+                None => Redirect::to("/error?msg=paused").into_response(),
+            },
             AppError::KeycloakError(e) => {
                 tracing::error!("Keycloak error: {:?}", e);
                 Redirect::to("/error?msg=server_error").into_response()
@@ -46,6 +107,67 @@ impl From<anyhow::Error> for AppError {
 
 impl From<redis::RedisError> for AppError {
     fn from(err: redis::RedisError) -> Self {
-        AppError::RedisError(err.into())
+        AppError::RedisError(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn account_mismatches_are_terminal() {
+        assert!(AppError::WrongDiscordAccount.is_terminal());
+        assert!(
+            !AppError::AlreadyLinkedToDifferentAccount {
+                state_token: "token".to_string()
+            }
+            .is_terminal()
+        );
+    }
+
+    #[test]
+    fn retryable_errors_keep_the_link() {
+        // The user can cancel the Discord link or hit a transient failure and try again
+        assert!(
+            !AppError::DiscordNotLinked {
+                state_token: "token".to_string()
+            }
+            .is_terminal()
+        );
+        assert!(
+            !AppError::DiscordLinkDeclined {
+                state_token: "token".to_string()
+            }
+            .is_terminal()
+        );
+        assert!(!AppError::VerificationExpired.is_terminal());
+        assert!(!AppError::VerificationPaused { message: None }.is_terminal());
+        assert!(!AppError::KeycloakError(anyhow::anyhow!("down")).is_terminal());
+        assert!(
+            !AppError::from(redis::RedisError::from((redis::ErrorKind::IoError, "down")))
+                .is_terminal()
+        );
+        assert!(!AppError::InternalError(anyhow::anyhow!("oops")).is_terminal());
+    }
+
+    #[test]
+    fn redis_errors_keep_their_kind() {
+        let refused = AppError::from(redis::RedisError::from(std::io::Error::from(
+            std::io::ErrorKind::ConnectionRefused,
+        )));
+        assert_eq!(refused.redis_kind(), Some(redis::ErrorKind::IoError));
+        assert!(refused.is_redis_connection_error());
+
+        let wrong_type = AppError::from(redis::RedisError::from((
+            redis::ErrorKind::TypeError,
+            "WRONGTYPE Operation against a key holding the wrong kind of value",
+        )));
+        assert_eq!(wrong_type.redis_kind(), Some(redis::ErrorKind::TypeError));
+        assert!(!wrong_type.is_redis_connection_error());
+
+        let internal = AppError::InternalError(anyhow::anyhow!("oops"));
+        assert_eq!(internal.redis_kind(), None);
+        assert!(!internal.is_redis_connection_error());
     }
 }
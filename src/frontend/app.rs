@@ -1,4 +1,7 @@
-use crate::frontend::pages::{error::ErrorPage, success::SuccessPage};
+use crate::frontend::layout::Layout;
+use crate::frontend::pages::{
+    error::ErrorPage, pending::PendingPage, status::StatusPage, success::SuccessPage,
+};
 use leptos::{IntoView, component, prelude::ElementChild, view};
 use leptos_router::{
     StaticSegment,
@@ -9,12 +12,16 @@ use leptos_router::{
 pub fn App() -> impl IntoView {
     view! {
         <Router>
-            <main>
-                <Routes fallback=|| "Page not found".into_view()>
-                    <Route path=StaticSegment("/success") view=SuccessPage/>
-                    <Route path=StaticSegment("/error") view=ErrorPage/>
-                </Routes>
-            </main>
+            <Layout>
+                <main>
+                    <Routes fallback=|| "Page not found".into_view()>
+                        <Route path=StaticSegment("/pending") view=PendingPage/>
+                        <Route path=StaticSegment("/success") view=SuccessPage/>
+                        <Route path=StaticSegment("/error") view=ErrorPage/>
+                        <Route path=StaticSegment("/status") view=StatusPage/>
+                    </Routes>
+                </main>
+            </Layout>
         </Router>
     }
 }
@@ -0,0 +1,23 @@
+use leptos::{
+    IntoView, component,
+    prelude::{Children, ClassAttribute, ElementChild},
+    view,
+};
+
+/// Enough styling to stay readable, and follow the system theme, if `/style.css`
+/// can't be loaded (e.g. the asset directory is missing)
+const FALLBACK_CSS: &str = ":root { color-scheme: light dark; } \
+    body { font-family: system-ui, sans-serif; max-width: 36rem; margin: 3rem auto; padding: 0 1rem; }";
+
+/// Shared page chrome for every frontend page
+#[component]
+pub fn Layout(children: Children) -> impl IntoView {
+    view! {
+        <style>{FALLBACK_CSS}</style>
+        <link rel="stylesheet" href="/style.css"/>
+        <div class="layout">
+            <header>"ScottyLabs Verification"</header>
+            {children()}
+        </div>
+    }
+}
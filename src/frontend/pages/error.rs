@@ -22,6 +22,12 @@ pub fn ErrorPage() -> impl IntoView {
                             <code>"/verify"</code>
                             " in Discord again to get a new link."
                         </p>
+                        <p>
+                            <a class="button" href="https://discord.com/app" target="_blank" rel="noopener noreferrer">
+                                "Open Discord"
+                            </a>
+                        </p>
+                        <p><a href="#help">"How do I get a new link?"</a></p>
                     </div>
                 }.into_view()
             ),
@@ -40,28 +46,122 @@ pub fn ErrorPage() -> impl IntoView {
                     </div>
                 }.into_view()
             ),
-            "already_linked" => (
-                "Account Already Linked",
-                view! {
-                    <div>
-                        <p>
-                            "Your account is already linked to a different Discord account. "
-                            "Please unlink it first in your "
-                            <a href="https://idp.scottylabs.org/realms/scottylabs/account" target="_blank" rel="noopener noreferrer">
-                                "account settings"
-                            </a>
-                            "."
-                        </p>
-                    </div>
-                }.into_view()
-            ),
-            "not_linked" => (
-                "Discord Account Not Linked",
+            "already_linked" => {
+                // Unlinking asks the user to sign in again before continuing with this link
+                let relink = query
+                    .get()
+                    .get("state")
+                    .filter(|s| !s.is_empty())
+                    .map(|state| {
+                        view! {
+                            <p>
+                                "If you linked the wrong Discord account, you can "
+                                <a href=format!("/relink?state={}", urlencoding::encode(&state))>
+                                    "unlink it and verify again"
+                                </a>
+                                ". You'll be asked to sign in again first."
+                            </p>
+                        }
+                    });
+                (
+                    "Account Already Linked",
+                    view! {
+                        <div>
+                            <p>
+                                "Your account is already linked to a different Discord account. "
+                                "You can also unlink it in your "
+                                <a href="https://idp.scottylabs.org/realms/scottylabs/account" target="_blank" rel="noopener noreferrer">
+                                    "account settings"
+                                </a>
+                                "."
+                            </p>
+                            {relink}
+                        </div>
+                    }.into_view()
+                )
+            }
+            "not_linked" => {
+                // The verification is still pending, so the same link can be retried
+                let retry = query
+                    .get()
+                    .get("state")
+                    .filter(|s| !s.is_empty())
+                    .map(|state| {
+                        view! {
+                            <p>
+                                <a class="button" href=format!("/verify?state={}", urlencoding::encode(&state))>
+                                    "Try again"
+                                </a>
+                            </p>
+                        }
+                    });
+                (
+                    "Discord Account Not Linked",
+                    view! {
+                        <div>
+                            <p>
+                                "Your Discord account was not successfully linked. "
+                                "Please try the verification process again."
+                            </p>
+                            {retry}
+                        </div>
+                    }.into_view()
+                )
+            }
+            "link_declined" => {
+                // The link only works while the verification is pending, /verify issues a new one
+                let retry = query
+                    .get()
+                    .get("state")
+                    .filter(|s| !s.is_empty())
+                    .map(|state| {
+                        view! {
+                            <p>
+                                <a class="button" href=format!("/verify?state={}", urlencoding::encode(&state))>
+                                    "Try linking again"
+                                </a>
+                            </p>
+                        }
+                    });
+                (
+                    "Discord Linking Declined",
+                    view! {
+                        <div>
+                            <p>
+                                "You declined to link your Discord account, so we couldn't verify you. "
+                                "Linking Discord is needed to give you roles in the server."
+                            </p>
+                            {retry}
+                        </div>
+                    }.into_view()
+                )
+            }
+            "paused" => {
+                // The operator's message when they gave one
+                let detail = query
+                    .get()
+                    .get("detail")
+                    .filter(|s| !s.is_empty())
+                    .unwrap_or_else(|| {
+                        "Verification is temporarily paused for maintenance. Please run /verify again later."
+                            .to_string()
+                    });
+                (
+                    "Verification Paused",
+                    view! {
+                        <div>
+                            <p>{detail}</p>
+                        </div>
+                    }.into_view()
+                )
+            }
+            "verification_failed" => (
+                "Role Assignment Failed",
                 view! {
                     <div>
                         <p>
-                            "Your Discord account was not successfully linked. "
-                            "Please try the verification process again."
+                            "Your account was verified, but your roles couldn't be assigned. "
+                            "Check your Discord DMs for details or contact a server administrator."
                         </p>
                     </div>
                 }.into_view()
@@ -97,5 +197,19 @@ pub fn ErrorPage() -> impl IntoView {
             {move || error_content().1}
             <p><small>"You can close this window and return to Discord."</small></p>
         </article>
+        <article id="help">
+            <h2>"Need help?"</h2>
+            <p>
+                "Verification links come from the bot in Discord. Run "
+                <code>"/verify"</code>
+                " in the server you want to verify in, or press its Verify button, and open the "
+                "link it gives you within 10 minutes. Only you can see the bot's reply."
+            </p>
+            <p>
+                "If it keeps failing, ask a server administrator. They can check your status with "
+                <code>"/userinfo"</code>
+                "."
+            </p>
+        </article>
     }
 }
@@ -1,2 +1,4 @@
 pub mod error;
+pub mod pending;
+pub mod status;
 pub mod success;
@@ -0,0 +1,94 @@
+use gloo_net::http::Request;
+use gloo_timers::future::TimeoutFuture;
+use leptos::{
+    IntoView, component,
+    prelude::{ElementChild, Get, GetUntracked, IntoAny, Set, signal},
+    task::spawn_local,
+    view,
+};
+use leptos_router::hooks::{use_location, use_navigate, use_query_map};
+use serde::Deserialize;
+
+/// Delay between status checks
+const POLL_INTERVAL_MS: u32 = 1000;
+/// Stop polling after about half a minute, role assignment is usually done in seconds
+const MAX_POLLS: u32 = 30;
+
+#[derive(Deserialize)]
+struct StatusResponse {
+    status: String,
+}
+
+/// Fetch the verification status, `None` if the request failed
+async fn fetch_status(state_token: &str) -> Option<String> {
+    let response = Request::get(&format!(
+        "/api/verify-status/{}",
+        urlencoding::encode(state_token)
+    ))
+    .send()
+    .await
+    .ok()?;
+
+    response
+        .json::<StatusResponse>()
+        .await
+        .ok()
+        .map(|r| r.status)
+}
+
+#[component]
+pub fn PendingPage() -> impl IntoView {
+    let query = use_query_map();
+    let location = use_location();
+    let navigate = use_navigate();
+    let (timed_out, set_timed_out) = signal(false);
+
+    let state_token = query.get_untracked().get("state").unwrap_or_default();
+    // Pass the guild through to the success page
+    let search = location.search.get_untracked();
+    let search = search.trim_start_matches('?').to_string();
+
+    spawn_local(async move {
+        for _ in 0..MAX_POLLS {
+            match fetch_status(&state_token).await.as_deref() {
+                Some("complete") => {
+                    navigate(&format!("/success?{}", search), Default::default());
+                    return;
+                }
+                Some("awaiting_join") => {
+                    navigate(
+                        &format!("/success?{}&awaiting_join=1", search),
+                        Default::default(),
+                    );
+                    return;
+                }
+                Some("failed") => {
+                    navigate("/error?msg=verification_failed", Default::default());
+                    return;
+                }
+                _ => {}
+            }
+            TimeoutFuture::new(POLL_INTERVAL_MS).await;
+        }
+        set_timed_out.set(true);
+    });
+
+    view! {
+        <article>
+            {move || if timed_out.get() {
+                view! {
+                    <p>
+                        "This is taking longer than expected. Your roles will be assigned shortly, "
+                        "and you'll get a message from the bot in Discord once they are."
+                    </p>
+                }
+                .into_any()
+            } else {
+                view! {
+                    <p>"Assigning your roles, please keep this window open..."</p>
+                }
+                .into_any()
+            }}
+        </article>
+    }
+}
@@ -0,0 +1,67 @@
+use gloo_net::http::Request;
+use leptos::{
+    IntoView, component,
+    prelude::{ClassAttribute, ElementChild, IntoAny, LocalResource, Suspend, Suspense},
+    view,
+};
+use serde::Deserialize;
+
+#[derive(Clone, Deserialize)]
+struct HealthStatus {
+    redis: bool,
+    keycloak: bool,
+    bot: bool,
+}
+
+/// Fetch dependency status, `None` if the web server itself didn't answer
+async fn fetch_health() -> Option<HealthStatus> {
+    Request::get("/api/health/status")
+        .send()
+        .await
+        .ok()?
+        .json::<HealthStatus>()
+        .await
+        .ok()
+}
+
+/// One dependency with a green or red indicator
+#[component]
+fn StatusRow(name: &'static str, ok: bool) -> impl IntoView {
+    view! {
+        <li>
+            <span class=if ok { "status-dot status-ok" } else { "status-dot status-down" }></span>
+            {name}
+            <small>{if ok { "Operational" } else { "Unavailable" }}</small>
+        </li>
+    }
+}
+
+#[component]
+pub fn StatusPage() -> impl IntoView {
+    let health = LocalResource::new(fetch_health);
+
+    view! {
+        <article>
+            <h1>"Service Status"</h1>
+            <Suspense fallback=|| view! { <p>"Checking..."</p> }>
+                {move || Suspend::new(async move {
+                    match health.await {
+                        Some(status) => view! {
+                            <ul class="status-list">
+                                <StatusRow name="Web server" ok=true/>
+                                <StatusRow name="Redis" ok=status.redis/>
+                                <StatusRow name="Keycloak" ok=status.keycloak/>
+                                <StatusRow name="Discord bot" ok=status.bot/>
+                            </ul>
+                        }
+                        .into_any(),
+                        None => view! {
+                            <p>"The verification server isn't responding."</p>
+                        }
+                        .into_any(),
+                    }
+                })}
+            </Suspense>
+        </article>
+    }
+}
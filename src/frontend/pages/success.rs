@@ -1,10 +1,60 @@
-use leptos::{IntoView, component, prelude::ElementChild, view};
+use leptos::{
+    IntoView, component,
+    prelude::{ElementChild, Get},
+    view,
+};
+use leptos_router::hooks::use_query_map;
 
 #[component]
 pub fn SuccessPage() -> impl IntoView {
+    let query = use_query_map();
+
+    // Only link to numeric guild ids so the query can't point somewhere else
+    let return_link = move || {
+        let query = query.get();
+        let guild = query
+            .get("guild")
+            .filter(|g| !g.is_empty() && g.chars().all(|c| c.is_ascii_digit()))?;
+        let guild_name = query
+            .get("guild_name")
+            .filter(|n| !n.is_empty())
+            .unwrap_or_else(|| "Discord".to_string());
+
+        Some(view! {
+            <p>
+                <a href=format!("https://discord.com/channels/{}", guild)>
+                    {format!("Return to {}", guild_name)}
+                </a>
+            </p>
+        })
+    };
+
+    // Set by the server from IDENTITY_LABEL
+    let linked_message = move || {
+        let label = query
+            .get()
+            .get("label")
+            .filter(|l| !l.is_empty())
+            .unwrap_or_else(|| "account".to_string());
+        format!("Your {} has been successfully linked to Discord.", label)
+    };
+
+    // The user left the server before finishing, roles wait until they rejoin
+    let awaiting_join = move || {
+        query.get().get("awaiting_join").is_some().then(|| {
+            view! {
+                <p>
+                    "You're no longer in the server, your roles will be assigned when you rejoin."
+                </p>
+            }
+        })
+    };
+
     view! {
         <article>
-            <p>"Your Andrew ID has been successfully linked to Discord."</p>
+            <p>{linked_message}</p>
+            {awaiting_join}
+            {return_link}
             <p><small>"You can now close this window."</small></p>
         </article>
     }
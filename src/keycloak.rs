@@ -1,13 +1,36 @@
 use anyhow::Result;
-use keycloak::{KeycloakAdmin, KeycloakServiceAccountAdminTokenRetriever, types::*};
+use keycloak::{KeycloakAdmin, KeycloakError, KeycloakServiceAccountAdminTokenRetriever, types::*};
 use reqwest;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+/// How often the admin client is re-validated
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Consecutive failed checks before the outage is reported as sustained
+const SUSTAINED_FAILURE_CHECKS: u32 = 5;
+
+/// Groups fetched per request when listing a user's groups
+const GROUPS_PAGE_SIZE: i32 = 100;
+
 pub struct KeycloakClient {
     admin: KeycloakAdmin<KeycloakServiceAccountAdminTokenRetriever>,
     realm: String,
+    /// Result of the latest admin client validation
+    healthy: AtomicBool,
+    /// Bounds concurrent admin requests so a verification rush doesn't trip Keycloak's rate limits
+    permits: Semaphore,
 }
 
 impl KeycloakClient {
-    pub async fn new(url: &str, realm: &str, client_id: &str, client_secret: &str) -> Result<Self> {
+    pub async fn new(
+        url: &str,
+        realm: &str,
+        client_id: &str,
+        client_secret: &str,
+        max_concurrent_requests: usize,
+    ) -> Result<Self> {
         let http_client = reqwest::Client::new();
 
         // Automatically acquires fresh tokens when needed
@@ -22,21 +45,82 @@ impl KeycloakClient {
         let client = Self {
             admin,
             realm: realm.to_string(),
+            healthy: AtomicBool::new(false),
+            permits: Semaphore::new(max_concurrent_requests),
         };
 
         // Test the admin client by trying to get realm info
         match client.admin.realm_get(&client.realm).await {
-            Ok(_) => tracing::info!("Keycloak admin client validated successfully"),
+            Ok(_) => {
+                tracing::info!("Keycloak admin client validated successfully");
+                client.healthy.store(true, Ordering::SeqCst);
+            }
             Err(e) => tracing::warn!("Keycloak admin client validation failed: {:?}", e),
         }
 
         Ok(client)
     }
 
+    /// Whether the admin API was reachable with our credentials at the last check
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::SeqCst)
+    }
+
+    /// Wait for a free admin request slot, held until the returned permit is dropped.
+    /// Health checks skip this so a backlog doesn't read as Keycloak being down.
+    async fn permit(&self) -> Result<SemaphorePermit<'_>> {
+        if self.permits.available_permits() == 0 {
+            tracing::debug!("Keycloak admin requests saturated, queueing");
+        }
+        Ok(self.permits.acquire().await?)
+    }
+
+    /// Re-validate the admin client forever, so rotated credentials or a renamed realm
+    /// show up in the logs and on /status instead of only as failed verifications
+    pub async fn monitor_health(&self) {
+        let mut interval = tokio::time::interval_at(
+            tokio::time::Instant::now() + HEALTH_CHECK_INTERVAL,
+            HEALTH_CHECK_INTERVAL,
+        );
+        let mut consecutive_failures = 0;
+
+        loop {
+            interval.tick().await;
+
+            match self.admin.realm_get(&self.realm).await {
+                Ok(_) => {
+                    if !self.healthy.swap(true, Ordering::SeqCst) {
+                        tracing::info!(
+                            "Keycloak admin client recovered after {} failed checks",
+                            consecutive_failures
+                        );
+                    }
+                    consecutive_failures = 0;
+                }
+                Err(e) => {
+                    consecutive_failures += 1;
+                    if self.healthy.swap(false, Ordering::SeqCst) {
+                        tracing::warn!("Keycloak admin client became unhealthy: {:?}", e);
+                    }
+                    if consecutive_failures == SUSTAINED_FAILURE_CHECKS {
+                        tracing::error!(
+                            keycloak_admin_unhealthy = true,
+                            consecutive_failures,
+                            "Keycloak admin client has failed {} checks in a row, verifications will fail until it recovers: {:?}",
+                            consecutive_failures,
+                            e
+                        );
+                    }
+                }
+            }
+        }
+    }
+
     pub async fn get_federated_identities(
         &self,
         user_id: &str,
     ) -> Result<Vec<FederatedIdentityRepresentation>> {
+        let _permit = self.permit().await?;
         Ok(self
             .admin
             .realm_users_with_user_id_federated_identity_get(&self.realm, user_id)
@@ -44,6 +128,7 @@ impl KeycloakClient {
     }
 
     pub async fn delete_federated_identity(&self, user_id: &str, provider: &str) -> Result<()> {
+        let _permit = self.permit().await?;
         self.admin
             .realm_users_with_user_id_federated_identity_with_provider_delete(
                 &self.realm,
@@ -54,13 +139,94 @@ impl KeycloakClient {
         Ok(())
     }
 
+    /// End all of a user's Keycloak sessions, so their next login asks for credentials
+    pub async fn logout_user(&self, user_id: &str) -> Result<()> {
+        let _permit = self.permit().await?;
+        self.admin
+            .realm_users_with_user_id_logout_post(&self.realm, user_id)
+            .await?;
+        Ok(())
+    }
+
     pub async fn get_user(&self, user_id: &str) -> Result<UserRepresentation> {
+        let _permit = self.permit().await?;
         Ok(self
             .admin
             .realm_users_with_user_id_get(&self.realm, user_id, None)
             .await?)
     }
 
+    /// Whether a user still exists. Only a 404 counts as deleted, any other failure
+    /// (e.g. Keycloak being down) is returned as an error so callers can skip the user.
+    pub async fn user_exists(&self, user_id: &str) -> Result<bool> {
+        let _permit = self.permit().await?;
+        match self
+            .admin
+            .realm_users_with_user_id_get(&self.realm, user_id, None)
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(KeycloakError::HttpFailure { status: 404, .. }) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Look up a user by exact username
+    pub async fn find_user_by_username(
+        &self,
+        username: &str,
+    ) -> Result<Option<UserRepresentation>> {
+        let _permit = self.permit().await?;
+        let users = self
+            .admin
+            .realm_users_get(
+                &self.realm,
+                None,
+                None,
+                None,
+                None,
+                Some(true),
+                None,
+                None,
+                None,
+                None,
+                None,
+                Some(1),
+                None,
+                None,
+                Some(username.to_string()),
+            )
+            .await?;
+        Ok(users.into_iter().next())
+    }
+
+    /// Names of the groups a user is a direct member of. Keycloak returns at most 100
+    /// groups per request, so this pages through all of them.
+    pub async fn get_user_groups(&self, user_id: &str) -> Result<Vec<String>> {
+        let _permit = self.permit().await?;
+        let mut names = Vec::new();
+        let mut first = 0;
+        loop {
+            let groups = self
+                .admin
+                .realm_users_with_user_id_groups_get(
+                    &self.realm,
+                    user_id,
+                    Some(true),
+                    Some(first),
+                    Some(GROUPS_PAGE_SIZE),
+                    None,
+                )
+                .await?;
+            let page_len = groups.len() as i32;
+            names.extend(groups.into_iter().filter_map(|g| g.name));
+            if page_len < GROUPS_PAGE_SIZE {
+                return Ok(names);
+            }
+            first += GROUPS_PAGE_SIZE;
+        }
+    }
+
     /// Helper to check if a specific Discord account is linked to a Keycloak user
     pub async fn get_discord_identity(
         &self,
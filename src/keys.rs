@@ -0,0 +1,203 @@
+//! Redis key namespacing, so the bot can share a Redis instance with other apps, and
+//! builders for the keys that are also parsed back out of KEYS and SCAN results
+
+use std::sync::OnceLock;
+
+static PREFIX: OnceLock<String> = OnceLock::new();
+
+/// Set the prefix from `REDIS_PREFIX`. Called once at startup, before any key is built.
+pub fn init(prefix: &str) {
+    let _ = PREFIX.set(prefix.to_string());
+}
+
+/// The configured prefix, empty when unset
+pub fn prefix() -> &'static str {
+    PREFIX.get().map_or("", String::as_str)
+}
+
+/// A key returned by KEYS or SCAN without the prefix, so it can be split on ':'
+pub fn unprefixed(key: &str) -> &str {
+    key.strip_prefix(prefix()).unwrap_or(key)
+}
+
+/// Build a Redis key or pattern with the configured prefix, taking `format!` arguments,
+/// e.g. `redis_key!("guild:{}:role_mode", guild_id)`
+macro_rules! redis_key {
+    ($($arg:tt)*) => {
+        format!("{}{}", $crate::keys::prefix(), format_args!($($arg)*))
+    };
+}
+
+pub(crate) use redis_key;
+
+/// What a `guild:{guild_id}:role:...` key maps to a Discord role
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RoleSlot {
+    Verified,
+    Unverified,
+    Level(String),
+    Class(String),
+    Group(String),
+}
+
+impl RoleSlot {
+    /// The part of the key after `role:`, e.g. `level:Undergrad`. /setuproles also uses
+    /// this as the role's key when diffing modes.
+    pub fn suffix(&self) -> String {
+        match self {
+            RoleSlot::Verified => "verified".to_string(),
+            RoleSlot::Unverified => "unverified".to_string(),
+            RoleSlot::Level(name) => format!("level:{}", name),
+            RoleSlot::Class(name) => format!("class:{}", name),
+            RoleSlot::Group(name) => format!("group:{}", name),
+        }
+    }
+
+    /// Inverse of [`RoleSlot::suffix`]. Names can contain ':', group names often do.
+    pub fn from_suffix(suffix: &str) -> Option<Self> {
+        match suffix {
+            "verified" => return Some(RoleSlot::Verified),
+            "unverified" => return Some(RoleSlot::Unverified),
+            _ => {}
+        }
+
+        let (kind, name) = suffix.split_once(':')?;
+        let name = name.to_string();
+        match kind {
+            "level" => Some(RoleSlot::Level(name)),
+            "class" => Some(RoleSlot::Class(name)),
+            "group" => Some(RoleSlot::Group(name)),
+            _ => None,
+        }
+    }
+}
+
+/// `guild:{guild_id}:role:{slot}`, the role a guild maps to a slot
+pub fn guild_role(guild_id: impl Into<u64>, slot: &RoleSlot) -> String {
+    redis_key!("guild:{}:role:{}", guild_id.into(), slot.suffix())
+}
+
+/// Parse a key built by [`guild_role`], with or without the prefix
+pub fn parse_guild_role(key: &str) -> Option<(u64, RoleSlot)> {
+    let guild_id = guild_id_of(key)?;
+    let suffix = unprefixed(key).strip_prefix(&format!("guild:{}:role:", guild_id))?;
+    Some((guild_id, RoleSlot::from_suffix(suffix)?))
+}
+
+/// The guild id of any `guild:{guild_id}:...` key, e.g. one returned by
+/// `KEYS guild:*:reminder_interval`
+pub fn guild_id_of(key: &str) -> Option<u64> {
+    let rest = unprefixed(key).strip_prefix("guild:")?;
+    let (id, _) = rest.split_once(':')?;
+    parse_id(id)
+}
+
+/// `discord:{user_id}:keycloak`, the Keycloak user a Discord user verified as
+pub fn discord_keycloak(user_id: impl Into<u64>) -> String {
+    redis_key!("discord:{}:keycloak", user_id.into())
+}
+
+/// The Discord user id of any `discord:{user_id}:...` key
+pub fn discord_id_of(key: &str) -> Option<u64> {
+    let rest = unprefixed(key).strip_prefix("discord:")?;
+    let (id, _) = rest.split_once(':')?;
+    parse_id(id)
+}
+
+/// Snowflakes are never zero, and serenity's id types panic on it
+fn parse_id(id: &str) -> Option<u64> {
+    // u64's FromStr accepts a leading '+', which would let two spellings share an id
+    if !id.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    id.parse::<u64>().ok().filter(|id| *id != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn slot() -> impl Strategy<Value = RoleSlot> {
+        prop_oneof![
+            Just(RoleSlot::Verified),
+            Just(RoleSlot::Unverified),
+            any::<String>().prop_map(RoleSlot::Level),
+            any::<String>().prop_map(RoleSlot::Class),
+            any::<String>().prop_map(RoleSlot::Group),
+        ]
+    }
+
+    #[test]
+    fn builds_the_documented_formats() {
+        assert_eq!(
+            guild_role(1u64, &RoleSlot::Verified),
+            "guild:1:role:verified"
+        );
+        assert_eq!(
+            guild_role(1u64, &RoleSlot::Level("Undergrad".to_string())),
+            "guild:1:role:level:Undergrad"
+        );
+        assert_eq!(
+            guild_role(1u64, &RoleSlot::Group("cmu:staff".to_string())),
+            "guild:1:role:group:cmu:staff"
+        );
+        assert_eq!(discord_keycloak(2u64), "discord:2:keycloak");
+    }
+
+    #[test]
+    fn rejects_malformed_ids() {
+        assert_eq!(guild_id_of("guild:0:role:verified"), None);
+        assert_eq!(guild_id_of("guild:+1:role:verified"), None);
+        assert_eq!(guild_id_of("guild::role:verified"), None);
+        assert_eq!(guild_id_of("guild:18446744073709551616:role_mode"), None);
+        assert_eq!(discord_id_of("discord:1"), None);
+        assert_eq!(parse_guild_role("guild:1:role:mystery"), None);
+    }
+
+    proptest! {
+        #[test]
+        fn role_keys_round_trip(guild_id in 1..=u64::MAX, slot in slot()) {
+            let key = guild_role(guild_id, &slot);
+            prop_assert_eq!(guild_id_of(&key), Some(guild_id));
+            prop_assert_eq!(parse_guild_role(&key), Some((guild_id, slot.clone())));
+            prop_assert_eq!(RoleSlot::from_suffix(&slot.suffix()), Some(slot));
+        }
+
+        #[test]
+        fn distinct_role_keys_never_collide(
+            a in (1..=u64::MAX, slot()),
+            b in (1..=u64::MAX, slot()),
+        ) {
+            prop_assume!(a != b);
+            prop_assert_ne!(guild_role(a.0, &a.1), guild_role(b.0, &b.1));
+        }
+
+        #[test]
+        fn level_and_class_names_never_shadow_other_slots(name in any::<String>()) {
+            // e.g. a level named "group:x" must not land on the group "x"
+            let level = guild_role(1u64, &RoleSlot::Level(name.clone()));
+            let class = guild_role(1u64, &RoleSlot::Class(name.clone()));
+            let group = guild_role(1u64, &RoleSlot::Group(name));
+            prop_assert_ne!(&level, &class);
+            prop_assert_ne!(&level, &group);
+            prop_assert_ne!(&class, &group);
+            prop_assert_ne!(&level, &guild_role(1u64, &RoleSlot::Verified));
+            prop_assert_ne!(&level, &guild_role(1u64, &RoleSlot::Unverified));
+        }
+
+        #[test]
+        fn user_keys_round_trip(user_id in 1..=u64::MAX) {
+            let key = discord_keycloak(user_id);
+            prop_assert_eq!(discord_id_of(&key), Some(user_id));
+            prop_assert_eq!(guild_id_of(&key), None);
+        }
+
+        #[test]
+        fn parsers_never_panic(key in any::<String>()) {
+            let _ = guild_id_of(&key);
+            let _ = discord_id_of(&key);
+            let _ = parse_guild_role(&key);
+        }
+    }
+}
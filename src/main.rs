@@ -1,6 +1,7 @@
 use std::sync::Arc;
 
 use anyhow::Result;
+use opentelemetry::trace::TracerProvider as _;
 use tokio::sync::mpsc;
 use tracing_subscriber::{EnvFilter, layer::SubscriberExt, util::SubscriberInitExt};
 
@@ -9,49 +10,127 @@ pub mod config;
 pub mod error;
 pub mod frontend;
 pub mod keycloak;
+pub mod keys;
+pub mod redact;
 pub mod state;
+pub mod telemetry;
 pub mod web;
+pub mod webhook;
 
 use config::Config;
 use state::AppState;
 
+/// What to do on startup, chosen by the first command line argument
+enum Mode {
+    /// Run the bot and web server
+    Serve,
+    /// Register the global slash commands and exit
+    RegisterCommands,
+    /// Remove the global slash commands and exit
+    ClearCommands,
+}
+
+impl Mode {
+    fn from_args() -> Result<Self> {
+        match std::env::args().nth(1).as_deref() {
+            None => Ok(Self::Serve),
+            Some("register-commands") => Ok(Self::RegisterCommands),
+            Some("clear-commands") => Ok(Self::ClearCommands),
+            Some(other) => anyhow::bail!(
+                "Unknown subcommand `{}`, expected `register-commands` or `clear-commands`",
+                other
+            ),
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
+    let mode = Mode::from_args()?;
+
     // Load configuration from environment
     let config = Config::from_env()?;
 
+    // Optional OTLP export, a no-op layer when no endpoint is configured
+    let tracer_provider = telemetry::init_tracer_provider(&config)?;
+    let otel_layer = tracer_provider
+        .as_ref()
+        .map(|p| tracing_opentelemetry::layer().with_tracer(p.tracer("discord-verify")));
+
     tracing_subscriber::registry()
         .with(EnvFilter::new(std::env::var("RUST_LOG").unwrap_or_else(
             |_| "axum_oidc=debug,tower_sessions=debug,tower_http=debug,discord_verify=debug".into(),
         )))
         .with(tracing_subscriber::fmt::layer())
+        .with(otel_layer)
         .try_init()?;
 
     tracing::info!("Configuration loaded successfully");
 
+    // Every Redis key is built through the prefix, so set it before anything runs
+    keys::init(&config.redis_prefix);
+
+    // Management subcommands only talk to the Discord HTTP API
+    let register = match mode {
+        Mode::Serve => None,
+        Mode::RegisterCommands => Some(true),
+        Mode::ClearCommands => Some(false),
+    };
+    if let Some(register) = register {
+        return bot::manage_commands(&config, register)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to manage slash commands: {}", e));
+    }
+
     // Create channel for verification completion events
     let (verification_tx, verification_rx) = mpsc::unbounded_channel();
 
     // Create channel for reverify batch jobs
     let (reverify_tx, reverify_rx) = mpsc::unbounded_channel();
 
+    // Create channel for admin API requests
+    let (admin_tx, admin_rx) = mpsc::unbounded_channel();
+
     // Initialize shared state
-    let app_state = Arc::new(AppState::new(config, verification_tx, reverify_tx).await?);
+    let app_state = Arc::new(AppState::new(config, verification_tx, reverify_tx, admin_tx).await?);
     tracing::info!("App state created successfully");
 
-    // Spawn Discord bot in background
-    let bot_state = app_state.clone();
-    tokio::spawn(async move {
-        tracing::info!("Starting Discord bot...");
+    // Keep re-validating the Keycloak admin client, reported on /status
+    let keycloak_state = app_state.clone();
+    tokio::spawn(async move { keycloak_state.keycloak.monitor_health().await });
+
+    if !app_state.config.enable_web {
+        // Bot only, it runs in the foreground
+        tracing::info!("Web server disabled, starting Discord bot only...");
+        bot::run(app_state, verification_rx, reverify_rx, admin_rx)
+            .await
+            .map_err(|e| anyhow::anyhow!("Discord bot error: {}", e))?;
+    } else {
+        if app_state.config.enable_bot {
+            // Spawn Discord bot in background
+            let bot_state = app_state.clone();
+            tokio::spawn(async move {
+                tracing::info!("Starting Discord bot...");
 
-        if let Err(e) = bot::run(bot_state, verification_rx, reverify_rx).await {
-            tracing::error!("Discord bot error: {}", e);
+                if let Err(e) = bot::run(bot_state, verification_rx, reverify_rx, admin_rx).await {
+                    tracing::error!("Discord bot error: {}", e);
+                }
+            });
+        } else {
+            tracing::info!("Discord bot disabled, completions are queued in Redis for it");
         }
-    });
 
-    // Start web server
-    tracing::info!("Starting web server...");
-    web::serve(app_state).await?;
+        // Start web server
+        tracing::info!("Starting web server...");
+        web::serve(app_state).await?;
+    }
+
+    // Flush any spans still buffered in the batch exporter
+    if let Some(provider) = tracer_provider
+        && let Err(e) = provider.shutdown()
+    {
+        tracing::warn!("Failed to shut down tracer provider: {}", e);
+    }
 
     Ok(())
 }
@@ -0,0 +1,13 @@
+use std::fmt::Display;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+/// Short, stable fingerprint of an identifier for use in info-level logs.
+///
+/// The same input always maps to the same fingerprint within a build, so log
+/// lines can still be correlated without exposing Keycloak subjects, Discord
+/// user ids or state tokens. The raw value should only be logged at `trace`.
+pub fn redact(value: impl Display) -> String {
+    let mut hasher = DefaultHasher::new();
+    value.to_string().hash(&mut hasher);
+    format!("#{:08x}", hasher.finish() as u32)
+}
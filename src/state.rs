@@ -1,27 +1,127 @@
 use redis::{AsyncCommands, Client, aio::ConnectionManager};
 use serde::{Deserialize, Serialize};
-use serenity::all::{GuildId, Http, RoleId, UserId};
+use serenity::all::{GuildId, Http, HttpError, Role, RoleId, UserId};
 use std::sync::atomic::AtomicBool;
+use std::time::Duration;
 use std::{collections::HashMap, sync::Arc};
-use tokio::sync::{RwLock, mpsc};
-
-use crate::{config::Config, keycloak::KeycloakClient};
+use tokio::sync::{RwLock, mpsc, oneshot};
+use uuid::Uuid;
+
+use crate::{
+    bot::guild_config::RoleMode,
+    config::Config,
+    keycloak::KeycloakClient,
+    keys::{RoleSlot, guild_role, redis_key},
+    web::claims::VerifyClaims,
+    webhook::WebhookClient,
+};
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct PendingVerification {
     pub discord_user_id: UserId,
     pub discord_username: String,
     pub guild_id: GuildId,
+    /// Guild name from the bot's cache, shown on the success page
+    #[serde(default)]
+    pub guild_name: String,
+    /// Discord locale of the /verify interaction, used for the success DM
+    #[serde(default)]
+    pub locale: String,
     pub created_at: i64,
 }
 
-#[derive(Clone, Debug)]
+/// How long a /verify link stays valid
+pub const PENDING_VERIFICATION_TTL_SECS: i64 = 600;
+
+/// Sorted set of all live state tokens scored by creation time, shared across instances
+pub fn pending_index_key() -> String {
+    redis_key!("pending_verifications")
+}
+
+/// Sorted set of a guild's live state tokens scored by creation time
+pub fn guild_pending_index_key(guild_id: GuildId) -> String {
+    redis_key!("guild:{}:pending_verifications", guild_id)
+}
+
+/// List of completions a web-only process hands to the bot process
+pub fn completion_queue_key() -> String {
+    redis_key!("verification_queue")
+}
+
+/// Count of completions the in-process bot couldn't take, which were queued in Redis instead
+pub fn dropped_completions_key() -> String {
+    redis_key!("completions_dropped")
+}
+
+/// Pauses new verifications everywhere while set, holding the message to show (may be empty)
+pub fn maintenance_key() -> String {
+    redis_key!("maintenance")
+}
+
+/// Pauses new verifications in one guild while set, like `maintenance_key`
+pub fn guild_maintenance_key(guild_id: GuildId) -> String {
+    redis_key!("guild:{}:maintenance", guild_id)
+}
+
+/// New verifications are paused, with the operator's message if they gave one
+#[derive(Debug, Clone)]
+pub struct Maintenance {
+    pub message: Option<String>,
+}
+
+impl PendingVerification {
+    /// Unix time the verification link stops working
+    pub fn expires_at(&self) -> i64 {
+        self.created_at + PENDING_VERIFICATION_TTL_SECS
+    }
+
+    pub fn is_expired(&self) -> bool {
+        chrono::Utc::now().timestamp() - self.created_at >= PENDING_VERIFICATION_TTL_SECS
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct VerificationComplete {
     pub discord_user_id: UserId,
     pub guild_id: GuildId,
     pub keycloak_user_id: String,
+    /// ID token claims from the login, `None` when there was no login (e.g. reverify)
+    pub claims: Option<VerifyClaims>,
+    /// Discord locale of the user, empty if unknown
+    pub locale: String,
+    /// When the /verify link was created, `None` if the user didn't go through /verify
+    pub started_at: Option<i64>,
+    /// Token of the web flow, so its status page can follow the role assignment
+    pub state_token: Option<String>,
+    /// Span of the originating request, so the bot's completion is traced as its child
+    #[serde(skip, default = "tracing::Span::none")]
+    pub span: tracing::Span,
 }
 
+/// Progress of a verification after the web flow hands it to the bot
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VerifyStatus {
+    Processing,
+    Complete,
+    /// Linked, but the user left the guild so roles wait until they rejoin
+    AwaitingJoin,
+    Failed,
+}
+
+impl VerifyStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Processing => "processing",
+            Self::Complete => "complete",
+            Self::AwaitingJoin => "awaiting_join",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+/// How long the status of a handed-off verification stays readable
+pub const VERIFY_STATUS_TTL_SECS: u64 = 600;
+
 // Job sent over the reverify channel, one per batch of users
 #[derive(Clone, Debug)]
 pub struct ReverifyJob {
@@ -33,20 +133,67 @@ pub struct ReverifyJob {
     pub total_batches: usize,
 }
 
+/// Operation requested through the admin HTTP API, carried out by the bot
+#[derive(Debug)]
+pub enum AdminAction {
+    Unverify {
+        guild_id: GuildId,
+        user_id: UserId,
+    },
+    Link {
+        guild_id: GuildId,
+        user_id: UserId,
+        /// Keycloak username or user ID
+        keycloak: String,
+    },
+}
+
+/// Admin API request sent over the admin channel. The bot answers on `reply` with a
+/// summary on success, or `AdminFailure` if the action couldn't be carried out.
+#[derive(Debug)]
+pub struct AdminCommand {
+    pub action: AdminAction,
+    pub reply: oneshot::Sender<Result<String, AdminFailure>>,
+}
+
+/// Why an admin API action failed
+#[derive(Debug)]
+pub enum AdminFailure {
+    /// The user or Keycloak account wasn't found
+    NotFound(String),
+    /// The action conflicts with existing state
+    Conflict(String),
+    /// An unexpected error
+    Internal(String),
+}
+
+/// How long an abandoned /setuproles session is kept before being evicted
+pub const SETUPROLES_SESSION_TTL_SECS: i64 = 15 * 60;
+
 #[derive(Clone, Debug)]
 pub struct SetupRolesSession {
-    pub mode: String,
+    pub mode: RoleMode,
     pub custom_roles: Vec<String>,
+    /// Embedded in the session's component custom_ids so clicks on an older menu are rejected
+    pub nonce: Uuid,
+    pub created_at: i64,
 }
 
 impl SetupRolesSession {
-    pub fn new(mode: String) -> Self {
+    pub fn new(mode: RoleMode) -> Self {
         Self {
             mode,
             custom_roles: Vec::new(),
+            nonce: Uuid::new_v4(),
+            created_at: chrono::Utc::now().timestamp(),
         }
     }
 
+    /// Whether the session has outlived its TTL
+    pub fn is_expired(&self) -> bool {
+        chrono::Utc::now().timestamp() - self.created_at > SETUPROLES_SESSION_TTL_SECS
+    }
+
     /// Update the custom roles selection
     pub fn set_custom_roles(&mut self, roles: Vec<String>) {
         self.custom_roles = roles;
@@ -54,7 +201,7 @@ impl SetupRolesSession {
 
     /// Validate that the session has all required data to proceed
     pub fn validate(&self) -> Result<(), &'static str> {
-        if self.mode == "custom" && self.custom_roles.is_empty() {
+        if self.mode == RoleMode::Custom && self.custom_roles.is_empty() {
             return Err("Please select at least one role to create for custom mode.");
         }
 
@@ -63,12 +210,12 @@ impl SetupRolesSession {
 
     /// Determine which roles to create based on the mode and selections
     pub fn get_roles_to_create(&self) -> Vec<(String, String)> {
-        match self.mode.as_str() {
-            "levels" => vec![
+        match self.mode {
+            RoleMode::Levels => vec![
                 ("Undergrad".to_string(), "level:Undergrad".to_string()),
                 ("Graduate".to_string(), "level:Graduate".to_string()),
             ],
-            "classes" => vec![
+            RoleMode::Classes => vec![
                 ("First-Year".to_string(), "class:First-Year".to_string()),
                 ("Sophomore".to_string(), "class:Sophomore".to_string()),
                 ("Junior".to_string(), "class:Junior".to_string()),
@@ -80,7 +227,7 @@ impl SetupRolesSession {
                 ("Masters".to_string(), "class:Masters".to_string()),
                 ("Doctoral".to_string(), "class:Doctoral".to_string()),
             ],
-            "custom" => {
+            RoleMode::Custom => {
                 // Map the selections to (display_name, redis_key_suffix)
                 self.custom_roles
                     .iter()
@@ -106,23 +253,28 @@ impl SetupRolesSession {
                     })
                     .collect()
             }
-            _ => vec![],
+            RoleMode::None | RoleMode::Groups => vec![],
         }
     }
 
-    /// Create the roles in Discord and save configuration to Redis
+    /// Create the roles in Discord and save configuration to Redis.
+    /// `on_progress` is called before each role creation with the 1-based index,
+    /// the number of roles to create and the role name.
     pub async fn save_and_create_roles(
         &self,
         http: &Http,
         guild_id: GuildId,
         redis: &mut ConnectionManager,
+        mut on_progress: impl AsyncFnMut(usize, usize, &str),
     ) -> Result<Vec<(String, RoleId)>, Box<dyn std::error::Error + Send + Sync>> {
         let guild = guild_id.to_partial_guild(http).await?;
 
         // Get the current mode to determine what roles exist
-        let current_mode_key = format!("guild:{}:role_mode", guild_id);
+        let current_mode_key = redis_key!("guild:{}:role_mode", guild_id);
         let current_mode: Option<String> = redis.get(&current_mode_key).await?;
-        let current_mode = current_mode.unwrap_or_else(|| "none".to_string());
+        let current_mode = current_mode
+            .and_then(|mode| mode.parse().ok())
+            .unwrap_or(RoleMode::None);
 
         // Get roles in the old mode and new mode
         let current_roles = self
@@ -162,109 +314,118 @@ impl SetupRolesSession {
             .cloned()
             .collect();
 
-        // Delete old roles and their Redis keys
-        for (role_key, role_id) in &roles_to_delete {
-            // Check if the role still exists in the guild before trying to delete it
-            if guild.roles.contains_key(role_id) {
-                if let Err(e) = guild_id.delete_role(http, *role_id, None).await {
-                    tracing::warn!("Failed to delete role {}: {}", role_id, e);
-                }
-            } else {
-                tracing::debug!(
-                    "Role {} (ID: {}) already deleted from Discord, cleaning up Redis key",
-                    role_key,
-                    role_id
-                );
-            }
+        // Create all new roles first, so a failure leaves the existing configuration untouched
+        let mut all_roles = Vec::new();
+        let mut created_roles = Vec::new();
 
-            // Clean up the Redis key regardless of whether the Discord role exists
-            let redis_key = format!("guild:{}:role:{}", guild_id, role_key);
-            let _: () = redis.del(&redis_key).await?;
+        let mut pending: Vec<_> = roles_to_create
+            .iter()
+            .map(|(role_name, role_key)| (role_key.clone(), role_name.clone(), None))
+            .collect();
+        for (role_key, role_id) in &roles_to_keep {
+            // Get the display name in case this role needs recreating
+            let display_name = role_key_to_name
+                .get(role_key.as_str())
+                .ok_or(format!("Missing display name for role key: {}", role_key))?;
+            pending.push((role_key.clone(), display_name.to_string(), Some(*role_id)));
         }
 
-        let mut all_roles = Vec::new();
+        // Progress only counts roles that need creating
+        let guild_roles: HashMap<RoleId, String> = guild
+            .roles
+            .iter()
+            .map(|role| (role.id, role.name.to_string()))
+            .collect();
+        let (existing, missing) = split_existing_roles(pending, &guild_roles);
+        all_roles.extend(existing);
 
-        // Create all new roles
-        for (role_name, role_key) in &roles_to_create {
-            // Check if a role with this name already exists in the guild
-            let existing_role = guild.roles.iter().find(|r| r.name == *role_name);
+        let total = missing.len();
+        for (index, (role_key, role_name)) in missing.into_iter().enumerate() {
+            on_progress(index + 1, total, &role_name).await;
 
-            let role_id = if let Some(existing) = existing_role {
-                tracing::info!(
-                    "Role '{}' already exists in Discord (ID: {}), reusing it",
-                    role_name,
-                    existing.id
-                );
-                existing.id
-            } else {
-                // Create the role
-                let new_role = guild_id
-                    .create_role(
-                        http,
-                        serenity::all::EditRole::new().name(role_name.as_str()),
-                    )
-                    .await?;
-                new_role.id
-            };
+            // Space out creations so a large mode doesn't trip the role creation limit
+            if !created_roles.is_empty() {
+                tokio::time::sleep(ROLE_CREATE_DELAY).await;
+            }
 
-            all_roles.push((role_key.clone(), role_id));
+            match create_role_with_retry(http, guild_id, &role_name).await {
+                Ok(new_role) => {
+                    created_roles.push(new_role.id);
+                    all_roles.push((role_key, new_role.id));
+                }
+                Err(e) => {
+                    let rollback = rollback_created_roles(http, guild_id, &created_roles).await;
+                    return Err(format!(
+                        "Failed to create role {} after creating {} other roles: {}. \
+                        No configuration changes were saved. {}",
+                        role_name,
+                        created_roles.len(),
+                        e,
+                        rollback
+                    )
+                    .into());
+                }
+            }
+        }
 
-            // Store role ID in Redis
-            let redis_key = format!("guild:{}:role:{}", guild_id, role_key);
-            let _: () = redis.set(&redis_key, role_id.get()).await?;
+        // Save the role keys and mode in a single transaction
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for (role_key, _) in &roles_to_delete {
+            pipe.del(guild_role(guild_id, &role_slot(role_key)?))
+                .ignore();
+        }
+        for (role_key, role_id) in &all_roles {
+            pipe.set(guild_role(guild_id, &role_slot(role_key)?), role_id.get())
+                .ignore();
+        }
+        pipe.set(
+            redis_key!("guild:{}:role_mode", guild_id),
+            self.mode.as_str(),
+        )
+        .ignore();
+
+        if let Err(e) = pipe.query_async::<()>(redis).await {
+            let rollback = rollback_created_roles(http, guild_id, &created_roles).await;
+            return Err(format!(
+                "Failed to save role configuration: {}. No configuration changes were saved. {}",
+                e, rollback
+            )
+            .into());
         }
 
-        // Add kept roles to the list
-        for (role_key, role_id) in &roles_to_keep {
-            if !guild.roles.contains_key(role_id) {
-                // Role was manually deleted from Discord but still in Redis, recreate it
-                tracing::info!(
-                    "Role {} (ID: {}) was deleted from Discord, recreating it",
+        // Only delete old roles once the new configuration is in place
+        for (role_key, role_id) in &roles_to_delete {
+            // Check if the role still exists in the guild before trying to delete it
+            if guild.roles.contains_key(role_id) {
+                if let Err(e) = guild_id.delete_role(http, *role_id, None).await {
+                    tracing::warn!("Failed to delete role {}: {}", role_id, e);
+                }
+            } else {
+                tracing::debug!(
+                    "Role {} (ID: {}) already deleted from Discord, cleaned up Redis key",
                     role_key,
                     role_id
                 );
-
-                // Get the display name for this role
-                let display_name = role_key_to_name
-                    .get(role_key.as_str())
-                    .ok_or(format!("Missing display name for role key: {}", role_key))?;
-
-                // Create the role
-                let new_role = guild_id
-                    .create_role(http, serenity::all::EditRole::new().name(*display_name))
-                    .await?;
-
-                // Update Redis with the new role ID
-                let redis_key = format!("guild:{}:role:{}", guild_id, role_key);
-                let _: () = redis.set(&redis_key, new_role.id.get()).await?;
-
-                all_roles.push((role_key.clone(), new_role.id));
-            } else {
-                // Role exists, just add it to the list
-                all_roles.push((role_key.clone(), *role_id));
             }
         }
 
-        // Save mode in Redis
-        let role_mode_key = format!("guild:{}:role_mode", guild_id);
-        let _: () = redis.set(&role_mode_key, self.mode.as_str()).await?;
-
         Ok(all_roles)
     }
 
     /// Get currently configured roles based on the old mode
     async fn get_current_roles(
         &self,
-        current_mode: &str,
+        current_mode: &RoleMode,
         redis: &mut ConnectionManager,
         guild_id: GuildId,
     ) -> Result<Vec<(String, RoleId)>, Box<dyn std::error::Error + Send + Sync>> {
         let mut current_roles = Vec::new();
 
         match current_mode {
-            "levels" => {
+            RoleMode::Levels => {
                 for level_name in &["Undergrad", "Graduate"] {
-                    let key = format!("guild:{}:role:level:{}", guild_id, level_name);
+                    let key = guild_role(guild_id, &RoleSlot::Level(level_name.to_string()));
                     if let Ok(Some(role_id_str)) = redis.get::<_, Option<String>>(&key).await
                         && let Ok(role_id_u64) = role_id_str.parse::<u64>()
                     {
@@ -273,7 +434,7 @@ impl SetupRolesSession {
                     }
                 }
             }
-            "classes" => {
+            RoleMode::Classes => {
                 for class_name in &[
                     "First-Year",
                     "Sophomore",
@@ -283,7 +444,7 @@ impl SetupRolesSession {
                     "Masters",
                     "Doctoral",
                 ] {
-                    let key = format!("guild:{}:role:class:{}", guild_id, class_name);
+                    let key = guild_role(guild_id, &RoleSlot::Class(class_name.to_string()));
                     if let Ok(Some(role_id_str)) = redis.get::<_, Option<String>>(&key).await
                         && let Ok(role_id_u64) = role_id_str.parse::<u64>()
                     {
@@ -292,7 +453,7 @@ impl SetupRolesSession {
                     }
                 }
             }
-            "custom" => {
+            RoleMode::Custom => {
                 // For custom mode, check all possible roles
                 let all_possible = vec![
                     ("level:Undergrad", "Undergrad"),
@@ -306,7 +467,7 @@ impl SetupRolesSession {
                     ("class:Doctoral", "Doctoral"),
                 ];
                 for (redis_suffix, _name) in all_possible {
-                    let key = format!("guild:{}:role:{}", guild_id, redis_suffix);
+                    let key = guild_role(guild_id, &role_slot(redis_suffix)?);
                     if let Ok(Some(role_id_str)) = redis.get::<_, Option<String>>(&key).await
                         && let Ok(role_id_u64) = role_id_str.parse::<u64>()
                     {
@@ -314,50 +475,304 @@ impl SetupRolesSession {
                     }
                 }
             }
-            _ => {} // "none" or unknown mode has no roles
+            RoleMode::None | RoleMode::Groups => {} // Group roles are mapped individually
         }
 
         Ok(current_roles)
     }
 }
 
+/// The slot for a role key like `level:Undergrad`
+fn role_slot(role_key: &str) -> Result<RoleSlot, Box<dyn std::error::Error + Send + Sync>> {
+    RoleSlot::from_suffix(role_key).ok_or_else(|| format!("Unknown role key: {}", role_key).into())
+}
+
+/// Split roles to set up into the ones still in the guild's roles (id to name), by their
+/// stored id, and the ones to create. Roles are only ever matched by id, so renaming one
+/// in Discord (or `/renamerole`) doesn't lose it, and an unrelated role that happens to
+/// share a name is never adopted and later deleted.
+fn split_existing_roles(
+    pending: Vec<(String, String, Option<RoleId>)>,
+    guild_roles: &HashMap<RoleId, String>,
+) -> (Vec<(String, RoleId)>, Vec<(String, String)>) {
+    let mut existing = Vec::new();
+    let mut missing = Vec::new();
+    for (role_key, role_name, stored_role) in pending {
+        // Kept roles only need recreating if they were manually deleted from Discord
+        match stored_role {
+            Some(role_id) if guild_roles.contains_key(&role_id) => {
+                existing.push((role_key, role_id))
+            }
+            _ => missing.push((role_key, role_name)),
+        }
+    }
+    (existing, missing)
+}
+
+/// Delay between consecutive role creations in a single save
+const ROLE_CREATE_DELAY: Duration = Duration::from_millis(500);
+
+/// Longest total time to keep retrying a rate limited role creation
+const ROLE_CREATE_MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Create a role, retrying with exponential backoff while Discord rate limits us.
+/// Serenity's ratelimiter already waits out `Retry-After` and retries on its own, so
+/// only 429s without a usable `Retry-After` surface here, and those are backed off.
+async fn create_role_with_retry(
+    http: &Http,
+    guild_id: GuildId,
+    name: &str,
+) -> Result<Role, serenity::Error> {
+    let mut delay = Duration::from_secs(1);
+    let mut waited = Duration::ZERO;
+
+    loop {
+        match guild_id
+            .create_role(http, serenity::all::EditRole::new().name(name))
+            .await
+        {
+            Err(serenity::Error::Http(HttpError::UnsuccessfulRequest(response)))
+                if response.status_code.as_u16() == 429
+                    && waited + delay <= ROLE_CREATE_MAX_BACKOFF =>
+            {
+                tracing::warn!(
+                    "Rate limited creating role '{}', retrying in {:?}",
+                    name,
+                    delay
+                );
+                tokio::time::sleep(delay).await;
+                waited += delay;
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Delete roles created by a failed save, describing the outcome for the error message
+async fn rollback_created_roles(
+    http: &Http,
+    guild_id: GuildId,
+    created_roles: &[RoleId],
+) -> String {
+    let mut leftover = Vec::new();
+    for role_id in created_roles {
+        if let Err(e) = guild_id.delete_role(http, *role_id, None).await {
+            tracing::warn!("Failed to roll back created role {}: {}", role_id, e);
+            leftover.push(format!("<@&{}>", role_id));
+        }
+    }
+
+    if leftover.is_empty() {
+        format!("Rolled back {} newly created roles.", created_roles.len())
+    } else {
+        format!(
+            "These newly created roles could not be removed and may need to be deleted manually: {}",
+            leftover.join(", ")
+        )
+    }
+}
+
 pub struct AppState {
     pub config: Config,
     pub keycloak: KeycloakClient,
     pub redis: ConnectionManager,
+    /// Discord REST client for the web server, which has no gateway connection
+    pub discord_http: Arc<Http>,
     pub verification_tx: mpsc::UnboundedSender<VerificationComplete>,
     pub pending_verifications: Arc<RwLock<HashMap<String, PendingVerification>>>,
     pub setuproles_sessions: Arc<RwLock<HashMap<(GuildId, UserId), SetupRolesSession>>>,
     pub reverify_tx: mpsc::UnboundedSender<ReverifyJob>,
     pub reverify_in_progress: Arc<AtomicBool>,
+    /// Whether the bot's gateway connection is up, for the status page
+    pub bot_connected: Arc<AtomicBool>,
+    pub admin_tx: mpsc::UnboundedSender<AdminCommand>,
+    /// Notified of completed verifications, `None` if no webhook is configured
+    pub webhook: Option<WebhookClient>,
 }
 
 impl AppState {
+    /// Record a verification's progress for the /pending page. Failures are only
+    /// logged since the page falls back to telling the user to check Discord.
+    pub async fn set_verify_status(&self, state_token: &str, status: VerifyStatus) {
+        let mut conn = self.redis.clone();
+        if let Err(e) = conn
+            .set_ex::<_, _, ()>(
+                redis_key!("verify_status:{}", state_token),
+                status.as_str(),
+                VERIFY_STATUS_TTL_SECS,
+            )
+            .await
+        {
+            tracing::warn!("Failed to record verification status: {}", e);
+        }
+    }
+
+    /// Progress of a verification handed to the bot, `None` if unknown or expired
+    pub async fn verify_status(&self, state_token: &str) -> Option<String> {
+        let mut conn = self.redis.clone();
+        conn.get(redis_key!("verify_status:{}", state_token))
+            .await
+            .ok()
+            .flatten()
+    }
+
+    /// Whether new verifications in a guild are paused. The guild's own pause takes
+    /// precedence over the global one for its message. Redis failures count as not
+    /// paused, maintenance mode shouldn't become another way for /verify to break.
+    pub async fn maintenance(&self, guild_id: GuildId) -> Option<Maintenance> {
+        let mut conn = self.redis.clone();
+        let (guild, global): (Option<String>, Option<String>) = match redis::pipe()
+            .get(guild_maintenance_key(guild_id))
+            .get(maintenance_key())
+            .query_async(&mut conn)
+            .await
+        {
+            Ok(values) => values,
+            Err(e) => {
+                tracing::warn!("Failed to read maintenance mode: {}", e);
+                return None;
+            }
+        };
+
+        guild.or(global).map(|message| Maintenance {
+            message: Some(message.trim().to_string()).filter(|m| !m.is_empty()),
+        })
+    }
+
+    /// The pending verification for a state token. Falls back to Redis for links this
+    /// process didn't issue, e.g. a web-only process with the bot running elsewhere.
+    pub async fn pending_verification(&self, state_token: &str) -> Option<PendingVerification> {
+        if let Some(verification) = self.pending_verifications.read().await.get(state_token) {
+            return Some(verification.clone());
+        }
+
+        let mut conn = self.redis.clone();
+        let data: Option<String> = match conn.get(redis_key!("verify:{}", state_token)).await {
+            Ok(data) => data,
+            Err(e) => {
+                tracing::warn!("Failed to read pending verification from Redis: {}", e);
+                None
+            }
+        };
+        serde_json::from_str(&data?).ok()
+    }
+
+    /// Hand a completed login to the bot, through Redis when the bot runs in another
+    /// process. If this process's bot has stopped taking completions they're queued in
+    /// Redis too, for another bot process or this one after a restart, and counted.
+    /// Only an error when the completion couldn't be queued either.
+    pub async fn send_completion(&self, completion: VerificationComplete) -> anyhow::Result<()> {
+        let mut conn = self.redis.clone();
+
+        let completion = if self.config.enable_bot {
+            match self.verification_tx.send(completion) {
+                Ok(()) => return Ok(()),
+                Err(mpsc::error::SendError(completion)) => {
+                    tracing::error!(
+                        completion_dropped = true,
+                        "Completion handler is not running, queueing the verification in Redis"
+                    );
+                    if let Err(e) = conn.incr::<_, _, ()>(dropped_completions_key(), 1).await {
+                        tracing::warn!("Failed to count dropped completion: {}", e);
+                    }
+                    completion
+                }
+            }
+        } else {
+            completion
+        };
+
+        let data = serde_json::to_string(&completion)?;
+        let _: () = conn.lpush(completion_queue_key(), data).await?;
+        Ok(())
+    }
+
     pub async fn new(
         config: Config,
         verification_tx: mpsc::UnboundedSender<VerificationComplete>,
         reverify_tx: mpsc::UnboundedSender<ReverifyJob>,
+        admin_tx: mpsc::UnboundedSender<AdminCommand>,
     ) -> anyhow::Result<Self> {
         let keycloak = KeycloakClient::new(
             &config.keycloak_url,
             &config.keycloak_realm,
             &config.keycloak_admin_client_id,
             &config.keycloak_admin_client_secret,
+            config.keycloak_admin_concurrency,
         )
         .await?;
 
         let redis_client = Client::open(config.redis_url.clone())?;
         let redis = ConnectionManager::new(redis_client).await?;
 
+        let webhook = WebhookClient::from_config(&config)?;
+
+        let discord_http = Arc::new(Http::new(config.discord_token.parse()?));
+
         Ok(Self {
             config,
             keycloak,
             redis,
+            discord_http,
             verification_tx,
             pending_verifications: Arc::new(RwLock::new(HashMap::new())),
             setuproles_sessions: Arc::new(RwLock::new(HashMap::new())),
             reverify_tx,
             reverify_in_progress: Arc::new(AtomicBool::new(false)),
+            bot_connected: Arc::new(AtomicBool::new(false)),
+            admin_tx,
+            webhook,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_named_roles_are_not_adopted() {
+        // The guild already has an unrelated "Senior" role (999) the bot didn't create
+        let guild_roles = HashMap::from([
+            (RoleId::new(5), "Undergrad".to_string()),
+            (RoleId::new(999), "Senior".to_string()),
+        ]);
+        let pending = vec![
+            (
+                "level:Undergrad".to_string(),
+                "Undergrad".to_string(),
+                Some(RoleId::new(5)),
+            ),
+            ("class:Senior".to_string(), "Senior".to_string(), None),
+        ];
+
+        let (existing, missing) = split_existing_roles(pending, &guild_roles);
+
+        assert_eq!(
+            existing,
+            vec![("level:Undergrad".to_string(), RoleId::new(5))]
+        );
+        assert_eq!(
+            missing,
+            vec![("class:Senior".to_string(), "Senior".to_string())]
+        );
+    }
+
+    #[test]
+    fn deleted_roles_are_recreated() {
+        let pending = vec![(
+            "level:Graduate".to_string(),
+            "Graduate".to_string(),
+            Some(RoleId::new(6)),
+        )];
+
+        let (existing, missing) = split_existing_roles(pending, &HashMap::new());
+
+        assert!(existing.is_empty());
+        assert_eq!(
+            missing,
+            vec![("level:Graduate".to_string(), "Graduate".to_string())]
+        );
+    }
+}
@@ -0,0 +1,31 @@
+use anyhow::Result;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{Resource, trace::SdkTracerProvider};
+
+use crate::config::Config;
+
+/// Build an OTLP trace exporter if `OTEL_EXPORTER_OTLP_ENDPOINT` is configured.
+/// Returns `None` when tracing export is disabled so the layer is a no-op.
+pub fn init_tracer_provider(config: &Config) -> Result<Option<SdkTracerProvider>> {
+    let Some(endpoint) = config.otlp_endpoint.as_deref() else {
+        return Ok(None);
+    };
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+        .build()?;
+
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name("discord-verify")
+                .build(),
+        )
+        .build();
+
+    opentelemetry::global::set_tracer_provider(provider.clone());
+
+    Ok(Some(provider))
+}
@@ -1,11 +1,27 @@
-use crate::{error::AppError, state::AppState};
+use crate::{
+    bot::guild_config::GuildConfig,
+    error::AppError,
+    keys::{discord_keycloak, redis_key},
+    state::{AdminAction, AdminCommand, AdminFailure, AppState},
+};
 use axum::{
     Json,
-    extract::{Path, State},
+    body::Body,
+    extract::{FromRequestParts, Path, State},
+    http::{StatusCode, header, request::Parts},
     response::IntoResponse,
 };
+use redis::AsyncCommands;
 use serde::{Deserialize, Serialize};
+use serenity::all::{ChannelId, GuildId, RoleId, UserId};
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::ReceiverStream;
+
+/// Verified members read from Redis per batch of the CSV export
+const EXPORT_BATCH_SIZE: usize = 500;
 
 #[derive(Serialize, Deserialize)]
 pub struct VerifyStatusResponse {
@@ -19,21 +35,25 @@ pub async fn verify_status(
     Path(state_token): Path<String>,
 ) -> Result<Json<VerifyStatusResponse>, AppError> {
     // Check if verification exists
-    let verification = {
-        let verifications = state.pending_verifications.read().await;
-        verifications.get(&state_token).cloned()
-    };
+    let verification = state.pending_verification(&state_token).await;
 
-    match verification {
-        Some(v) => Ok(Json(VerifyStatusResponse {
+    if let Some(v) = verification {
+        return Ok(Json(VerifyStatusResponse {
             status: "pending".to_string(),
             discord_username: Some(v.discord_username),
-        })),
-        None => Ok(Json(VerifyStatusResponse {
-            status: "not_found".to_string(),
-            discord_username: None,
-        })),
+        }));
     }
+
+    // Handed to the bot, report how role assignment is going
+    let status = state
+        .verify_status(&state_token)
+        .await
+        .unwrap_or_else(|| "not_found".to_string());
+
+    Ok(Json(VerifyStatusResponse {
+        status,
+        discord_username: None,
+    }))
 }
 
 /// Health check endpoint
@@ -41,3 +61,396 @@ pub async fn verify_status(
 pub async fn health() -> impl IntoResponse {
     "OK"
 }
+
+/// Connectivity of each dependency. Booleans only, so the public status page
+/// doesn't reveal internal hostnames or error details.
+#[derive(Serialize, Deserialize)]
+pub struct HealthStatusResponse {
+    pub redis: bool,
+    pub keycloak: bool,
+    pub bot: bool,
+}
+
+/// Detailed health check backing the /status page
+#[axum::debug_handler]
+pub async fn health_status(State(state): State<Arc<AppState>>) -> Json<HealthStatusResponse> {
+    let mut conn = state.redis.clone();
+    let redis = redis::cmd("PING")
+        .query_async::<String>(&mut conn)
+        .await
+        .is_ok();
+
+    Json(HealthStatusResponse {
+        redis,
+        // Validated in the background, see KeycloakClient::monitor_health
+        keycloak: state.keycloak.is_healthy(),
+        bot: state.bot_connected.load(Ordering::SeqCst),
+    })
+}
+
+/// Extractor guarding the admin API. Requires `Authorization: Bearer <ADMIN_API_TOKEN>`,
+/// and rejects every request when no token is configured.
+pub struct AdminAuth;
+
+impl FromRequestParts<Arc<AppState>> for AdminAuth {
+    type Rejection = (StatusCode, Json<AdminResponse>);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let provided = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+
+        match (state.config.admin_api_token.as_deref(), provided) {
+            (Some(expected), Some(provided)) if constant_time_eq(expected, provided) => Ok(Self),
+            _ => Err((
+                StatusCode::UNAUTHORIZED,
+                Json(AdminResponse {
+                    success: false,
+                    message: "Missing or invalid bearer token".to_string(),
+                }),
+            )),
+        }
+    }
+}
+
+/// Compare tokens without returning early on the first differing byte
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AdminResponse {
+    pub success: bool,
+    pub message: String,
+}
+
+#[derive(Deserialize)]
+pub struct AdminUnverifyRequest {
+    pub guild_id: GuildId,
+    pub user_id: UserId,
+}
+
+#[derive(Deserialize)]
+pub struct AdminLinkRequest {
+    pub guild_id: GuildId,
+    pub user_id: UserId,
+    /// Keycloak username or user ID
+    pub keycloak: String,
+}
+
+/// Unverify a user, as /unverify does
+#[axum::debug_handler]
+pub async fn admin_unverify(
+    _auth: AdminAuth,
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<AdminUnverifyRequest>,
+) -> (StatusCode, Json<AdminResponse>) {
+    run_admin_action(
+        &state,
+        AdminAction::Unverify {
+            guild_id: request.guild_id,
+            user_id: request.user_id,
+        },
+    )
+    .await
+}
+
+/// Link a user to a Keycloak account without a login, as /forcelink does
+#[axum::debug_handler]
+pub async fn admin_link(
+    _auth: AdminAuth,
+    State(state): State<Arc<AppState>>,
+    Json(request): Json<AdminLinkRequest>,
+) -> (StatusCode, Json<AdminResponse>) {
+    run_admin_action(
+        &state,
+        AdminAction::Link {
+            guild_id: request.guild_id,
+            user_id: request.user_id,
+            keycloak: request.keycloak,
+        },
+    )
+    .await
+}
+
+/// Hand the action to the bot and wait for its result
+async fn run_admin_action(
+    state: &AppState,
+    action: AdminAction,
+) -> (StatusCode, Json<AdminResponse>) {
+    let (reply, result) = oneshot::channel();
+    let result = match state.admin_tx.send(AdminCommand { action, reply }) {
+        Ok(()) => result
+            .await
+            .unwrap_or_else(|_| Err(AdminFailure::Internal("The bot dropped the request".into()))),
+        Err(_) => Err(AdminFailure::Internal("The bot is not running".into())),
+    };
+
+    let (status, success, message) = match result {
+        Ok(message) => (StatusCode::OK, true, message),
+        Err(AdminFailure::NotFound(message)) => (StatusCode::NOT_FOUND, false, message),
+        Err(AdminFailure::Conflict(message)) => (StatusCode::CONFLICT, false, message),
+        Err(AdminFailure::Internal(message)) => {
+            tracing::error!("Admin API action failed: {}", message);
+            (StatusCode::INTERNAL_SERVER_ERROR, false, message)
+        }
+    };
+
+    (status, Json(AdminResponse { success, message }))
+}
+
+#[derive(Serialize)]
+pub struct AttributeRoleResponse {
+    pub attribute: String,
+    pub value: String,
+    pub role_id: RoleId,
+}
+
+#[derive(Serialize)]
+pub struct GuildConfigResponse {
+    pub guild_id: GuildId,
+    pub mode: String,
+    pub verified_role: Option<RoleId>,
+    pub always_roles: Vec<RoleId>,
+    pub unverified_role: Option<RoleId>,
+    pub log_channel: Option<ChannelId>,
+    pub level_roles: HashMap<String, RoleId>,
+    pub class_roles: HashMap<String, RoleId>,
+    pub group_roles: HashMap<String, RoleId>,
+    pub attribute_roles: Vec<AttributeRoleResponse>,
+    pub protected_roles: Vec<RoleId>,
+    pub exempt_roles: Vec<RoleId>,
+    pub verified_count: usize,
+}
+
+/// A guild's verification configuration and verified member count, for dashboards
+#[axum::debug_handler]
+pub async fn guild_config(
+    _auth: AdminAuth,
+    State(state): State<Arc<AppState>>,
+    Path(guild_id): Path<GuildId>,
+) -> Result<Json<GuildConfigResponse>, (StatusCode, Json<AdminResponse>)> {
+    let internal_error = |e: crate::bot::Error| {
+        tracing::error!("Failed to load config for guild {}: {}", guild_id, e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(AdminResponse {
+                success: false,
+                message: "Failed to load the guild configuration".to_string(),
+            }),
+        )
+    };
+
+    let mut conn = state.redis.clone();
+    let config = GuildConfig::load(&mut conn, state.discord_http.as_ref(), guild_id)
+        .await
+        .map_err(internal_error)?;
+
+    // Nothing works without a verified role, treat the guild as unconfigured
+    if config.verified_role.is_none() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(AdminResponse {
+                success: false,
+                message: format!("Guild {} has no verification config", guild_id),
+            }),
+        ));
+    }
+
+    let verified_count: usize = conn
+        .scard(redis_key!("guild:{}:verified_members", guild_id))
+        .await
+        .map_err(|e| internal_error(e.into()))?;
+
+    Ok(Json(GuildConfigResponse {
+        guild_id,
+        mode: config.mode.as_str().to_string(),
+        verified_role: config.verified_role,
+        always_roles: config.always_roles,
+        unverified_role: config.unverified_role,
+        log_channel: config.log_channel,
+        level_roles: config.level_roles,
+        class_roles: config.class_roles,
+        group_roles: config.group_roles,
+        attribute_roles: config
+            .attribute_roles
+            .into_iter()
+            .map(|((attribute, value), role_id)| AttributeRoleResponse {
+                attribute,
+                value,
+                role_id,
+            })
+            .collect(),
+        protected_roles: config.protected_roles.into_iter().collect(),
+        exempt_roles: config.exempt_roles.into_iter().collect(),
+        verified_count,
+    }))
+}
+
+/// The guild's verified members as CSV, streamed in batches so large guilds aren't
+/// buffered in memory. Only Redis is read: Discord id, linked Keycloak id, the Keycloak
+/// username cached from their last login and when the link was made. Emails are
+/// included from the same cache when `USERINFO_SHOW_EMAIL` allows it.
+#[axum::debug_handler]
+pub async fn export_verified_csv(
+    _auth: AdminAuth,
+    State(state): State<Arc<AppState>>,
+    Path(guild_id): Path<GuildId>,
+) -> impl IntoResponse {
+    let (tx, rx) = mpsc::channel::<Result<String, std::io::Error>>(4);
+    let show_email = state.config.userinfo_show_email;
+
+    tokio::spawn(async move {
+        let header = if show_email {
+            "discord_id,keycloak_id,username,email,verified_at\n"
+        } else {
+            "discord_id,keycloak_id,username,verified_at\n"
+        };
+        if tx.send(Ok(header.to_string())).await.is_err() {
+            return;
+        }
+
+        let mut conn = state.redis.clone();
+        let members_key = redis_key!("guild:{}:verified_members", guild_id);
+        let mut cursor = 0u64;
+
+        loop {
+            let batch = async {
+                let (next, members): (u64, Vec<u64>) = redis::cmd("SSCAN")
+                    .arg(&members_key)
+                    .arg(cursor)
+                    .arg("COUNT")
+                    .arg(EXPORT_BATCH_SIZE)
+                    .query_async(&mut conn)
+                    .await?;
+                if members.is_empty() {
+                    return Ok::<_, redis::RedisError>((next, String::new()));
+                }
+
+                let mut pipe = redis::pipe();
+                for user_id in &members {
+                    pipe.get(discord_keycloak(*user_id))
+                        .get(redis_key!("discord:{}:verified_at", user_id));
+                }
+                let links: Vec<Option<String>> = pipe.query_async(&mut conn).await?;
+
+                // The profile is cached under the Keycloak id, so it needs a second round trip
+                let mut pipe = redis::pipe();
+                for link in links.chunks(2) {
+                    let keycloak_id = link[0].as_deref().unwrap_or_default().trim();
+                    pipe.hget(
+                        redis_key!("keycloak:{}:profile", keycloak_id),
+                        &["username", "email"],
+                    );
+                }
+                let profiles: Vec<(Option<String>, Option<String>)> =
+                    pipe.query_async(&mut conn).await?;
+
+                let mut rows = String::new();
+                for ((user_id, link), (username, email)) in
+                    members.iter().zip(links.chunks(2)).zip(profiles)
+                {
+                    let keycloak_id = link[0].as_deref().unwrap_or_default();
+                    let verified_at = link[1]
+                        .as_deref()
+                        .and_then(|t| t.trim().parse::<i64>().ok())
+                        .and_then(|t| chrono::DateTime::from_timestamp(t, 0))
+                        .map(|t| t.to_rfc3339())
+                        .unwrap_or_default();
+                    let mut row = format!(
+                        "{},{},{},",
+                        user_id,
+                        csv_field(keycloak_id),
+                        csv_field(username.as_deref().unwrap_or_default())
+                    );
+                    if show_email {
+                        row.push_str(&csv_field(email.as_deref().unwrap_or_default()));
+                        row.push(',');
+                    }
+                    row.push_str(&verified_at);
+                    row.push('\n');
+                    rows.push_str(&row);
+                }
+                Ok((next, rows))
+            }
+            .await;
+
+            match batch {
+                Ok((next, rows)) => {
+                    if !rows.is_empty() && tx.send(Ok(rows)).await.is_err() {
+                        // Client went away
+                        return;
+                    }
+                    if next == 0 {
+                        return;
+                    }
+                    cursor = next;
+                }
+                Err(e) => {
+                    // Cut the body short so the client sees a failed download, not a partial roster
+                    tracing::error!(
+                        "Failed to export verified members of guild {}: {}",
+                        guild_id,
+                        e
+                    );
+                    let _ = tx.send(Err(std::io::Error::other(e))).await;
+                    return;
+                }
+            }
+        }
+    });
+
+    (
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"verified-{}.csv\"", guild_id),
+            ),
+        ],
+        Body::from_stream(ReceiverStream::new(rx)),
+    )
+}
+
+/// Quote a CSV field when it contains a delimiter, quote or newline. Values a spreadsheet
+/// would run as a formula are prefixed with `'` and quoted, so they're shown as text.
+fn csv_field(value: &str) -> String {
+    if value.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("\"'{}\"", value.replace('"', "\"\""))
+    } else if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_csv_fields_only_when_needed() {
+        assert_eq!(csv_field("scotty"), "scotty");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn neutralizes_spreadsheet_formulas() {
+        assert_eq!(csv_field("=HYPERLINK(\"x\")"), "\"'=HYPERLINK(\"\"x\"\")\"");
+        assert_eq!(csv_field("+1"), "\"'+1\"");
+        assert_eq!(csv_field("-1"), "\"'-1\"");
+        assert_eq!(csv_field("@SUM(A1)"), "\"'@SUM(A1)\"");
+        assert_eq!(csv_field("\tx"), "\"'\tx\"");
+        assert_eq!(csv_field("a=b"), "a=b");
+    }
+}
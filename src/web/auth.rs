@@ -1,36 +1,173 @@
-use crate::{error::AppError, state::AppState};
+use crate::{
+    error::AppError,
+    keys::redis_key,
+    redact::redact,
+    state::{
+        AppState, PendingVerification, VerifyStatus, guild_pending_index_key, pending_index_key,
+    },
+    web::{OIDC_SESSION_KEY, claims::VerifyClaims, end_session_url},
+};
 use axum::{
     extract::{Query, State},
     response::{IntoResponse, Redirect, Response},
 };
-use axum_oidc::{EmptyAdditionalClaims, OidcClaims};
-use serde::Deserialize;
+use axum_oidc::OidcClaims;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tower_sessions::Session;
 
+/// Session key for a verification that hit a Keycloak account linked to another Discord
+const RELINK_SESSION_KEY: &str = "pending_relink";
+
+/// How recently the user must have entered their credentials to unlink Discord
+const RELINK_MAX_AUTH_AGE_SECS: i64 = 300;
+
+/// Recorded by `verify_start` so `/relink` only unlinks the account that hit the mismatch
+#[derive(Serialize, Deserialize)]
+struct PendingRelink {
+    state_token: String,
+    keycloak_user_id: String,
+}
+
+/// Pending page URL, which waits for the bot to assign roles before showing success.
+/// Carries the guild so the success page can link back to Discord, and the identity
+/// label since the pages can't read the server's config.
+fn pending_path(
+    state_token: &str,
+    verification: &PendingVerification,
+    identity_label: &str,
+) -> String {
+    format!(
+        "/pending?state={}&guild={}&guild_name={}&label={}",
+        state_token,
+        verification.guild_id,
+        urlencoding::encode(&verification.guild_name),
+        urlencoding::encode(identity_label)
+    )
+}
+
+fn pending_redirect(
+    state_token: &str,
+    verification: &PendingVerification,
+    identity_label: &str,
+) -> Redirect {
+    Redirect::to(&pending_path(state_token, verification, identity_label))
+}
+
+/// Drop a verification's pending state from memory and Redis
+async fn discard_verification(state: &AppState, state_token: &str) {
+    let removed = state
+        .pending_verifications
+        .write()
+        .await
+        .remove(state_token);
+    // Issued by a bot in another process, only Redis has it
+    let removed = match removed {
+        Some(verification) => Some(verification),
+        None => state.pending_verification(state_token).await,
+    };
+
+    let mut pipe = redis::pipe();
+    pipe.del(redis_key!("verify:{}", state_token))
+        .ignore()
+        .zrem(pending_index_key(), state_token)
+        .ignore();
+    if let Some(verification) = removed {
+        pipe.del(redis_key!(
+            "user:{}:verify_token",
+            verification.discord_user_id
+        ))
+        .ignore()
+        .zrem(guild_pending_index_key(verification.guild_id), state_token)
+        .ignore();
+    }
+
+    let mut conn = state.redis.clone();
+    if let Err(e) = pipe.query_async::<()>(&mut conn).await {
+        tracing::warn!("Failed to delete pending verification from Redis: {}", e);
+    }
+}
+
+/// Keep the username and email from a login that's being handed to the bot, for the
+/// CSV export. Best effort, a failure only leaves those columns empty.
+async fn cache_profile(state: &AppState, claims: &OidcClaims<VerifyClaims>) {
+    let mut fields = Vec::new();
+    if let Some(username) = claims.preferred_username() {
+        fields.push(("username", username.to_string()));
+    }
+    if let Some(email) = claims.email() {
+        fields.push(("email", email.to_string()));
+    }
+    if fields.is_empty() {
+        return;
+    }
+
+    let mut conn = state.redis.clone();
+    if let Err(e) = conn
+        .hset_multiple::<_, _, _, ()>(
+            redis_key!("keycloak:{}:profile", claims.subject().as_str()),
+            &fields,
+        )
+        .await
+    {
+        tracing::warn!("Failed to cache Keycloak profile: {}", e);
+    }
+}
+
+/// Respond with an error, discarding the verification if its link can't be retried
+async fn fail(state: &AppState, state_token: &str, error: AppError) -> Response {
+    if error.is_terminal() {
+        discard_verification(state, state_token).await;
+    }
+    error.into_response()
+}
+
 #[derive(Deserialize)]
 pub struct VerifyQuery {
     state: String,
 }
 
+/// What Keycloak reports back from the Discord linking action
+#[derive(Deserialize)]
+pub struct LinkCallbackQuery {
+    /// Set when the user denied consent, e.g. `access_denied`
+    error: Option<String>,
+    /// `cancelled` when the user backed out of the linking page
+    kc_action_status: Option<String>,
+}
+
+impl LinkCallbackQuery {
+    fn declined(&self) -> bool {
+        self.error.is_some()
+            || matches!(
+                self.kc_action_status.as_deref(),
+                Some("cancelled") | Some("error")
+            )
+    }
+}
+
 #[axum::debug_handler]
+#[tracing::instrument(skip_all, fields(state = %redact(&query.state)))]
 pub async fn verify_start(
     State(state): State<Arc<AppState>>,
     Query(query): Query<VerifyQuery>,
-    oidc_claims: OidcClaims<EmptyAdditionalClaims>,
+    oidc_claims: OidcClaims<VerifyClaims>,
     session: Session,
 ) -> Response {
-    tracing::info!("verify_start called with state token: {}", query.state);
+    tracing::info!(
+        "verify_start called with state token {}",
+        redact(&query.state)
+    );
+    tracing::trace!("verify_start raw state token: {}", query.state);
 
     let state_token = query.state;
     let user_id = oidc_claims.subject().to_string();
-    tracing::info!("User authenticated: {}", user_id);
+    tracing::info!("User authenticated: {}", redact(&user_id));
+    tracing::trace!("Raw Keycloak subject: {}", user_id);
 
     // Get verification data
-    let verification = {
-        let verifications = state.pending_verifications.read().await;
-        verifications.get(&state_token).cloned()
-    };
+    let verification = state.pending_verification(&state_token).await;
 
     tracing::debug!(
         "Verification data lookup result: {:?}",
@@ -42,7 +179,7 @@ pub async fn verify_start(
         None => {
             tracing::warn!(
                 "Verification expired or not found for state: {}",
-                state_token
+                redact(&state_token)
             );
             return AppError::VerificationExpired.into_response();
         }
@@ -50,7 +187,7 @@ pub async fn verify_start(
 
     tracing::info!(
         "Checking Keycloak federated identities for user: {}",
-        user_id
+        redact(&user_id)
     );
 
     // Check if Discord already linked
@@ -73,35 +210,57 @@ pub async fn verify_start(
         // Already linked, validate
         if discord.user_id.as_deref() == Some(&verification.discord_user_id.to_string()) {
             tracing::info!("Discord ID matches, completing verification");
+            cache_profile(&state, &oidc_claims).await;
 
             // Send verification completion event to bot
             let completion = crate::state::VerificationComplete {
                 discord_user_id: verification.discord_user_id,
                 guild_id: verification.guild_id,
                 keycloak_user_id: user_id.clone(),
+                claims: Some(oidc_claims.additional_claims().clone()),
+                locale: verification.locale.clone(),
+                started_at: Some(verification.created_at),
+                state_token: Some(state_token.clone()),
+                span: tracing::Span::current(),
             };
 
-            if let Err(e) = state.verification_tx.send(completion) {
-                tracing::error!("Failed to send verification completion event: {}", e);
-            }
+            let status = match state.send_completion(completion).await {
+                Ok(()) => VerifyStatus::Processing,
+                Err(e) => {
+                    tracing::error!("Failed to send verification completion event: {}", e);
+                    VerifyStatus::Failed
+                }
+            };
+            state.set_verify_status(&state_token, status).await;
 
             // Clean up
-            state
-                .pending_verifications
-                .write()
-                .await
-                .remove(&state_token);
+            discard_verification(&state, &state_token).await;
 
-            tracing::info!("Redirecting to success page");
-            return Redirect::to(&format!("/success?state={}", state_token)).into_response();
+            tracing::info!("Redirecting to pending page");
+            return pending_redirect(&state_token, &verification, &state.config.identity_label)
+                .into_response();
         } else {
             // Linked to different Discord account
             tracing::warn!(
-                "Discord account mismatch. Expected: {}, Got: {:?}",
+                "Discord account mismatch. Expected: {}, Got: {}",
+                redact(verification.discord_user_id),
+                discord.user_id.as_deref().map(redact).unwrap_or_default()
+            );
+            tracing::trace!(
+                "Discord account mismatch raw ids. Expected: {}, Got: {:?}",
                 verification.discord_user_id,
                 discord.user_id
             );
-            return AppError::AlreadyLinkedToDifferentAccount.into_response();
+
+            // Keep the verification alive so the user can unlink and retry with it
+            let relink = PendingRelink {
+                state_token: state_token.clone(),
+                keycloak_user_id: user_id.clone(),
+            };
+            if let Err(e) = session.insert(RELINK_SESSION_KEY, &relink).await {
+                tracing::warn!("Failed to store relink state in session: {}", e);
+            }
+            return AppError::AlreadyLinkedToDifferentAccount { state_token }.into_response();
         }
     }
 
@@ -120,11 +279,12 @@ pub async fn verify_start(
     // State carries /link-callback
     let relay_state = super::relay_state(&format!("{}/link-callback", state.config.app_url));
     let linking_url = format!(
-        "{}/realms/{}/protocol/openid-connect/auth?client_id={}&redirect_uri={}&response_type=code&scope=openid%20email%20profile&state={}&kc_action=idp_link:discord",
+        "{}/realms/{}/protocol/openid-connect/auth?client_id={}&redirect_uri={}&response_type=code&scope={}&state={}&kc_action=idp_link:discord",
         state.config.keycloak_url,
         state.config.keycloak_realm,
         urlencoding::encode(&state.config.keycloak_oidc_client_id),
         urlencoding::encode(&state.config.oauth_relay_url),
+        urlencoding::encode(&state.config.oidc_scopes.join(" ")),
         urlencoding::encode(&relay_state),
     );
 
@@ -132,9 +292,11 @@ pub async fn verify_start(
 }
 
 #[axum::debug_handler]
+#[tracing::instrument(skip_all, fields(state = tracing::field::Empty))]
 pub async fn link_callback(
     State(state): State<Arc<AppState>>,
-    oidc_claims: Option<OidcClaims<EmptyAdditionalClaims>>,
+    Query(query): Query<LinkCallbackQuery>,
+    oidc_claims: Option<OidcClaims<VerifyClaims>>,
     session: Session,
 ) -> Response {
     tracing::info!("link_callback called");
@@ -144,7 +306,8 @@ pub async fn link_callback(
     };
 
     let user_id = claims.subject().to_string();
-    tracing::info!("User authenticated: {}", user_id);
+    tracing::info!("User authenticated: {}", redact(&user_id));
+    tracing::trace!("Raw Keycloak subject: {}", user_id);
 
     // Retrieve state_token from session
     let state_token: String = match session.get("pending_verification_state").await {
@@ -159,25 +322,41 @@ pub async fn link_callback(
         }
     };
 
-    tracing::info!("Retrieved state_token from session: {}", state_token);
+    tracing::info!(
+        "Retrieved state_token from session: {}",
+        redact(&state_token)
+    );
+    tracing::trace!("Raw state_token from session: {}", state_token);
+    tracing::Span::current().record("state", redact(&state_token));
 
     // Clean up session
     if let Err(e) = session.remove::<String>("pending_verification_state").await {
         tracing::warn!("Failed to remove state from session: {}", e);
     }
 
+    // The user backed out of linking Discord. Drop the whole session so the Keycloak
+    // login doesn't linger, retrying the link starts a fresh login.
+    if query.declined() {
+        tracing::info!(
+            "User declined to link Discord (error: {:?}, status: {:?})",
+            query.error,
+            query.kc_action_status
+        );
+        if let Err(e) = session.flush().await {
+            tracing::warn!("Failed to clear session after declined link: {}", e);
+        }
+        return AppError::DiscordLinkDeclined { state_token }.into_response();
+    }
+
     // Get verification data
-    let verification = {
-        let verifications = state.pending_verifications.read().await;
-        verifications.get(&state_token).cloned()
-    };
+    let verification = state.pending_verification(&state_token).await;
 
     let verification = match verification {
         Some(v) => v,
         None => {
             tracing::warn!(
                 "Verification expired or not found for state: {}",
-                state_token
+                redact(&state_token)
             );
             return AppError::VerificationExpired.into_response();
         }
@@ -188,7 +367,7 @@ pub async fn link_callback(
         Ok(i) => {
             tracing::info!("Found {} federated identities after Discord auth", i.len());
             for identity in &i {
-                tracing::debug!(
+                tracing::trace!(
                     "Identity provider: {:?}, user_id: {:?}",
                     identity.identity_provider,
                     identity.user_id
@@ -207,12 +386,15 @@ pub async fn link_callback(
         .find(|i| i.identity_provider.as_deref() == Some("discord"))
     {
         Some(i) => {
-            tracing::info!("Found Discord identity with user_id: {:?}", i.user_id);
+            tracing::info!(
+                "Found Discord identity with user_id: {}",
+                i.user_id.as_deref().map(redact).unwrap_or_default()
+            );
             i
         }
         None => {
             tracing::warn!("Discord identity not found after auth flow. User may have cancelled.");
-            return AppError::DiscordNotLinked.into_response();
+            return AppError::DiscordNotLinked { state_token }.into_response();
         }
     };
 
@@ -222,27 +404,303 @@ pub async fn link_callback(
             .keycloak
             .delete_federated_identity(&user_id, "discord")
             .await;
-        return AppError::WrongDiscordAccount.into_response();
+        return fail(&state, &state_token, AppError::WrongDiscordAccount).await;
     }
 
+    cache_profile(&state, &claims).await;
+
     // Send verification completion event to bot
     let completion = crate::state::VerificationComplete {
         discord_user_id: verification.discord_user_id,
         guild_id: verification.guild_id,
         keycloak_user_id: user_id.clone(),
+        claims: Some(claims.additional_claims().clone()),
+        locale: verification.locale.clone(),
+        started_at: Some(verification.created_at),
+        state_token: Some(state_token.clone()),
+        span: tracing::Span::current(),
     };
 
-    if let Err(e) = state.verification_tx.send(completion) {
-        tracing::error!("Failed to send verification completion event: {}", e);
-        // Continue anyway, user verified but role assignment will fail
-    }
+    let status = match state.send_completion(completion).await {
+        Ok(()) => VerifyStatus::Processing,
+        Err(e) => {
+            // Continue anyway, user verified but role assignment will fail
+            tracing::error!("Failed to send verification completion event: {}", e);
+            VerifyStatus::Failed
+        }
+    };
+    state.set_verify_status(&state_token, status).await;
 
     // Success, clean up and redirect
-    state
-        .pending_verifications
-        .write()
+    discard_verification(&state, &state_token).await;
+
+    // On shared computers the Keycloak login shouldn't outlive the verification. The
+    // pending page only needs the state token, so nothing in the session is still needed.
+    if state.config.end_session_after_verify {
+        if let Err(e) = session.flush().await {
+            tracing::warn!("Failed to clear session after verification: {}", e);
+        }
+        return Redirect::to(&end_session_url(
+            &state.config,
+            &pending_path(&state_token, &verification, &state.config.identity_label),
+        ))
+        .into_response();
+    }
+
+    pending_redirect(&state_token, &verification, &state.config.identity_label).into_response()
+}
+
+/// Unlink the Discord account from the user's Keycloak account and restart verification,
+/// for users who linked someone else's Discord. Requires a recent login so a borrowed
+/// session can't unlink.
+#[axum::debug_handler]
+#[tracing::instrument(skip_all, fields(state = %redact(&query.state)))]
+pub async fn relink(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<VerifyQuery>,
+    oidc_claims: OidcClaims<VerifyClaims>,
+    session: Session,
+) -> Response {
+    let state_token = query.state;
+    let user_id = oidc_claims.subject().to_string();
+
+    // Only the account that hit the mismatch in this browser, and only its own link
+    let relink: Option<PendingRelink> = match session.get(RELINK_SESSION_KEY).await {
+        Ok(relink) => relink,
+        Err(e) => {
+            tracing::error!("Failed to retrieve relink state from session: {}", e);
+            return AppError::InternalError(anyhow::anyhow!("Session error")).into_response();
+        }
+    };
+    let Some(relink) = relink.filter(|r| r.state_token == state_token) else {
+        tracing::warn!("No relink pending for this verification");
+        return AppError::VerificationExpired.into_response();
+    };
+    if relink.keycloak_user_id != user_id {
+        tracing::warn!(
+            "Relink requested by {} for {}",
+            redact(&user_id),
+            redact(&relink.keycloak_user_id)
+        );
+        return AppError::VerificationExpired.into_response();
+    }
+
+    if state.pending_verification(&state_token).await.is_none() {
+        tracing::warn!(
+            "Verification expired or not found for state: {}",
+            redact(&state_token)
+        );
+        return AppError::VerificationExpired.into_response();
+    }
+
+    let recently_authenticated = oidc_claims.auth_time().is_some_and(|auth_time| {
+        chrono::Utc::now().timestamp() - auth_time.timestamp() <= RELINK_MAX_AUTH_AGE_SECS
+    });
+    if !recently_authenticated {
+        // Keycloak's SSO would sign the user straight back in, so end their Keycloak
+        // sessions first and come back here once they've entered their credentials
+        tracing::info!(
+            "Relink needs a fresh login, signing out {}",
+            redact(&user_id)
+        );
+        if let Err(e) = state.keycloak.logout_user(&user_id).await {
+            tracing::error!("Failed to end Keycloak sessions: {:?}", e);
+            return AppError::KeycloakError(e).into_response();
+        }
+        if let Err(e) = session.remove::<serde_json::Value>(OIDC_SESSION_KEY).await {
+            tracing::error!("Failed to clear login from session: {}", e);
+            return AppError::InternalError(anyhow::anyhow!("Session error")).into_response();
+        }
+        return Redirect::to(&format!(
+            "/relink?state={}",
+            urlencoding::encode(&state_token)
+        ))
+        .into_response();
+    }
+
+    if let Err(e) = state
+        .keycloak
+        .delete_federated_identity(&user_id, "discord")
         .await
-        .remove(&state_token);
+    {
+        tracing::error!("Failed to unlink Discord: {:?}", e);
+        return AppError::KeycloakError(e).into_response();
+    }
+    tracing::info!("Unlinked Discord from {}", redact(&user_id));
+
+    if let Err(e) = session.remove::<PendingRelink>(RELINK_SESSION_KEY).await {
+        tracing::warn!("Failed to remove relink state from session: {}", e);
+    }
+
+    // Nothing is linked now, so /verify starts linking the requesting Discord account
+    Redirect::to(&format!(
+        "/verify?state={}",
+        urlencoding::encode(&state_token)
+    ))
+    .into_response()
+}
+
+/// Tests run against a throwaway Redis container, so they need Docker:
+/// `cargo test -- --ignored web::auth`
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use serenity::all::{GuildId, UserId};
+    use testcontainers_modules::{
+        redis::{REDIS_PORT, Redis},
+        testcontainers::{ContainerAsync, runners::AsyncRunner},
+    };
+    use tokio::sync::mpsc;
+
+    const STATE_TOKEN: &str = "state-token";
+    const USER: UserId = UserId::new(7);
+    const GUILD: GuildId = GuildId::new(1);
+
+    /// App state backed by a fresh Redis. Keycloak and Discord aren't reachable, which
+    /// only matters to tests that call them.
+    async fn state() -> (ContainerAsync<Redis>, AppState) {
+        let container = Redis::default().start().await.unwrap();
+        let mut config = Config::for_tests();
+        config.redis_url = format!(
+            "redis://{}:{}",
+            container.get_host().await.unwrap(),
+            container.get_host_port_ipv4(REDIS_PORT).await.unwrap()
+        );
+        config.discord_token =
+            "MTAwMDAwMDAwMDAwMDAwMDAw.AAAAAA.AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA".to_string();
+
+        let state = AppState::new(
+            config,
+            mpsc::unbounded_channel().0,
+            mpsc::unbounded_channel().0,
+            mpsc::unbounded_channel().0,
+        )
+        .await
+        .unwrap();
+        (container, state)
+    }
+
+    /// Store a pending verification in Redis the way /verify does, and in memory
+    /// unless it was issued by another process
+    async fn seed(state: &AppState, in_memory: bool) {
+        let verification = PendingVerification {
+            discord_user_id: USER,
+            discord_username: "scotty".to_string(),
+            guild_id: GUILD,
+            guild_name: "Test".to_string(),
+            locale: String::new(),
+            created_at: chrono::Utc::now().timestamp(),
+        };
+
+        let mut conn = state.redis.clone();
+        let _: () = redis::pipe()
+            .set(
+                redis_key!("verify:{}", STATE_TOKEN),
+                serde_json::to_string(&verification).unwrap(),
+            )
+            .ignore()
+            .set(redis_key!("user:{}:verify_token", USER), STATE_TOKEN)
+            .ignore()
+            .zadd(pending_index_key(), STATE_TOKEN, verification.created_at)
+            .ignore()
+            .zadd(
+                guild_pending_index_key(GUILD),
+                STATE_TOKEN,
+                verification.created_at,
+            )
+            .ignore()
+            .query_async(&mut conn)
+            .await
+            .unwrap();
+
+        if in_memory {
+            state
+                .pending_verifications
+                .write()
+                .await
+                .insert(STATE_TOKEN.to_string(), verification);
+        }
+    }
+
+    /// How many of the verification's memory entry, keys and index entries are left
+    async fn leftovers(state: &AppState) -> usize {
+        let in_memory = state
+            .pending_verifications
+            .read()
+            .await
+            .contains_key(STATE_TOKEN);
+
+        let mut conn = state.redis.clone();
+        let (verification, user_token): (bool, bool) = redis::pipe()
+            .exists(redis_key!("verify:{}", STATE_TOKEN))
+            .exists(redis_key!("user:{}:verify_token", USER))
+            .query_async(&mut conn)
+            .await
+            .unwrap();
+        let indexed: Option<f64> = conn.zscore(pending_index_key(), STATE_TOKEN).await.unwrap();
+        let guild_indexed: Option<f64> = conn
+            .zscore(guild_pending_index_key(GUILD), STATE_TOKEN)
+            .await
+            .unwrap();
+
+        [
+            in_memory,
+            verification,
+            user_token,
+            indexed.is_some(),
+            guild_indexed.is_some(),
+        ]
+        .into_iter()
+        .filter(|left| *left)
+        .count()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Docker"]
+    async fn discarding_removes_everything() {
+        let (_container, state) = state().await;
+        seed(&state, true).await;
+        assert_eq!(leftovers(&state).await, 5);
+
+        discard_verification(&state, STATE_TOKEN).await;
+        assert_eq!(leftovers(&state).await, 0);
+    }
 
-    Redirect::to(&format!("/success?state={}", state_token)).into_response()
+    #[tokio::test]
+    #[ignore = "needs Docker"]
+    async fn discarding_a_link_from_another_process_uses_redis() {
+        let (_container, state) = state().await;
+        seed(&state, false).await;
+
+        discard_verification(&state, STATE_TOKEN).await;
+        assert_eq!(leftovers(&state).await, 0);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Docker"]
+    async fn terminal_failures_discard_the_verification() {
+        let (_container, state) = state().await;
+        seed(&state, true).await;
+
+        fail(&state, STATE_TOKEN, AppError::WrongDiscordAccount).await;
+        assert_eq!(leftovers(&state).await, 0);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Docker"]
+    async fn retryable_failures_keep_the_verification() {
+        let (_container, state) = state().await;
+        seed(&state, true).await;
+
+        fail(
+            &state,
+            STATE_TOKEN,
+            AppError::DiscordNotLinked {
+                state_token: STATE_TOKEN.to_string(),
+            },
+        )
+        .await;
+        assert_eq!(leftovers(&state).await, 5);
+    }
 }
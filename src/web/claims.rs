@@ -0,0 +1,45 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Extra ID token claims used for role assignment.
+/// Keycloak emits these when a client scope maps user attributes (e.g. `level`/`class`)
+/// to claims. Claims are kept by name since the attribute names are configurable.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct VerifyClaims {
+    #[serde(flatten)]
+    pub claims: HashMap<String, serde_json::Value>,
+}
+
+impl axum_oidc::openidconnect::AdditionalClaims for VerifyClaims {}
+impl axum_oidc::AdditionalClaims for VerifyClaims {}
+
+impl VerifyClaims {
+    /// Whether the token carried every one of the given attributes
+    pub fn has_attributes(&self, names: &[&str]) -> bool {
+        let attributes = self.attributes();
+        names.iter().all(|name| {
+            attributes
+                .get(*name)
+                .is_some_and(|values| !values.is_empty())
+        })
+    }
+
+    /// Claims in the same shape as Keycloak user attributes. A claim mapped either as a
+    /// single string or as a multivalued array is accepted, other claims are skipped.
+    pub fn attributes(&self) -> HashMap<String, Vec<String>> {
+        self.claims
+            .iter()
+            .filter_map(|(name, value)| {
+                let values = match value {
+                    serde_json::Value::String(value) => vec![value.clone()],
+                    serde_json::Value::Array(values) => values
+                        .iter()
+                        .filter_map(|v| v.as_str().map(str::to_string))
+                        .collect(),
+                    _ => return None,
+                };
+                Some((name.clone(), values))
+            })
+            .collect()
+    }
+}
@@ -1,19 +1,24 @@
 mod api;
 mod auth;
+pub mod claims;
 
+use crate::config::Config;
+use crate::error::AppError;
 use crate::frontend::app;
 use crate::state::AppState;
+use crate::web::claims::VerifyClaims;
+use anyhow::Context;
 use axum::{
     Router,
     error_handling::HandleErrorLayer,
-    extract::FromRequestParts,
-    http::{Uri, request::Parts},
-    response::IntoResponse,
-    routing::get,
+    extract::{FromRequestParts, Query, Request, State},
+    http::{HeaderMap, Uri, header, request::Parts},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
 };
 use axum_oidc::{
-    AdditionalClaims, EmptyAdditionalClaims, OidcAuthLayer, OidcClient, OidcLoginLayer,
-    OidcSession,
+    AdditionalClaims, OidcAuthLayer, OidcClient, OidcLoginLayer, OidcSession,
     error::MiddlewareError,
     handle_oidc_redirect,
     openidconnect::{ClientId, ClientSecret, CsrfToken, IssuerUrl, Scope, core::CoreGenderClaim},
@@ -21,7 +26,9 @@ use axum_oidc::{
 use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
 use leptos::{config::get_configuration, prelude::provide_context};
 use leptos_axum::{LeptosRoutes, generate_route_list};
+use reqwest::{StatusCode, Url};
 use serde::Serialize;
+use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::signal;
 use tower::ServiceBuilder;
@@ -31,6 +38,12 @@ use tower_sessions::{
     cookie::{SameSite, time::Duration},
 };
 
+/// Session key holding the axum-oidc login
+pub(crate) const OIDC_SESSION_KEY: &str = "axum-oidc";
+
+/// Session key holding the unix time the session started
+const SESSION_STARTED_AT_KEY: &str = "started_at";
+
 struct SessionWrapper(Session);
 
 impl<S: Send + Sync> FromRequestParts<S> for SessionWrapper {
@@ -45,11 +58,11 @@ impl<AC: AdditionalClaims> axum_oidc::Session<AC> for SessionWrapper {
     type Error = tower_sessions::session::Error;
 
     async fn get(&self) -> Result<OidcSession<AC, CoreGenderClaim>, Self::Error> {
-        Ok(self.0.get("axum-oidc").await?.unwrap_or_default())
+        Ok(self.0.get(OIDC_SESSION_KEY).await?.unwrap_or_default())
     }
 
     async fn set(&mut self, value: OidcSession<AC, CoreGenderClaim>) -> Result<(), Self::Error> {
-        self.0.insert("axum-oidc", value).await?;
+        self.0.insert(OIDC_SESSION_KEY, value).await?;
         Ok(())
     }
 }
@@ -70,6 +83,252 @@ fn relay_state(return_to: &str) -> String {
     URL_SAFE_NO_PAD.encode(serde_json::to_vec(&state).expect("serialize relay state"))
 }
 
+/// Keycloak's end-session endpoint, returning to `return_path` on this app once the user
+/// is signed out. The URL must be allowed as a post logout redirect URI on the client.
+pub(crate) fn end_session_url(config: &Config, return_path: &str) -> String {
+    format!(
+        "{}/realms/{}/protocol/openid-connect/logout?client_id={}&post_logout_redirect_uri={}",
+        config.keycloak_url.trim_end_matches('/'),
+        config.keycloak_realm,
+        urlencoding::encode(&config.keycloak_oidc_client_id),
+        urlencoding::encode(&format!(
+            "{}{}",
+            config.app_url.trim_end_matches('/'),
+            return_path
+        ))
+    )
+}
+
+/// The URI the client requested, rebuilt from the `X-Forwarded-Proto` and
+/// `X-Forwarded-Host` headers a TLS terminating proxy sets. `None` without a valid scheme.
+fn forwarded_uri(headers: &HeaderMap, uri: &Uri) -> Option<Uri> {
+    // Proxies chained behind each other append, so only the last value was set by the
+    // trusted proxy in front of us. Earlier ones could have come from the client.
+    let last = |name| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next_back())
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+    };
+
+    let scheme = last("x-forwarded-proto").filter(|s| matches!(*s, "http" | "https"))?;
+    let host = last("x-forwarded-host").or_else(|| last(header::HOST.as_str()))?;
+    let path = uri.path_and_query().map_or("/", |p| p.as_str());
+
+    Uri::builder()
+        .scheme(scheme)
+        .authority(host)
+        .path_and_query(path)
+        .build()
+        .ok()
+}
+
+/// Give handlers the externally visible scheme and host when behind a trusted proxy.
+/// Clients can set these headers too, so they're ignored unless `TRUST_PROXY_HEADERS` is on.
+async fn forwarded_headers(
+    State(state): State<Arc<AppState>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if state.config.trust_proxy_headers
+        && let Some(uri) = forwarded_uri(request.headers(), request.uri())
+    {
+        *request.uri_mut() = uri;
+    }
+    next.run(request).await
+}
+
+/// Turn away new `/verify` logins while verification is paused, before the OIDC login
+/// redirects to Keycloak, which may be the thing under maintenance. Logins already past
+/// this point, and their completions, are left alone.
+async fn pause_new_verifications(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if request.uri().path() == "/verify"
+        && let Ok(Query(query)) = Query::<HashMap<String, String>>::try_from_uri(request.uri())
+        && let Some(state_token) = query.get("state")
+        && let Some(verification) = state.pending_verification(state_token).await
+        && let Some(maintenance) = state.maintenance(verification.guild_id).await
+    {
+        return AppError::VerificationPaused {
+            message: maintenance.message,
+        }
+        .into_response();
+    }
+    next.run(request).await
+}
+
+/// Flush sessions older than `SESSION_MAX_LIFETIME_MINUTES`, however active they've been.
+/// The inactivity expiry alone would let a session that keeps making requests live forever.
+async fn enforce_session_lifetime(
+    State(state): State<Arc<AppState>>,
+    session: Session,
+    request: Request,
+    next: Next,
+) -> Response {
+    let now = chrono::Utc::now().timestamp();
+    let started_at = match session.get::<i64>(SESSION_STARTED_AT_KEY).await {
+        Ok(started_at) => started_at,
+        Err(e) => {
+            tracing::warn!("Failed to read session start: {}", e);
+            None
+        }
+    };
+    if let Some(started_at) = started_at
+        && session_expired(
+            started_at,
+            now,
+            state.config.session_max_lifetime_minutes * 60,
+        )
+    {
+        tracing::debug!("Session reached its maximum lifetime, flushing it");
+        if let Err(e) = session.flush().await {
+            tracing::warn!("Failed to flush expired session: {}", e);
+        }
+    }
+
+    let response = next.run(request).await;
+
+    // Empty sessions aren't saved, so the clock starts once the first request stores
+    // something, normally the OIDC login
+    if started_at.is_none()
+        && !session.is_empty().await
+        && let Err(e) = session.insert(SESSION_STARTED_AT_KEY, now).await
+    {
+        tracing::warn!("Failed to record session start: {}", e);
+    }
+    response
+}
+
+/// Whether a session that started at `started_at` has outlived `max_lifetime_secs`
+fn session_expired(started_at: i64, now: i64, max_lifetime_secs: i64) -> bool {
+    now - started_at >= max_lifetime_secs
+}
+
+/// Longest wait between discovery attempts
+const DISCOVERY_MAX_BACKOFF: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Parse a configured URL, naming the variable in the error
+fn parse_url(name: &str, value: &str) -> anyhow::Result<Url> {
+    let url = Url::parse(value).with_context(|| format!("{name} is not a valid URL: {value:?}"))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        anyhow::bail!("{name} must be an http or https URL, got {value:?}");
+    }
+    Ok(url)
+}
+
+/// Why a discovery attempt failed
+enum DiscoveryFailure {
+    /// The realm doesn't exist, retrying won't help
+    RealmNotFound,
+    /// Keycloak is unreachable or still starting
+    Retryable(String),
+}
+
+/// Check the realm's discovery document, then discover the OIDC client from it
+async fn try_discover(
+    config: &Config,
+    probe: &reqwest::Client,
+    issuer_url: &IssuerUrl,
+    redirect_url: &Uri,
+) -> Result<OidcClient<VerifyClaims>, DiscoveryFailure> {
+    // Probe first since the discovery error doesn't tell a missing realm from a down server
+    let discovery_url = format!("{}/.well-known/openid-configuration", issuer_url.as_str());
+    match probe.get(&discovery_url).send().await {
+        Ok(response) if response.status() == StatusCode::NOT_FOUND => {
+            return Err(DiscoveryFailure::RealmNotFound);
+        }
+        Ok(response) if !response.status().is_success() => {
+            return Err(DiscoveryFailure::Retryable(format!(
+                "Keycloak returned {}",
+                response.status()
+            )));
+        }
+        Ok(_) => {}
+        Err(e) => {
+            return Err(DiscoveryFailure::Retryable(format!(
+                "Keycloak is unreachable: {e}"
+            )));
+        }
+    }
+
+    let scopes = config
+        .oidc_scopes
+        .iter()
+        .map(|s| Scope::new(s.clone()))
+        .collect::<Vec<_>>();
+
+    // State carries /auth/callback
+    let auth_return_to = format!("{}/auth/callback", config.app_url);
+    let builder = OidcClient::<VerifyClaims>::builder()
+        .with_default_http_client()
+        .with_redirect_url(redirect_url.clone())
+        .with_client_id(ClientId::new(config.keycloak_oidc_client_id.clone()))
+        .with_client_secret(ClientSecret::new(
+            config.keycloak_oidc_client_secret.clone(),
+        ))
+        .with_scopes(scopes)
+        .with_state_generator(move || CsrfToken::new(relay_state(&auth_return_to)))
+        .discover(issuer_url.clone())
+        .await
+        .map_err(|e| DiscoveryFailure::Retryable(format!("OIDC discovery failed: {e}")))?;
+
+    Ok(builder.build())
+}
+
+/// Discover the OIDC client, retrying with exponential backoff until
+/// `OIDC_DISCOVERY_TIMEOUT_SECS` passes. Keycloak often starts alongside us, so
+/// failures are retried, except a missing realm which fails immediately.
+async fn discover_with_retry(
+    config: &Config,
+    issuer_url: IssuerUrl,
+    redirect_url: Uri,
+) -> anyhow::Result<OidcClient<VerifyClaims>> {
+    let probe = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(10))
+        .build()
+        .context("Failed to build discovery HTTP client")?;
+
+    let timeout = std::time::Duration::from_secs(config.oidc_discovery_timeout_secs);
+    let deadline = tokio::time::Instant::now() + timeout;
+    let mut backoff = std::time::Duration::from_secs(1);
+    let mut attempt = 1;
+    loop {
+        let error = match try_discover(config, &probe, &issuer_url, &redirect_url).await {
+            Ok(client) => return Ok(client),
+            Err(DiscoveryFailure::RealmNotFound) => anyhow::bail!(
+                "Keycloak realm {:?} was not found at {}, check KEYCLOAK_REALM",
+                config.keycloak_realm,
+                issuer_url.as_str()
+            ),
+            Err(DiscoveryFailure::Retryable(error)) => error,
+        };
+
+        if tokio::time::Instant::now() + backoff > deadline {
+            anyhow::bail!(
+                "{} (gave up after {} attempts over {}s), check KEYCLOAK_URL and that Keycloak is running",
+                error,
+                attempt,
+                timeout.as_secs()
+            );
+        }
+
+        tracing::warn!(
+            "OIDC discovery attempt {} failed ({}), retrying in {}s",
+            attempt,
+            error,
+            backoff.as_secs()
+        );
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(DISCOVERY_MAX_BACKOFF);
+        attempt += 1;
+    }
+}
+
 async fn shutdown_signal() {
     let ctrl_c = async {
         signal::ctrl_c()
@@ -95,13 +354,16 @@ async fn shutdown_signal() {
 }
 
 pub async fn serve(state: Arc<AppState>) -> anyhow::Result<()> {
-    // Set up session management
+    // Set up session management. Cookies are secure whenever users reach us over HTTPS,
+    // even if a proxy terminates TLS and forwards plain HTTP.
     let session_store = MemoryStore::default();
     let session_service = ServiceBuilder::new().layer(
         SessionManagerLayer::new(session_store)
-            .with_secure(false)
+            .with_secure(state.config.app_url.starts_with("https://"))
             .with_same_site(SameSite::Lax)
-            .with_expiry(Expiry::OnInactivity(Duration::minutes(10))),
+            .with_expiry(Expiry::OnInactivity(Duration::minutes(
+                state.config.session_inactivity_minutes,
+            ))),
     );
 
     let oidc_login_service = ServiceBuilder::new()
@@ -110,38 +372,25 @@ pub async fn serve(state: Arc<AppState>) -> anyhow::Result<()> {
             tracing::error!("Error details: {}", e);
             e.into_response()
         }))
-        .layer(OidcLoginLayer::<EmptyAdditionalClaims, SessionWrapper>::new());
+        .layer(OidcLoginLayer::<VerifyClaims, SessionWrapper>::new());
 
-    // Initialize OIDC client
-    let scopes = vec![
-        Scope::new("openid".into()),
-        Scope::new("email".into()),
-        Scope::new("profile".into()),
-    ];
+    // Validate URLs up front so a typo gets a clear message instead of a discovery failure
+    parse_url("APP_URL", &state.config.app_url)?;
+    parse_url("KEYCLOAK_URL", &state.config.keycloak_url)?;
+    let redirect_url =
+        Uri::try_from(parse_url("OAUTH_RELAY_URL", &state.config.oauth_relay_url)?.as_str())
+            .context("OAUTH_RELAY_URL is not a valid URI")?;
 
-    let issuer_url = IssuerUrl::new(format!(
+    let issuer = format!(
         "{}/realms/{}",
-        state.config.keycloak_url, state.config.keycloak_realm
-    ))
-    .expect("valid IssuerUrl");
+        state.config.keycloak_url.trim_end_matches('/'),
+        state.config.keycloak_realm
+    );
+    let issuer_url = IssuerUrl::new(issuer.clone())
+        .with_context(|| format!("Keycloak issuer URL is invalid: {issuer:?}"))?;
 
-    // State carries /auth/callback
-    let auth_return_to = format!("{}/auth/callback", state.config.app_url);
-    let oidc_client = OidcClient::<EmptyAdditionalClaims>::builder()
-        .with_default_http_client()
-        .with_redirect_url(
-            Uri::try_from(state.config.oauth_relay_url.clone()).expect("valid OAUTH_RELAY_URL"),
-        )
-        .with_client_id(ClientId::new(state.config.keycloak_oidc_client_id.clone()))
-        .with_client_secret(ClientSecret::new(
-            state.config.keycloak_oidc_client_secret.clone(),
-        ))
-        .with_scopes(scopes)
-        .with_state_generator(move || CsrfToken::new(relay_state(&auth_return_to)))
-        .discover(issuer_url)
-        .await
-        .map_err(|e| anyhow::anyhow!("oidc discovery failed: {e}"))?
-        .build();
+    // Initialize OIDC client
+    let oidc_client = discover_with_retry(&state.config, issuer_url, redirect_url).await?;
 
     tracing::info!("OIDC discovery completed successfully");
 
@@ -151,13 +400,27 @@ pub async fn serve(state: Arc<AppState>) -> anyhow::Result<()> {
             tracing::error!("Error details: {}", e);
             e.into_response()
         }))
-        .layer(OidcAuthLayer::<EmptyAdditionalClaims, SessionWrapper>::new(
+        .layer(OidcAuthLayer::<VerifyClaims, SessionWrapper>::new(
             oidc_client,
         ));
 
-    // Leptos configuration
-    let conf = get_configuration(Some("Cargo.toml")).unwrap();
-    let leptos_options = conf.leptos_options;
+    // Static assets must exist before we start serving
+    let site_root = std::path::PathBuf::from(&state.config.site_root);
+    if !site_root.is_dir() {
+        anyhow::bail!(
+            "SITE_ROOT {:?} is not a directory, build the frontend or point SITE_ROOT at its output",
+            state.config.site_root
+        );
+    }
+
+    // Leptos configuration, read from the LEPTOS_* environment when Cargo.toml isn't deployed
+    let conf_file = std::path::Path::new("Cargo.toml")
+        .is_file()
+        .then_some("Cargo.toml");
+    let conf = get_configuration(conf_file)
+        .map_err(|e| anyhow::anyhow!("Failed to load Leptos configuration: {e}"))?;
+    let mut leptos_options = conf.leptos_options;
+    leptos_options.site_root = state.config.site_root.as_str().into();
     let routes = generate_route_list(app::App);
 
     // Build router
@@ -165,16 +428,38 @@ pub async fn serve(state: Arc<AppState>) -> anyhow::Result<()> {
         // Protected routes
         .route("/verify", get(auth::verify_start))
         .route("/link-callback", get(auth::link_callback))
+        .route("/relink", get(auth::relink))
         .layer(oidc_login_service)
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            pause_new_verifications,
+        ))
         // Public routes
         .route("/api/health", get(api::health))
+        .route("/api/health/status", get(api::health_status))
         .route("/api/verify-status/{state}", get(api::verify_status))
         .route(
             "/auth/callback",
-            get(handle_oidc_redirect::<EmptyAdditionalClaims, SessionWrapper>),
+            get(handle_oidc_redirect::<VerifyClaims, SessionWrapper>),
         )
         .layer(oidc_auth_service)
+        // Admin API, authenticated by bearer token rather than OIDC
+        .route("/admin/unverify", post(api::admin_unverify))
+        .route("/admin/link", post(api::admin_link))
+        .route("/api/guild/{guild_id}/config", get(api::guild_config))
+        .route(
+            "/api/guild/{guild_id}/export.csv",
+            get(api::export_verified_csv),
+        )
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            enforce_session_lifetime,
+        ))
         .layer(session_service)
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            forwarded_headers,
+        ))
         .layer(TraceLayer::new_for_http())
         .with_state(state.clone())
         .leptos_routes_with_context(
@@ -183,7 +468,7 @@ pub async fn serve(state: Arc<AppState>) -> anyhow::Result<()> {
             move || provide_context(state.clone()),
             app::App,
         )
-        .fallback_service(ServeDir::new("target/site"))
+        .fallback_service(ServeDir::new(site_root))
         .with_state(leptos_options);
 
     let port = std::env::var("PORT").unwrap_or_else(|_| "3000".into());
@@ -197,3 +482,90 @@ pub async fn serve(state: Arc<AppState>) -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| {
+                (
+                    header::HeaderName::from_static(name),
+                    HeaderValue::from_static(value),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn forwarded_uri_uses_proxy_scheme_and_host() {
+        let uri = Uri::from_static("/auth/callback?code=abc");
+        let headers = headers(&[
+            ("x-forwarded-proto", "https"),
+            ("x-forwarded-host", "verify.example.com"),
+            ("host", "localhost:3000"),
+        ]);
+
+        assert_eq!(
+            forwarded_uri(&headers, &uri).unwrap(),
+            "https://verify.example.com/auth/callback?code=abc"
+        );
+    }
+
+    #[test]
+    fn forwarded_uri_takes_the_nearest_proxy() {
+        // The client sent its own headers, the proxy appended the real values
+        let uri = Uri::from_static("/verify");
+        let headers = headers(&[
+            ("x-forwarded-proto", "http, https"),
+            ("x-forwarded-host", "evil.example.com, verify.example.com"),
+            ("host", "localhost:3000"),
+        ]);
+
+        assert_eq!(
+            forwarded_uri(&headers, &uri).unwrap(),
+            "https://verify.example.com/verify"
+        );
+    }
+
+    #[test]
+    fn forwarded_uri_needs_a_known_scheme() {
+        let uri = Uri::from_static("/verify");
+        assert!(forwarded_uri(&headers(&[("host", "verify.example.com")]), &uri).is_none());
+        assert!(
+            forwarded_uri(
+                &headers(&[
+                    ("x-forwarded-proto", "gopher"),
+                    ("host", "verify.example.com")
+                ]),
+                &uri
+            )
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn end_session_url_returns_to_the_app() {
+        let mut config = Config::for_tests();
+        config.keycloak_url = "https://idp.example.com/".to_string();
+        config.keycloak_oidc_client_id = "discord-verify".to_string();
+        config.app_url = "https://verify.example.com".to_string();
+
+        assert_eq!(
+            end_session_url(&config, "/pending?state=abc&guild=1"),
+            "https://idp.example.com/realms/test/protocol/openid-connect/logout\
+            ?client_id=discord-verify\
+            &post_logout_redirect_uri=https%3A%2F%2Fverify.example.com%2Fpending%3Fstate%3Dabc%26guild%3D1"
+        );
+    }
+
+    #[test]
+    fn sessions_expire_at_their_max_lifetime() {
+        assert!(!session_expired(1000, 1000 + 1799, 1800));
+        assert!(session_expired(1000, 1000 + 1800, 1800));
+        assert!(session_expired(1000, 1000 + 7200, 1800));
+    }
+}
@@ -0,0 +1,90 @@
+use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::time::Duration;
+
+use crate::config::Config;
+
+/// Header carrying the hex HMAC-SHA256 of the request body, prefixed with `sha256=`
+pub const SIGNATURE_HEADER: &str = "X-Verify-Signature-256";
+
+/// Payload POSTed to the verification webhook. Ids are strings so receivers in
+/// languages without 64-bit integers don't lose precision.
+#[derive(Debug, Clone, Serialize)]
+pub struct VerificationEvent {
+    pub discord_user_id: String,
+    pub keycloak_user_id: String,
+    pub guild_id: String,
+    pub roles_added: Vec<String>,
+    pub verified_at: i64,
+}
+
+/// Sends signed verification events to the configured webhook
+#[derive(Clone)]
+pub struct WebhookClient {
+    client: reqwest::Client,
+    url: String,
+    secret: String,
+}
+
+impl WebhookClient {
+    /// Build the client if `VERIFICATION_WEBHOOK_URL` is configured
+    pub fn from_config(config: &Config) -> Result<Option<Self>> {
+        let (Some(url), Some(secret)) = (
+            config.verification_webhook_url.clone(),
+            config.verification_webhook_secret.clone(),
+        ) else {
+            return Ok(None);
+        };
+
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .context("Failed to build webhook HTTP client")?;
+
+        Ok(Some(Self {
+            client,
+            url,
+            secret,
+        }))
+    }
+
+    /// POST the event, signing the exact body bytes so receivers can verify them
+    pub async fn send(&self, event: &VerificationEvent) -> Result<()> {
+        let body = serde_json::to_vec(event)?;
+
+        self.client
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, sign(&self.secret, &body))
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
+/// Signature header value for a body: `sha256=` followed by the hex HMAC-SHA256
+fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signs_with_hmac_sha256() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign("Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}